@@ -0,0 +1,174 @@
+//! 分词吞吐量的基准测试
+//!
+//! 使用 `tests/common` 中构造的小词表，不需要下载模型，可以离线运行：
+//!
+//! ```shell
+//! cargo +nightly bench --bench tokenize
+//! ```
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::{
+    fs::{self, File},
+    hint::black_box,
+    path::Path,
+};
+
+use common::{bpe_gguf, spm_gguf, spm_vocab};
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use memmap2::Mmap;
+use try_tokenize::{
+    TokenizerConfig,
+    common::{GPT2, LLAMA3, QWEN},
+    load, load_cached,
+    unicode::unicode_regex_split,
+};
+
+const ENGLISH: &str = "The tokenizer splits text into pieces before merging them. Hello world, \
+    the quick brown fox jumps over the lazy dog while 123 people are watching.\n\n";
+const CODE: &str = "fn main() {\n    let mut v: Vec<u32> = (0..10).map(|x| x * 2).collect();\n    \
+    if v.len() > 3 { v.sort_unstable(); }\n    println!(\"{:?}\", &v[..3]);\n}\n";
+const CJK: &str = "你好，世界。分词器先把文本切成片段，再合并成标记。日本語のテキストも、한국어 문장도 섞여 있습니다。\n";
+const EMOJI: &str = "Hello 👋🏽 world 🌍! 👨‍👩‍👧‍👦 family 🎉🎉🎉 flags 🇨🇳🇺🇸 ❤️‍🔥 done ✅\n";
+
+/// 把一段文本重复到约 `len` 字节
+fn corpus(text: &str, len: usize) -> String {
+    text.repeat(len.div_ceil(text.len()))
+}
+
+/// GGUF 写入临时文件后映射
+fn mmap(path: &Path) -> Mmap {
+    let file = File::open(path).unwrap();
+    unsafe { Mmap::map(&file) }.unwrap()
+}
+
+/// 带大量控制标记的 SPM 词表，控制标记按特殊标记逐个匹配
+fn many_specials(n: usize) -> (TokenizerConfig, String) {
+    let mut vocab = spm_vocab();
+    for i in 0..n {
+        vocab.tokens.push(format!("<|reserved_special_token_{i}|>"));
+        vocab.types.push(3);
+        vocab.scores.push(0.);
+    }
+    let config = common::load_gguf(&spm_gguf(&vocab));
+    let text = (0..n)
+        .map(|i| format!("<|reserved_special_token_{}|>Hello world ", i * 7 % n))
+        .collect::<String>();
+    (config, text)
+}
+
+fn bench_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    for (name, gguf) in [("bpe", bpe_gguf("llama3")), ("spm", spm_gguf(&spm_vocab()))] {
+        let path = std::env::temp_dir().join(format!(
+            "try-tokenize-bench-{}-{name}.gguf",
+            std::process::id()
+        ));
+        fs::write(&path, gguf).unwrap();
+        group.bench_function(name, |b| {
+            b.iter_batched(|| mmap(&path), load, BatchSize::SmallInput)
+        });
+        // 缓存命中时省去 GGUF 解析，但仍要重建 token_to_id 和 bpe_ranks
+        let cache = path.with_extension("cache");
+        load_cached(mmap(&path), &cache).unwrap();
+        group.bench_function(format!("{name}-cached"), |b| {
+            b.iter_batched(
+                || mmap(&path),
+                |file| load_cached(file, &cache).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&cache).unwrap();
+    }
+    group.finish();
+}
+
+fn bench_tokenize(c: &mut Criterion) {
+    let configs = [("bpe", common::bpe("llama3")), ("spm", common::spm())];
+    let corpora = [
+        ("english", corpus(ENGLISH, 16 << 10)),
+        ("code", corpus(CODE, 16 << 10)),
+        ("cjk", corpus(CJK, 16 << 10)),
+        ("emoji", corpus(EMOJI, 16 << 10)),
+        // 病态输入：长空白和长数字串
+        (
+            "whitespace",
+            " ".repeat(8 << 10) + "\n\t ".repeat(1 << 10).as_str(),
+        ),
+        ("digits", "1234567890".repeat(1 << 10)),
+    ];
+    let mut group = c.benchmark_group("tokenize");
+    for (corpus_name, text) in &corpora {
+        group.throughput(Throughput::Bytes(text.len() as u64));
+        for (config_name, config) in &configs {
+            group.bench_function(format!("{config_name}/{corpus_name}"), |b| {
+                b.iter(|| config.tokenize(black_box(text), false, false))
+            });
+        }
+    }
+    group.finish();
+}
+
+/// `count_tokens` 与 `tokenize(...).len()` 对比，夹杂特殊标记时才会分割片段
+fn bench_count(c: &mut Criterion) {
+    let configs = [("bpe", common::bpe("qwen2")), ("spm", common::spm())];
+    let corpora = [
+        ("english", corpus(ENGLISH, 16 << 10)),
+        (
+            "specials",
+            corpus(&format!("{ENGLISH}{}{CODE}", common::CONTROL[1]), 16 << 10),
+        ),
+    ];
+    let mut group = c.benchmark_group("count");
+    for (corpus_name, text) in &corpora {
+        group.throughput(Throughput::Bytes(text.len() as u64));
+        for (config_name, config) in &configs {
+            group.bench_function(format!("{config_name}/{corpus_name}/count_tokens"), |b| {
+                b.iter(|| config.count_tokens(black_box(text), true, true))
+            });
+            group.bench_function(format!("{config_name}/{corpus_name}/tokenize_len"), |b| {
+                b.iter(|| config.tokenize(black_box(text), true, true).len())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_partition(c: &mut Criterion) {
+    let mut group = c.benchmark_group("partition_special");
+    for n in [16, 256] {
+        let (config, text) = many_specials(n);
+        group.throughput(Throughput::Bytes(text.len() as u64));
+        group.bench_function(format!("{n}_specials"), |b| {
+            b.iter(|| config.partition_special(black_box(&text), true))
+        });
+    }
+    group.finish();
+}
+
+fn bench_regex_split(c: &mut Criterion) {
+    let text = [ENGLISH, CODE, CJK, EMOJI]
+        .map(|text| corpus(text, 4 << 10))
+        .concat();
+    let mut group = c.benchmark_group("regex_split");
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    for (name, regex) in [("gpt2", GPT2), ("qwen2", QWEN), ("llama3", LLAMA3)] {
+        let regex_exprs = [regex.to_string()];
+        group.bench_function(name, |b| {
+            b.iter(|| unicode_regex_split(black_box(&text), &regex_exprs))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_load,
+    bench_tokenize,
+    bench_count,
+    bench_partition,
+    bench_regex_split
+);
+criterion_main!(benches);
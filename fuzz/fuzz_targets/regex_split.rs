@@ -0,0 +1,38 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use try_tokenize::{
+    common::{BLOOM, DEFAULT, FALCON, GPT2, GPT4O, LLAMA3, QWEN, STARCODER, TEKKEN},
+    unicode::{unicode_regex_split, unicode_utf8_to_byte},
+};
+
+// 各预分词器的表达式序列，第一个为空，整个文本作为一段
+const EXPRS: &[&[&str]] = &[
+    &[],
+    &[GPT2],
+    &[QWEN],
+    &[LLAMA3],
+    &[TEKKEN],
+    &[GPT4O],
+    &[BLOOM],
+    DEFAULT,
+    FALCON,
+    STARCODER,
+];
+
+// 非空文本的片段不为空，还原为字节后拼接与输入相同
+fuzz_target!(|input: (u8, &str)| {
+    let (which, text) = input;
+    let exprs = EXPRS[which as usize % EXPRS.len()]
+        .iter()
+        .map(|expr| expr.to_string())
+        .collect::<Vec<_>>();
+    let words = unicode_regex_split(text, &exprs);
+    assert!(text.is_empty() || words.iter().all(|word| !word.is_empty()));
+    let bytes = words
+        .iter()
+        .flat_map(|word| word.chars())
+        .map(|ch| unicode_utf8_to_byte(ch).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(bytes, text.as_bytes());
+});
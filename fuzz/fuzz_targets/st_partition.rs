@@ -0,0 +1,36 @@
+#![no_main]
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+use try_tokenize::{TextFragment, TokenizerConfig};
+
+// 分词器内部有 RefCell，每个线程各自构造
+thread_local! {
+    static CONFIGS: [TokenizerConfig; 2] = [common::bpe("qwen2"), common::spm()];
+}
+
+// 按特殊标记分割后的片段拼接与输入相同，文本片段不为空，不解析时不会产生控制标记
+fuzz_target!(|input: (bool, &str)| {
+    let (parse_special, text) = input;
+    CONFIGS.with(|configs| {
+        for config in configs {
+            let mut joined = String::new();
+            for fragment in config.partition_special(text, parse_special) {
+                match fragment {
+                    TextFragment::Token(id) => {
+                        let text = &config.id_to_token[id as usize].text;
+                        assert!(parse_special || !common::CONTROL.contains(&text.as_str()));
+                        joined.push_str(text);
+                    }
+                    TextFragment::Text(s) => {
+                        assert!(!s.is_empty());
+                        joined.push_str(s);
+                    }
+                }
+            }
+            assert_eq!(joined, text);
+        }
+    })
+});
@@ -0,0 +1,33 @@
+#![no_main]
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+use try_tokenize::TokenizerConfig;
+
+// 分词器内部有 RefCell，每个线程各自构造
+thread_local! {
+    static CONFIGS: [TokenizerConfig; 4] = [
+        common::bpe("gpt2"),
+        common::bpe("qwen2"),
+        common::bpe("llama3"),
+        common::spm(),
+    ];
+}
+
+// 任意输入都不会 panic，标记都在词表内，计数与分词结果一致
+fuzz_target!(|input: (bool, bool, &str)| {
+    let (add_special, parse_special, text) = input;
+    CONFIGS.with(|configs| {
+        for config in configs {
+            let ids = config.tokenize(text, add_special, parse_special);
+            assert!(ids.iter().all(|&id| id < config.n_tokens()));
+            assert_eq!(
+                config.count_tokens(text, add_special, parse_special),
+                ids.len()
+            );
+            config.detokenize(&ids, add_special, parse_special);
+        }
+    })
+});
@@ -0,0 +1,26 @@
+#!/usr/bin/env python3
+"""下载 Unicode 官方的 NormalizationTest.txt 到 tests/data
+
+版本与 src/unicode_data.rs 的表相同。期望结果来自 Unicode 而不是生成表用的 unicodedata，
+可以独立地检查规范化的实现：
+
+    python3 scripts/fetch_normalization_test.py
+    cargo test --test normalize -- --ignored
+"""
+
+import pathlib
+import urllib.request
+
+VERSION = "14.0.0"
+URL = f"https://www.unicode.org/Public/{VERSION}/ucd/NormalizationTest.txt"
+OUTPUT = pathlib.Path(__file__).resolve().parent.parent / "tests" / "data" / "NormalizationTest.txt"
+
+with urllib.request.urlopen(URL) as response:
+    data = response.read()
+# 第一行是文件名和版本
+header = data.split(b"\n", 1)[0].decode()
+if header != f"# NormalizationTest-{VERSION}.txt":
+    raise SystemExit(f"unexpected header {header!r}")
+OUTPUT.parent.mkdir(parents=True, exist_ok=True)
+OUTPUT.write_bytes(data)
+print(f"{OUTPUT}: {len(data)} bytes")
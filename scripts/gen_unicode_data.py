@@ -0,0 +1,106 @@
+#!/usr/bin/env python3
+"""生成 src/unicode_data.rs 中的规范化表
+
+数据来自 Python 自带的 unicodedata，版本与运行脚本的 Python 相同：
+
+    python3 scripts/gen_unicode_data.py > src/unicode_data.rs
+
+韩文音节的分解与组合按算法处理，不在表中。
+"""
+
+import unicodedata
+
+MAX_CODEPOINT = 0x10FFFF
+HANGUL = range(0xAC00, 0xD7A4)
+
+
+def codepoints():
+    for cpt in range(MAX_CODEPOINT + 1):
+        # 代理项不是字符
+        if 0xD800 <= cpt <= 0xDFFF:
+            continue
+        yield cpt
+
+
+def char(cpt):
+    return f"'\\u{{{cpt:x}}}'"
+
+
+def chars(text):
+    return "&[" + ", ".join(char(ord(c)) for c in text) + "]"
+
+
+def ranges(predicate):
+    """满足 predicate 的码点合并为 (first, last, value) 区间，value 为 None 时不输出"""
+    result = []
+    for cpt in codepoints():
+        value = predicate(cpt)
+        if value is None:
+            continue
+        if result and result[-1][1] == cpt - 1 and result[-1][2] == value:
+            result[-1][1] = cpt
+        else:
+            result.append([cpt, cpt, value])
+    return result
+
+
+canonical = []
+compatibility = []
+composition = []
+for cpt in codepoints():
+    if cpt in HANGUL:
+        continue
+    c = chr(cpt)
+    nfd = unicodedata.normalize("NFD", c)
+    nfkd = unicodedata.normalize("NFKD", c)
+    if nfd != c:
+        canonical.append((cpt, nfd))
+    if nfkd != nfd:
+        compatibility.append((cpt, nfkd))
+    # 主组合字符：两个字符的规范分解，且组合排除之外（重新组合后不变）
+    decomposition = unicodedata.decomposition(c)
+    if decomposition and not decomposition.startswith("<"):
+        parts = [int(part, 16) for part in decomposition.split()]
+        if len(parts) == 2 and unicodedata.normalize("NFC", nfd) == c:
+            composition.append((parts[0], parts[1], cpt))
+composition.sort()
+
+combining_class = ranges(lambda cpt: unicodedata.combining(chr(cpt)) or None)
+nonspacing_mark = ranges(lambda cpt: True if unicodedata.category(chr(cpt)) == "Mn" else None)
+
+print(f"//! 由 `scripts/gen_unicode_data.py` 生成，不要手动修改，Unicode {unicodedata.unidata_version}")
+print()
+print("/// 完全规范分解（NFD），不含韩文音节")
+print("#[rustfmt::skip]")
+print("pub(crate) static CANONICAL_DECOMPOSITION: &[(char, &[char])] = &[")
+for cpt, text in canonical:
+    print(f"    ({char(cpt)}, {chars(text)}),")
+print("];")
+print()
+print("/// 与完全规范分解不同的完全兼容分解（NFKD）")
+print("#[rustfmt::skip]")
+print("pub(crate) static COMPATIBILITY_DECOMPOSITION: &[(char, &[char])] = &[")
+for cpt, text in compatibility:
+    print(f"    ({char(cpt)}, {chars(text)}),")
+print("];")
+print()
+print("/// 规范组合 (first, second, composite)，按 (first, second) 排序，不含韩文音节")
+print("#[rustfmt::skip]")
+print("pub(crate) static COMPOSITION: &[(char, char, char)] = &[")
+for first, second, cpt in composition:
+    print(f"    ({char(first)}, {char(second)}, {char(cpt)}),")
+print("];")
+print()
+print("/// 非零的规范组合类 (first, last, ccc)")
+print("#[rustfmt::skip]")
+print("pub(crate) static COMBINING_CLASS: &[(char, char, u8)] = &[")
+for first, last, ccc in combining_class:
+    print(f"    ({char(first)}, {char(last)}, {ccc}),")
+print("];")
+print()
+print("/// 非间距标记（Mn）的区间 (first, last)")
+print("#[rustfmt::skip]")
+print("pub(crate) static NONSPACING_MARK: &[(char, char)] = &[")
+for first, last, _ in nonspacing_mark:
+    print(f"    ({char(first)}, {char(last)}),")
+print("];")
@@ -0,0 +1,493 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    common::{NULL, Piece, TokenAttribute, TokenData, TokenId},
+    config::{LoadError, TokenizerConfig, VocabType, try_load},
+    gguf::read_metadata,
+    ugm::Charsmap,
+    unicode::{NormalizationForm, Normalizer, try_compiled_regex},
+};
+
+/// 缓存文件的魔数
+const MAGIC: &[u8; 8] = b"GGTKCACH";
+/// 缓存格式的版本，布局或构造逻辑变化时递增，旧的缓存随之失效
+pub const CACHE_VERSION: u32 = 5;
+
+/// 固定头部中特殊标记 id 的数量
+const N_IDS: usize = 15;
+/// 固定头部中计数的数量
+const N_COUNTS: usize = 8;
+/// 固定头部的字节数：魔数、版本、词表类型、键、特殊标记、开关、计数
+const HEADER_LEN: usize = 8 + 4 + 4 + 8 + N_IDS * 4 + 4 + N_COUNTS * 4;
+
+/// GGUF 中分词器元数据（`tokenizer.` 开头的键）的 64 位 FNV-1a 哈希，用作缓存的键
+///
+/// 键值对按键排序后再哈希，结果与它们在文件中的顺序无关。
+pub fn gguf_tokenizer_key(gguf: &[u8]) -> Result<u64, LoadError> {
+    let metadata = read_metadata(gguf)?;
+    let mut kvs = metadata
+        .kvs()
+        .filter(|kv| kv.key().starts_with("tokenizer."))
+        .collect::<Vec<_>>();
+    kvs.sort_unstable_by_key(|kv| kv.key());
+    let mut hash = Fnv::new();
+    for kv in kvs {
+        hash.write(kv.key().as_bytes());
+        hash.write(&(kv.ty() as u32).to_le_bytes());
+        hash.write(kv.value_bytes());
+    }
+    Ok(hash.0)
+}
+
+/// 加载 GGUF 的分词器，优先使用 `cache` 处的缓存
+///
+/// 缓存命中时省去 GGUF 解析、合并规则文本的拆分和查找以及特殊标记的识别，标记的文本直接借用
+/// 映射而不复制（见 [`CacheView::to_shared_config`]）。加载并不是完全零拷贝：`token_to_id` 和
+/// `bpe_ranks` 两个哈希表仍按缓存中的 id 重建，耗时与词表大小成正比（`benches/tokenize.rs` 的
+/// `load/*-cached`）。需要零拷贝查询时直接使用 [`CacheView`]。
+/// 缓存不存在、版本不同、键与 GGUF 的分词器元数据不符或已损坏时按 [`try_load`] 解析 GGUF，
+/// 并重新写入缓存。写入失败只记录警告，GGUF 本身无效时返回错误。
+pub fn load_cached(file: Mmap, cache: impl AsRef<Path>) -> Result<TokenizerConfig, LoadError> {
+    let cache = cache.as_ref();
+    let key = gguf_tokenizer_key(&file)?;
+    match map_cache(cache) {
+        Ok(mapped) => match CacheView::new(&mapped) {
+            Ok(view) if view.key() == key => return Ok(view.to_shared_config(&mapped)),
+            Ok(_) => log::info!("{}: stale tokenizer cache", cache.display()),
+            Err(e) => log::warn!("{}: {e}", cache.display()),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!("{}: {e}", cache.display()),
+    }
+    let config = try_load(file)?;
+    if let Err(e) = save_cache(&config, key, cache) {
+        log::warn!("{}: failed to write tokenizer cache: {e}", cache.display());
+    }
+    Ok(config)
+}
+
+/// 映射缓存文件
+///
+/// [`save_cache`] 总是写入新文件再改名，已映射的旧文件不会被原地修改。
+fn map_cache(path: &Path) -> io::Result<Arc<Mmap>> {
+    let file = File::open(path)?;
+    Ok(Arc::new(unsafe { Mmap::map(&file) }?))
+}
+
+/// 将构造完成的分词器写入缓存文件
+pub fn save_cache(
+    config: &TokenizerConfig,
+    key: u64,
+    path: impl AsRef<Path>,
+) -> Result<(), LoadError> {
+    // 先写临时文件再改名，避免并发读到写了一半的缓存
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let written =
+        fs::write(&tmp, to_cache_bytes(config, key)).and_then(|()| fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    Ok(written?)
+}
+
+/// 缓存文件的内容
+///
+/// 所有整数按小端序存放，依次为固定头部、分数、属性、`token_to_id` 的 id、特殊标记、
+/// 结束生成的标记、合并规则（左右标记的 id 和序号）、规范化步骤、字符串偏移表、字符串数据和
+/// `precompiled_charsmap`。字符串依次为所有标记的文本和预分词正则。
+pub fn to_cache_bytes(config: &TokenizerConfig, key: u64) -> Vec<u8> {
+    let mut map = config.token_to_id.values().copied().collect::<Vec<_>>();
+    map.sort_unstable();
+    let mut eog = config.special_eog_ids.iter().copied().collect::<Vec<_>>();
+    eog.sort_unstable();
+    let mut merges = config.bpe_ranks.iter().collect::<Vec<_>>();
+    merges.sort_unstable_by_key(|&(pair, &rank)| (rank, pair));
+    let regex_exprs = config.session.borrow().regex_exprs().to_vec();
+
+    let mut bytes = Vec::new();
+    let mut put = |value: u32| bytes.extend(value.to_le_bytes());
+    let flags = [
+        config.add_space_prefix,
+        config.add_bos,
+        config.add_eos,
+        config.ignore_merges,
+        config.clean_spaces,
+        config.remove_extra_whitespaces,
+        config.escape_whitespaces,
+        config.treat_whitespace_as_suffix,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |flags, (i, &flag)| flags | u32::from(flag) << i);
+
+    let ids = [
+        config.bos,
+        config.eos,
+        config.eot,
+        config.eom,
+        config.unk,
+        config.sep,
+        config.pad,
+        config.fim_pre,
+        config.fim_suf,
+        config.fim_mid,
+        config.fim_pad,
+        config.fim_rep,
+        config.fim_sep,
+        config.linefeed,
+        config.mask,
+    ];
+    let counts = [
+        config.id_to_token.len(),
+        map.len(),
+        config.special_tokens.len(),
+        eog.len(),
+        merges.len(),
+        regex_exprs.len(),
+        config.precompiled_charsmap.len(),
+        config.normalizers.len(),
+    ];
+
+    put(u32::from_le_bytes(MAGIC[..4].try_into().unwrap()));
+    put(u32::from_le_bytes(MAGIC[4..].try_into().unwrap()));
+    put(CACHE_VERSION);
+    put(config.vocab_type as u32);
+    put(key as u32);
+    put((key >> 32) as u32);
+    ids.into_iter().for_each(&mut put);
+    put(flags);
+    counts.into_iter().for_each(|n| put(n as u32));
+
+    for token in &config.id_to_token {
+        put(token.score.to_bits());
+    }
+    for token in &config.id_to_token {
+        put(token.attribute.bits() as u32);
+    }
+    map.into_iter().for_each(&mut put);
+    config.special_tokens.iter().copied().for_each(&mut put);
+    eog.into_iter().for_each(&mut put);
+    for &(&(left, right), &rank) in &merges {
+        put(left);
+        put(right);
+        put(rank as u32);
+    }
+    for &normalizer in &config.normalizers {
+        put(normalizer_code(normalizer));
+    }
+
+    let strings = config
+        .id_to_token
+        .iter()
+        .map(|token| token.text.as_str())
+        .chain(regex_exprs.iter().map(String::as_str))
+        .collect::<Vec<_>>();
+    let mut offset = 0;
+    put(0);
+    for s in &strings {
+        offset += s.len();
+        put(offset as u32);
+    }
+    for s in strings {
+        bytes.extend(s.as_bytes());
+    }
+    bytes.extend(&config.precompiled_charsmap);
+    bytes
+}
+
+/// 直接读取缓存文件内容的视图，不复制数据
+///
+/// 通常由 mmap 得到的字节构造，标记的文本、分数和属性都可以按 id 直接访问。
+#[derive(Clone, Copy, Debug)]
+pub struct CacheView<'a> {
+    data: &'a [u8],
+    n_tokens: usize,
+    counts: [usize; N_COUNTS],
+    /// 各段的起点：分数、属性、映射、特殊标记、结束标记、合并规则、规范化步骤、偏移表
+    sections: [usize; 8],
+    strings: &'a str,
+    charsmap: &'a [u8],
+}
+
+impl<'a> CacheView<'a> {
+    /// 检查魔数、版本、各段的边界、所有标记 id 和预分词正则
+    pub fn new(data: &'a [u8]) -> Result<Self, LoadError> {
+        let error = |msg: &str| LoadError::Format(format!("tokenizer cache: {msg}"));
+        if data.len() < HEADER_LEN || &data[..8] != MAGIC {
+            return Err(error("bad magic"));
+        }
+        let version = read_u32(data, 8);
+        if version != CACHE_VERSION {
+            return Err(error(&format!(
+                "version {version}, expected {CACHE_VERSION}"
+            )));
+        }
+        let counts: [usize; N_COUNTS] =
+            std::array::from_fn(|i| read_u32(data, HEADER_LEN - (N_COUNTS - i) * 4) as usize);
+        let [
+            n_tokens,
+            n_map,
+            n_special,
+            n_eog,
+            n_merges,
+            n_regex,
+            n_charsmap,
+            n_normalizers,
+        ] = counts;
+        let n_strings = n_tokens + n_regex;
+        let lens = [
+            n_tokens,
+            n_tokens,
+            n_map,
+            n_special,
+            n_eog,
+            3 * n_merges,
+            n_normalizers,
+            n_strings + 1,
+        ];
+        let mut sections = [0; 8];
+        let mut pos = HEADER_LEN;
+        for (section, len) in sections.iter_mut().zip(lens) {
+            *section = pos;
+            pos += len * 4;
+        }
+        if data.len() < pos + n_charsmap {
+            return Err(error("truncated"));
+        }
+        let (strings, charsmap) = data[pos..].split_at(data.len() - pos - n_charsmap);
+        let strings = std::str::from_utf8(strings).map_err(|_| error("strings are not utf-8"))?;
+        Charsmap::check(charsmap)?;
+        let view = Self {
+            data,
+            n_tokens,
+            counts,
+            sections,
+            strings,
+            charsmap,
+        };
+        // 偏移必须单调且落在字符边界上，之后的访问才不需要再检查
+        let mut last = 0;
+        for i in 0..=n_strings {
+            let offset = view.u32_at(7, i) as usize;
+            if offset < last || !strings.is_char_boundary(offset) {
+                return Err(error("bad string offset"));
+            }
+            last = offset;
+        }
+        if last != strings.len() {
+            return Err(error("bad string offset"));
+        }
+        let in_range = |id: u32| (id as usize) < n_tokens;
+        let header_ids = (0..N_IDS).map(|i| read_u32(data, 24 + i * 4));
+        if !(0..n_map).all(|i| in_range(view.u32_at(2, i)))
+            || !(0..n_special).all(|i| in_range(view.u32_at(3, i)))
+            || !(0..n_eog).all(|i| in_range(view.u32_at(4, i)))
+            || !(0..n_merges)
+                .all(|i| in_range(view.u32_at(5, 3 * i)) && in_range(view.u32_at(5, 3 * i + 1)))
+            || !header_ids.into_iter().all(|id| id == NULL || in_range(id))
+        {
+            return Err(error("token id out of range"));
+        }
+        if !(0..n_normalizers).all(|i| normalizer_from_code(view.u32_at(6, i)).is_some()) {
+            return Err(error("unknown normalizer"));
+        }
+        // 编译结果进入全局缓存，之后构造分词器时不会再失败
+        for i in 0..n_regex {
+            let regex_expr = view.string(n_tokens + i);
+            try_compiled_regex(regex_expr)
+                .map_err(|e| error(&format!("invalid regex {regex_expr:?}: {e}")))?;
+        }
+        Ok(view)
+    }
+
+    /// 构造缓存时的分词器元数据哈希，见 [`gguf_tokenizer_key`]
+    pub fn key(&self) -> u64 {
+        u64::from(read_u32(self.data, 16)) | u64::from(read_u32(self.data, 20)) << 32
+    }
+
+    pub fn n_tokens(&self) -> usize {
+        self.n_tokens
+    }
+
+    pub fn text(&self, id: TokenId) -> &'a str {
+        self.string(id as usize)
+    }
+
+    pub fn score(&self, id: TokenId) -> f32 {
+        f32::from_bits(self.u32_at(0, id as usize))
+    }
+
+    pub fn attribute(&self, id: TokenId) -> TokenAttribute {
+        (self.u32_at(1, id as usize) as i32).into()
+    }
+
+    /// 按合并规则的顺序遍历 `(左, 右, 序号)`，左右为标记的 id
+    pub fn merges(&self) -> impl Iterator<Item = (TokenId, TokenId, usize)> + '_ {
+        (0..self.counts[4]).map(move |i| {
+            (
+                self.u32_at(5, 3 * i),
+                self.u32_at(5, 3 * i + 1),
+                self.u32_at(5, 3 * i + 2) as usize,
+            )
+        })
+    }
+
+    /// 复制为 [`TokenizerConfig`]，不需要重新识别特殊标记
+    pub fn to_config(&self) -> TokenizerConfig {
+        self.build(|text| text.into())
+    }
+
+    /// 与 [`to_config`](Self::to_config) 相同，但标记的文本借用 `file` 而不复制
+    ///
+    /// `file` 必须是构造这个视图的映射，否则 panic。
+    pub fn to_shared_config(&self, file: &Arc<Mmap>) -> TokenizerConfig {
+        self.build(|text| Piece::mapped(file, text))
+    }
+
+    fn build(&self, piece: impl Fn(&str) -> Piece) -> TokenizerConfig {
+        let mut config = TokenizerConfig::new();
+        config.vocab_type = match read_u32(self.data, 12) {
+            1 => VocabType::Spm,
+            2 => VocabType::Bpe,
+            3 => VocabType::Wpm,
+            4 => VocabType::Ugm,
+            5 => VocabType::Rwkv,
+            _ => VocabType::None,
+        };
+        let id = |i: usize| read_u32(self.data, 24 + i * 4);
+        [
+            config.bos,
+            config.eos,
+            config.eot,
+            config.eom,
+            config.unk,
+            config.sep,
+            config.pad,
+            config.fim_pre,
+            config.fim_suf,
+            config.fim_mid,
+            config.fim_pad,
+            config.fim_rep,
+            config.fim_sep,
+            config.linefeed,
+            config.mask,
+        ] = std::array::from_fn(id);
+        let flags = read_u32(self.data, 24 + N_IDS * 4);
+        let flag = |i: u32| flags & (1 << i) != 0;
+        config.add_space_prefix = flag(0);
+        config.add_bos = flag(1);
+        config.add_eos = flag(2);
+        config.ignore_merges = flag(3);
+        config.clean_spaces = flag(4);
+        config.remove_extra_whitespaces = flag(5);
+        config.escape_whitespaces = flag(6);
+        config.treat_whitespace_as_suffix = flag(7);
+
+        let [
+            n_tokens,
+            n_map,
+            n_special,
+            n_eog,
+            n_merges,
+            n_regex,
+            _,
+            n_normalizers,
+        ] = self.counts;
+        config.id_to_token = (0..n_tokens as TokenId)
+            .map(|id| TokenData {
+                text: piece(self.text(id)),
+                score: self.score(id),
+                attribute: self.attribute(id),
+            })
+            .collect();
+        config.token_to_id = (0..n_map)
+            .map(|i| {
+                let id = self.u32_at(2, i);
+                (config.id_to_token[id as usize].text.clone(), id)
+            })
+            .collect::<HashMap<_, _>>();
+        config.special_tokens = (0..n_special).map(|i| self.u32_at(3, i)).collect();
+        config.special_eog_ids = (0..n_eog).map(|i| self.u32_at(4, i)).collect();
+        let mut bpe_ranks = HashMap::with_capacity(n_merges);
+        for (left, right, rank) in self.merges() {
+            bpe_ranks.insert((left, right), rank);
+        }
+        config.bpe_ranks = bpe_ranks;
+        let regex_exprs = (0..n_regex)
+            .map(|i| self.string(n_tokens + i))
+            .collect::<Vec<_>>();
+        config.set_regex_exprs(&regex_exprs);
+        config.precompiled_charsmap = self.charsmap.to_vec();
+        // 构造视图时已检查
+        config.normalizers = (0..n_normalizers)
+            .filter_map(|i| normalizer_from_code(self.u32_at(6, i)))
+            .collect();
+        config
+    }
+
+    /// 第 `section` 段的第 `i` 个 u32
+    fn u32_at(&self, section: usize, i: usize) -> u32 {
+        read_u32(self.data, self.sections[section] + i * 4)
+    }
+
+    fn string(&self, i: usize) -> &'a str {
+        let start = self.u32_at(7, i) as usize;
+        let end = self.u32_at(7, i + 1) as usize;
+        &self.strings[start..end]
+    }
+}
+
+/// 规范化步骤在缓存中的编号
+fn normalizer_code(normalizer: Normalizer) -> u32 {
+    match normalizer {
+        Normalizer::Form(NormalizationForm::Nfc) => 0,
+        Normalizer::Form(NormalizationForm::Nfd) => 1,
+        Normalizer::Form(NormalizationForm::Nfkc) => 2,
+        Normalizer::Form(NormalizationForm::Nfkd) => 3,
+        Normalizer::Lowercase => 4,
+        Normalizer::StripAccents => 5,
+    }
+}
+
+fn normalizer_from_code(code: u32) -> Option<Normalizer> {
+    Some(match code {
+        0 => Normalizer::Form(NormalizationForm::Nfc),
+        1 => Normalizer::Form(NormalizationForm::Nfd),
+        2 => Normalizer::Form(NormalizationForm::Nfkc),
+        3 => Normalizer::Form(NormalizationForm::Nfkd),
+        4 => Normalizer::Lowercase,
+        5 => Normalizer::StripAccents,
+        _ => return None,
+    })
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+/// 64 位 FNV-1a 哈希，结果只取决于输入的字节
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
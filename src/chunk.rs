@@ -0,0 +1,245 @@
+use std::ops::Range;
+
+use crate::{
+    common::TokenId,
+    config::{TextFragment, TokenizerConfig, VocabType},
+    unicode::NormalizedText,
+};
+
+/// [`TokenizerConfig::chunk`] 的选项
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkOptions {
+    /// 每个窗口的最大标记数，包含 BOS/EOS
+    pub max_tokens: usize,
+    /// 相邻窗口起点之间的标记数，小于窗口长度时窗口重叠
+    pub stride: usize,
+    /// 按词表设置为每个窗口添加 BOS/EOS
+    pub add_special: bool,
+    /// 与 [`TokenizerConfig::tokenize`] 相同，匹配文本中的控制标记
+    pub parse_special: bool,
+}
+
+/// 文档中的一个窗口
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    /// 窗口的标记，按选项包含 BOS/EOS
+    pub ids: Vec<TokenId>,
+    /// 窗口对应的原文字节范围，总是落在字符边界上
+    pub range: Range<usize>,
+}
+
+/// 分块选项不合法的原因
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChunkError {
+    /// 窗口放不下 BOS/EOS 之外的任何标记
+    WindowTooSmall { max_tokens: usize, special: usize },
+    /// 步长为 0，或大于窗口中文本标记的数量，会跳过部分标记
+    InvalidStride { stride: usize, window: usize },
+    /// 无法给出标记原文范围的词表类型，只支持 SPM 和 BPE
+    UnsupportedVocab(VocabType),
+}
+
+impl std::fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WindowTooSmall {
+                max_tokens,
+                special,
+            } => write!(
+                f,
+                "window of {max_tokens} tokens leaves no room after {special} special tokens"
+            ),
+            Self::InvalidStride { stride, window } => {
+                write!(f, "stride {stride} must be in 1..={window}")
+            }
+            Self::UnsupportedVocab(vocab_type) => {
+                write!(f, "chunking {vocab_type:?} vocabularies is not supported")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChunkError {}
+
+impl TokenizerConfig {
+    /// 分词并给出每个标记对应的原文字节范围
+    ///
+    /// 范围按标记的字节长度依次排列，SPM 开头补的空格不计入原文。有 `normalizers` 时
+    /// 按 [`NormalizedText`] 的对齐把规范化文本中的位置映射回原文。
+    /// 自动添加的 BOS/EOS 对应空范围，字节回退的标记可能不在字符边界上。
+    /// 只支持 SPM 和 BPE 词表，其他词表类型返回空结果。
+    pub fn tokenize_with_offsets(
+        &self,
+        text: &str,
+        add_special: bool,
+        parse_special: bool,
+    ) -> Vec<(TokenId, Range<usize>)> {
+        let mut output = Vec::new();
+        if !matches!(self.vocab_type, VocabType::Spm | VocabType::Bpe) {
+            return output;
+        }
+        let mut bos = Vec::new();
+        if add_special {
+            self.append_bos(&mut bos);
+        }
+        output.extend(bos.into_iter().map(|id| (id, 0..0)));
+
+        let mut cursor = 0;
+        for fragment in self.partition_special(text, parse_special) {
+            match fragment {
+                TextFragment::Token(id) => {
+                    // 特殊标记两侧的空白可能被去除，在剩余的原文中查找
+                    let piece = &self.id_to_token[id as usize].text;
+                    let start = text[cursor..]
+                        .find(piece.as_str())
+                        .map_or(cursor, |pos| cursor + pos);
+                    let end = start + piece.len();
+                    output.push((id, start..end));
+                    cursor = end;
+                }
+                TextFragment::Text(substring) => {
+                    let start = substring.as_ptr() as usize - text.as_ptr() as usize;
+                    let end = start + substring.len();
+                    let ids = self.tokenize(substring, false, parse_special);
+                    // 标记的长度按规范化后的文本计算，再映射回原文
+                    let offsets = (!self.normalizers.is_empty()).then(|| {
+                        let normalized = self
+                            .normalizers
+                            .iter()
+                            .fold(NormalizedText::new(substring), |text, &normalizer| {
+                                text.apply(normalizer)
+                            });
+                        original_offsets(&normalized, substring.len())
+                    });
+                    let normalized_len = offsets
+                        .as_ref()
+                        .map_or(substring.len(), |offsets| offsets.len() - 1);
+                    let original =
+                        |pos: usize| start + offsets.as_ref().map_or(pos, |offsets| offsets[pos]);
+                    let mut skip =
+                        usize::from(self.vocab_type == VocabType::Spm && self.add_space_prefix);
+                    let mut pos = 0;
+                    for (i, &id) in ids.iter().enumerate() {
+                        let mut len = self.token_to_piece(id, 0, true).len();
+                        let skipped = len.min(skip);
+                        len -= skipped;
+                        skip -= skipped;
+                        // 最后一个标记延伸到片段末尾，避免长度不一致时范围错位
+                        let token_end = if i + 1 == ids.len() {
+                            normalized_len
+                        } else {
+                            (pos + len).min(normalized_len)
+                        };
+                        output.push((id, original(pos)..original(token_end)));
+                        pos = token_end;
+                    }
+                    cursor = end;
+                }
+            }
+        }
+
+        let mut eos = Vec::new();
+        if add_special {
+            self.append_eos(&mut eos);
+        }
+        output.extend(eos.into_iter().map(|id| (id, text.len()..text.len())));
+        output
+    }
+
+    /// 将长文本分为按步长滑动的标记窗口
+    ///
+    /// 窗口末尾附近有换行时在换行后断开，否则在句末标点后断开，都没有时在窗口末尾断开。
+    /// 断开的位置只在窗口的后半部分中查找，下一个窗口的起点不会越过上一个窗口的终点。
+    /// 与 [`tokenize_with_offsets`](Self::tokenize_with_offsets) 相同只支持 SPM 和 BPE 词表，
+    /// 其他词表类型返回 [`ChunkError::UnsupportedVocab`]。
+    pub fn chunk(&self, text: &str, options: &ChunkOptions) -> Result<Vec<Chunk>, ChunkError> {
+        if !matches!(self.vocab_type, VocabType::Spm | VocabType::Bpe) {
+            return Err(ChunkError::UnsupportedVocab(self.vocab_type));
+        }
+        let mut prefix = Vec::new();
+        let mut suffix = Vec::new();
+        if options.add_special {
+            self.append_bos(&mut prefix);
+            self.append_eos(&mut suffix);
+        }
+        let special = prefix.len() + suffix.len();
+        let window = options
+            .max_tokens
+            .checked_sub(special)
+            .filter(|&window| window > 0)
+            .ok_or(ChunkError::WindowTooSmall {
+                max_tokens: options.max_tokens,
+                special,
+            })?;
+        if options.stride == 0 || options.stride > window {
+            return Err(ChunkError::InvalidStride {
+                stride: options.stride,
+                window,
+            });
+        }
+
+        let tokens = self.tokenize_with_offsets(text, false, options.parse_special);
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < tokens.len() {
+            let mut end = (start + window).min(tokens.len());
+            if end < tokens.len() {
+                end = self
+                    .boundary(&tokens[start..end], window)
+                    .map_or(end, |i| start + i);
+            }
+            let ids = prefix
+                .iter()
+                .copied()
+                .chain(tokens[start..end].iter().map(|(id, _)| *id))
+                .chain(suffix.iter().copied())
+                .collect();
+            let range = text.floor_char_boundary(tokens[start].1.start)
+                ..text.ceil_char_boundary(tokens[end - 1].1.end);
+            chunks.push(Chunk { ids, range });
+            if end == tokens.len() {
+                break;
+            }
+            start = (start + options.stride).min(end);
+        }
+        Ok(chunks)
+    }
+
+    /// 在窗口后半部分查找最后一个段落或句子边界，返回边界后的位置
+    fn boundary(&self, tokens: &[(TokenId, Range<usize>)], window: usize) -> Option<usize> {
+        let find = |is_boundary: &dyn Fn(TokenId, &str) -> bool| {
+            (window / 2 + 1..=tokens.len()).rev().find(|&i| {
+                let id = tokens[i - 1].0;
+                is_boundary(
+                    id,
+                    &String::from_utf8_lossy(&self.token_to_piece(id, 0, false)),
+                )
+            })
+        };
+        find(&|id, piece| id == self.linefeed || piece.contains('\n')).or_else(|| {
+            find(&|_, piece| {
+                piece
+                    .trim_end()
+                    .ends_with(['.', '!', '?', '。', '！', '？'])
+            })
+        })
+    }
+}
+
+/// 规范化文本中每个字节位置对应的原文位置，长度为规范化文本的字节数加一
+///
+/// 每个位置映射为其后所有字符在原文中的最小起点，重排和删除字符后结果仍单调不减。
+/// 开头映射为 0，被删除的前导字符归入第一个标记；字符内部的位置与该字符的起点相同。
+fn original_offsets(normalized: &NormalizedText, original_len: usize) -> Vec<usize> {
+    let chars = normalized.alignments().collect::<Vec<_>>();
+    let mut offsets = vec![original_len; normalized.as_str().len() + 1];
+    let mut pos = normalized.as_str().len();
+    let mut min = original_len;
+    for (ch, original) in chars.into_iter().rev() {
+        min = min.min(original.start);
+        pos -= ch.len_utf8();
+        offsets[pos..pos + ch.len_utf8()].fill(min);
+    }
+    offsets[0] = 0;
+    offsets
+}
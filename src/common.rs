@@ -1,37 +1,240 @@
-use crate::session::{LlmTokenizerBpeSession, LlmTokenizerSpmSession};
+use std::{borrow::Borrow, fmt, hash, ops::Deref, sync::Arc};
+
+use memmap2::Mmap;
 
 pub const NULL: u32 = u32::MAX;
 pub type TokenId = u32;
 
+pub static GPT2: &str =
+    "'s|'t|'re|'ve|'m|'ll|'d| ?\\p{L}+| ?\\p{N}+| ?[^\\s\\p{L}\\p{N}]+|\\s+(?!\\S)";
 pub static QWEN: &str = "(?:'[sS]|'[tT]|'[rR][eE]|'[vV][eE]|'[mM]|'[lL][lL]|'[dD])|[^\\r\\n\\p{L}\\p{N}]?\\p{L}+|\\p{N}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+";
+pub static LLAMA3: &str = "(?:'[sS]|'[tT]|'[rR][eE]|'[vV][eE]|'[mM]|'[lL][lL]|'[dD])|[^\\r\\n\\p{L}\\p{N}]?\\p{L}+|\\p{N}{1,3}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+";
+pub static TEKKEN: &str = "[^\\r\\n\\p{L}\\p{N}]?((?=[\\p{L}])([^a-z]))*((?=[\\p{L}])([^A-Z]))+|[^\\r\\n\\p{L}\\p{N}]?((?=[\\p{L}])([^a-z]))+((?=[\\p{L}])([^A-Z]))*|\\p{N}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n/]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+";
+pub static GPT4O: &str = "[^\\r\\n\\p{L}\\p{N}]?[\\p{Lu}\\p{Lt}\\p{Lm}\\p{Lo}\\p{M}]*[\\p{Ll}\\p{Lm}\\p{Lo}\\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?|[^\\r\\n\\p{L}\\p{N}]?[\\p{Lu}\\p{Lt}\\p{Lm}\\p{Lo}\\p{M}]+[\\p{Ll}\\p{Lm}\\p{Lo}\\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?|\\p{N}{1,3}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n/]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+";
+pub static BLOOM: &str = " ?[^(\\s|.,!?…。，、।۔،)]+";
+
+/// 以下为多个正则依次分割的预分词，与 llama.cpp 的 `llm_tokenizer_bpe` 相同
+///
+/// `tokenizer.ggml.pre` 缺失或为 `default` 时使用
+pub static DEFAULT: &[&str] = &[
+    "[\\p{P}\\$\\+<=>\\^~\\|]+",
+    GPT2,
+    "\\p{N}+",
+    "[0-9][0-9][0-9]",
+];
+pub static FALCON: &[&str] = &["[\\p{P}\\$\\+<=>\\^~\\|`]+", GPT2, "[0-9][0-9][0-9]"];
+pub static STARCODER: &[&str] = &["\\p{N}", GPT2];
+pub static DEEPSEEK_CODER: &[&str] = &[
+    "[\r\n]",
+    "\\s?\\p{L}+",
+    "\\s?\\p{P}+",
+    "[一-龥ࠀ-一가-퟿]+",
+    "\\p{N}",
+];
+pub static VIKING: &[&str] = &[BLOOM, "\\p{N}"];
 
 #[derive(Debug, Clone)]
 pub struct TokenData {
-    pub text: String,
+    pub text: Piece,
     pub score: f32,
     pub attribute: TokenAttribute,
 }
 
-#[repr(i32)]
-#[derive(Copy, Clone, Debug)]
-pub enum TokenAttribute {
-    Undefined = 0,
-    Unknown = 1 << 0,
-    Unused = 1 << 1,
-    Normal = 1 << 2,
-    Control = 1 << 3, // SPECIAL?
-    UserDefined = 1 << 4,
-    Byte = 1 << 5,
-    Normalized = 1 << 6,
-    LStrIp = 1 << 7,
-    RStrIp = 1 << 8,
-    SingleWord = 1 << 9,
+/// 标记的文本
+///
+/// 可以持有自己的字符串，也可以借用映射的 GGUF 文件中的字符串数组（见 [`load_shared`](crate::config::load_shared)）。
+/// 两种情况复制都只增加引用计数，`token_to_id` 和 `id_to_token` 共享同一份文本。
+/// 比较、哈希和格式化都与 `str` 相同。
+#[derive(Clone)]
+pub struct Piece(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Owned(Arc<str>),
+    Mapped {
+        file: Arc<Mmap>,
+        start: usize,
+        len: u32,
+    },
+}
+
+impl Piece {
+    /// 借用 `file` 中的 `text`，`text` 必须位于 `file` 的映射范围内
+    pub fn mapped(file: &Arc<Mmap>, text: &str) -> Self {
+        let start = (text.as_ptr() as usize)
+            .checked_sub(file.as_ptr() as usize)
+            .filter(|start| start + text.len() <= file.len())
+            .expect("text is not borrowed from the mapped file");
+        Self(Repr::Mapped {
+            file: file.clone(),
+            start,
+            len: text.len().try_into().unwrap(),
+        })
+    }
+
+    /// 是否借用了映射的文件
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.0, Repr::Mapped { .. })
+    }
+
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            Repr::Owned(text) => text,
+            // SAFETY: 构造时这段字节来自 `&str`，映射在引用计数归零前不会释放
+            Repr::Mapped { file, start, len } => unsafe {
+                std::str::from_utf8_unchecked(&file[*start..*start + *len as usize])
+            },
+        }
+    }
+}
+
+impl Default for Piece {
+    fn default() -> Self {
+        Self::from("")
+    }
+}
+
+impl Deref for Piece {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Piece {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for Piece {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Piece {
+    fn from(text: &str) -> Self {
+        Self(Repr::Owned(text.into()))
+    }
+}
+
+impl From<String> for Piece {
+    fn from(text: String) -> Self {
+        Self(Repr::Owned(text.into()))
+    }
+}
+
+impl PartialEq for Piece {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Piece {}
+
+impl PartialEq<str> for Piece {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Piece {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for Piece {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<Piece> for &str {
+    fn eq(&self, other: &Piece) -> bool {
+        *self == other.as_str()
+    }
+}
+
+impl PartialEq<Piece> for String {
+    fn eq(&self, other: &Piece) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl hash::Hash for Piece {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl fmt::Debug for Piece {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Piece {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl serde::Serialize for Piece {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// 标记属性，与 llama.cpp 的 `llama_token_attr` 一样按位组合
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct TokenAttribute(i32);
+
+#[allow(non_upper_case_globals)]
+impl TokenAttribute {
+    pub const Undefined: Self = Self(0);
+    pub const Unknown: Self = Self(1 << 0);
+    pub const Unused: Self = Self(1 << 1);
+    pub const Normal: Self = Self(1 << 2);
+    pub const Control: Self = Self(1 << 3); // SPECIAL?
+    pub const UserDefined: Self = Self(1 << 4);
+    pub const Byte: Self = Self(1 << 5);
+    pub const Normalized: Self = Self(1 << 6);
+    pub const LStrIp: Self = Self(1 << 7);
+    pub const RStrIp: Self = Self(1 << 8);
+    pub const SingleWord: Self = Self(1 << 9);
+
+    /// 原始位值
+    pub const fn bits(self) -> i32 {
+        self.0
+    }
+    /// 是否包含 `other` 的全部位
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    /// 是否与 `other` 有任意相同的位
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl std::ops::BitOr for TokenAttribute {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for TokenAttribute {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0
+    }
 }
 
 impl From<i32> for TokenAttribute {
     fn from(value: i32) -> Self {
-        // 这里我们简单地使用 unsafe 将 i32 转换为 TokenAttribute
-        // 因为我们已经使用 #[repr(i32)] 确保了内存布局兼容
-        unsafe { std::mem::transmute(value) }
+        Self(value)
     }
 }
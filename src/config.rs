@@ -1,22 +1,58 @@
 use std::{
-    cell::RefCell,
-    collections::{HashMap, HashSet, LinkedList},
+    borrow::Cow,
+    cell::{OnceCell, RefCell},
+    collections::{HashMap, HashSet},
+    ops::ControlFlow,
+    sync::Arc,
 };
 
-use ggus::{GGuf, GGufMetaError, GGufMetaMapExt};
+use ggus::{
+    GGufMetaDataValueType, GGufMetaError, GGufMetaMap, GGufMetaMapExt, GGufReadError, GGufReader,
+};
 use memmap2::Mmap;
 
 use crate::{
-    FragmentBufferVariant, FragmentBufferVariantType,
-    common::{NULL, QWEN, TokenAttribute, TokenData, TokenId},
-    session::{LlmTokenizerBpe, LlmTokenizerBpeSession},
-    unicode::unicode_byte_to_utf8,
+    common::{
+        BLOOM, DEEPSEEK_CODER, DEFAULT, FALCON, GPT2, GPT4O, LLAMA3, NULL, Piece, QWEN, STARCODER,
+        TEKKEN, TokenAttribute, TokenData, TokenId, VIKING,
+    },
+    gguf::{Metadata, read_metadata},
+    prefix::PrefixIndex,
+    session::{LlmTokenizerBpe, LlmTokenizerBpeSession, LlmTokenizerSpmSession},
+    ugm::{Charsmap, LlmTokenizerUgm},
+    unicode::{NormalizedText, Normalizer, unicode_byte_to_utf8, unicode_utf8_to_byte},
     untils::llama_escape_whitespace,
 };
 
 //  load 函数 默认都是gpt2
+///
+/// 格式错误或不支持的词表会 panic，需要处理错误时使用 [`try_load`]。
 pub fn load(file: Mmap) -> TokenizerConfig {
-    let gguf = GGuf::new(&file).unwrap();
+    try_load(file).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// 与 [`load`] 相同，但标记的文本直接借用映射中的 `tokenizer.ggml.tokens`，不复制
+///
+/// 返回的分词器持有 `file` 的引用，同一个映射可以继续用于加载张量。
+pub fn load_shared(file: Arc<Mmap>) -> TokenizerConfig {
+    try_load_shared(file).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// 与 [`load`] 相同，元数据格式错误、词表类型或预分词类型未知时返回错误
+pub fn try_load(file: Mmap) -> Result<TokenizerConfig, LoadError> {
+    load_with(&file, |text| text.into())
+}
+
+/// 与 [`load_shared`] 相同，出错时返回错误
+pub fn try_load_shared(file: Arc<Mmap>) -> Result<TokenizerConfig, LoadError> {
+    load_with(&file, |text| Piece::mapped(&file, text))
+}
+
+pub(crate) fn load_with(
+    file: &[u8],
+    piece: impl Fn(&str) -> Piece,
+) -> Result<TokenizerConfig, LoadError> {
+    let gguf = read_metadata(file)?;
     // 添加多模型支持需要根据 tokenizer_ggml_mode 和tokenizer.ggml.pre对词表进行不同的初始化
 
     let mut config = TokenizerConfig::new();
@@ -32,62 +68,106 @@ pub fn load(file: Mmap) -> TokenizerConfig {
     config.add_space_prefix = false;
     config.clean_spaces = true;
     // gpt2 默认填充规则  LLAMA_VOCAB_PRE_TYPE_GPT2
-    config.vocab_type = VocabType::Bpe;
+    config.vocab_type = match gguf.get_str("tokenizer.ggml.model").unwrap_or("gpt2") {
+        "no_vocab" | "none" => VocabType::None,
+        "llama" => VocabType::Spm,
+        "gpt2" => VocabType::Bpe,
+        "bert" => VocabType::Wpm,
+        "t5" => VocabType::Ugm,
+        "rwkv" => VocabType::Rwkv,
+        model => return Err(format_error(format!("unknown tokenizer model `{model}`"))),
+    };
+    // SPM 默认在开头补空格，与 llama.cpp 相同
+    if config.vocab_type == VocabType::Spm {
+        config.bos = 1;
+        config.eos = 2;
+        config.unk = 0;
+        config.add_space_prefix = true;
+        config.clean_spaces = false;
+    }
+    // T5 的默认特殊标记，不添加 BOS，结尾添加 EOS
+    if config.vocab_type == VocabType::Ugm {
+        config.bos = NULL;
+        config.eos = 1;
+        config.unk = 2;
+        config.pad = 0;
+        config.add_bos = false;
+        config.add_eos = true;
+        config.clean_spaces = false;
+        config.precompiled_charsmap = read_bytes(&gguf, "tokenizer.ggml.precompiled_charsmap")?;
+        Charsmap::check(&config.precompiled_charsmap)?;
+    }
     // 检查是是否有填充字段，
 
+    // 只有 BPE 词表使用 tokenizer.ggml.pre
+    if config.vocab_type == VocabType::Bpe {
+        let pre = gguf.get_str("tokenizer.ggml.pre").unwrap_or("default");
+        set_pre_tokenizer(&mut config, pre)?;
+    }
+
+    let tokens = gguf
+        .tokenizer_ggml_tokens()
+        .map_err(|e| meta_error("tokenizer.ggml.tokens", e))?;
+    let n_tokens = tokens.len();
+
     // 加载特殊字符
     {
         // SPM进行分词需要
         config.add_space_prefix = gguf
             .get_bool("tokenizer.ggml.add_space_prefix")
-            .unwrap_or(false);
+            .unwrap_or(config.add_space_prefix);
         // remove_extra_whitespaces
         config.remove_extra_whitespaces = gguf
             .get_bool("tokenizer.ggml.remove_extra_whitespaces")
             .unwrap_or(false);
 
-        let matche_token = |token: Result<u32, GGufMetaError>, target: u32| -> u32 {
-            if token.is_ok() {
-                token.unwrap()
-            } else {
-                target
-            }
-        };
-        config.bos = matche_token(gguf.tokenizer_ggml_bos_token_id(), config.bos);
-        config.eos = matche_token(gguf.tokenizer_ggml_eos_token_id(), config.eos);
-        config.eot = matche_token(gguf.get_u32("tokenizer.ggml.eot_token_id"), config.eot);
-        config.eom = matche_token(gguf.get_u32("tokenizer.ggml.eom_token_id"), config.eom);
-        config.unk = matche_token(gguf.get_u32("tokenizer.ggml.unknown_token_id"), config.unk);
+        // 文件中的 id 超出词表时报错，默认值超出词表时视为没有
+        let matche_token =
+            |token: Result<u32, GGufMetaError>, target: u32| -> Result<u32, LoadError> {
+                match token {
+                    Ok(id) if id as usize >= n_tokens => Err(format_error(format!(
+                        "special token id {id} out of range for {n_tokens} tokens"
+                    ))),
+                    Ok(id) => Ok(id),
+                    Err(_) if target != NULL && target as usize >= n_tokens => Ok(NULL),
+                    Err(_) => Ok(target),
+                }
+            };
+        config.bos = matche_token(gguf.tokenizer_ggml_bos_token_id(), config.bos)?;
+        config.eos = matche_token(gguf.tokenizer_ggml_eos_token_id(), config.eos)?;
+        config.eot = matche_token(gguf.get_u32("tokenizer.ggml.eot_token_id"), config.eot)?;
+        config.eom = matche_token(gguf.get_u32("tokenizer.ggml.eom_token_id"), config.eom)?;
+        config.unk = matche_token(gguf.get_u32("tokenizer.ggml.unknown_token_id"), config.unk)?;
         config.sep = matche_token(
             gguf.get_u32("tokenizer.ggml.seperator_token_id"),
             config.sep,
-        );
-        config.pad = matche_token(gguf.get_u32("tokenizer.ggml.padding_token_id"), config.pad);
-        config.mask = matche_token(gguf.get_u32("tokenizer.ggml.mask_token_id"), config.mask);
+        )?;
+        config.pad = matche_token(gguf.get_u32("tokenizer.ggml.padding_token_id"), config.pad)?;
+        config.mask = matche_token(gguf.get_u32("tokenizer.ggml.mask_token_id"), config.mask)?;
         config.fim_pre = matche_token(
             gguf.get_u32("tokenizer.ggml.fim_pre_token_id"),
             config.fim_pre,
-        );
+        )?;
         config.fim_suf = matche_token(
             gguf.get_u32("tokenizer.ggml.fim_suf_token_id"),
             config.fim_suf,
-        );
+        )?;
         config.fim_mid = matche_token(
             gguf.get_u32("tokenizer.ggml.fim_mid_token_id"),
             config.fim_mid,
-        );
+        )?;
         config.fim_pad = matche_token(
             gguf.get_u32("tokenizer.ggml.fim_pad_token_id"),
             config.fim_pad,
-        );
+        )?;
         config.fim_rep = matche_token(
             gguf.get_u32("tokenizer.ggml.fim_rep_token_id"),
             config.fim_rep,
-        );
+        )?;
         config.fim_sep = matche_token(
             gguf.get_u32("tokenizer.ggml.fim_sep_token_id"),
             config.fim_sep,
-        );
+        )?;
 
         config.add_bos = gguf
             .get_bool("tokenizer.ggml.add_bos_token")
@@ -97,26 +177,29 @@ pub fn load(file: Mmap) -> TokenizerConfig {
             .unwrap_or(config.add_eos);
     }
 
-    let tokens = gguf.tokenizer_ggml_tokens().unwrap();
-    let scores = gguf
-        .tokenizer_ggml_scores()
-        .ok()
-        .map(|arr| arr.map(|r| r.unwrap()).collect::<Vec<_>>());
-    let token_type = gguf
-        .tokenizer_ggml_token_type()
-        .ok()
-        .map(|arr| arr.map(|r| r.unwrap()).collect::<Vec<_>>())
-        .unwrap();
-    // 此处等同于llama.cpp的合并
-    let bpe_ranks = load_gpt2(&gguf);
-    let mut id_to_token = Vec::with_capacity(tokens.len());
+    // 分数和类型可以没有，有时长度必须与标记数相同
+    let scores = match gguf.tokenizer_ggml_scores() {
+        Err(GGufMetaError::NotExist) => None,
+        scores => Some(read_array("tokenizer.ggml.scores", scores, n_tokens)?),
+    };
+    let token_type = match gguf.tokenizer_ggml_token_type() {
+        Err(GGufMetaError::NotExist) => None,
+        token_type => Some(read_array(
+            "tokenizer.ggml.token_type",
+            token_type,
+            n_tokens,
+        )?),
+    };
+    let mut id_to_token = Vec::with_capacity(n_tokens);
 
-    let mut token_to_id: HashMap<String, TokenId> = HashMap::with_capacity(tokens.len());
+    let mut token_to_id: HashMap<Piece, TokenId> = HashMap::with_capacity(n_tokens);
 
     for (i, text) in tokens.into_iter().enumerate() {
-        let text = text.unwrap().to_string();
+        let text =
+            piece(text.map_err(|e| meta_error("tokenizer.ggml.tokens", GGufMetaError::Read(e)))?);
         let score = scores.as_ref().map_or(0.0, |s| s[i]);
-        let attribute = match token_type[i] {
+        // 没有类型时与 llama.cpp 相同，都是普通标记
+        let attribute = match token_type.as_ref().map_or(1, |t| t[i]) {
             1 => TokenAttribute::Normal,
             2 => TokenAttribute::Unknown,
             3 => TokenAttribute::Control,
@@ -134,236 +217,211 @@ pub fn load(file: Mmap) -> TokenizerConfig {
 
         token_to_id.insert(text, i as u32);
     }
-    config.token_to_id = token_to_id.clone();
-    config.id_to_token = id_to_token.clone();
+    // BPE 构造换行符时需要字节对应的标记，与 llama.cpp 相同，缺少时无法加载
+    if config.vocab_type == VocabType::Bpe
+        && !token_to_id.contains_key(unicode_byte_to_utf8(b'\n').as_str())
+    {
+        return Err(format_error("missing byte token for `\\n`".into()));
+    }
+    // 此处等同于llama.cpp的合并，只有 BPE 词表需要
+    if config.vocab_type == VocabType::Bpe {
+        config.bpe_ranks = load_gpt2(&gguf, &token_to_id)?;
+    }
+    config.token_to_id = token_to_id;
+    config.id_to_token = id_to_token;
+    config.init_special_tokens();
+    Ok(config)
+}
 
-    // 待完善 linefeed_id 暂时不支持SPM  构造换行符
-    match config.vocab_type {
-        VocabType::None | VocabType::Bpe => {
-            let ids = config.tokenize("\n", false, false);
-            if ids.is_empty() {
-                config.linefeed = config.pad;
-            } else {
-                config.linefeed = ids[0];
-            }
-        }
-        VocabType::Spm => {
-            config.linefeed = if token_to_id.contains_key("\n") {
-                *token_to_id.get("\n").unwrap()
-            } else {
-                config.pad
-            };
-        }
-        VocabType::Wpm => todo!(),
-        VocabType::Ugm => todo!(),
-        VocabType::Rwkv => todo!(),
+/// 读取 u8 或 i8 数组的内容，没有时为空
+fn read_bytes(gguf: &Metadata, key: &str) -> Result<Vec<u8>, LoadError> {
+    let Some((ty, value)) = gguf.get(key) else {
+        return Ok(Vec::new());
+    };
+    if ty != GGufMetaDataValueType::Array {
+        return Err(meta_error(key, GGufMetaError::TypeMismatch(ty)));
     }
+    let mut reader = GGufReader::new(value);
+    let (ty, len) = reader
+        .read_arr_header()
+        .map_err(|e| meta_error(key, GGufMetaError::Read(e)))?;
+    if !matches!(ty, GGufMetaDataValueType::U8 | GGufMetaDataValueType::I8) {
+        return Err(meta_error(key, GGufMetaError::ArrTypeMismatch(ty)));
+    }
+    // 数组头为 4 字节的类型和 8 字节的长度
+    value
+        .get(12..12 + len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| format_error(format!("{key} is truncated")))
+}
 
-    for (key, value) in &token_to_id {
-        if config.eot == NULL {
-            if key == "<|eot_id|>"
-                || key == "<|im_end|>"
-                || key == "<|end|>"
-                || key == "<end_of_turn>"
-                || key == "<|endoftext|>"
-                || key == "< EOT >"
-                || key == "_< EOT >"
-                || key == "<｜end▁of▁sentence｜>"
-            // DeepSeek
-            {
-                config.eot = *value;
-                if (id_to_token[*value as usize].attribute as i32 & TokenAttribute::Control as i32)
-                    == 0
-                {
-                    id_to_token[*value as usize].attribute = TokenAttribute::Control;
-                }
-            }
+/// 读取长度为 `len` 的数组
+fn read_array<T>(
+    key: &str,
+    array: Result<impl Iterator<Item = Result<T, GGufReadError>>, GGufMetaError>,
+    len: usize,
+) -> Result<Vec<T>, LoadError> {
+    let values = array
+        .map_err(|e| meta_error(key, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| meta_error(key, GGufMetaError::Read(e)))?;
+    if values.len() != len {
+        return Err(format_error(format!(
+            "{key} has {} values but there are {len} tokens",
+            values.len()
+        )));
+    }
+    Ok(values)
+}
+
+fn meta_error(key: &str, e: GGufMetaError) -> LoadError {
+    match e {
+        GGufMetaError::NotExist => format_error(format!("missing {key}")),
+        e => format_error(format!("invalid {key}: {e:?}")),
+    }
+}
+
+fn format_error(msg: String) -> LoadError {
+    LoadError::Format(format!("gguf: {msg}"))
+}
+
+/// 根据 tokenizer.ggml.pre 选择预分词正则和相关选项，与 llama.cpp 的 `llama_vocab::load` 相同
+///
+/// 不认识的预分词类型直接报错，不会退回到其他正则。
+pub(crate) fn set_pre_tokenizer(config: &mut TokenizerConfig, pre: &str) -> Result<(), LoadError> {
+    match pre {
+        "default" => config.set_regex_exprs(DEFAULT),
+        // LLaMA-3 风格的词表需要忽略合并
+        "llama3" | "llama-v3" | "llama-bpe" | "falcon3" => {
+            config.set_regex_exprs(&[LLAMA3]);
+            config.ignore_merges = true;
+            config.add_bos = true;
         }
-        if config.eom == NULL {
-            if key == "<|eom_id|>" {
-                config.eom = *value;
-                if (id_to_token[*value as usize].attribute as i32 & TokenAttribute::Control as i32)
-                    == 0
-                {
-                    id_to_token[*value as usize].attribute = TokenAttribute::Control;
-                }
-            }
+        "dbrx" | "smaug-bpe" => config.set_regex_exprs(&[LLAMA3]),
+        "deepseek-coder" => {
+            config.set_regex_exprs(DEEPSEEK_CODER);
+            config.clean_spaces = false;
         }
-        if config.fim_pre == NULL {
-            if key == "<|fim_prefix|>" // Qwen
-                || key == "<fim-prefix>"
-                || key == "<｜fim▁begin｜>" // DeepSeek
-                || key == "<PRE>"
-                || key == "▁<PRE>"
-            {
-                config.fim_pre = *value;
-                if (id_to_token[*value as usize].attribute as i32 & TokenAttribute::Control as i32)
-                    == 0
-                {
-                    id_to_token[*value as usize].attribute = TokenAttribute::Control;
-                }
-            }
+        "falcon" => config.set_regex_exprs(FALCON),
+        "starcoder" | "refact" | "codeshell" | "exaone" | "minerva-7b" => {
+            config.set_regex_exprs(STARCODER)
         }
-        if config.fim_suf == NULL {
-            if key == "<|fim_suffix|>" // Qwen
-            || key == "<fim-suffix>"
-            || key == "<｜fim▁hole｜>" // DeepSeek
-            || key == "<SUF>"
-            || key == "▁<SUF>"
-            // CodeLlama
-            {
-                config.fim_suf = *value;
-                if (id_to_token[*value as usize].attribute as i32 & TokenAttribute::Control as i32)
-                    == 0
-                {
-                    id_to_token[*value as usize].attribute = TokenAttribute::Control;
-                }
-            }
+        "command-r" | "smollm" => {
+            config.set_regex_exprs(STARCODER);
+            config.clean_spaces = false;
         }
-        if config.fim_mid == NULL {
-            if key == "<|fim_middle|>" // Qwen
-            || key == "<fim-middle>"
-            || key == "<｜fim▁end｜>" // DeepSeek
-            || key == "<MID>"
-            || key == "▁<MID>"
-            // CodeLlama
-            {
-                config.fim_mid = *value;
-                if (id_to_token[*value as usize].attribute as i32 & TokenAttribute::Control as i32)
-                    == 0
-                {
-                    id_to_token[*value as usize].attribute = TokenAttribute::Control;
-                }
-            }
+        "gpt2" | "gpt-2" | "phi-2" | "jina-es" | "jina-de" | "gigachat" | "jina-v1-en"
+        | "jina-v2-es" | "jina-v2-de" | "jina-v2-code" | "roberta-bpe" | "mpt" | "olmo"
+        | "jais" => config.set_regex_exprs(&[GPT2]),
+        "qwen2" | "deepseek-r1-qwen" | "megrez" => {
+            config.set_regex_exprs(&[QWEN]);
+            config.clean_spaces = false;
         }
-        if config.fim_mid == NULL {
-            if key== "<|fim_middle|>" // Qwen
-            || key== "<fim-middle>"
-            || key== "<｜fim▁end｜>"  // DeepSeek
-            || key== "<MID>"
-            || key== "▁<MID>"
-            // CodeLlama
-            {
-                config.fim_mid = *value;
-                if (id_to_token[*value as usize].attribute as i32 & TokenAttribute::Control as i32)
-                    == 0
-                {
-                    id_to_token[*value as usize].attribute = TokenAttribute::Control;
-                }
-            }
+        "stablelm2" => config.set_regex_exprs(&[QWEN]),
+        "poro-chat" => {
+            config.set_regex_exprs(&[BLOOM]);
+            config.clean_spaces = false;
         }
-        if config.fim_pad == NULL {
-            if key == "<|fim_pad|>" // Qwen
-                || key == "<fim-pad>"
-                || key == "<PAD>"
-            {
-                config.fim_pad = *value;
-                if (id_to_token[*value as usize].attribute as i32 & TokenAttribute::Control as i32)
-                    == 0
-                {
-                    id_to_token[*value as usize].attribute = TokenAttribute::Control;
-                }
-            }
+        "bloom" | "gpt3-finnish" => config.set_regex_exprs(&[BLOOM]),
+        "viking" => {
+            config.set_regex_exprs(VIKING);
+            config.clean_spaces = false;
         }
-        if config.fim_rep == NULL {
-            if key == "<|fim_repo|>"  // Qwen
-            || key == "<|repo_name|>"
-            || key == "<fim-repo>"
-            || key == "<REPO>"
-            {
-                config.fim_rep = *value;
-                if (id_to_token[*value as usize].attribute as i32 & TokenAttribute::Control as i32)
-                    == 0
-                {
-                    id_to_token[*value as usize].attribute = TokenAttribute::Control;
-                }
-            }
+        "chatglm-bpe" => {
+            config.set_regex_exprs(&[LLAMA3]);
+            config.bos = NULL;
         }
-        if config.fim_sep == NULL {
-            if key == "<|file_sep|>"
-            // Qwen
-            {
-                config.fim_sep = *value;
-                if (id_to_token[*value as usize].attribute as i32 & TokenAttribute::Control as i32)
-                    == 0
-                {
-                    id_to_token[*value as usize].attribute = TokenAttribute::Control;
-                }
-            }
+        "tekken" => {
+            config.set_regex_exprs(&[TEKKEN]);
+            config.clean_spaces = false;
+            config.ignore_merges = true;
+            config.add_bos = true;
         }
-    }
-    let mut special_eog_ids = HashSet::new();
-    // maintain a list of tokens that cause end-of-generation
-    if config.fim_pad != NULL && !special_eog_ids.contains(&config.fim_pad) {
-        special_eog_ids.insert(config.fim_pad);
-    }
-    if config.fim_rep != NULL && !special_eog_ids.contains(&config.fim_rep) {
-        special_eog_ids.insert(config.fim_rep);
-    }
-    if config.fim_sep != NULL && !special_eog_ids.contains(&config.fim_sep) {
-        special_eog_ids.insert(config.fim_sep);
-    }
-
-    // 第二个循环也使用引用
-    for (key, value) in &token_to_id {
-        if key == "<|eot_id|>"
-            || key == "<|im_end|>"
-            || key == "<|end|>"
-            || key == "<end_of_turn>"
-            || key == "<|endoftext|>"
-            || key == "<|eom_id|>"
-            || key == "< EOT >"
-            || key == "_< EOT >"
-        {
-            special_eog_ids.insert(*value);
-            if (id_to_token[*value as usize].attribute as i32 & TokenAttribute::Control as i32) == 0
-            {
-                id_to_token[*value as usize].attribute = TokenAttribute::Control;
-            }
-        } else {
-            if (id_to_token[*value as usize].attribute as i32 & TokenAttribute::Control as i32) == 0
-                && !special_eog_ids.contains(value)
-            {
-                log::warn!("{}", key);
-            }
+        "gpt-4o" => {
+            config.set_regex_exprs(&[GPT4O]);
+            config.clean_spaces = false;
+        }
+        pre => {
+            return Err(LoadError::Format(format!(
+                "unsupported pre-tokenizer `{pre}`"
+            )));
         }
     }
+    Ok(())
+}
 
-    config.special_tokens = id_to_token
-        .iter()
-        .enumerate() // 获取索引 (TokenId) 和 TokenData
-        .filter(|(_, token_data)| {
-            // 检查 token 的属性是否为 Control, UserDefined 或 Unknown
-            match token_data.attribute {
-                TokenAttribute::Control | TokenAttribute::UserDefined | TokenAttribute::Unknown => {
-                    true
-                }
-                _ => false,
-            }
+fn load_gpt2(
+    gguf: &Metadata,
+    token_to_id: &HashMap<Piece, TokenId>,
+) -> Result<HashMap<(TokenId, TokenId), usize>, LoadError> {
+    let key = "tokenizer.ggml.merges";
+    let merges = gguf
+        .tokenizer_ggml_merges()
+        .map_err(|e| meta_error(key, e))?
+        .enumerate()
+        .map(|(i, x)| {
+            let piece = x.map_err(|e| meta_error(key, GGufMetaError::Read(e)))?;
+            let pair = piece
+                .split_once(' ')
+                .ok_or_else(|| format_error(format!("invalid merge `{piece}`")))?;
+            Ok((pair, i))
         })
-        .map(|(index, _)| index as TokenId) // 提取符合条件的 TokenId (索引)
-        .collect(); // 收集到 Vec<TokenId> 中
-    config.token_to_id = token_to_id;
-    config.id_to_token = id_to_token;
-    config.bpe_ranks = bpe_ranks;
-    config
+        .collect::<Result<Vec<_>, LoadError>>()?;
+    Ok(merge_ranks(token_to_id, merges))
 }
 
-fn load_gpt2(gguf: &GGuf) -> HashMap<(String, String), usize> {
-    gguf.tokenizer_ggml_merges()
-        .unwrap()
-        .map(|x| {
-            let piece = x.unwrap();
-            let (first, second) = piece.split_once(' ').unwrap();
-            (first.to_string(), second.to_string())
+/// 把按文本给出的合并规则转为标记 id 对到排名的映射，重复的规则保留后出现的排名
+///
+/// 分词时只查找词表中标记之间的合并，两侧不都在词表中的规则用不到，直接跳过。
+pub(crate) fn merge_ranks<'a>(
+    token_to_id: &HashMap<Piece, TokenId>,
+    merges: impl IntoIterator<Item = ((&'a str, &'a str), usize)>,
+) -> HashMap<(TokenId, TokenId), usize> {
+    merges
+        .into_iter()
+        .filter_map(|((left, right), rank)| {
+            Some(((*token_to_id.get(left)?, *token_to_id.get(right)?), rank))
         })
-        .enumerate()
-        .map(|(i, pair)| (pair, i))
         .collect()
 }
 
+/// 加载词表时的错误
+#[derive(Debug)]
+pub enum LoadError {
+    /// 读取文件失败
+    Io(std::io::Error),
+    /// JSON 解析失败
+    Json(serde_json::Error),
+    /// 文件内容不符合预期格式
+    Format(String),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Json(e) => write!(f, "json error: {e}"),
+            Self::Format(msg) => write!(f, "format error: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
 #[repr(i32)]
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum VocabType {
     None = 0, // For models without vocab
     Spm = 1,  // LLaMA tokenizer based on byte-level BPE with byte fallback
@@ -398,11 +456,22 @@ pub struct TokenizerConfig {
     pub remove_extra_whitespaces: bool,
     pub escape_whitespaces: bool,
     pub treat_whitespace_as_suffix: bool,
-    pub token_to_id: HashMap<String, TokenId>,
+    pub token_to_id: HashMap<Piece, TokenId>,
     pub special_tokens: Vec<TokenId>,
+    /// 结束生成的标记，见 [`TokenizerConfig::is_eog`]
+    pub special_eog_ids: HashSet<TokenId>,
     pub id_to_token: Vec<TokenData>,
-    pub bpe_ranks: HashMap<(String, String), usize>,
+    /// 合并规则两侧标记的 id 到排名，排名越小越先合并
+    pub bpe_ranks: HashMap<(TokenId, TokenId), usize>,
+    /// UGM 词表规范化使用的 SentencePiece `precompiled_charsmap`，为空时不替换
+    pub precompiled_charsmap: Vec<u8>,
+    /// 分词前依次对特殊标记之间的文本执行的规范化步骤，来自 HF 的 `normalizer`
+    pub normalizers: Vec<Normalizer>,
     pub session: RefCell<LlmTokenizerBpeSession>,
+    /// 延迟构造的前缀索引，见 [`TokenizerConfig::prefix_index`]
+    pub(crate) prefix_index: OnceCell<PrefixIndex>,
+    /// 延迟构造的 UGM 分词器，见 [`TokenizerConfig::ugm`]
+    pub(crate) ugm: OnceCell<LlmTokenizerUgm>,
 }
 impl TokenizerConfig {
     pub fn new() -> Self {
@@ -433,15 +502,257 @@ impl TokenizerConfig {
             treat_whitespace_as_suffix: false,
             token_to_id: HashMap::new(),
             special_tokens: Vec::new(),
+            special_eog_ids: HashSet::new(),
             id_to_token: Vec::new(),
             bpe_ranks: HashMap::new(),
+            precompiled_charsmap: Vec::new(),
+            normalizers: Vec::new(),
             session: LlmTokenizerBpeSession::new(LlmTokenizerBpe {
                 // qwen
                 regex_exprs: vec![QWEN.to_string()],
             })
             .into(),
+            prefix_index: OnceCell::new(),
+            ugm: OnceCell::new(),
         }
     }
+    /// UGM 分词器，第一次分词时按词表构造
+    ///
+    /// 与 [`prefix_index`](Self::prefix_index) 相同，构造之后再修改词表不会更新。
+    pub(crate) fn ugm(&self) -> &LlmTokenizerUgm {
+        self.ugm.get_or_init(|| LlmTokenizerUgm::new(self))
+    }
+    /// 替换 BPE 会话使用的预分词正则表达式
+    pub fn set_regex_exprs(&mut self, regex_exprs: &[&str]) {
+        self.session = LlmTokenizerBpeSession::new(LlmTokenizerBpe {
+            regex_exprs: regex_exprs.iter().map(|s| s.to_string()).collect(),
+        })
+        .into();
+    }
+    /// 词表加载完成后的公共处理：构造换行符，按名称补全特殊标记，收集特殊标记列表
+    pub fn init_special_tokens(&mut self) {
+        // 待完善 linefeed_id 暂时不支持SPM  构造换行符
+        match self.vocab_type {
+            VocabType::Bpe => {
+                let ids = self.tokenize("\n", false, false);
+                if ids.is_empty() {
+                    self.linefeed = self.pad;
+                } else {
+                    self.linefeed = ids[0];
+                }
+            }
+            VocabType::Spm | VocabType::Ugm | VocabType::Rwkv => {
+                self.linefeed = if self.token_to_id.contains_key("\n") {
+                    *self.token_to_id.get("\n").unwrap()
+                } else {
+                    self.pad
+                };
+            }
+            VocabType::None | VocabType::Wpm => self.linefeed = self.pad,
+        }
+
+        for (key, value) in &self.token_to_id {
+            if self.eot == NULL {
+                if key == "<|eot_id|>"
+                    || key == "<|im_end|>"
+                    || key == "<|end|>"
+                    || key == "<end_of_turn>"
+                    || key == "<|endoftext|>"
+                    || key == "< EOT >"
+                    || key == "_< EOT >"
+                    || key == "<｜end▁of▁sentence｜>"
+                // DeepSeek
+                {
+                    self.eot = *value;
+                    if !self.id_to_token[*value as usize]
+                        .attribute
+                        .contains(TokenAttribute::Control)
+                    {
+                        self.id_to_token[*value as usize].attribute = TokenAttribute::Control;
+                    }
+                }
+            }
+            if self.eom == NULL {
+                if key == "<|eom_id|>" {
+                    self.eom = *value;
+                    if !self.id_to_token[*value as usize]
+                        .attribute
+                        .contains(TokenAttribute::Control)
+                    {
+                        self.id_to_token[*value as usize].attribute = TokenAttribute::Control;
+                    }
+                }
+            }
+            if self.fim_pre == NULL {
+                if key == "<|fim_prefix|>" // Qwen
+                    || key == "<fim-prefix>"
+                    || key == "<｜fim▁begin｜>" // DeepSeek
+                    || key == "<PRE>"
+                    || key == "▁<PRE>"
+                {
+                    self.fim_pre = *value;
+                    if !self.id_to_token[*value as usize]
+                        .attribute
+                        .contains(TokenAttribute::Control)
+                    {
+                        self.id_to_token[*value as usize].attribute = TokenAttribute::Control;
+                    }
+                }
+            }
+            if self.fim_suf == NULL {
+                if key == "<|fim_suffix|>" // Qwen
+                || key == "<fim-suffix>"
+                || key == "<｜fim▁hole｜>" // DeepSeek
+                || key == "<SUF>"
+                || key == "▁<SUF>"
+                // CodeLlama
+                {
+                    self.fim_suf = *value;
+                    if !self.id_to_token[*value as usize]
+                        .attribute
+                        .contains(TokenAttribute::Control)
+                    {
+                        self.id_to_token[*value as usize].attribute = TokenAttribute::Control;
+                    }
+                }
+            }
+            if self.fim_mid == NULL {
+                if key == "<|fim_middle|>" // Qwen
+                || key == "<fim-middle>"
+                || key == "<｜fim▁end｜>" // DeepSeek
+                || key == "<MID>"
+                || key == "▁<MID>"
+                // CodeLlama
+                {
+                    self.fim_mid = *value;
+                    if !self.id_to_token[*value as usize]
+                        .attribute
+                        .contains(TokenAttribute::Control)
+                    {
+                        self.id_to_token[*value as usize].attribute = TokenAttribute::Control;
+                    }
+                }
+            }
+            if self.fim_mid == NULL {
+                if key== "<|fim_middle|>" // Qwen
+                || key== "<fim-middle>"
+                || key== "<｜fim▁end｜>"  // DeepSeek
+                || key== "<MID>"
+                || key== "▁<MID>"
+                // CodeLlama
+                {
+                    self.fim_mid = *value;
+                    if !self.id_to_token[*value as usize]
+                        .attribute
+                        .contains(TokenAttribute::Control)
+                    {
+                        self.id_to_token[*value as usize].attribute = TokenAttribute::Control;
+                    }
+                }
+            }
+            if self.fim_pad == NULL {
+                if key == "<|fim_pad|>" // Qwen
+                    || key == "<fim-pad>"
+                    || key == "<PAD>"
+                {
+                    self.fim_pad = *value;
+                    if !self.id_to_token[*value as usize]
+                        .attribute
+                        .contains(TokenAttribute::Control)
+                    {
+                        self.id_to_token[*value as usize].attribute = TokenAttribute::Control;
+                    }
+                }
+            }
+            if self.fim_rep == NULL {
+                if key == "<|fim_repo|>"  // Qwen
+                || key == "<|repo_name|>"
+                || key == "<fim-repo>"
+                || key == "<REPO>"
+                {
+                    self.fim_rep = *value;
+                    if !self.id_to_token[*value as usize]
+                        .attribute
+                        .contains(TokenAttribute::Control)
+                    {
+                        self.id_to_token[*value as usize].attribute = TokenAttribute::Control;
+                    }
+                }
+            }
+            if self.fim_sep == NULL {
+                if key == "<|file_sep|>"
+                // Qwen
+                {
+                    self.fim_sep = *value;
+                    if !self.id_to_token[*value as usize]
+                        .attribute
+                        .contains(TokenAttribute::Control)
+                    {
+                        self.id_to_token[*value as usize].attribute = TokenAttribute::Control;
+                    }
+                }
+            }
+        }
+        let mut special_eog_ids = HashSet::new();
+        // maintain a list of tokens that cause end-of-generation
+        for id in [self.eos, self.eot, self.eom] {
+            if id != NULL {
+                special_eog_ids.insert(id);
+            }
+        }
+        if self.fim_pad != NULL && !special_eog_ids.contains(&self.fim_pad) {
+            special_eog_ids.insert(self.fim_pad);
+        }
+        if self.fim_rep != NULL && !special_eog_ids.contains(&self.fim_rep) {
+            special_eog_ids.insert(self.fim_rep);
+        }
+        if self.fim_sep != NULL && !special_eog_ids.contains(&self.fim_sep) {
+            special_eog_ids.insert(self.fim_sep);
+        }
+
+        // 第二个循环也使用引用
+        for (key, value) in &self.token_to_id {
+            if key == "<|eot_id|>"
+                || key == "<|im_end|>"
+                || key == "<|end|>"
+                || key == "<end_of_turn>"
+                || key == "<|endoftext|>"
+                || key == "<|eom_id|>"
+                || key == "< EOT >"
+                || key == "_< EOT >"
+            {
+                special_eog_ids.insert(*value);
+                if !self.id_to_token[*value as usize]
+                    .attribute
+                    .contains(TokenAttribute::Control)
+                {
+                    self.id_to_token[*value as usize].attribute = TokenAttribute::Control;
+                }
+            } else {
+                if !self.id_to_token[*value as usize]
+                    .attribute
+                    .contains(TokenAttribute::Control)
+                    && !special_eog_ids.contains(value)
+                {
+                    log::warn!("{}", key);
+                }
+            }
+        }
+
+        self.special_tokens = self
+            .id_to_token
+            .iter()
+            .enumerate() // 获取索引 (TokenId) 和 TokenData
+            .filter(|(_, token_data)| {
+                // 检查 token 的属性是否为 Control, UserDefined 或 Unknown
+                token_data.attribute.intersects(
+                    TokenAttribute::Control | TokenAttribute::UserDefined | TokenAttribute::Unknown,
+                )
+            })
+            .map(|(index, _)| index as TokenId) // 提取符合条件的 TokenId (索引)
+            .collect(); // 收集到 Vec<TokenId> 中
+        self.special_eog_ids = special_eog_ids;
+    }
     /// 将文本字符串转换为标记 ID
     ///
     /// 如果文本在词汇表中存在，返回对应的标记 ID
@@ -454,6 +765,10 @@ impl TokenizerConfig {
             NULL
         }
     }
+    /// 是否为结束生成的标记（EOS、EOT、EOM、FIM 结束标记等）
+    pub fn is_eog(&self, id: TokenId) -> bool {
+        self.special_eog_ids.contains(&id)
+    }
     pub fn n_tokens(&self) -> u32 {
         self.id_to_token.len() as u32
     }
@@ -477,6 +792,8 @@ impl TokenizerConfig {
         self.id_to_token[id as usize].clone()
     }
     /// 将单个字节转换为标记 ID
+    ///
+    /// 词表没有对应的字节标记时返回未知标记 `unk`。
     pub fn byte_to_token(&self, ch: u8) -> TokenId {
         // 十六进制字符数组
         static HEX: &[u8; 16] = b"0123456789ABCDEF";
@@ -491,26 +808,28 @@ impl TokenizerConfig {
                 );
 
                 // 尝试在词汇表中查找该字符串
-                if let Some(token) = self.token_to_id.get(&buf) {
+                if let Some(token) = self.token_to_id.get(buf.as_str()) {
                     return *token;
                 }
 
                 // 如果找不到，尝试回退到仅将字节作为字符串
                 let buf2 = String::from_utf8_lossy(&[ch]).to_string();
 
-                // 使用 at 方法获取标记 ID，如果不存在则会 panic
-                *self.token_to_id.get(&buf2).expect("无法找到字节对应的标记")
+                // 都没有时按未知标记处理，与不带 byte_fallback 的 SentencePiece 相同
+                self.token_to_id
+                    .get(buf2.as_str())
+                    .copied()
+                    .unwrap_or(self.unk)
             }
 
             VocabType::Wpm | VocabType::Bpe => {
                 // 对于 WPM 和 BPE 类型，使用 unicode_byte_to_utf8 函数
                 let utf8_str = unicode_byte_to_utf8(ch);
 
-                // 使用 at 方法获取标记 ID，如果不存在则会 panic
-                *self
-                    .token_to_id
-                    .get(&utf8_str)
-                    .expect("无法找到字节对应的标记")
+                self.token_to_id
+                    .get(utf8_str.as_str())
+                    .copied()
+                    .unwrap_or(self.unk)
             }
 
             _ => {
@@ -519,239 +838,446 @@ impl TokenizerConfig {
             }
         }
     }
+    /// 两个文本对应的标记之间合并规则的排名，没有这条规则时为 -1
     pub fn find_bpe_rank(&self, token_left: &str, token_right: &str) -> i32 {
-        match self
-            .bpe_ranks
-            .get(&(token_left.to_string(), token_right.to_string()))
-        {
+        let (Some(&left), Some(&right)) = (
+            self.token_to_id.get(token_left),
+            self.token_to_id.get(token_right),
+        ) else {
+            return -1;
+        };
+        match self.bpe_ranks.get(&(left, right)) {
             Some(rank) => *rank as i32,
             None => -1,
         }
     }
-    pub fn tokenize<'a>(
-        &self,
-        raw_text: &'a str,
-        add_special: bool,
-        parse_special: bool,
-    ) -> Vec<u32> {
-        let mut buffer = LinkedList::new();
+    /// 分词，尚未实现分词的词表类型（WPM、RWKV 和无词表）返回空结果
+    ///
+    /// 按 [`for_each_fragment`](Self::for_each_fragment) 逐个处理片段，与
+    /// [`count_tokens`](Self::count_tokens) 和 [`partition_special`](Self::partition_special) 的分割相同。
+    pub fn tokenize(&self, raw_text: &str, add_special: bool, parse_special: bool) -> Vec<u32> {
         let mut output = Vec::new();
-        if !raw_text.is_empty() {
-            buffer.push_front(
-                FragmentBufferVariant::new_raw_text(raw_text.to_string(), 0, raw_text.len() as i64)
-                    .unwrap(),
-            );
-            self.tokenizer_st_partition(&mut buffer, parse_special);
-        }
         match self.vocab_type {
-            VocabType::None => todo!(),
             VocabType::Spm => {
                 let mut is_prev_special = true; // prefix with space if first token
                 if add_special && self.add_bos {
                     output.push(self.bos);
                     is_prev_special = true;
                 }
-                for fragment in buffer.iter_mut() {
-                    let substring = &fragment.raw_text
-                        [(fragment.offset as usize)..(fragment.offset + fragment.length) as usize];
-                    let mut text = String::new();
-                    if fragment.variant_type == FragmentBufferVariantType::RawText {
-                        if self.add_space_prefix && is_prev_special {
-                            text.push(' ');
+                let mut text = String::new();
+                let _ = self.for_each_fragment(raw_text, parse_special, &mut |fragment| {
+                    match fragment {
+                        TextFragment::Text(substring) => {
+                            text.clear();
+                            if self.add_space_prefix && is_prev_special {
+                                text.push(' ');
+                            }
+                            text.push_str(&self.normalize(substring));
+
+                            llama_escape_whitespace(&mut text);
+                            LlmTokenizerSpmSession::new().tokenize(&text, &mut output, self);
+                            is_prev_special = false;
+                        }
+                        TextFragment::Token(id) => {
+                            output.push(id);
+                            is_prev_special = true;
                         }
-                        text.push_str(substring);
-
-                        llama_escape_whitespace(&mut text);
-                        todo!();
-                        // SPM_SESSION.get_mut().unwrap()
-                        //     .tokenize(&text, &mut output);
-                        is_prev_special = false;
-                    } else {
-                        output.push(fragment.token);
-                        is_prev_special = true;
-                    }
-                    // 检查是否有重复的 BOS 标记
-                    if add_special && self.add_bos && output.len() >= 2 && output[1] == self.bos {
-                        log::warn!(
-                            " Added a BOS token to the prompt as specified by the model but the prompt"
-                        );
                     }
+                    ControlFlow::Continue(())
+                });
+                // 检查是否有重复的 BOS 标记
+                if add_special && self.add_bos && output.len() >= 2 && output[1] == self.bos {
+                    log::warn!(
+                        " Added a BOS token to the prompt as specified by the model but the prompt"
+                    );
+                }
 
-                    // 添加 EOS 标记
-                    if add_special && self.add_eos {
-                        output.push(self.eos);
-                    }
+                // 添加 EOS 标记
+                if add_special && self.add_eos {
+                    output.push(self.eos);
                 }
             }
             VocabType::Bpe => {
-                let mut session_ref = self.session.borrow_mut();
+                let mut session = self.session.borrow_mut();
                 if add_special {
                     self.append_bos(&mut output);
                 }
-                for fragment in buffer.iter_mut() {
-                    if fragment.variant_type == FragmentBufferVariantType::RawText {
-                        let substring: String = fragment
-                            .raw_text
-                            .chars()
-                            .skip(fragment.offset as usize)
-                            .take(fragment.length as usize)
-                            .collect();
-                        session_ref.tokenize(substring.as_str(), &mut output, &self);
-                    } else {
+                let _ = self.for_each_fragment(raw_text, parse_special, &mut |fragment| {
+                    match fragment {
+                        TextFragment::Text(substring) => {
+                            session.tokenize(&self.normalize(substring), &mut output, self)
+                        }
+                        TextFragment::Token(id) => output.push(id),
                     }
-                }
+                    ControlFlow::Continue(())
+                });
 
                 if add_special {
                     self.append_eos(&mut output);
                 }
             }
-            VocabType::Wpm => todo!(),
-            VocabType::Ugm => todo!(),
-            VocabType::Rwkv => todo!(),
+            VocabType::Ugm => {
+                // T5 等模型没有 BOS
+                if add_special && self.add_bos && self.bos != NULL {
+                    output.push(self.bos);
+                }
+                let ugm = self.ugm();
+                let _ = self.for_each_fragment(raw_text, parse_special, &mut |fragment| {
+                    match fragment {
+                        TextFragment::Text(substring) => {
+                            ugm.tokenize(&self.normalize(substring), &mut output, self)
+                        }
+                        TextFragment::Token(id) => output.push(id),
+                    }
+                    ControlFlow::Continue(())
+                });
+                if add_special && self.add_eos && self.eos != NULL {
+                    output.push(self.eos);
+                }
+            }
+            // 尚未实现分词的词表类型不产生任何标记
+            VocabType::None | VocabType::Wpm | VocabType::Rwkv => {}
         }
         output
     }
-    /// 检查文本是否有特殊标记，如果有则将其分割
+    /// 分词得到的标记数，与 `tokenize(text, add_special, parse_special).len()` 相同
+    ///
+    /// 不保存分词结果，比 [`tokenize`](Self::tokenize) 占用的内存更少。
+    pub fn count_tokens(&self, text: &str, add_special: bool, parse_special: bool) -> usize {
+        self.count_until(text, add_special, parse_special, usize::MAX)
+    }
+    /// 文本按默认方式分词（添加 BOS/EOS，不匹配控制标记）后是否不超过 `limit` 个标记
+    ///
+    /// 超过 `limit` 后立即结束，不再处理剩余的文本。
+    pub fn fits_within(&self, text: &str, limit: usize) -> bool {
+        self.count_until(text, true, false, limit) <= limit
+    }
+    /// 统计标记数，超过 `limit` 后提前结束，此时返回值大于 `limit` 但可能小于实际的标记数
     ///
-    /// 例如，将 "Hello <|eot_id|> World" 分割为 "Hello" 和 "World"
-    fn tokenizer_st_partition(
+    /// 按 [`for_each_fragment`](Self::for_each_fragment) 逐个处理片段，不构造片段链表，
+    /// 也不保存分词结果。
+    fn count_until(
         &self,
-        buffer: &mut LinkedList<FragmentBufferVariant>,
+        text: &str,
+        add_special: bool,
         parse_special: bool,
-    ) {
-        // 遍历每个特殊标记
-        for special_id in &self.special_tokens {
-            let data = self.id_to_token[*special_id as usize].clone();
-            let text = &data.text;
-
-            // 如果不解析特殊标记且当前标记是控制标记或未知标记，则跳过
-            if !parse_special
-                && ((data.attribute as u32)
-                    & (TokenAttribute::Control as u32 | TokenAttribute::Unknown as u32))
-                    != 0
-            {
-                continue;
+        limit: usize,
+    ) -> usize {
+        let mut count = 0;
+        if add_special && self.add_bos && (self.bos != NULL || self.vocab_type != VocabType::Ugm) {
+            count += 1;
+        }
+        if add_special && self.add_eos && (self.eos != NULL || self.vocab_type != VocabType::Ugm) {
+            count += 1;
+        }
+        // 与逐个片段循环时相同，处理每个片段之前检查是否已经超过上限
+        let check = |count: usize| {
+            if count > limit {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
             }
-
-            // 遍历每个文本片段
-            let mut cursor = buffer.cursor_front_mut();
-            while let Some(fragment) = cursor.current() {
-                // 如果片段是原始文本（尚未处理）
-                if fragment.variant_type == FragmentBufferVariantType::RawText {
-                    let FragmentBufferVariant {
-                        raw_text,
-                        offset,
-                        length,
-                        ..
-                    } = &fragment.clone();
-                    let mut raw_text_base_offset = *offset;
-                    let mut raw_text_base_length = *length;
-
-                    // 在文本中循环查找特殊标记
-                    loop {
-                        // 在当前片段中查找特殊标记的第一次出现
-                        let text_slice = &raw_text[raw_text_base_offset as usize
-                            ..(raw_text_base_offset + raw_text_base_length) as usize];
-                        let match_pos = text_slice.find(text);
-
-                        // 如果没有找到，停止处理该片段
-                        let match_pos = match match_pos {
-                            None => break,
-                            Some(pos) => raw_text_base_offset as usize + pos,
-                        };
-
-                        // 如果匹配位置在基础偏移量之后，处理左侧文本
-                        if match_pos > raw_text_base_offset as usize {
-                            let left_reminder_offset = raw_text_base_offset as i64;
-                            let mut left_reminder_length =
-                                match_pos as i64 - raw_text_base_offset as i64;
-
-                            // 如果需要去除左侧空白
-                            if (data.attribute as u32 & TokenAttribute::LStrIp as u32) != 0 {
-                                while left_reminder_length > 0 {
-                                    let last_char = raw_text
-                                        .chars()
-                                        .nth(
-                                            (left_reminder_offset + left_reminder_length - 1)
-                                                as usize,
-                                        )
-                                        .unwrap();
-                                    if !last_char.is_whitespace() {
-                                        break;
-                                    }
-                                    left_reminder_length -= 1;
-                                }
-                            }
-
-                            // 插入左侧文本片段
-                            if left_reminder_length > 0 {
-                                cursor.insert_after(
-                                    FragmentBufferVariant::new_raw_text(
-                                        raw_text.clone(),
-                                        left_reminder_offset,
-                                        left_reminder_length,
-                                    )
-                                    .unwrap(),
-                                );
-                                cursor.move_next();
+        };
+        match self.vocab_type {
+            VocabType::Spm => {
+                let mut output = Vec::new();
+                let mut buf = String::new();
+                let mut is_prev_special = true;
+                let _ = self.for_each_fragment(text, parse_special, &mut |fragment| {
+                    check(count)?;
+                    match fragment {
+                        TextFragment::Text(substring) => {
+                            buf.clear();
+                            if self.add_space_prefix && is_prev_special {
+                                buf.push(' ');
                             }
+                            buf.push_str(&self.normalize(substring));
+                            llama_escape_whitespace(&mut buf);
+                            output.clear();
+                            LlmTokenizerSpmSession::new().tokenize(&buf, &mut output, self);
+                            count += output.len();
+                            is_prev_special = false;
                         }
-
-                        // 插入特殊标记
-                        cursor.insert_after(FragmentBufferVariant::new_token(*special_id));
-                        cursor.move_next();
-
-                        // 处理右侧文本
-                        let right_start = match_pos + text.len();
-                        if right_start < (raw_text_base_offset + raw_text_base_length) as usize {
-                            let mut right_reminder_offset = right_start as i64;
-                            let mut right_reminder_length = raw_text_base_length
-                                - ((match_pos as u64 - raw_text_base_offset as u64)
-                                    + text.len() as u64);
-
-                            // 如果需要去除右侧空白
-                            if (data.attribute as u32 & TokenAttribute::RStrIp as u32) != 0 {
-                                while right_reminder_length > 0 {
-                                    let next_char = raw_text
-                                        .chars()
-                                        .nth(right_reminder_offset as usize)
-                                        .unwrap();
-                                    if !next_char.is_whitespace() {
-                                        break;
-                                    }
-                                    right_reminder_offset += 1;
-                                    right_reminder_length -= 1;
-                                }
-                            }
-
-                            // 插入右侧文本片段
-                            if right_reminder_length > 0 {
-                                cursor.insert_after(
-                                    FragmentBufferVariant::new_raw_text(
-                                        raw_text.clone(),
-                                        right_reminder_offset,
-                                        right_reminder_length as i64,
-                                    )
-                                    .unwrap(),
-                                );
-                                cursor.move_next();
-                            }
-
-                            // 继续处理右侧文本
-                            raw_text_base_offset = right_reminder_offset as u64;
-                            raw_text_base_length = right_reminder_length;
-                        } else {
-                            // 删除当前片段并退出循环
-                            cursor.remove_current();
-                            break;
+                        TextFragment::Token(_) => {
+                            count += 1;
+                            is_prev_special = true;
+                        }
+                    }
+                    ControlFlow::Continue(())
+                });
+            }
+            VocabType::Bpe => {
+                let mut session = self.session.borrow_mut();
+                let _ = self.for_each_fragment(text, parse_special, &mut |fragment| {
+                    check(count)?;
+                    match fragment {
+                        TextFragment::Text(substring) => {
+                            count += session.count(&self.normalize(substring), limit - count, self);
                         }
+                        TextFragment::Token(_) => count += 1,
                     }
+                    ControlFlow::Continue(())
+                });
+            }
+            VocabType::Ugm => {
+                let ugm = self.ugm();
+                let mut output = Vec::new();
+                let _ = self.for_each_fragment(text, parse_special, &mut |fragment| {
+                    check(count)?;
+                    match fragment {
+                        TextFragment::Text(substring) => {
+                            output.clear();
+                            ugm.tokenize(&self.normalize(substring), &mut output, self);
+                            count += output.len();
+                        }
+                        TextFragment::Token(_) => count += 1,
+                    }
+                    ControlFlow::Continue(())
+                });
+            }
+            // 与 tokenize 一致，尚未实现分词的词表类型没有标记
+            VocabType::None | VocabType::Wpm | VocabType::Rwkv => count = 0,
+        }
+        count
+    }
+    /// 按 `normalizers` 规范化特殊标记之间的文本，没有规范化步骤时不复制
+    fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.normalizers.is_empty() {
+            return Cow::Borrowed(text);
+        }
+        let normalized = self
+            .normalizers
+            .iter()
+            .fold(NormalizedText::new(text), |text, &normalizer| {
+                text.apply(normalizer)
+            });
+        Cow::Owned(normalized.into_string())
+    }
+    /// 按特殊标记分割文本，是分词的第一步
+    ///
+    /// `parse_special` 为假时只匹配用户定义的标记，不匹配控制标记。
+    pub fn partition_special<'a>(
+        &self,
+        text: &'a str,
+        parse_special: bool,
+    ) -> Vec<TextFragment<'a>> {
+        let mut fragments = Vec::new();
+        let _ = self.for_each_fragment(text, parse_special, &mut |fragment| {
+            fragments.push(fragment);
+            ControlFlow::Continue(())
+        });
+        fragments
+    }
+    /// 依次把按特殊标记分割出的片段交给 `f`，是分词的第一步
+    ///
+    /// 不构造片段链表，文本片段直接借用 `text`。`f` 返回 `Break` 时立即结束。
+    fn for_each_fragment<'a>(
+        &self,
+        text: &'a str,
+        parse_special: bool,
+        f: &mut impl FnMut(TextFragment<'a>) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        if text.is_empty() {
+            return ControlFlow::Continue(());
+        }
+        self.split_fragment(text, &self.special_tokens, parse_special, f)
+    }
+    /// 用 `specials` 中第一个出现在 `text` 中的特殊标记分割 `text`，两侧的文本再用其后的特殊标记分割
+    ///
+    /// 与 llama.cpp 的 `tokenizer_st_partition` 按特殊标记逐个分割所有片段的顺序等价。
+    fn split_fragment<'a>(
+        &self,
+        text: &'a str,
+        specials: &[TokenId],
+        parse_special: bool,
+        f: &mut impl FnMut(TextFragment<'a>) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        for (i, &special_id) in specials.iter().enumerate() {
+            let data = &self.id_to_token[special_id as usize];
+            if !parse_special
+                && data
+                    .attribute
+                    .intersects(TokenAttribute::Control | TokenAttribute::Unknown)
+            {
+                continue;
+            }
+            let token = data.text.as_str();
+            if token.is_empty() {
+                continue;
+            }
+            let Some(mut pos) = text.find(token) else {
+                continue;
+            };
+            let rest = &specials[i + 1..];
+            let mut base = 0;
+            loop {
+                let match_pos = base + pos;
+                let mut left = &text[base..match_pos];
+                if data.attribute.contains(TokenAttribute::LStrIp) {
+                    left = left.trim_end();
+                }
+                if !left.is_empty() {
+                    self.split_fragment(left, rest, parse_special, f)?;
+                }
+                f(TextFragment::Token(special_id))?;
+                base = match_pos + token.len();
+                if data.attribute.contains(TokenAttribute::RStrIp) {
+                    base = text.len() - text[base..].trim_start().len();
                 }
-                cursor.move_next();
+                match text[base..].find(token) {
+                    Some(next) => pos = next,
+                    None => break,
+                }
+            }
+            if base < text.len() {
+                self.split_fragment(&text[base..], rest, parse_special, f)?;
             }
+            return ControlFlow::Continue(());
         }
+        f(TextFragment::Text(text))
     }
+    /// 标记对应的字节，与 llama.cpp 的 `token_to_piece` 相同
+    ///
+    /// `special` 为假时控制标记输出为空。`lstrip` 为最多去除的前导空格数。
+    pub fn token_to_piece(&self, id: TokenId, lstrip: usize, special: bool) -> Vec<u8> {
+        let token = &self.id_to_token[id as usize];
+        let attribute = token.attribute;
+        if !special && attribute.contains(TokenAttribute::Control) {
+            return Vec::new();
+        }
+        let piece = match self.vocab_type {
+            VocabType::Spm | VocabType::Wpm | VocabType::Ugm => {
+                if attribute.intersects(TokenAttribute::Control | TokenAttribute::UserDefined) {
+                    token.text.as_bytes().to_vec()
+                } else if attribute.contains(TokenAttribute::Normal) {
+                    token.text.replace('\u{2581}', " ").into_bytes()
+                } else if attribute.contains(TokenAttribute::Byte) {
+                    vec![self.token_to_byte(id)]
+                } else if attribute.contains(TokenAttribute::Unknown) {
+                    "\u{2585}".as_bytes().to_vec()
+                } else {
+                    Vec::new()
+                }
+            }
+            VocabType::Bpe => {
+                if attribute.intersects(TokenAttribute::Control | TokenAttribute::UserDefined) {
+                    token.text.as_bytes().to_vec()
+                } else if attribute.contains(TokenAttribute::Normal) {
+                    token
+                        .text
+                        .chars()
+                        .flat_map(|ch| match unicode_utf8_to_byte(ch) {
+                            Some(byte) => vec![byte],
+                            // 不在字节映射中的字符原样输出
+                            None => ch.to_string().into_bytes(),
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                }
+            }
+            VocabType::None | VocabType::Rwkv => Vec::new(),
+        };
+        let skip = piece
+            .iter()
+            .take(lstrip)
+            .take_while(|&&byte| byte == b' ')
+            .count();
+        piece[skip..].to_vec()
+    }
+    /// 字节标记 `<0xXY>` 对应的字节
+    fn token_to_byte(&self, id: TokenId) -> u8 {
+        let text = &self.id_to_token[id as usize].text;
+        u8::from_str_radix(&text[3..5], 16).expect("无效的字节标记")
+    }
+    /// 将标记序列还原为文本，与 llama.cpp 的 `detokenize` 相同
+    ///
+    /// `remove_special` 为真时去除自动添加的 BOS/EOS，`unparse_special` 为真时输出控制标记的文本。
+    /// 不是合法 UTF-8 的字节按 [`String::from_utf8_lossy`] 替换。
+    pub fn detokenize(
+        &self,
+        ids: &[TokenId],
+        remove_special: bool,
+        unparse_special: bool,
+    ) -> String {
+        let mut ids = ids;
+        // 去除 SPM 开头补的空格
+        let mut remove_space = self.add_space_prefix;
+        if remove_special && self.add_bos && ids.first() == Some(&self.bos) {
+            remove_space = false;
+            ids = &ids[1..];
+        }
+        if remove_special && self.add_eos && ids.last() == Some(&self.eos) {
+            ids = &ids[..ids.len() - 1];
+        }
+
+        let mut text = Vec::new();
+        for &id in ids {
+            text.extend(self.token_to_piece(id, remove_space as usize, unparse_special));
+            remove_space = false;
+        }
+        if self.clean_spaces {
+            clean_up_spaces(&mut text);
+        }
+        String::from_utf8_lossy(&text).into_owned()
+    }
+}
+
+/// 去除标点和缩写前多余的空格，与 llama.cpp 的 `clean_spaces` 处理相同
+fn clean_up_spaces(text: &mut Vec<u8>) {
+    // 第一遍：" ?"、" !"、" ."、" ," 去除空格
+    let mut out = Vec::with_capacity(text.len());
+    for (i, &x) in text.iter().enumerate() {
+        if i > 0 && text[i - 1] == b' ' && matches!(x, b'?' | b'!' | b'.' | b',') {
+            out.pop();
+        }
+        out.push(x);
+    }
+    // 第二遍：去除两侧空格之间的单引号 " ' " 两侧的空格
+    let text1 = std::mem::take(&mut out);
+    let mut i = 0;
+    while i < text1.len() {
+        let x = text1[i];
+        if i > 0
+            && x == b'\''
+            && i + 1 < text1.len()
+            && text1[i - 1] == b' '
+            && text1[i + 1] == b' '
+        {
+            out.pop();
+            out.push(x);
+            i += 2;
+            continue;
+        }
+        out.push(x);
+        i += 1;
+    }
+    // 第三遍：" 's"、" 'm"、" 're"、" 've" 去除空格
+    let text2 = std::mem::take(&mut out);
+    for (i, &x) in text2.iter().enumerate() {
+        if i > 0 && text2[i - 1] == b' ' && x == b'\'' {
+            let rest = &text2[i + 1..];
+            if matches!(rest.first(), Some(b's' | b'm'))
+                || rest.starts_with(b"re")
+                || rest.starts_with(b"ve")
+            {
+                out.pop();
+            }
+        }
+        out.push(x);
+    }
+    *text = out;
+}
+
+/// [`TokenizerConfig::partition_special`] 分割出的片段
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextFragment<'a> {
+    /// 特殊标记
+    Token(TokenId),
+    /// 需要继续分词的文本
+    Text(&'a str),
 }
+
 impl std::fmt::Debug for TokenizerConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("TokenizerConfig")
@@ -0,0 +1,182 @@
+use crate::{
+    common::{NULL, TokenId},
+    config::TokenizerConfig,
+};
+
+/// 超过最大长度时保留的部分
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Truncation {
+    /// 保留开头
+    #[default]
+    Head,
+    /// 保留结尾
+    Tail,
+    /// 保留开头和结尾，中间以 `ellipsis` 连接
+    ///
+    /// 最大长度放不下 `ellipsis` 时退化为 [`Truncation::Head`]。
+    HeadTail { ellipsis: Vec<TokenId> },
+}
+
+/// 填充到的长度
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Padding {
+    /// 不填充
+    #[default]
+    None,
+    /// 填充到固定长度，已经更长时不填充
+    Fixed(usize),
+    /// 填充到该值的整数倍
+    Multiple(usize),
+}
+
+/// 填充标记添加的位置
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PaddingSide {
+    Left,
+    #[default]
+    Right,
+}
+
+/// [`TokenizerConfig::encode`] 的选项
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// 与 [`TokenizerConfig::tokenize`] 相同，按词表设置添加 BOS/EOS
+    pub add_special: bool,
+    /// 与 [`TokenizerConfig::tokenize`] 相同，匹配文本中的控制标记
+    pub parse_special: bool,
+    /// 包含 BOS/EOS 的最大长度，不含填充
+    pub max_length: Option<usize>,
+    pub truncation: Truncation,
+    pub padding: Padding,
+    pub padding_side: PaddingSide,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            add_special: true,
+            parse_special: false,
+            max_length: None,
+            truncation: Truncation::Head,
+            padding: Padding::None,
+            padding_side: PaddingSide::Right,
+        }
+    }
+}
+
+/// [`TokenizerConfig::encode`] 的结果
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Encoding {
+    /// 截断并填充后的标记
+    pub ids: Vec<TokenId>,
+    /// 与 `ids` 等长，真实标记为 1，填充为 0
+    pub attention_mask: Vec<u8>,
+    /// 截断丢弃的标记数，不含插入的省略标记
+    pub truncated: usize,
+}
+
+/// 编码失败的原因
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    /// 需要填充但词表没有填充标记
+    NoPadToken,
+    /// 最大长度放不下自动添加的 BOS/EOS
+    MaxLengthTooSmall { max_length: usize, special: usize },
+    /// 填充到 0 的整数倍
+    ZeroMultiple,
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoPadToken => write!(f, "padding requested but the vocab has no pad token"),
+            Self::MaxLengthTooSmall {
+                max_length,
+                special,
+            } => write!(
+                f,
+                "max_length {max_length} cannot hold {special} special tokens"
+            ),
+            Self::ZeroMultiple => write!(f, "cannot pad to a multiple of 0"),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+impl TokenizerConfig {
+    /// 分词后按选项截断和填充
+    ///
+    /// 截断只作用于文本的标记，[`append_bos`](Self::append_bos)/[`append_eos`](Self::append_eos)
+    /// 添加的标记总是保留。没有截断和填充时结果与 [`tokenize`](Self::tokenize) 相同。
+    pub fn encode(&self, text: &str, options: &EncodeOptions) -> Result<Encoding, EncodeError> {
+        match options.padding {
+            Padding::None => {}
+            Padding::Multiple(0) => return Err(EncodeError::ZeroMultiple),
+            Padding::Fixed(_) | Padding::Multiple(_) if self.pad == NULL => {
+                return Err(EncodeError::NoPadToken);
+            }
+            Padding::Fixed(_) | Padding::Multiple(_) => {}
+        }
+
+        let mut prefix = Vec::new();
+        let mut suffix = Vec::new();
+        if options.add_special {
+            self.append_bos(&mut prefix);
+            self.append_eos(&mut suffix);
+        }
+        let special = prefix.len() + suffix.len();
+        let mut body = self.tokenize(text, false, options.parse_special);
+
+        let mut truncated = 0;
+        if let Some(max_length) = options.max_length {
+            let budget = max_length
+                .checked_sub(special)
+                .ok_or(EncodeError::MaxLengthTooSmall {
+                    max_length,
+                    special,
+                })?;
+            if body.len() > budget {
+                truncated = body.len() - budget;
+                body = match &options.truncation {
+                    Truncation::Head => body[..budget].to_vec(),
+                    Truncation::Tail => body[truncated..].to_vec(),
+                    Truncation::HeadTail { ellipsis } if ellipsis.len() < budget => {
+                        // 省略标记也占用长度，开头多保留一个
+                        let kept = budget - ellipsis.len();
+                        let head = kept.div_ceil(2);
+                        let tail = kept - head;
+                        truncated = body.len() - kept;
+                        [&body[..head], ellipsis, &body[body.len() - tail..]].concat()
+                    }
+                    Truncation::HeadTail { .. } => body[..budget].to_vec(),
+                };
+            }
+        }
+
+        let mut ids = [prefix, body, suffix].concat();
+        let len = ids.len();
+        let target = match options.padding {
+            Padding::Fixed(target) => target.max(len),
+            Padding::Multiple(n) => len.div_ceil(n) * n,
+            Padding::None => len,
+        };
+        let mut attention_mask = vec![1; len];
+        let padding = vec![self.pad; target - len];
+        match options.padding_side {
+            PaddingSide::Left => {
+                ids.splice(0..0, padding);
+                attention_mask.splice(0..0, vec![0; target - len]);
+            }
+            PaddingSide::Right => {
+                ids.extend(padding);
+                attention_mask.resize(target, 0);
+            }
+        }
+        Ok(Encoding {
+            ids,
+            attention_mask,
+            truncated,
+        })
+    }
+}
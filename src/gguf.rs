@@ -0,0 +1,498 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use ggus::{GGuf, GGufMetaDataValueType, GGufMetaKV, GGufMetaMap, GGufMetaMapExt, GGufReader};
+use memmap2::Mmap;
+use regex::Regex;
+
+use crate::{
+    common::TokenId,
+    config::{LoadError, TokenizerConfig, load_with, set_pre_tokenizer},
+};
+
+/// GGUF 固定头部的字节数：魔数、版本、张量数、元数据数
+const HEADER_LEN: usize = 24;
+/// 读取元数据时每次至少读取的字节数
+const CHUNK: usize = 1 << 20;
+
+/// 分片文件名，与 llama.cpp 的 `llama_split_path` 相同
+static SPLIT_NAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.*)-(\d{5})-of-(\d{5})\.gguf$").unwrap());
+
+/// 从内存中的 GGUF 内容加载分词器，例如通过网络收到的文件
+///
+/// 与 [`load`](crate::config::load) 相同，但只解析元数据，格式错误或缺少词表时返回错误。
+pub fn load_gguf_bytes(data: &[u8]) -> Result<TokenizerConfig, LoadError> {
+    let metadata = metadata_only(data).map_err(|e| e.into_load_error())?;
+    load_with(&metadata, |text| text.into())
+}
+
+/// 从 `reader` 读取 GGUF 的元数据并加载分词器，不读取张量信息和数据
+pub fn load_gguf_reader<R: Read + Seek>(reader: &mut R) -> Result<TokenizerConfig, LoadError> {
+    load_gguf_bytes(&read_gguf_metadata(reader)?)
+}
+
+/// 从分片的 GGUF 加载分词器
+///
+/// `path` 可以是任意一个 `*-00001-of-00004.gguf` 形式的分片，分词器的元数据总在第一个分片中，
+/// 只读取第一个分片的元数据。文件名不是分片形式时按单个文件读取。
+pub fn load_gguf_split(path: impl AsRef<Path>) -> Result<TokenizerConfig, LoadError> {
+    load_gguf_bytes(&read_split_metadata(path.as_ref())?)
+}
+
+/// 提取 GGUF 中的词表，写成 llama.cpp 的 `ggml-vocab-*.gguf` 那样只有元数据的小文件
+///
+/// 保留 `general.architecture`、`tokenizer.ggml.*` 和 `tokenizer.chat_template`（包括具名的模板），
+/// 不含张量。`src` 可以是分片，按 [`load_gguf_split`] 的规则找到第一个分片。
+pub fn write_vocab_only(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<(), LoadError> {
+    let metadata = read_split_metadata(src.as_ref())?;
+    fs::write(dst, vocab_only_gguf(&metadata)?)?;
+    Ok(())
+}
+
+/// [`write_vocab_only`] 的文件内容，`data` 是完整的 GGUF 或它的元数据
+///
+/// 保留的键与原文件顺序相同，值按字节原样复制，因此加载结果与原文件相同。
+pub fn vocab_only_gguf(data: &[u8]) -> Result<Vec<u8>, LoadError> {
+    let metadata = metadata_only(data).map_err(|e| e.into_load_error())?;
+    let gguf = parse(&metadata)?;
+    if !gguf.meta_kvs.contains_key("tokenizer.ggml.tokens") {
+        return Err(format_error("missing tokenizer.ggml.tokens"));
+    }
+    let kvs = gguf
+        .meta_kvs
+        .values()
+        .filter(|kv| is_vocab_key(kv.key()))
+        .collect::<Vec<_>>();
+    let mut buf = Vec::new();
+    write_header(&mut buf, 0, kvs.len())?;
+    for kv in kvs {
+        write_kv(&mut buf, kv.key(), kv.ty() as u32, kv.value_bytes())?;
+    }
+    Ok(buf)
+}
+
+/// 对 [`edit_gguf`] 中分词器元数据的修改，`None` 和空表示不修改
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GGufEdit {
+    /// 特殊标记的 id，键是 `tokenizer.ggml.<name>_token_id` 中的 `name`，如 `eos`、`eot`
+    ///
+    /// 也接受 `unk`、`sep`、`pad` 这样的简写。
+    pub special_ids: BTreeMap<String, TokenId>,
+    /// `tokenizer.ggml.add_bos_token`
+    pub add_bos: Option<bool>,
+    /// `tokenizer.ggml.add_eos_token`
+    pub add_eos: Option<bool>,
+    /// `tokenizer.chat_template`
+    pub chat_template: Option<String>,
+    /// `tokenizer.ggml.pre`，必须是支持的预分词类型
+    pub pre: Option<String>,
+}
+
+/// 可以设置的特殊标记，与 llama.cpp 的键名相同（包括 `seperator` 的拼写）
+const SPECIAL_NAMES: &[&str] = &[
+    "bos",
+    "eos",
+    "eot",
+    "eom",
+    "unknown",
+    "seperator",
+    "padding",
+    "mask",
+    "fim_pre",
+    "fim_suf",
+    "fim_mid",
+    "fim_pad",
+    "fim_rep",
+    "fim_sep",
+];
+
+impl GGufEdit {
+    /// 要写入的键、类型和值，检查 id 范围和预分词类型
+    fn kvs(&self, n_tokens: usize) -> Result<Vec<(String, u32, Vec<u8>)>, LoadError> {
+        let mut kvs = Vec::new();
+        for (name, &id) in &self.special_ids {
+            let name = match name.as_str() {
+                "unk" => "unknown",
+                "sep" => "seperator",
+                "pad" => "padding",
+                name => name,
+            };
+            if !SPECIAL_NAMES.contains(&name) {
+                return Err(format_error(&format!("unknown special token `{name}`")));
+            }
+            if id as usize >= n_tokens {
+                return Err(format_error(&format!(
+                    "{name} token id {id} out of range, vocab size is {n_tokens}"
+                )));
+            }
+            let key = format!("tokenizer.ggml.{name}_token_id");
+            kvs.push((key, U32, id.to_le_bytes().to_vec()));
+        }
+        for (key, value) in [
+            ("tokenizer.ggml.add_bos_token", self.add_bos),
+            ("tokenizer.ggml.add_eos_token", self.add_eos),
+        ] {
+            if let Some(value) = value {
+                kvs.push((key.into(), BOOL, vec![value.into()]));
+            }
+        }
+        if let Some(pre) = &self.pre {
+            set_pre_tokenizer(&mut TokenizerConfig::new(), pre)?;
+        }
+        for (key, value) in [
+            ("tokenizer.chat_template", &self.chat_template),
+            ("tokenizer.ggml.pre", &self.pre),
+        ] {
+            if let Some(value) = value {
+                let mut bytes = (value.len() as u64).to_le_bytes().to_vec();
+                bytes.extend(value.as_bytes());
+                kvs.push((key.into(), STRING, bytes));
+            }
+        }
+        Ok(kvs)
+    }
+}
+
+/// 修改 GGUF 中分词器的元数据并写入 `dst`，返回修改后的分词器
+///
+/// 张量信息和数据原样复制。写入前先加载修改后的元数据检查，不能加载时返回错误，不会写入任何文件。
+/// `dst` 可以与 `src` 相同，先写入同目录下的 `<文件名>.tmp` 再改名。
+pub fn edit_gguf(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    edit: &GGufEdit,
+) -> Result<TokenizerConfig, LoadError> {
+    let dst = dst.as_ref();
+    let file = File::open(src)?;
+    let data = unsafe { Mmap::map(&file) }?;
+    let (head, data_start) = edited_head(&data, edit)?;
+    let config = load_with(&head, |text| text.into())?;
+
+    let mut tmp = dst.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let written = File::create(&tmp).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        out.write_all(&head)?;
+        out.write_all(&data[data_start..])?;
+        out.into_inner()?.sync_all()
+    });
+    drop(data);
+    if let Err(e) = written.and_then(|()| fs::rename(&tmp, dst)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(config)
+}
+
+/// [`edit_gguf`] 的新文件内容
+pub fn edit_gguf_bytes(data: &[u8], edit: &GGufEdit) -> Result<Vec<u8>, LoadError> {
+    let (mut head, data_start) = edited_head(data, edit)?;
+    head.extend(&data[data_start..]);
+    Ok(head)
+}
+
+/// 修改后的头部、元数据和张量信息（已对齐到数据段），以及原文件中数据段的起始位置
+fn edited_head(data: &[u8], edit: &GGufEdit) -> Result<(Vec<u8>, usize), LoadError> {
+    let metadata = metadata_only(data).map_err(|e| e.into_load_error())?;
+    let gguf = parse(&metadata)?;
+    let tokens = gguf
+        .tokenizer_ggml_tokens()
+        .map_err(|_| format_error("missing tokenizer.ggml.tokens"))?;
+    let mut changes = edit.kvs(tokens.len())?;
+
+    let n_tensors = u64::from_le_bytes(data[8..16].try_into().unwrap());
+    let infos_end =
+        tensor_infos_end(data, metadata.len(), n_tensors).map_err(|e| e.into_load_error())?;
+    let data_start = if n_tensors == 0 {
+        infos_end
+    } else {
+        infos_end.next_multiple_of(gguf.alignment)
+    };
+    if data.len() < data_start {
+        return Err(format_error("truncated tensor data"));
+    }
+
+    // 已有的键原地替换，新的键追加在最后
+    let n_new = changes
+        .iter()
+        .filter(|(key, ..)| !gguf.meta_kvs.contains_key(key.as_str()))
+        .count();
+    let mut head = Vec::with_capacity(metadata.len());
+    write_header(&mut head, n_tensors, gguf.meta_kvs.len() + n_new)?;
+    for kv in gguf.meta_kvs.values() {
+        match changes.iter().position(|(key, ..)| key == kv.key()) {
+            Some(i) => {
+                let (key, ty, value) = changes.remove(i);
+                write_kv(&mut head, &key, ty, &value)?;
+            }
+            None => write_kv(&mut head, kv.key(), kv.ty() as u32, kv.value_bytes())?,
+        }
+    }
+    for (key, ty, value) in changes {
+        write_kv(&mut head, &key, ty, &value)?;
+    }
+    // 张量信息中的偏移相对于数据段，原样复制后只需要重新对齐数据段
+    head.extend(&data[metadata.len()..infos_end]);
+    if n_tensors > 0 {
+        head.resize(head.len().next_multiple_of(gguf.alignment), 0);
+    }
+    Ok((head, data_start))
+}
+
+fn write_header(out: &mut impl Write, n_tensors: u64, n_kvs: usize) -> io::Result<()> {
+    out.write_all(b"GGUF")?;
+    out.write_all(&3u32.to_le_bytes())?;
+    out.write_all(&n_tensors.to_le_bytes())?;
+    out.write_all(&(n_kvs as u64).to_le_bytes())
+}
+
+fn write_kv(out: &mut impl Write, key: &str, ty: u32, value: &[u8]) -> io::Result<()> {
+    out.write_all(&(key.len() as u64).to_le_bytes())?;
+    out.write_all(key.as_bytes())?;
+    out.write_all(&ty.to_le_bytes())?;
+    out.write_all(value)
+}
+
+fn is_vocab_key(key: &str) -> bool {
+    key == "general.architecture"
+        || key.starts_with("tokenizer.ggml.")
+        || key == "tokenizer.chat_template"
+        || key.starts_with("tokenizer.chat_template.")
+}
+
+/// 读取第一个分片的元数据，检查分片编号和分片数
+fn read_split_metadata(path: &Path) -> Result<Vec<u8>, LoadError> {
+    let (first, count) = match split_first(path) {
+        Some((first, count)) => (first, Some(count)),
+        None => (path.to_path_buf(), None),
+    };
+    let metadata = read_gguf_metadata(&mut File::open(&first)?)?;
+    if let Some(count) = count {
+        let gguf = parse(&metadata)?;
+        let error = |msg: String| LoadError::Format(format!("{}: {msg}", first.display()));
+        match gguf.get_usize("split.no") {
+            Ok(0) | Err(_) => {}
+            Ok(no) => return Err(error(format!("expected the first shard, found shard {no}"))),
+        }
+        match gguf.get_usize("split.count") {
+            Ok(n) if n != count => {
+                return Err(error(format!("split.count is {n}, file name says {count}")));
+            }
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+/// 读取 GGUF 的头部和元数据，返回只含元数据、没有张量的 GGUF 内容
+///
+/// 从文件开头读取，按需逐块扩大缓冲区，不会读到张量数据。
+pub fn read_gguf_metadata<R: Read + Seek>(reader: &mut R) -> Result<Vec<u8>, LoadError> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut buf = Vec::new();
+    let mut want = CHUNK;
+    loop {
+        reader
+            .by_ref()
+            .take((want - buf.len()) as u64)
+            .read_to_end(&mut buf)?;
+        match metadata_only(&buf) {
+            Ok(metadata) => return Ok(metadata),
+            // 没有读到文件末尾，继续读取
+            Err(ScanError::Eos) if buf.len() == want => want *= 2,
+            Err(e) => return Err(e.into_load_error()),
+        }
+    }
+}
+
+/// 去掉张量后的头部和元数据，本身就是合法的 GGUF
+fn metadata_only(data: &[u8]) -> Result<Vec<u8>, ScanError> {
+    let len = metadata_len(data)?;
+    let mut metadata = data[..len].to_vec();
+    metadata[8..16].copy_from_slice(&0u64.to_le_bytes());
+    Ok(metadata)
+}
+
+enum ScanError {
+    /// 数据不完整
+    Eos,
+    Invalid(&'static str),
+}
+
+impl ScanError {
+    fn into_load_error(self) -> LoadError {
+        match self {
+            Self::Eos => format_error("truncated metadata"),
+            Self::Invalid(msg) => format_error(msg),
+        }
+    }
+}
+
+/// 头部和元数据的总字节数
+///
+/// ggus 直接把读到的类型编号转换为枚举，遇到未知的编号时行为未定义，
+/// 所以先在这里检查每个值的类型和长度，之后再交给 ggus 解析。
+fn metadata_len(data: &[u8]) -> Result<usize, ScanError> {
+    let mut scanner = Scanner { data, pos: 0 };
+    if scanner.take(4)? != b"GGUF" {
+        return Err(ScanError::Invalid("magic mismatch"));
+    }
+    if scanner.u32()? != 3 {
+        return Err(ScanError::Invalid("unsupported version"));
+    }
+    let _n_tensors = scanner.u64()?;
+    let n_kvs = scanner.u64()?;
+    debug_assert_eq!(scanner.pos, HEADER_LEN);
+    for _ in 0..n_kvs {
+        let len = scanner.u64()?;
+        scanner.take(len)?;
+        let ty = scanner.u32()?;
+        if ty == ARRAY {
+            let ty = scanner.u32()?;
+            if ty == ARRAY {
+                return Err(ScanError::Invalid("nested arrays are not supported"));
+            }
+            let len = scanner.u64()?;
+            scanner.values(ty, len)?;
+        } else {
+            scanner.values(ty, 1)?;
+        }
+    }
+    Ok(scanner.pos)
+}
+
+/// GGUF 值的类型编号
+const U32: u32 = 4;
+const BOOL: u32 = 7;
+const STRING: u32 = 8;
+const ARRAY: u32 = 9;
+/// 张量的最大维数，与 ggml 的 `GGML_MAX_DIMS` 相同
+const MAX_DIMS: u32 = 4;
+
+/// 张量信息的结束位置，`start` 是元数据的结束位置
+fn tensor_infos_end(data: &[u8], start: usize, n_tensors: u64) -> Result<usize, ScanError> {
+    let mut scanner = Scanner { data, pos: start };
+    for _ in 0..n_tensors {
+        let len = scanner.u64()?;
+        scanner.take(len)?;
+        let n_dims = scanner.u32()?;
+        if n_dims > MAX_DIMS {
+            return Err(ScanError::Invalid("too many tensor dimensions"));
+        }
+        // 各维大小、类型和偏移
+        scanner.take(u64::from(n_dims) * 8 + 4 + 8)?;
+    }
+    Ok(scanner.pos)
+}
+
+struct Scanner<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn take(&mut self, len: u64) -> Result<&'a [u8], ScanError> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.pos.checked_add(len))
+            .ok_or(ScanError::Eos)?;
+        let bytes = self.data.get(self.pos..end).ok_or(ScanError::Eos)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, ScanError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, ScanError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// 跳过 `len` 个类型为 `ty` 的值
+    fn values(&mut self, ty: u32, len: u64) -> Result<(), ScanError> {
+        let size = match ty {
+            // u8、i8、bool
+            0 | 1 | 7 => 1,
+            // u16、i16
+            2 | 3 => 2,
+            // u32、i32、f32
+            4..=6 => 4,
+            // u64、i64、f64
+            10..=12 => 8,
+            STRING => {
+                for _ in 0..len {
+                    let len = self.u64()?;
+                    self.take(len)?;
+                }
+                return Ok(());
+            }
+            _ => return Err(ScanError::Invalid("unknown value type")),
+        };
+        self.take(len.checked_mul(size).ok_or(ScanError::Eos)?)?;
+        Ok(())
+    }
+}
+
+/// 检查过的 GGUF 元数据，不解析张量信息
+pub(crate) struct Metadata<'a> {
+    kvs: HashMap<&'a str, GGufMetaKV<'a>>,
+}
+
+impl<'a> Metadata<'a> {
+    /// 所有键值对，顺序不确定
+    pub(crate) fn kvs(&self) -> impl Iterator<Item = &GGufMetaKV<'a>> {
+        self.kvs.values()
+    }
+}
+
+impl GGufMetaMap for Metadata<'_> {
+    fn get(&self, key: &str) -> Option<(GGufMetaDataValueType, &[u8])> {
+        self.kvs.get(key).map(|kv| (kv.ty(), kv.value_bytes()))
+    }
+}
+
+/// 读取 GGUF 的元数据，格式错误时返回错误
+///
+/// 先用 [`metadata_len`] 检查每个值的类型和长度，之后交给 ggus 读取才是安全的。
+pub(crate) fn read_metadata(data: &[u8]) -> Result<Metadata<'_>, LoadError> {
+    let len = metadata_len(data).map_err(|e| e.into_load_error())?;
+    let n_kvs = u64::from_le_bytes(data[16..HEADER_LEN].try_into().unwrap());
+    let mut reader = GGufReader::new(&data[HEADER_LEN..len]);
+    let mut kvs = HashMap::new();
+    for _ in 0..n_kvs {
+        let kv = reader
+            .read_meta_kv()
+            .map_err(|e| format_error(&format!("{e:?}")))?;
+        if kvs.insert(kv.key(), kv.clone()).is_some() {
+            return Err(format_error(&format!("duplicate meta key: {}", kv.key())));
+        }
+    }
+    Ok(Metadata { kvs })
+}
+
+fn parse(data: &[u8]) -> Result<GGuf<'_>, LoadError> {
+    GGuf::new(data).map_err(|e| format_error(&e.to_string()))
+}
+
+/// 分片文件名对应的第一个分片和分片数
+fn split_first(path: &Path) -> Option<(PathBuf, usize)> {
+    let name = path.file_name()?.to_str()?;
+    let captures = SPLIT_NAME.captures(name)?;
+    let count = &captures[3];
+    Some((
+        path.with_file_name(format!("{}-00001-of-{count}.gguf", &captures[1])),
+        count.parse().ok()?,
+    ))
+}
+
+fn format_error(msg: &str) -> LoadError {
+    LoadError::Format(format!("gguf: {msg}"))
+}
@@ -0,0 +1,828 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{common::TokenId, config::TokenizerConfig};
+
+/// 语法规则中的元素，与 llama.cpp 的 `llama_gretype` 对应
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Element {
+    /// 规则结束
+    End,
+    /// 下一个备选的开始
+    Alt,
+    /// 引用另一条规则
+    RuleRef(usize),
+    /// 匹配字符，之后可以有 `CharAlt` 和 `CharRngUpper`
+    Char(u32),
+    /// 不匹配字符，即 `[^...]`
+    CharNot(u32),
+    /// 与前一个元素构成字符范围的上界
+    CharRngUpper(u32),
+    /// 字符类中的另一个字符
+    CharAlt(u32),
+    /// 匹配任意字符，即 `.`
+    CharAny,
+}
+
+impl Element {
+    fn is_end_of_sequence(self) -> bool {
+        matches!(self, Self::End | Self::Alt)
+    }
+
+    fn value(self) -> u32 {
+        match self {
+            Self::Char(c) | Self::CharNot(c) | Self::CharRngUpper(c) | Self::CharAlt(c) => c,
+            Self::RuleRef(id) => id as u32,
+            Self::End | Self::Alt | Self::CharAny => 0,
+        }
+    }
+}
+
+/// 规则中的位置：（规则号，元素下标）
+type Pos = (usize, usize);
+/// 解析栈，栈顶在末尾，空栈表示语法已匹配完整
+type Stack = Vec<Pos>;
+
+/// 语法解析或约束失败的原因
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GrammarError {
+    /// GBNF 语法错误，`offset` 是出错处的字节偏移
+    Syntax { offset: usize, message: String },
+    /// 引用了未定义的规则
+    UndefinedRule(String),
+    /// 没有根规则
+    MissingRoot(String),
+    /// 规则左递归，无法展开
+    LeftRecursion(String),
+    /// 当前状态不允许该标记
+    Rejected(TokenId),
+    /// 标记序列结束时语法还没有匹配完整
+    Incomplete,
+}
+
+impl std::fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Syntax { offset, message } => write!(f, "{message} at offset {offset}"),
+            Self::UndefinedRule(name) => write!(f, "undefined rule identifier `{name}`"),
+            Self::MissingRoot(name) => write!(f, "grammar does not contain a `{name}` rule"),
+            Self::LeftRecursion(name) => write!(f, "left recursion detected for rule `{name}`"),
+            Self::Rejected(id) => write!(f, "token {id} is not allowed by the grammar"),
+            Self::Incomplete => write!(f, "token sequence ended before the grammar was complete"),
+        }
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+/// 单条规则的重复次数上限，避免展开后的规则过大
+const MAX_REPETITION: usize = 2000;
+
+/// 未完成的 UTF-8 字符：已读到的位和还缺少的字节数
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct PartialUtf8 {
+    value: u32,
+    remain: u8,
+}
+
+/// 解析器状态：所有可能的解析栈和未完成的字符
+#[derive(Clone, Debug)]
+struct State {
+    stacks: Vec<Stack>,
+    partial: PartialUtf8,
+}
+
+/// llama.cpp GBNF 语法约束
+///
+/// 记录已接受的标记后的解析状态，给出下一个可以接受的标记。
+/// 标记按 [`token_to_piece`](TokenizerConfig::token_to_piece) 还原的字节匹配，
+/// 多字节字符可以跨越多个标记。语法匹配完整后才允许结束生成的标记。
+#[derive(Clone, Debug)]
+pub struct Grammar {
+    rules: Arc<[Vec<Element>]>,
+    state: State,
+}
+
+impl Grammar {
+    /// 解析 GBNF 文本，从名为 `root` 的规则开始匹配
+    pub fn parse(src: &str, root: &str) -> Result<Self, GrammarError> {
+        let mut parser = Parser {
+            src,
+            pos: 0,
+            symbol_ids: HashMap::new(),
+            rules: Vec::new(),
+        };
+        parser.parse()?;
+        let Parser {
+            symbol_ids, rules, ..
+        } = parser;
+
+        let mut names = vec![String::new(); symbol_ids.len()];
+        for (name, &id) in &symbol_ids {
+            names[id] = name.clone();
+        }
+        for rule in &rules {
+            for element in rule {
+                if let &Element::RuleRef(id) = element
+                    && rules.get(id).is_none_or(Vec::is_empty)
+                {
+                    return Err(GrammarError::UndefinedRule(names[id].clone()));
+                }
+            }
+        }
+        let root = match symbol_ids.get(root) {
+            Some(&id) if rules.get(id).is_some_and(|rule| !rule.is_empty()) => id,
+            _ => return Err(GrammarError::MissingRoot(root.into())),
+        };
+        let mut visited = vec![false; rules.len()];
+        let mut in_progress = vec![false; rules.len()];
+        let mut may_be_empty = vec![false; rules.len()];
+        for (id, name) in names.iter().enumerate().take(rules.len()) {
+            if left_recursive(
+                &rules,
+                id,
+                &mut visited,
+                &mut in_progress,
+                &mut may_be_empty,
+            ) {
+                return Err(GrammarError::LeftRecursion(name.clone()));
+            }
+        }
+
+        let mut stacks = Vec::new();
+        expand_rule(&rules, Vec::new(), root, &mut stacks);
+        Ok(Self {
+            rules: rules.into(),
+            state: State {
+                stacks,
+                partial: PartialUtf8::default(),
+            },
+        })
+    }
+
+    /// 已接受的内容是否匹配完整，此时允许结束生成
+    pub fn is_complete(&self) -> bool {
+        self.state.partial.remain == 0 && self.state.stacks.iter().any(Vec::is_empty)
+    }
+
+    /// 当前状态下允许的标记，按 id 排序
+    ///
+    /// 遍历 [`prefix_index`](TokenizerConfig::prefix_index)，相同前缀的标记共享匹配状态。
+    /// 还原为空的标记（控制标记等）不允许，结束生成的标记只在 [`is_complete`](Self::is_complete) 时允许。
+    pub fn allowed_tokens(&self, config: &TokenizerConfig) -> Vec<TokenId> {
+        let mut allowed = Vec::new();
+        if self.is_complete() {
+            allowed.extend(config.special_eog_ids.iter().copied());
+        }
+        // states[i] 是接受 prev[..i] 后的状态，前缀失配后不再增长
+        let mut states = vec![self.state.clone()];
+        let mut prev: &[u8] = &[];
+        for (piece, id) in config.prefix_index().iter() {
+            if config.is_eog(id) {
+                continue;
+            }
+            let common = prev.iter().zip(piece).take_while(|(a, b)| a == b).count();
+            states.truncate(common + 1);
+            prev = piece;
+            if states.len() <= common {
+                continue;
+            }
+            while states.len() <= piece.len() {
+                match states
+                    .last()
+                    .unwrap()
+                    .advance(&self.rules, piece[states.len() - 1])
+                {
+                    Some(state) => states.push(state),
+                    None => break,
+                }
+            }
+            if states.len() > piece.len() {
+                allowed.push(id);
+            }
+        }
+        allowed.sort_unstable();
+        allowed.dedup();
+        allowed
+    }
+
+    /// 与 [`allowed_tokens`](Self::allowed_tokens) 相同，以词表大小的掩码表示
+    pub fn token_mask(&self, config: &TokenizerConfig) -> Vec<bool> {
+        let mut mask = vec![false; config.n_tokens() as usize];
+        for id in self.allowed_tokens(config) {
+            mask[id as usize] = true;
+        }
+        mask
+    }
+
+    /// 检查完整的标记序列是否符合语法，不改变当前状态
+    ///
+    /// 序列可以以结束生成的标记结尾。
+    pub fn validate(&self, config: &TokenizerConfig, ids: &[TokenId]) -> Result<(), GrammarError> {
+        let mut grammar = self.clone();
+        for &id in ids {
+            grammar.accept_token(config, id)?;
+        }
+        if grammar.is_complete() {
+            Ok(())
+        } else {
+            Err(GrammarError::Incomplete)
+        }
+    }
+
+    /// 接受一个标记，更新解析状态
+    ///
+    /// 不允许的标记返回 [`GrammarError::Rejected`]，状态不变。
+    pub fn accept_token(
+        &mut self,
+        config: &TokenizerConfig,
+        id: TokenId,
+    ) -> Result<(), GrammarError> {
+        if config.is_eog(id) {
+            return if self.is_complete() {
+                Ok(())
+            } else {
+                Err(GrammarError::Rejected(id))
+            };
+        }
+        let piece = config.token_to_piece(id, 0, false);
+        if piece.is_empty() {
+            return Err(GrammarError::Rejected(id));
+        }
+        let mut state = self.state.clone();
+        for byte in piece {
+            state = state
+                .advance(&self.rules, byte)
+                .ok_or(GrammarError::Rejected(id))?;
+        }
+        self.state = state;
+        Ok(())
+    }
+}
+
+impl State {
+    /// 接受一个字节，没有解析栈能接受时返回 `None`
+    fn advance(&self, rules: &[Vec<Element>], byte: u8) -> Option<Self> {
+        let partial = if self.partial.remain > 0 {
+            if byte >> 6 != 0b10 {
+                return None;
+            }
+            PartialUtf8 {
+                value: self.partial.value << 6 | u32::from(byte & 0x3f),
+                remain: self.partial.remain - 1,
+            }
+        } else {
+            let (value, remain) = match byte {
+                0x00..=0x7f => (byte, 0),
+                0xc0..=0xdf => (byte & 0x1f, 1),
+                0xe0..=0xef => (byte & 0x0f, 2),
+                0xf0..=0xf7 => (byte & 0x07, 3),
+                _ => return None,
+            };
+            PartialUtf8 {
+                value: value.into(),
+                remain,
+            }
+        };
+        let stacks = if partial.remain == 0 {
+            accept_char(rules, &self.stacks, partial.value)
+        } else {
+            // 字符还不完整，先排除首个字符不可能匹配的栈
+            self.stacks
+                .iter()
+                .filter(|stack| {
+                    stack
+                        .last()
+                        .is_some_and(|&pos| match_partial_char(rules, pos, partial))
+                })
+                .cloned()
+                .collect()
+        };
+        if stacks.is_empty() {
+            None
+        } else {
+            Some(Self {
+                stacks,
+                partial: if partial.remain == 0 {
+                    PartialUtf8::default()
+                } else {
+                    partial
+                },
+            })
+        }
+    }
+}
+
+/// 所有栈接受字符 `chr` 后得到的新栈
+fn accept_char(rules: &[Vec<Element>], stacks: &[Stack], chr: u32) -> Vec<Stack> {
+    let mut new_stacks = Vec::new();
+    for stack in stacks {
+        let Some(&(rule, pos)) = stack.last() else {
+            continue;
+        };
+        let (matched, next) = match_char(rules, (rule, pos), chr);
+        if matched {
+            let mut new_stack = stack[..stack.len() - 1].to_vec();
+            if !rules[rule][next].is_end_of_sequence() {
+                new_stack.push((rule, next));
+            }
+            advance_stack(rules, new_stack, &mut new_stacks);
+        }
+    }
+    new_stacks
+}
+
+/// 展开栈顶的规则引用，直到栈顶都是字符元素
+fn advance_stack(rules: &[Vec<Element>], stack: Stack, new_stacks: &mut Vec<Stack>) {
+    let Some(&(rule, pos)) = stack.last() else {
+        if !new_stacks.contains(&stack) {
+            new_stacks.push(stack);
+        }
+        return;
+    };
+    match rules[rule][pos] {
+        Element::RuleRef(sub) => {
+            let mut base = stack[..stack.len() - 1].to_vec();
+            if !rules[rule][pos + 1].is_end_of_sequence() {
+                base.push((rule, pos + 1));
+            }
+            expand_rule(rules, base, sub, new_stacks);
+        }
+        Element::Char(_) | Element::CharNot(_) | Element::CharAny => {
+            if !new_stacks.contains(&stack) {
+                new_stacks.push(stack);
+            }
+        }
+        // 栈顶不会停在规则结束或字符类的中间
+        Element::End | Element::Alt | Element::CharRngUpper(_) | Element::CharAlt(_) => {
+            unreachable!()
+        }
+    }
+}
+
+/// 将规则 `rule` 的每个备选压入 `base` 后展开
+fn expand_rule(rules: &[Vec<Element>], base: Stack, rule: usize, new_stacks: &mut Vec<Stack>) {
+    let elements = &rules[rule];
+    let mut pos = 0;
+    loop {
+        let mut stack = base.clone();
+        if !elements[pos].is_end_of_sequence() {
+            stack.push((rule, pos));
+        }
+        advance_stack(rules, stack, new_stacks);
+        while !elements[pos].is_end_of_sequence() {
+            pos += 1;
+        }
+        if elements[pos] == Element::Alt {
+            pos += 1;
+        } else {
+            break;
+        }
+    }
+}
+
+/// 字符元素是否匹配 `chr`，同时返回字符类之后的位置
+fn match_char(rules: &[Vec<Element>], (rule, pos): Pos, chr: u32) -> (bool, usize) {
+    let elements = &rules[rule];
+    let positive = matches!(elements[pos], Element::Char(_) | Element::CharAny);
+    let mut found = false;
+    let mut i = pos;
+    loop {
+        if let Element::CharRngUpper(upper) = elements[i + 1] {
+            found |= (elements[i].value()..=upper).contains(&chr);
+            i += 2;
+        } else if elements[i] == Element::CharAny {
+            found = true;
+            i += 1;
+        } else {
+            found |= elements[i].value() == chr;
+            i += 1;
+        }
+        if !matches!(elements[i], Element::CharAlt(_)) {
+            break;
+        }
+    }
+    (found == positive, i)
+}
+
+/// 以未完成的字节开头的字符是否可能匹配字符元素
+fn match_partial_char(rules: &[Vec<Element>], (rule, pos): Pos, partial: PartialUtf8) -> bool {
+    let elements = &rules[rule];
+    let positive = matches!(elements[pos], Element::Char(_) | Element::CharAny);
+    // 两字节字符的首字节不能编码小于 0x80 的字符
+    if partial.remain == 1 && partial.value < 2 {
+        return false;
+    }
+    let shift = 6 * u32::from(partial.remain);
+    let mut low = partial.value << shift;
+    let high = low | ((1 << shift) - 1);
+    if low == 0 {
+        low = match partial.remain {
+            2 => 1 << 11,
+            3 => 1 << 16,
+            _ => low,
+        };
+    }
+    let mut i = pos;
+    loop {
+        if let Element::CharRngUpper(upper) = elements[i + 1] {
+            if low <= upper && elements[i].value() <= high {
+                return positive;
+            }
+            i += 2;
+        } else if elements[i] == Element::CharAny {
+            return true;
+        } else {
+            if (low..=high).contains(&elements[i].value()) {
+                return positive;
+            }
+            i += 1;
+        }
+        if !matches!(elements[i], Element::CharAlt(_)) {
+            break;
+        }
+    }
+    !positive
+}
+
+/// 规则是否左递归，同时记录规则能否匹配空串
+fn left_recursive(
+    rules: &[Vec<Element>],
+    id: usize,
+    visited: &mut [bool],
+    in_progress: &mut [bool],
+    may_be_empty: &mut [bool],
+) -> bool {
+    if in_progress[id] {
+        return true;
+    }
+    if visited[id] {
+        return false;
+    }
+    in_progress[id] = true;
+    let rule = &rules[id];
+
+    // 有空的备选时规则可以为空
+    let mut at_rule_start = true;
+    for element in rule {
+        if element.is_end_of_sequence() {
+            if at_rule_start {
+                may_be_empty[id] = true;
+                break;
+            }
+            at_rule_start = true;
+        } else {
+            at_rule_start = false;
+        }
+    }
+
+    // 检查每个备选开头的规则引用，引用的规则可以为空时继续检查下一个元素
+    let mut recurse = true;
+    for &element in rule {
+        match element {
+            Element::RuleRef(sub) if recurse => {
+                if left_recursive(rules, sub, visited, in_progress, may_be_empty) {
+                    return true;
+                }
+                if !may_be_empty[sub] {
+                    recurse = false;
+                }
+            }
+            element if element.is_end_of_sequence() => recurse = true,
+            _ => recurse = false,
+        }
+    }
+
+    in_progress[id] = false;
+    visited[id] = true;
+    false
+}
+
+/// GBNF 的递归下降解析器，与 llama.cpp 的 `llama_grammar_parser` 对应
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    symbol_ids: HashMap<String, usize>,
+    rules: Vec<Vec<Element>>,
+}
+
+impl Parser<'_> {
+    fn parse(&mut self) -> Result<(), GrammarError> {
+        self.skip_space(true);
+        while self.pos < self.src.len() {
+            self.parse_rule()?;
+        }
+        Ok(())
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, GrammarError> {
+        Err(GrammarError::Syntax {
+            offset: self.pos,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.src.as_bytes().get(self.pos + offset).copied()
+    }
+
+    /// 跳过空白和注释，`newline_ok` 时也跳过换行
+    fn skip_space(&mut self, newline_ok: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                b'#' => {
+                    while self.peek().is_some_and(|c| c != b'\r' && c != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                b' ' | b'\t' => self.pos += 1,
+                b'\r' | b'\n' if newline_ok => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn parse_name(&mut self) -> Result<&str, GrammarError> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'-')
+        {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return self.error("expecting name");
+        }
+        Ok(&self.src[start..self.pos])
+    }
+
+    fn parse_int(&mut self) -> Result<usize, GrammarError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return self.error("expecting integer");
+        }
+        self.src[start..self.pos]
+            .parse()
+            .or_else(|_| self.error("integer out of range"))
+    }
+
+    fn parse_hex(&mut self, len: usize) -> Result<u32, GrammarError> {
+        match self.src.get(self.pos..self.pos + len) {
+            Some(digits) if digits.bytes().all(|c| c.is_ascii_hexdigit()) => {
+                self.pos += len;
+                Ok(u32::from_str_radix(digits, 16).unwrap())
+            }
+            _ => self.error(format!("expecting {len} hex chars")),
+        }
+    }
+
+    /// 解析一个字符，处理转义
+    fn parse_char(&mut self) -> Result<u32, GrammarError> {
+        let Some(c) = self.src[self.pos..].chars().next() else {
+            return self.error("unexpected end of input");
+        };
+        self.pos += c.len_utf8();
+        if c != '\\' {
+            return Ok(c.into());
+        }
+        let Some(escape) = self.peek() else {
+            return self.error("unexpected end of input");
+        };
+        self.pos += 1;
+        match escape {
+            b'x' => self.parse_hex(2),
+            b'u' => self.parse_hex(4),
+            b'U' => self.parse_hex(8),
+            b't' => Ok('\t'.into()),
+            b'r' => Ok('\r'.into()),
+            b'n' => Ok('\n'.into()),
+            b'\\' | b'"' | b'[' | b']' => Ok(escape.into()),
+            _ => {
+                self.pos -= 1;
+                self.error("unknown escape")
+            }
+        }
+    }
+
+    fn symbol_id(&mut self, name: &str) -> usize {
+        let next = self.symbol_ids.len();
+        *self.symbol_ids.entry(name.into()).or_insert(next)
+    }
+
+    /// 为括号和重复生成的匿名规则分配 id
+    fn generate_symbol_id(&mut self, base: &str) -> usize {
+        let id = self.symbol_ids.len();
+        self.symbol_ids.insert(format!("{base}_{id}"), id);
+        id
+    }
+
+    fn add_rule(&mut self, id: usize, rule: Vec<Element>) {
+        if self.rules.len() <= id {
+            self.rules.resize(id + 1, Vec::new());
+        }
+        self.rules[id] = rule;
+    }
+
+    fn parse_rule(&mut self) -> Result<(), GrammarError> {
+        let name = self.parse_name()?.to_string();
+        self.skip_space(false);
+        let id = self.symbol_id(&name);
+        if !self.src[self.pos..].starts_with("::=") {
+            return self.error("expecting ::=");
+        }
+        self.pos += 3;
+        self.skip_space(true);
+        self.parse_alternates(&name, id, false)?;
+        match self.peek() {
+            Some(b'\r') => self.pos += if self.peek_at(1) == Some(b'\n') { 2 } else { 1 },
+            Some(b'\n') => self.pos += 1,
+            Some(_) => return self.error("expecting newline or end"),
+            None => {}
+        }
+        self.skip_space(true);
+        Ok(())
+    }
+
+    fn parse_alternates(
+        &mut self,
+        name: &str,
+        id: usize,
+        nested: bool,
+    ) -> Result<(), GrammarError> {
+        let mut rule = Vec::new();
+        self.parse_sequence(name, &mut rule, nested)?;
+        while self.peek() == Some(b'|') {
+            rule.push(Element::Alt);
+            self.pos += 1;
+            self.skip_space(true);
+            self.parse_sequence(name, &mut rule, nested)?;
+        }
+        rule.push(Element::End);
+        self.add_rule(id, rule);
+        Ok(())
+    }
+
+    fn parse_sequence(
+        &mut self,
+        name: &str,
+        out: &mut Vec<Element>,
+        nested: bool,
+    ) -> Result<(), GrammarError> {
+        let mut last_sym_start = out.len();
+        while let Some(c) = self.peek() {
+            match c {
+                b'"' => {
+                    self.pos += 1;
+                    last_sym_start = out.len();
+                    while self.peek() != Some(b'"') {
+                        out.push(Element::Char(self.parse_char()?));
+                    }
+                    self.pos += 1;
+                    self.skip_space(nested);
+                }
+                b'[' => {
+                    self.pos += 1;
+                    let negated = self.peek() == Some(b'^');
+                    if negated {
+                        self.pos += 1;
+                    }
+                    last_sym_start = out.len();
+                    while self.peek() != Some(b']') {
+                        let chr = self.parse_char()?;
+                        out.push(if last_sym_start < out.len() {
+                            Element::CharAlt(chr)
+                        } else if negated {
+                            Element::CharNot(chr)
+                        } else {
+                            Element::Char(chr)
+                        });
+                        if self.peek() == Some(b'-') && self.peek_at(1) != Some(b']') {
+                            self.pos += 1;
+                            out.push(Element::CharRngUpper(self.parse_char()?));
+                        }
+                    }
+                    self.pos += 1;
+                    self.skip_space(nested);
+                }
+                c if c.is_ascii_alphanumeric() || c == b'-' => {
+                    let sub = self.parse_name()?.to_string();
+                    let id = self.symbol_id(&sub);
+                    self.skip_space(nested);
+                    last_sym_start = out.len();
+                    out.push(Element::RuleRef(id));
+                }
+                b'(' => {
+                    self.pos += 1;
+                    self.skip_space(true);
+                    let id = self.generate_symbol_id(name);
+                    self.parse_alternates(name, id, true)?;
+                    last_sym_start = out.len();
+                    out.push(Element::RuleRef(id));
+                    if self.peek() != Some(b')') {
+                        return self.error("expecting ')'");
+                    }
+                    self.pos += 1;
+                    self.skip_space(nested);
+                }
+                b'.' => {
+                    self.pos += 1;
+                    last_sym_start = out.len();
+                    out.push(Element::CharAny);
+                    self.skip_space(nested);
+                }
+                b'*' | b'+' | b'?' => {
+                    self.pos += 1;
+                    self.skip_space(nested);
+                    let (min, max) = match c {
+                        b'*' => (0, None),
+                        b'+' => (1, None),
+                        _ => (0, Some(1)),
+                    };
+                    self.repeat(name, out, last_sym_start, min, max)?;
+                }
+                b'{' => {
+                    self.pos += 1;
+                    self.skip_space(nested);
+                    let min = self.parse_int()?;
+                    self.skip_space(nested);
+                    let max = match self.peek() {
+                        Some(b'}') => Some(min),
+                        Some(b',') => {
+                            self.pos += 1;
+                            self.skip_space(nested);
+                            let max = if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                                let max = self.parse_int()?;
+                                self.skip_space(nested);
+                                Some(max)
+                            } else {
+                                None
+                            };
+                            if self.peek() != Some(b'}') {
+                                return self.error("expecting '}'");
+                            }
+                            max
+                        }
+                        _ => return self.error("expecting ',' or '}'"),
+                    };
+                    self.pos += 1;
+                    self.skip_space(nested);
+                    self.repeat(name, out, last_sym_start, min, max)?;
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// 将 `out[last_sym_start..]` 重复 `min..=max` 次，`max` 为 `None` 时不限次数
+    ///
+    /// 必须的部分直接展开，可选的部分改写为嵌套的规则：
+    /// `x{1,3}` 即 `x (x (x)?)?`，`x+` 即 `x x_1`，其中 `x_1 ::= x x_1 |`。
+    fn repeat(
+        &mut self,
+        name: &str,
+        out: &mut Vec<Element>,
+        last_sym_start: usize,
+        min: usize,
+        max: Option<usize>,
+    ) -> Result<(), GrammarError> {
+        if last_sym_start == out.len() {
+            return self.error("expecting preceding item to */+/?/{");
+        }
+        if min > MAX_REPETITION || max.is_some_and(|max| max > MAX_REPETITION) {
+            return self.error(format!("number of repetitions exceeds {MAX_REPETITION}"));
+        }
+        if max.is_some_and(|max| max < min) {
+            return self.error("repetition upper bound is less than lower bound");
+        }
+        let item = out.split_off(last_sym_start);
+        for _ in 0..min {
+            out.extend_from_slice(&item);
+        }
+        let optional = max.map_or(1, |max| max - min);
+        let mut last = None;
+        for _ in 0..optional {
+            let id = self.generate_symbol_id(name);
+            let mut rule = item.clone();
+            match (max, last) {
+                (None, _) => rule.push(Element::RuleRef(id)),
+                (Some(_), Some(last)) => rule.push(Element::RuleRef(last)),
+                (Some(_), None) => {}
+            }
+            rule.push(Element::Alt);
+            rule.push(Element::End);
+            self.add_rule(id, rule);
+            last = Some(id);
+        }
+        if let Some(last) = last {
+            out.push(Element::RuleRef(last));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{
+    common::{TokenAttribute, TokenId},
+    config::TokenizerConfig,
+};
+
+/// [`TokenizerConfig::heal`] 的结果
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenHealing {
+    /// 保留的提示词标记
+    pub ids: Vec<TokenId>,
+    /// 回退的标记还原后的字节，生成的内容必须以它开头
+    pub prefix: Vec<u8>,
+    /// 下一个标记的候选：还原后以 `prefix` 开头，或者是 `prefix` 的前缀，按 id 排序
+    ///
+    /// `prefix` 为空时不限制，候选也为空。
+    pub candidates: Vec<TokenId>,
+}
+
+impl TokenHealing {
+    /// 采样得到一个标记后更新要求的前缀和候选
+    ///
+    /// 标记不在候选中时返回 `false`，状态不变。
+    pub fn accept(&mut self, config: &TokenizerConfig, id: TokenId) -> bool {
+        if self.prefix.is_empty() {
+            return true;
+        }
+        if self.candidates.binary_search(&id).is_err() {
+            return false;
+        }
+        let piece = config.token_to_piece(id, 0, false);
+        let consumed = piece.len().min(self.prefix.len());
+        self.prefix.drain(..consumed);
+        self.candidates = candidates(config, &self.prefix);
+        true
+    }
+}
+
+impl TokenizerConfig {
+    /// 分词后回退最后 `back_off` 个标记，把它们的文本作为生成时必须满足的前缀
+    ///
+    /// 提示词以半个词结尾（如 `http:`）时，分词的边界往往不是模型会选择的边界，
+    /// 回退后由采样器在候选中选择第一个标记即可修正。
+    /// 控制标记和自动添加的 BOS/EOS 不会回退，因此实际回退的标记可能更少。
+    pub fn heal(
+        &self,
+        text: &str,
+        back_off: usize,
+        add_special: bool,
+        parse_special: bool,
+    ) -> TokenHealing {
+        let mut ids = self.tokenize(text, add_special, parse_special);
+        let mut removed = Vec::new();
+        while removed.len() < back_off {
+            match ids.last() {
+                Some(&id)
+                    if !self.id_to_token[id as usize]
+                        .attribute
+                        .contains(TokenAttribute::Control) =>
+                {
+                    removed.push(ids.pop().unwrap());
+                }
+                _ => break,
+            }
+        }
+        let prefix = removed
+            .iter()
+            .rev()
+            .flat_map(|&id| self.token_to_piece(id, 0, false))
+            .collect::<Vec<_>>();
+        TokenHealing {
+            ids,
+            candidates: candidates(self, &prefix),
+            prefix,
+        }
+    }
+}
+
+/// 还原后与 `prefix` 相容的标记
+fn candidates(config: &TokenizerConfig, prefix: &[u8]) -> Vec<TokenId> {
+    if prefix.is_empty() {
+        return Vec::new();
+    }
+    let index = config.prefix_index();
+    let mut ids = index
+        .starting_with(prefix)
+        .chain(index.prefixes_of(prefix))
+        .map(|(_, id)| id)
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
@@ -0,0 +1,786 @@
+use std::{collections::HashMap, fs, io::ErrorKind, path::Path};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+use crate::{
+    common::{GPT2, NULL, TokenAttribute, TokenData, TokenId},
+    config::{LoadError, TokenizerConfig, VocabType, merge_ranks},
+    ugm::Charsmap,
+    unicode::{NormalizationForm, Normalizer, try_compiled_regex},
+};
+
+/// tokenizer.json 的顶层结构，只保留构造词表需要的部分
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HfTokenizer {
+    #[serde(default)]
+    pub added_tokens: Vec<HfAddedToken>,
+    #[serde(default)]
+    pub normalizer: Option<Value>,
+    #[serde(default)]
+    pub pre_tokenizer: Option<Value>,
+    #[serde(default)]
+    pub post_processor: Option<Value>,
+    #[serde(default)]
+    pub decoder: Option<Value>,
+    pub model: HfModel,
+}
+
+/// added_tokens 中的一项
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HfAddedToken {
+    pub id: TokenId,
+    pub content: String,
+    #[serde(default)]
+    pub single_word: bool,
+    #[serde(default)]
+    pub lstrip: bool,
+    #[serde(default)]
+    pub rstrip: bool,
+    #[serde(default)]
+    pub normalized: bool,
+    #[serde(default)]
+    pub special: bool,
+}
+
+/// tokenizer.json 中的 model 字段
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum HfModel {
+    #[serde(rename = "BPE")]
+    Bpe {
+        vocab: HashMap<String, TokenId>,
+        merges: Vec<HfMerge>,
+        #[serde(default)]
+        unk_token: Option<String>,
+        #[serde(default)]
+        byte_fallback: bool,
+        #[serde(default)]
+        ignore_merges: bool,
+    },
+    Unigram {
+        vocab: Vec<(String, f64)>,
+        #[serde(default)]
+        unk_id: Option<TokenId>,
+        #[serde(default)]
+        byte_fallback: bool,
+    },
+    WordPiece {
+        vocab: HashMap<String, TokenId>,
+        unk_token: String,
+    },
+}
+
+/// 合并规则，新版为二元数组，旧版为空格分隔的字符串
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum HfMerge {
+    Pair(String, String),
+    Joined(String),
+}
+
+/// 从 HF 仓库目录（或 tokenizer.json 文件路径）加载分词器
+///
+/// 同目录下的 tokenizer_config.json 是可选的。与 [`load_hf_json`] 相同，支持 BPE 和 Unigram 模型，
+/// WordPiece 模型以及不支持的 normalizer、pre_tokenizer 返回 [`LoadError::Format`]。
+pub fn load_hf(path: impl AsRef<Path>) -> Result<TokenizerConfig, LoadError> {
+    let path = path.as_ref();
+    let (tokenizer_path, dir) = if path.is_dir() {
+        (path.join("tokenizer.json"), path)
+    } else {
+        (path.to_path_buf(), path.parent().unwrap_or(Path::new(".")))
+    };
+    let tokenizer = fs::read_to_string(tokenizer_path)?;
+    let tokenizer_config = match fs::read_to_string(dir.join("tokenizer_config.json")) {
+        Ok(text) => Some(text),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    load_hf_json(&tokenizer, tokenizer_config.as_deref())
+}
+
+/// 从 tokenizer.json 与 tokenizer_config.json 的文本加载分词器
+///
+/// BPE 模型按 BPE（带字节回退时按 SPM）分词，Unigram 模型与 `load_sentencepiece` 相同按 UGM 分词，
+/// `Precompiled` normalizer 的 charsmap 用于 UGM 规范化。WordPiece 返回错误。`normalizer` 中的
+/// Unicode 规范化、`Lowercase` 和 `StripAccents` 在分词时对特殊标记之间的文本执行，
+/// 不支持的 normalizer 和 pre_tokenizer 返回错误。
+pub fn load_hf_json(
+    tokenizer: &str,
+    tokenizer_config: Option<&str>,
+) -> Result<TokenizerConfig, LoadError> {
+    let tokenizer: HfTokenizer = serde_json::from_str(tokenizer)?;
+    let tokenizer_config: Option<Value> = tokenizer_config.map(serde_json::from_str).transpose()?;
+
+    let mut config = TokenizerConfig::new();
+    config.bos = NULL;
+    config.eos = NULL;
+    config.unk = NULL;
+    config.sep = NULL;
+    config.pad = NULL;
+    config.mask = NULL;
+
+    // 合并规则在词表构造完成后转为 id 对
+    let merge_pairs: Vec<(&str, &str)>;
+    // 收集词表，id 可能不连续，added_tokens 也可能超出 model.vocab 的范围
+    let mut pieces: Vec<Option<(String, f32)>> = Vec::new();
+    let mut put = |id: TokenId, text: &str, score: f32| {
+        let id = id as usize;
+        if pieces.len() <= id {
+            pieces.resize(id + 1, None);
+        }
+        pieces[id] = Some((text.to_string(), score));
+    };
+    match &tokenizer.model {
+        HfModel::Bpe {
+            vocab,
+            merges,
+            unk_token,
+            byte_fallback,
+            ignore_merges,
+        } => {
+            // 带字节回退的 BPE 即 llama.cpp 中的 SPM，合并表保留在 bpe_ranks 中，
+            // SPM 分词时只按其中的合并规则和排名合并
+            if *byte_fallback {
+                config.vocab_type = VocabType::Spm;
+                config.add_space_prefix = true;
+            } else {
+                config.vocab_type = VocabType::Bpe;
+                config.clean_spaces = true;
+            }
+            config.ignore_merges = *ignore_merges;
+            for (text, &id) in vocab {
+                let score = match config.vocab_type {
+                    VocabType::Spm => -(id as f32),
+                    _ => 0.0,
+                };
+                put(id, text, score);
+            }
+            merge_pairs = merges
+                .iter()
+                .map(|merge| match merge {
+                    HfMerge::Pair(first, second) => Ok((first.as_str(), second.as_str())),
+                    HfMerge::Joined(piece) => piece
+                        .split_once(' ')
+                        .ok_or_else(|| LoadError::Format(format!("invalid merge: {piece}"))),
+                })
+                .collect::<Result<_, _>>()?;
+            if let Some(unk) = unk_token {
+                config.unk = vocab.get(unk).copied().unwrap_or(NULL);
+            }
+        }
+        // 与 load_sentencepiece 的 Unigram 模型相同，按 UGM 分词
+        HfModel::Unigram {
+            vocab,
+            unk_id,
+            byte_fallback,
+        } => {
+            // UGM 分词把词表外的字符作为未知标记，没有字节回退
+            if *byte_fallback {
+                return Err(LoadError::Format(
+                    "unsupported Unigram model with byte_fallback".into(),
+                ));
+            }
+            config.vocab_type = VocabType::Ugm;
+            config.clean_spaces = false;
+            for (id, (text, score)) in vocab.iter().enumerate() {
+                put(id as TokenId, text, *score as f32);
+            }
+            merge_pairs = Vec::new();
+            config.unk = unk_id.unwrap_or(NULL);
+        }
+        // 尚未实现分词的模型直接报错
+        HfModel::WordPiece { .. } => {
+            return Err(LoadError::Format("unsupported model WordPiece".into()));
+        }
+    }
+    for token in &tokenizer.added_tokens {
+        put(token.id, &token.content, 0.0);
+    }
+
+    let added = tokenizer
+        .added_tokens
+        .iter()
+        .map(|token| (token.id, token))
+        .collect::<HashMap<_, _>>();
+    config.id_to_token = pieces
+        .into_iter()
+        .enumerate()
+        .map(|(id, piece)| {
+            let Some((mut text, score)) = piece else {
+                return TokenData {
+                    text: format!("[PAD{id}]").into(),
+                    score: 0.0,
+                    attribute: TokenAttribute::Unused,
+                };
+            };
+            let id = id as TokenId;
+            let mut attribute = if id == config.unk {
+                TokenAttribute::Unknown
+            } else if let Some(token) = added.get(&id) {
+                if token.special || looks_special(&text) {
+                    TokenAttribute::Control
+                } else {
+                    // 与 convert_hf_to_gguf.py 一致，预先还原用户自定义标记中的空格
+                    if config.vocab_type == VocabType::Bpe {
+                        text = text.replace('\u{2581}', " ");
+                    }
+                    TokenAttribute::UserDefined
+                }
+            } else if config.vocab_type != VocabType::Bpe && is_byte_piece(&text) {
+                TokenAttribute::Byte
+            } else {
+                TokenAttribute::Normal
+            };
+            // lstrip/rstrip 等选项不写入 GGUF，只在从 tokenizer.json 加载时保留
+            if let Some(token) = added.get(&id) {
+                for (flag, flag_attribute) in [
+                    (token.lstrip, TokenAttribute::LStrIp),
+                    (token.rstrip, TokenAttribute::RStrIp),
+                    (token.single_word, TokenAttribute::SingleWord),
+                    (token.normalized, TokenAttribute::Normalized),
+                ] {
+                    if flag {
+                        attribute |= flag_attribute;
+                    }
+                }
+            }
+            TokenData {
+                text: text.into(),
+                score,
+                attribute,
+            }
+        })
+        .collect();
+    config.token_to_id = config
+        .id_to_token
+        .iter()
+        .enumerate()
+        .map(|(id, token)| (token.text.clone(), id as TokenId))
+        .collect();
+    config.bpe_ranks = merge_ranks(
+        &config.token_to_id,
+        merge_pairs
+            .into_iter()
+            .enumerate()
+            .map(|(i, pair)| (pair, i)),
+    );
+
+    if let Some(normalizer) = &tokenizer.normalizer {
+        apply_normalizer(&mut config, normalizer)?;
+    }
+    // 没有 pre_tokenizer 或其中没有正则时不预分词，不沿用 TokenizerConfig::new 的默认正则
+    let mut regex_exprs = Vec::new();
+    if let Some(pre_tokenizer) = &tokenizer.pre_tokenizer {
+        apply_pre_tokenizer(&mut config, pre_tokenizer, &mut regex_exprs)?;
+    }
+    let regex_exprs = regex_exprs.iter().map(String::as_str).collect::<Vec<_>>();
+    config.set_regex_exprs(&regex_exprs);
+    if let Some(post_processor) = &tokenizer.post_processor {
+        apply_post_processor(&mut config, post_processor);
+    }
+    if let Some(tokenizer_config) = &tokenizer_config {
+        apply_tokenizer_config(&mut config, tokenizer_config);
+    }
+
+    config.init_special_tokens();
+    Ok(config)
+}
+
+/// 与 convert_hf_to_gguf.py 的 does_token_look_special 一致
+fn looks_special(text: &str) -> bool {
+    matches!(text, "<pad>" | "<mask>" | "<2mass>" | "[@BOS@]")
+        || (text.starts_with("<|") && text.ends_with("|>"))
+        || (text.starts_with("<｜") && text.ends_with("｜>"))
+}
+
+/// 是否形如 `<0xXY>` 的字节标记
+fn is_byte_piece(text: &str) -> bool {
+    text.len() == 6
+        && text.starts_with("<0x")
+        && text.ends_with('>')
+        && text[3..5].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// 根据 normalizer 设置空格相关的选项，收集 Unicode 规范化、小写和去除重音步骤
+///
+/// UGM 词表的 `Precompiled` 写入 `precompiled_charsmap`，合并连续空格的 `Replace` 对应
+/// `remove_extra_whitespaces`。charsmap 在 UGM 分词时最后执行，其后不能再有规范化步骤。
+/// 其他 normalizer 无法在分词时还原，返回错误。
+fn apply_normalizer(config: &mut TokenizerConfig, normalizer: &Value) -> Result<(), LoadError> {
+    let step = match normalizer["type"].as_str() {
+        Some("Sequence") => {
+            for normalizer in normalizer["normalizers"].as_array().into_iter().flatten() {
+                apply_normalizer(config, normalizer)?;
+            }
+            return Ok(());
+        }
+        Some("Prepend") => {
+            config.add_space_prefix = normalizer["prepend"] == "\u{2581}";
+            return Ok(());
+        }
+        Some("Replace")
+            if normalizer["pattern"]["String"] == " " && normalizer["content"] == "\u{2581}" =>
+        {
+            config.escape_whitespaces = true;
+            return Ok(());
+        }
+        Some("Replace")
+            if config.vocab_type == VocabType::Ugm
+                && normalizer["pattern"]["Regex"] == " {2,}"
+                && normalizer["content"] == " " =>
+        {
+            config.remove_extra_whitespaces = true;
+            return Ok(());
+        }
+        Some("Precompiled") if config.vocab_type == VocabType::Ugm => {
+            let charsmap = normalizer["precompiled_charsmap"].as_str().unwrap_or("");
+            let charsmap = STANDARD
+                .decode(charsmap)
+                .map_err(|e| LoadError::Format(format!("invalid precompiled_charsmap: {e}")))?;
+            Charsmap::check(&charsmap)?;
+            config.precompiled_charsmap = charsmap;
+            return Ok(());
+        }
+        Some("NFC") => Normalizer::Form(NormalizationForm::Nfc),
+        Some("NFD") => Normalizer::Form(NormalizationForm::Nfd),
+        Some("NFKC") => Normalizer::Form(NormalizationForm::Nfkc),
+        Some("NFKD") => Normalizer::Form(NormalizationForm::Nfkd),
+        Some("Lowercase") => Normalizer::Lowercase,
+        Some("StripAccents") => Normalizer::StripAccents,
+        ty => {
+            return Err(LoadError::Format(format!(
+                "unsupported normalizer {}",
+                ty.unwrap_or("without type")
+            )));
+        }
+    };
+    if !config.precompiled_charsmap.is_empty() {
+        return Err(LoadError::Format(format!(
+            "unsupported normalizer {step:?} after Precompiled"
+        )));
+    }
+    config.normalizers.push(step);
+    Ok(())
+}
+
+/// 根据 pre_tokenizer 收集预分词正则并设置空格相关的选项
+///
+/// 预分词正则把匹配和匹配之间的文本都作为片段，相当于 `Split` 的 `Isolated` 且不反转；
+/// 其他 `behavior`、`invert` 为真和其他类型的 pre_tokenizer 返回错误。
+fn apply_pre_tokenizer(
+    config: &mut TokenizerConfig,
+    pre_tokenizer: &Value,
+    regex_exprs: &mut Vec<String>,
+) -> Result<(), LoadError> {
+    match pre_tokenizer["type"].as_str() {
+        Some("Sequence") => {
+            for pre_tokenizer in pre_tokenizer["pretokenizers"]
+                .as_array()
+                .into_iter()
+                .flatten()
+            {
+                apply_pre_tokenizer(config, pre_tokenizer, regex_exprs)?;
+            }
+        }
+        Some("Split") => {
+            let behavior = &pre_tokenizer["behavior"];
+            if !(behavior.is_null() || behavior == "Isolated") {
+                return Err(LoadError::Format(format!(
+                    "unsupported Split behavior {behavior}"
+                )));
+            }
+            if pre_tokenizer["invert"].as_bool() == Some(true) {
+                return Err(LoadError::Format("unsupported inverted Split".into()));
+            }
+            if let Some(regex) = pre_tokenizer["pattern"]["Regex"].as_str() {
+                // 构造分词器时才编译，这里先检查，无效的正则不能加载
+                try_compiled_regex(regex).map_err(|e| {
+                    LoadError::Format(format!("invalid Split regex {regex:?}: {e}"))
+                })?;
+                regex_exprs.push(regex.to_string());
+            } else if let Some(string) = pre_tokenizer["pattern"]["String"].as_str() {
+                regex_exprs.push(regex::escape(string));
+            } else {
+                return Err(LoadError::Format("Split without pattern".into()));
+            }
+        }
+        Some("ByteLevel") => {
+            // BPE 分词与 llama.cpp 相同，不在开头补空格
+            if pre_tokenizer["add_prefix_space"].as_bool() == Some(true) {
+                return Err(LoadError::Format(
+                    "unsupported ByteLevel with add_prefix_space".into(),
+                ));
+            }
+            if pre_tokenizer["use_regex"].as_bool().unwrap_or(true) {
+                regex_exprs.push(GPT2.to_string());
+            }
+            config.add_space_prefix = false;
+        }
+        Some("Digits") => {
+            // 不逐个拆分时连续的数字是一个片段
+            if pre_tokenizer["individual_digits"].as_bool() == Some(true) {
+                regex_exprs.push("\\p{N}".to_string());
+            } else {
+                regex_exprs.push("\\p{N}+".to_string());
+            }
+        }
+        Some("Metaspace") => {
+            // 按 ▁ 分割后标记不会跨越单词。词表中没有 ▁ 出现在开头以外的标记时，
+            // 不分割的结果相同，否则无法还原
+            if pre_tokenizer["split"].as_bool().unwrap_or(true)
+                && config
+                    .id_to_token
+                    .iter()
+                    .any(|token| token.text.chars().skip(1).any(|c| c == '\u{2581}'))
+            {
+                return Err(LoadError::Format("unsupported Metaspace with split".into()));
+            }
+            config.escape_whitespaces = true;
+            config.add_space_prefix = match pre_tokenizer["prepend_scheme"].as_str() {
+                Some(scheme) => scheme != "never",
+                None => pre_tokenizer["add_prefix_space"].as_bool().unwrap_or(true),
+            };
+        }
+        ty => {
+            return Err(LoadError::Format(format!(
+                "unsupported pre_tokenizer {}",
+                ty.unwrap_or("without type")
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// 根据 post_processor 的模板判断是否自动添加 BOS/EOS
+fn apply_post_processor(config: &mut TokenizerConfig, post_processor: &Value) {
+    match post_processor["type"].as_str() {
+        Some("Sequence") => {
+            for post_processor in post_processor["processors"]
+                .as_array()
+                .into_iter()
+                .flatten()
+            {
+                apply_post_processor(config, post_processor);
+            }
+        }
+        Some("TemplateProcessing") => {
+            let Some(single) = post_processor["single"].as_array() else {
+                return;
+            };
+            let special = |piece: Option<&Value>| {
+                piece
+                    .and_then(|piece| piece["SpecialToken"]["id"].as_str())
+                    .map(|text| config.text_to_token(text))
+            };
+            // 词表中没有的特殊标记不添加，否则分词结果中会出现 NULL
+            let first = special(single.first()).filter(|&id| id != NULL);
+            let last = special(single.last())
+                .filter(|&id| id != NULL)
+                .filter(|_| single.len() > 1);
+            config.add_bos = first.is_some();
+            config.add_eos = last.is_some();
+            if let Some(bos) = first
+                && config.bos == NULL
+            {
+                config.bos = bos;
+            }
+            if let Some(eos) = last
+                && config.eos == NULL
+            {
+                config.eos = eos;
+            }
+        }
+        Some("BertProcessing") | Some("RobertaProcessing") => {
+            let n_tokens = config.id_to_token.len() as u64;
+            let id = |key: &str| {
+                post_processor[key][1]
+                    .as_u64()
+                    .filter(|&id| id < n_tokens)
+                    .map_or(NULL, |id| id as TokenId)
+            };
+            config.bos = id("cls");
+            config.eos = id("sep");
+            config.sep = config.eos;
+            config.add_bos = config.bos != NULL;
+            config.add_eos = config.eos != NULL;
+        }
+        _ => {}
+    }
+}
+
+/// 读取 tokenizer_config.json 中的特殊标记与开关
+fn apply_tokenizer_config(config: &mut TokenizerConfig, tokenizer_config: &Value) {
+    // 特殊标记可能是字符串，也可能是带 content 字段的对象
+    let special = |key: &str| {
+        let value = &tokenizer_config[key];
+        value
+            .as_str()
+            .or_else(|| value["content"].as_str())
+            .map(|text| config.text_to_token(text))
+            .filter(|&id| id != NULL)
+    };
+    let bos = special("bos_token");
+    let eos = special("eos_token");
+    let unk = special("unk_token");
+    let sep = special("sep_token");
+    let pad = special("pad_token");
+    let mask = special("mask_token");
+    config.bos = bos.unwrap_or(config.bos);
+    config.eos = eos.unwrap_or(config.eos);
+    config.unk = unk.unwrap_or(config.unk);
+    config.sep = sep.unwrap_or(config.sep);
+    config.pad = pad.unwrap_or(config.pad);
+    config.mask = mask.unwrap_or(config.mask);
+
+    if let Some(add_bos) = tokenizer_config["add_bos_token"].as_bool() {
+        config.add_bos = add_bos;
+    }
+    if let Some(add_eos) = tokenizer_config["add_eos_token"].as_bool() {
+        config.add_eos = add_eos;
+    }
+    if let Some(clean_spaces) = tokenizer_config["clean_up_tokenization_spaces"].as_bool() {
+        config.clean_spaces = clean_spaces;
+    }
+}
+
+/// 将分词器写入目录下的 tokenizer.json 与 tokenizer_config.json
+pub fn save_hf(config: &TokenizerConfig, dir: impl AsRef<Path>) -> Result<(), LoadError> {
+    let dir = dir.as_ref();
+    let (tokenizer, tokenizer_config) = to_hf_json(config)?;
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("tokenizer.json"), tokenizer)?;
+    fs::write(dir.join("tokenizer_config.json"), tokenizer_config)?;
+    Ok(())
+}
+
+/// 将分词器导出为 tokenizer.json 与 tokenizer_config.json 的文本，可由 [`load_hf_json`] 重新加载
+///
+/// 只支持 BPE 和 SPM 词表。SPM 词表导出为带字节回退的 BPE 模型，
+/// 没有合并表时按 transformers 的 LlamaConverter 由分数生成。
+/// 未使用的标记导出为普通标记，文本重复的标记导出为特殊标记。
+pub fn to_hf_json(config: &TokenizerConfig) -> Result<(String, String), LoadError> {
+    let spm = match config.vocab_type {
+        VocabType::Bpe => false,
+        VocabType::Spm => true,
+        ty => return Err(LoadError::Format(format!("unsupported vocab type {ty:?}"))),
+    };
+    let text = |id: TokenId| {
+        config
+            .id_to_token
+            .get(id as usize)
+            .map(|token| token.text.as_str())
+    };
+
+    // 重复的文本只保留第一个 id，其余的通过 added_tokens 保留
+    let mut vocab = Map::new();
+    let mut added_tokens = Vec::new();
+    for (id, token) in config.id_to_token.iter().enumerate() {
+        let attribute = token.attribute;
+        let added = attribute.intersects(
+            TokenAttribute::Control | TokenAttribute::UserDefined | TokenAttribute::Unknown,
+        );
+        let duplicate = vocab.contains_key(token.text.as_str());
+        if !duplicate {
+            vocab.insert(token.text.to_string(), id.into());
+        }
+        if added || duplicate {
+            added_tokens.push(json!({
+                "id": id,
+                "content": token.text,
+                "single_word": attribute.contains(TokenAttribute::SingleWord),
+                "lstrip": attribute.contains(TokenAttribute::LStrIp),
+                "rstrip": attribute.contains(TokenAttribute::RStrIp),
+                "normalized": attribute.contains(TokenAttribute::Normalized),
+                "special": !attribute.contains(TokenAttribute::UserDefined),
+            }));
+        }
+    }
+
+    let merges = if config.bpe_ranks.is_empty() && spm {
+        spm_merges(config)
+    } else {
+        let mut merges = config.bpe_ranks.iter().collect::<Vec<_>>();
+        merges.sort_unstable_by_key(|&(_, rank)| rank);
+        merges
+            .into_iter()
+            .map(|(&(first, second), _)| {
+                let text = |id: TokenId| config.id_to_token[id as usize].text.to_string();
+                (text(first), text(second))
+            })
+            .collect()
+    };
+    let merges = merges
+        .into_iter()
+        .map(|(first, second)| json!([first, second]))
+        .collect::<Vec<_>>();
+
+    let (pre_tokenizer, decoder) = if spm {
+        // 与 LlamaConverter 一致，只在开头补空格
+        let prepend_scheme = if config.add_space_prefix {
+            "first"
+        } else {
+            "never"
+        };
+        let mut decoders = vec![
+            json!({ "type": "Replace", "pattern": { "String": "\u{2581}" }, "content": " " }),
+            json!({ "type": "ByteFallback" }),
+            json!({ "type": "Fuse" }),
+        ];
+        if config.add_space_prefix {
+            decoders.push(json!({ "type": "Strip", "content": " ", "start": 1, "stop": 0 }));
+        }
+        (
+            json!({ "type": "Metaspace", "replacement": "\u{2581}", "prepend_scheme": prepend_scheme, "split": false }),
+            json!({ "type": "Sequence", "decoders": decoders }),
+        )
+    } else {
+        let mut pretokenizers = config
+            .session
+            .borrow()
+            .regex_exprs()
+            .iter()
+            .map(|regex| json!({ "type": "Split", "pattern": { "Regex": regex }, "behavior": "Isolated", "invert": false }))
+            .collect::<Vec<_>>();
+        // BPE 分词不读取 add_space_prefix，总是不补空格
+        pretokenizers.push(json!({
+            "type": "ByteLevel",
+            "add_prefix_space": false,
+            "trim_offsets": false,
+            "use_regex": false,
+        }));
+        (
+            json!({ "type": "Sequence", "pretokenizers": pretokenizers }),
+            json!({ "type": "ByteLevel", "add_prefix_space": true, "trim_offsets": true, "use_regex": true }),
+        )
+    };
+
+    // 只添加词表中存在的 BOS/EOS
+    let bos = text(config.bos).filter(|_| config.add_bos);
+    let eos = text(config.eos).filter(|_| config.add_eos);
+    let special = |text: &str| json!({ "SpecialToken": { "id": text, "type_id": 0 } });
+    let sequence = |id: &str| json!({ "Sequence": { "id": id, "type_id": 0 } });
+    let template = |ids: &[&str]| {
+        let mut pieces = bos.map(special).into_iter().collect::<Vec<_>>();
+        for id in ids {
+            pieces.push(sequence(id));
+            pieces.extend(eos.map(special));
+        }
+        pieces
+    };
+    let special_tokens = bos
+        .into_iter()
+        .chain(eos)
+        .map(|text| {
+            (
+                text.to_string(),
+                json!({ "id": text, "ids": [config.text_to_token(text)], "tokens": [text] }),
+            )
+        })
+        .collect::<Map<_, _>>();
+    let post_processor = json!({
+        "type": "TemplateProcessing",
+        "single": template(&["A"]),
+        "pair": template(&["A", "B"]),
+        "special_tokens": special_tokens,
+    });
+
+    // 规范化步骤按原顺序写出，没有时为 null
+    let normalizers = config
+        .normalizers
+        .iter()
+        .map(|normalizer| {
+            let ty = match normalizer {
+                Normalizer::Form(NormalizationForm::Nfc) => "NFC",
+                Normalizer::Form(NormalizationForm::Nfd) => "NFD",
+                Normalizer::Form(NormalizationForm::Nfkc) => "NFKC",
+                Normalizer::Form(NormalizationForm::Nfkd) => "NFKD",
+                Normalizer::Lowercase => "Lowercase",
+                Normalizer::StripAccents => "StripAccents",
+            };
+            json!({ "type": ty })
+        })
+        .collect::<Vec<_>>();
+    let normalizer = match &normalizers[..] {
+        [] => Value::Null,
+        [normalizer] => normalizer.clone(),
+        _ => json!({ "type": "Sequence", "normalizers": normalizers }),
+    };
+
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": normalizer,
+        "pre_tokenizer": pre_tokenizer,
+        "post_processor": post_processor,
+        "decoder": decoder,
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": text(config.unk),
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": spm,
+            "byte_fallback": spm,
+            "ignore_merges": config.ignore_merges,
+            "vocab": vocab,
+            "merges": merges,
+        },
+    });
+
+    let mut tokenizer_config = Map::new();
+    tokenizer_config.insert("add_bos_token".into(), config.add_bos.into());
+    tokenizer_config.insert("add_eos_token".into(), config.add_eos.into());
+    for (key, id) in [
+        ("bos_token", config.bos),
+        ("eos_token", config.eos),
+        ("unk_token", config.unk),
+        ("sep_token", config.sep),
+        ("pad_token", config.pad),
+        ("mask_token", config.mask),
+    ] {
+        if let Some(text) = text(id) {
+            tokenizer_config.insert(key.into(), text.into());
+        }
+    }
+    tokenizer_config.insert(
+        "clean_up_tokenization_spaces".into(),
+        config.clean_spaces.into(),
+    );
+    tokenizer_config.insert("tokenizer_class".into(), "PreTrainedTokenizerFast".into());
+
+    Ok((
+        serde_json::to_string_pretty(&tokenizer)?,
+        serde_json::to_string_pretty(&tokenizer_config)?,
+    ))
+}
+
+/// 与 transformers 的 SentencePieceExtractor 一致，由分数生成 SPM 词表的合并表
+///
+/// 每个普通标记的所有两段拆分中，两段都在词表中的即为一条合并规则，按合并结果的分数从高到低排列。
+fn spm_merges(config: &TokenizerConfig) -> Vec<(String, String)> {
+    let mut merges = config
+        .id_to_token
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| token.attribute.contains(TokenAttribute::Normal))
+        .flat_map(|(id, token)| {
+            token
+                .text
+                .char_indices()
+                .skip(1)
+                .map(move |(i, _)| (token.score, id, token.text.split_at(i)))
+        })
+        .filter(|(.., (first, second))| {
+            config.token_to_id.contains_key(*first) && config.token_to_id.contains_key(*second)
+        })
+        .collect::<Vec<_>>();
+    merges.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    merges
+        .into_iter()
+        .map(|(.., (first, second))| (first.to_string(), second.to_string()))
+        .collect()
+}
@@ -0,0 +1,431 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde_json::Value;
+
+use crate::grammar::{Grammar, GrammarError};
+
+/// JSON Schema 无法转换的原因
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchemaError {
+    /// 不支持的关键字或写法
+    Unsupported(String),
+    /// `$ref` 不是本文档内能解析的 JSON 指针
+    InvalidRef(String),
+    /// 生成的语法无法解析
+    Grammar(GrammarError),
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported(what) => write!(f, "unsupported schema: {what}"),
+            Self::InvalidRef(reference) => write!(f, "cannot resolve $ref `{reference}`"),
+            Self::Grammar(e) => write!(f, "generated grammar is invalid: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// 会改变匹配范围但尚未支持的关键字，遇到时报错而不是忽略
+const UNSUPPORTED: &[&str] = &[
+    "allOf",
+    "not",
+    "if",
+    "pattern",
+    "patternProperties",
+    "prefixItems",
+    "format",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "multipleOf",
+    "uniqueItems",
+];
+
+/// 基本类型的规则及其依赖，与 llama.cpp 的 json-schema-to-grammar 相同
+const PRIMITIVES: &[(&str, &str, &[&str])] = &[
+    ("space", r#"| " " | "\n" [ \t]{0,20}"#, &[]),
+    ("boolean", r#"("true" | "false") space"#, &["space"]),
+    ("decimal-part", "[0-9]{1,16}", &[]),
+    ("integral-part", "[0] | [1-9] [0-9]{0,15}", &[]),
+    (
+        "number",
+        r#"("-"? integral-part) ("." decimal-part)? ([eE] [-+]? integral-part)? space"#,
+        &["integral-part", "decimal-part", "space"],
+    ),
+    (
+        "integer",
+        r#"("-"? integral-part) space"#,
+        &["integral-part", "space"],
+    ),
+    (
+        "value",
+        "object | array | string | number | boolean | null",
+        &["object", "array", "string", "number", "boolean", "null"],
+    ),
+    (
+        "object",
+        r#""{" space ( string ":" space value ("," space string ":" space value)* )? "}" space"#,
+        &["string", "value", "space"],
+    ),
+    (
+        "array",
+        r#""[" space ( value ("," space value)* )? "]" space"#,
+        &["value", "space"],
+    ),
+    (
+        "char",
+        r#"[^"\\\x7F\x00-\x1F] | [\\] (["\\bfnrt] | "u" [0-9a-fA-F]{4})"#,
+        &[],
+    ),
+    ("string", r#""\"" char* "\"" space"#, &["char", "space"]),
+    ("null", r#""null" space"#, &["space"]),
+];
+
+/// 将 JSON Schema 转换为 GBNF 语法，根规则为 `root`
+///
+/// 支持 `type`（含类型数组）、`properties`/`required`、`items`/`minItems`/`maxItems`、
+/// `minLength`/`maxLength`、`enum`、`const`、`anyOf`/`oneOf` 以及指向本文档的 `$ref`，
+/// 引用可以递归。对象的属性按属性名排序输出，未列出的属性只在没有 `properties` 时允许。
+/// 值之间可以有少量空白。
+pub fn json_schema_to_gbnf(schema: &Value) -> Result<String, SchemaError> {
+    let mut converter = Converter {
+        root: schema,
+        rules: Vec::new(),
+        names: HashMap::new(),
+        refs: HashMap::new(),
+    };
+    let root = converter.visit(schema, "root")?;
+    // 对象等复合类型已经生成了名为 root 的规则
+    if root != "root" {
+        converter.set_rule("root", root);
+    }
+    let mut gbnf = String::new();
+    for (name, body) in &converter.rules {
+        gbnf.push_str(&format!("{name} ::= {body}\n"));
+    }
+    Ok(gbnf)
+}
+
+impl Grammar {
+    /// 从 JSON Schema 构造语法约束，见 [`json_schema_to_gbnf`]
+    pub fn from_json_schema(schema: &Value) -> Result<Self, SchemaError> {
+        Self::parse(&json_schema_to_gbnf(schema)?, "root").map_err(SchemaError::Grammar)
+    }
+}
+
+struct Converter<'a> {
+    root: &'a Value,
+    /// 按生成顺序排列的规则
+    rules: Vec<(String, String)>,
+    names: HashMap<String, usize>,
+    /// `$ref` 到规则名的映射，先登记再展开以支持递归
+    refs: HashMap<String, String>,
+}
+
+impl<'a> Converter<'a> {
+    /// 添加规则，名称已被其他内容占用时加上序号，返回实际的名称
+    fn add_rule(&mut self, name: &str, body: String) -> String {
+        let mut unique = name.to_string();
+        let mut i = 1;
+        while let Some(&index) = self.names.get(&unique) {
+            if self.rules[index].1 == body {
+                return unique;
+            }
+            unique = format!("{name}-{i}");
+            i += 1;
+        }
+        self.set_rule(&unique, body);
+        unique
+    }
+
+    fn set_rule(&mut self, name: &str, body: String) {
+        match self.names.get(name) {
+            Some(&index) => self.rules[index].1 = body,
+            None => {
+                self.names.insert(name.into(), self.rules.len());
+                self.rules.push((name.into(), body));
+            }
+        }
+    }
+
+    /// 添加基本类型的规则及其依赖
+    fn primitive(&mut self, name: &str) -> String {
+        if !self.names.contains_key(name) {
+            let &(_, body, deps) = PRIMITIVES.iter().find(|(n, ..)| *n == name).unwrap();
+            self.set_rule(name, body.into());
+            for dep in deps {
+                self.primitive(dep);
+            }
+        }
+        name.into()
+    }
+
+    /// 转换一个子模式，返回可以写在规则体中的表达式
+    fn visit(&mut self, schema: &'a Value, name: &str) -> Result<String, SchemaError> {
+        let map = match schema {
+            Value::Bool(true) => return Ok(self.primitive("value")),
+            Value::Object(map) => map,
+            _ => return Err(SchemaError::Unsupported(format!("schema `{schema}`"))),
+        };
+        if let Some(key) = UNSUPPORTED.iter().find(|key| map.contains_key(**key)) {
+            return Err(SchemaError::Unsupported(format!("keyword `{key}`")));
+        }
+        if let Some(reference) = map.get("$ref") {
+            return self.reference(reference);
+        }
+        if let Some(value) = map.get("const") {
+            self.primitive("space");
+            return Ok(self.add_rule(name, format!("{} space", literal(&value.to_string()))));
+        }
+        if let Some(values) = map.get("enum") {
+            let Some(values) = values.as_array().filter(|values| !values.is_empty()) else {
+                return Err(SchemaError::Unsupported("empty or non-array `enum`".into()));
+            };
+            self.primitive("space");
+            let alternatives = values
+                .iter()
+                .map(|value| literal(&value.to_string()))
+                .collect::<Vec<_>>();
+            return Ok(self.add_rule(name, format!("({}) space", alternatives.join(" | "))));
+        }
+        if let Some(alternatives) = map.get("anyOf").or_else(|| map.get("oneOf")) {
+            let Some(alternatives) = alternatives.as_array().filter(|a| !a.is_empty()) else {
+                return Err(SchemaError::Unsupported(
+                    "empty or non-array `anyOf`".into(),
+                ));
+            };
+            let alternatives = alternatives
+                .iter()
+                .enumerate()
+                .map(|(i, alternative)| self.visit(alternative, &format!("{name}-{i}")))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(self.add_rule(name, alternatives.join(" | ")));
+        }
+        match map.get("type") {
+            Some(Value::String(ty)) => self.typed(ty, map, name),
+            Some(Value::Array(types)) if !types.is_empty() => {
+                let mut alternatives = Vec::new();
+                for ty in types {
+                    let Value::String(ty) = ty else {
+                        return Err(SchemaError::Unsupported(format!("type `{ty}`")));
+                    };
+                    alternatives.push(self.typed(ty, map, &format!("{name}-{ty}"))?);
+                }
+                Ok(self.add_rule(name, alternatives.join(" | ")))
+            }
+            Some(ty) => Err(SchemaError::Unsupported(format!("type `{ty}`"))),
+            None if map.contains_key("properties") => self.typed("object", map, name),
+            None => Ok(self.primitive("value")),
+        }
+    }
+
+    fn typed(
+        &mut self,
+        ty: &str,
+        map: &'a serde_json::Map<String, Value>,
+        name: &str,
+    ) -> Result<String, SchemaError> {
+        match ty {
+            "object" => self.object(map, name),
+            "array" => self.array(map, name),
+            "string" if map.contains_key("minLength") || map.contains_key("maxLength") => {
+                let min = usize_keyword(map, "minLength")?.unwrap_or(0);
+                let max = usize_keyword(map, "maxLength")?;
+                self.primitive("char");
+                self.primitive("space");
+                Ok(self.add_rule(
+                    name,
+                    format!(r#""\"" char{} "\"" space"#, repetition(min, max)?),
+                ))
+            }
+            "string" | "number" | "integer" | "boolean" | "null" => Ok(self.primitive(ty)),
+            _ => Err(SchemaError::Unsupported(format!("type `{ty}`"))),
+        }
+    }
+
+    fn object(
+        &mut self,
+        map: &'a serde_json::Map<String, Value>,
+        name: &str,
+    ) -> Result<String, SchemaError> {
+        let properties = match map.get("properties") {
+            Some(Value::Object(properties)) => Some(properties),
+            Some(_) => return Err(SchemaError::Unsupported("non-object `properties`".into())),
+            None => None,
+        };
+        let additional = match map.get("additionalProperties") {
+            None | Some(Value::Bool(false)) => false,
+            Some(Value::Bool(true)) => true,
+            Some(Value::Object(schema)) if schema.is_empty() => true,
+            Some(_) => {
+                return Err(SchemaError::Unsupported(
+                    "schema in `additionalProperties`".into(),
+                ));
+            }
+        };
+        let Some(properties) = properties.filter(|properties| !properties.is_empty()) else {
+            // 没有列出属性时，除非明确禁止，任意对象都可以
+            return if additional || !map.contains_key("additionalProperties") {
+                Ok(self.primitive("object"))
+            } else {
+                self.primitive("space");
+                Ok(self.add_rule(name, r#""{" space "}" space"#.into()))
+            };
+        };
+        if additional {
+            return Err(SchemaError::Unsupported(
+                "`additionalProperties` together with `properties`".into(),
+            ));
+        }
+        let required = match map.get("required") {
+            Some(Value::Array(required)) => required
+                .iter()
+                .map(|key| {
+                    key.as_str()
+                        .ok_or_else(|| SchemaError::Unsupported(format!("required `{key}`")))
+                })
+                .collect::<Result<BTreeSet<_>, _>>()?,
+            Some(_) => return Err(SchemaError::Unsupported("non-array `required`".into())),
+            None => BTreeSet::new(),
+        };
+        if let Some(key) = required.iter().find(|key| !properties.contains_key(**key)) {
+            return Err(SchemaError::Unsupported(format!(
+                "required property `{key}` without schema"
+            )));
+        }
+
+        self.primitive("space");
+        let mut members = Vec::new();
+        for (key, schema) in properties {
+            let value = self.visit(schema, &format!("{name}-{}", sanitize(key)))?;
+            let kv = format!(
+                r#"{} space ":" space {value}"#,
+                literal(&Value::from(key.as_str()).to_string())
+            );
+            members.push((kv, required.contains(key.as_str())));
+        }
+        // tails[i]：已有前面的属性时，第 i 个及之后的属性，每个前面都有逗号
+        let mut tails = vec![String::new(); members.len() + 1];
+        for (i, (kv, required)) in members.iter().enumerate().rev() {
+            let item = if *required {
+                format!(r#""," space {kv}"#)
+            } else {
+                format!(r#"("," space {kv})?"#)
+            };
+            tails[i] = format!("{item} {}", tails[i + 1]).trim_end().into();
+        }
+        // head：还没有属性时，第 i 个及之后的属性；可选属性可以跳过，为其生成单独的规则
+        let mut head = String::new();
+        for (i, (kv, required)) in members.iter().enumerate().rev() {
+            let present = format!("{kv} {}", tails[i + 1]).trim_end().to_string();
+            head = if *required {
+                present
+            } else {
+                // 空的备选写在前面，否则 `|` 之后的换行会接上下一条规则
+                let body = if head.is_empty() {
+                    format!("| {present}")
+                } else {
+                    format!("{present} | {head}")
+                };
+                self.add_rule(&format!("{name}-rest-{i}"), body)
+            };
+        }
+        Ok(self.add_rule(name, format!(r#""{{" space {head} "}}" space"#)))
+    }
+
+    fn array(
+        &mut self,
+        map: &'a serde_json::Map<String, Value>,
+        name: &str,
+    ) -> Result<String, SchemaError> {
+        let min = usize_keyword(map, "minItems")?.unwrap_or(0);
+        let max = usize_keyword(map, "maxItems")?;
+        self.primitive("space");
+        if max == Some(0) {
+            return Ok(self.add_rule(name, r#""[" space "]" space"#.into()));
+        }
+        let item = match map.get("items") {
+            Some(items) => self.visit(items, &format!("{name}-item"))?,
+            None => self.primitive("value"),
+        };
+        let rest = repetition(min.max(1) - 1, max.map(|max| max - 1))?;
+        let mut list = format!(r#"{item} ("," space {item}){rest}"#);
+        if min == 0 {
+            list = format!("({list})?");
+        }
+        Ok(self.add_rule(name, format!(r#""[" space {list} "]" space"#)))
+    }
+
+    /// 解析 `#` 开头的 JSON 指针，每个目标只生成一条规则
+    fn reference(&mut self, reference: &Value) -> Result<String, SchemaError> {
+        let Value::String(reference) = reference else {
+            return Err(SchemaError::InvalidRef(reference.to_string()));
+        };
+        if let Some(name) = self.refs.get(reference) {
+            return Ok(name.clone());
+        }
+        let target = reference
+            .strip_prefix('#')
+            .and_then(|pointer| self.root.pointer(pointer))
+            .ok_or_else(|| SchemaError::InvalidRef(reference.clone()))?;
+        let base = format!("ref{}", sanitize(&reference[1..]));
+        let name = self.add_rule(&base, String::new());
+        self.refs.insert(reference.clone(), name.clone());
+        let body = self.visit(target, &format!("{name}-body"))?;
+        self.set_rule(&name, body);
+        Ok(name)
+    }
+}
+
+/// 非负整数关键字
+fn usize_keyword(
+    map: &serde_json::Map<String, Value>,
+    key: &str,
+) -> Result<Option<usize>, SchemaError> {
+    match map.get(key) {
+        None => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(|n| Some(n as usize))
+            .ok_or_else(|| SchemaError::Unsupported(format!("`{key}` {value}"))),
+    }
+}
+
+/// GBNF 的重复次数 `{min,max}`
+fn repetition(min: usize, max: Option<usize>) -> Result<String, SchemaError> {
+    match max {
+        Some(max) if max < min => Err(SchemaError::Unsupported(format!(
+            "maximum {max} less than minimum {min}"
+        ))),
+        Some(max) => Ok(format!("{{{min},{max}}}")),
+        None => Ok(format!("{{{min},}}")),
+    }
+}
+
+/// 规则名只能包含字母、数字和 `-`
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+/// GBNF 字符串字面量
+fn literal(text: &str) -> String {
+    let mut escaped = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str(r#"\""#),
+            '\\' => escaped.push_str(r"\\"),
+            '\n' => escaped.push_str(r"\n"),
+            '\r' => escaped.push_str(r"\r"),
+            '\t' => escaped.push_str(r"\t"),
+            c if c.is_control() => escaped.push_str(&format!(r"\x{:02X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
@@ -0,0 +1,46 @@
+pub mod cache;
+pub mod chunk;
+pub mod common;
+pub mod config;
+pub mod encode;
+pub mod gguf;
+pub mod grammar;
+pub mod healing;
+pub mod hf;
+pub mod json_schema;
+pub mod prefix;
+pub mod regex_index;
+pub mod sentencepiece;
+pub mod session;
+pub mod stop;
+pub mod tiktoken;
+mod ugm;
+pub mod unicode;
+mod unicode_data;
+pub mod untils;
+pub mod vocab;
+
+pub use cache::{
+    CACHE_VERSION, CacheView, gguf_tokenizer_key, load_cached, save_cache, to_cache_bytes,
+};
+pub use chunk::{Chunk, ChunkError, ChunkOptions};
+pub use common::{NULL, Piece, TokenAttribute, TokenData, TokenId};
+pub use config::{
+    LoadError, TextFragment, TokenizerConfig, VocabType, load, load_shared, try_load,
+    try_load_shared,
+};
+pub use encode::{EncodeError, EncodeOptions, Encoding, Padding, PaddingSide, Truncation};
+pub use gguf::{
+    GGufEdit, edit_gguf, edit_gguf_bytes, load_gguf_bytes, load_gguf_reader, load_gguf_split,
+    read_gguf_metadata, vocab_only_gguf, write_vocab_only,
+};
+pub use grammar::{Grammar, GrammarError};
+pub use healing::TokenHealing;
+pub use hf::{load_hf, load_hf_json, save_hf, to_hf_json};
+pub use json_schema::{SchemaError, json_schema_to_gbnf};
+pub use prefix::PrefixIndex;
+pub use regex_index::{RegexIndex, RegexIndexError};
+pub use sentencepiece::{load_sentencepiece, load_sentencepiece_bytes};
+pub use stop::{StopMatch, StopMatcher, StopReason, StopStep};
+pub use tiktoken::{load_tiktoken, load_tiktoken_str};
+pub use unicode::{NormalizationForm, NormalizedText, Normalizer};
@@ -1,76 +1,406 @@
-#![feature(linked_list_cursors)]
-use common::{NULL, TokenAttribute};
-use config::{VocabType, load};
-
 use memmap2::Mmap;
-use untils::llama_escape_whitespace;
-
-use std::collections::LinkedList;
-
-use std::fs::File;
-
-mod common;
-mod config;
-mod session;
-mod unicode;
-mod untils;
-fn main() {
-    let prompt = "Hello my name is";
-    let path = std::env::args_os().nth(1).unwrap();
-    let file = File::open(path).unwrap();
-    let file = unsafe { Mmap::map(&file) }.unwrap();
-
-    let config = load(file);
-    let tmp = config.tokenize(prompt, true, true);
-    print!("test {:?}", tmp);
-    // println!("{}", gguf.general_architecture().unwrap())
+use serde_json::{Value, json};
+use try_tokenize::{
+    GGufEdit, NULL, TokenAttribute, TokenId, TokenizerConfig, VocabType, edit_gguf, load_hf,
+    load_sentencepiece, try_load,
+};
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+const USAGE: &str = "\
+用法: try-tokenize <命令> <分词器> [选项] [输入...]
+
+分词器可以是 GGUF 文件、HF 仓库目录或 tokenizer.json、SentencePiece .model 文件。
+
+命令:
+  tokenize    分词，输出标记 id
+  detokenize  将标记 id 还原为文本
+  count       输出分词得到的标记数
+  inspect     输出词表类型、特殊标记、开关和大小
+  edit        修改 GGUF 中分词器的元数据，写入 --output 后按 inspect 输出结果
+
+输入来自命令行参数，没有参数时从 --file 指定的文件或标准输入读取。
+
+选项:
+  --file <路径>      从文件读取输入
+  --no-bos           不自动添加 BOS/EOS（tokenize、count）
+  --parse-special    匹配文本中的控制标记（tokenize、count）
+  --skip-special     去除 BOS/EOS，不输出控制标记（detokenize）
+  --json             以 JSON 格式输出
+  --output <路径>    修改后的 GGUF 文件，可以与原文件相同（edit）
+  --set-id <名称=id> 设置特殊标记，如 eos=2、eot=32007，可以重复（edit）
+  --add-bos <开关>   设置 add_bos_token，true 或 false（edit）
+  --add-eos <开关>   设置 add_eos_token，true 或 false（edit）
+  --chat-template <路径>  用文件的内容替换 tokenizer.chat_template（edit）
+  --pre <名称>       设置 tokenizer.ggml.pre（edit）
+  -h, --help         显示帮助";
+
+/// 命令行错误，用法错误与运行错误使用不同的退出码
+enum Error {
+    Usage(String),
+    Runtime(String),
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-enum FragmentBufferVariantType {
-    Token,
-    RawText,
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Tokenize,
+    Detokenize,
+    Count,
+    Inspect,
+    Edit,
 }
 
-#[derive(Debug, Clone)]
-struct FragmentBufferVariant {
-    variant_type: FragmentBufferVariantType,
-    token: u32, // 假设 llama_token 是 i32 类型
-    raw_text: String,
-    offset: u64,
-    length: u64,
+struct Args {
+    command: Command,
+    tokenizer: PathBuf,
+    file: Option<PathBuf>,
+    inputs: Vec<String>,
+    add_special: bool,
+    parse_special: bool,
+    skip_special: bool,
+    json: bool,
+    output: Option<PathBuf>,
+    edit: GGufEdit,
 }
-impl FragmentBufferVariant {
-    // 创建 Token 类型的变体
-    fn new_token(token: u32) -> Self {
-        Self {
-            variant_type: FragmentBufferVariantType::Token,
-            token,
-            raw_text: String::new(),
-            offset: 0,
-            length: 0,
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+        println!("{USAGE}");
+        return if args.is_empty() {
+            ExitCode::from(2)
+        } else {
+            ExitCode::SUCCESS
+        };
+    }
+    match parse_args(args).and_then(run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Usage(msg)) => {
+            eprintln!("error: {msg}\n\n{USAGE}");
+            ExitCode::from(2)
+        }
+        Err(Error::Runtime(msg)) => {
+            eprintln!("error: {msg}");
+            ExitCode::FAILURE
         }
     }
+}
 
-    // 创建 RawText 类型的变体
-    fn new_raw_text(text: String, offset: i64, length: i64) -> Result<Self, &'static str> {
-        // 参数验证
-        if offset < 0 {
-            return Err("offset must be non-negative");
+fn parse_args(args: Vec<String>) -> Result<Args, Error> {
+    let mut args = args.into_iter();
+    let command = match args.next().as_deref() {
+        Some("tokenize") => Command::Tokenize,
+        Some("detokenize") => Command::Detokenize,
+        Some("count") => Command::Count,
+        Some("inspect") => Command::Inspect,
+        Some("edit") => Command::Edit,
+        Some(command) => return Err(Error::Usage(format!("unknown command `{command}`"))),
+        None => return Err(Error::Usage("missing command".into())),
+    };
+    let mut tokenizer = None;
+    let mut parsed = Args {
+        command,
+        tokenizer: PathBuf::new(),
+        file: None,
+        inputs: Vec::new(),
+        add_special: true,
+        parse_special: false,
+        skip_special: false,
+        json: false,
+        output: None,
+        edit: GGufEdit::default(),
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--file" => match args.next() {
+                Some(path) => parsed.file = Some(path.into()),
+                None => return Err(Error::Usage("`--file` requires a path".into())),
+            },
+            "--no-bos" => parsed.add_special = false,
+            "--parse-special" => parsed.parse_special = true,
+            "--skip-special" => parsed.skip_special = true,
+            "--json" => parsed.json = true,
+            "--output" => parsed.output = Some(value(&mut args, &arg)?.into()),
+            "--set-id" => {
+                let value = value(&mut args, &arg)?;
+                let (name, id) = value
+                    .split_once('=')
+                    .and_then(|(name, id)| Some((name, id.parse().ok()?)))
+                    .ok_or_else(|| Error::Usage(format!("invalid `--set-id {value}`")))?;
+                parsed.edit.special_ids.insert(name.into(), id);
+            }
+            "--add-bos" => parsed.edit.add_bos = Some(flag(&value(&mut args, &arg)?)?),
+            "--add-eos" => parsed.edit.add_eos = Some(flag(&value(&mut args, &arg)?)?),
+            "--chat-template" => {
+                let path = value(&mut args, &arg)?;
+                let template = fs::read_to_string(&path)
+                    .map_err(|e| Error::Runtime(format!("{path}: {e}")))?;
+                parsed.edit.chat_template = Some(template);
+            }
+            "--pre" => parsed.edit.pre = Some(value(&mut args, &arg)?),
+            // 单独的 `--` 之后都是输入，可以以 `-` 开头
+            "--" => parsed.inputs.extend(args.by_ref()),
+            option if option.starts_with("--") => {
+                return Err(Error::Usage(format!("unknown option `{option}`")));
+            }
+            _ if tokenizer.is_none() => tokenizer = Some(PathBuf::from(arg)),
+            _ => parsed.inputs.push(arg),
         }
-        if length < 1 {
-            return Err("length must be positive");
+    }
+    parsed.tokenizer = tokenizer.ok_or_else(|| Error::Usage("missing tokenizer path".into()))?;
+    let has_input = parsed.file.is_some() || !parsed.inputs.is_empty();
+    match command {
+        Command::Inspect if has_input => {
+            return Err(Error::Usage("`inspect` takes no input".into()));
+        }
+        Command::Edit if has_input => return Err(Error::Usage("`edit` takes no input".into())),
+        Command::Edit if parsed.output.is_none() => {
+            return Err(Error::Usage("`edit` requires `--output`".into()));
         }
-        if (offset + length) as usize > text.len() {
-            return Err("offset + length exceeds text length");
+        Command::Edit => {}
+        _ if parsed.output.is_some() || parsed.edit != GGufEdit::default() => {
+            return Err(Error::Usage(
+                "edit options are only valid for `edit`".into(),
+            ));
         }
+        _ => {}
+    }
+    if parsed.file.is_some() && !parsed.inputs.is_empty() {
+        return Err(Error::Usage(
+            "input is given both as arguments and `--file`".into(),
+        ));
+    }
+    Ok(parsed)
+}
+
+/// 选项的值
+fn value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, Error> {
+    args.next()
+        .ok_or_else(|| Error::Usage(format!("`{option}` requires a value")))
+}
+
+fn flag(value: &str) -> Result<bool, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::Usage(format!("expected `true` or `false`, found `{value}`")))
+}
 
-        Ok(Self {
-            variant_type: FragmentBufferVariantType::RawText,
-            token: NULL,
-            raw_text: text,
-            offset: offset as u64,
-            length: length as u64,
+fn run(args: Args) -> Result<(), Error> {
+    let config = match (&args.command, &args.output) {
+        (Command::Edit, Some(output)) => edit_gguf(&args.tokenizer, output, &args.edit)
+            .map_err(|e| Error::Runtime(format!("{}: {e}", args.tokenizer.display())))?,
+        _ => load_tokenizer(&args.tokenizer)?,
+    };
+    let output = match args.command {
+        Command::Tokenize => {
+            let text = read_input(&args)?;
+            let ids = config.tokenize(&text, args.add_special, args.parse_special);
+            if args.json {
+                json!({ "ids": ids }).to_string()
+            } else {
+                join(&ids)
+            }
+        }
+        Command::Detokenize => {
+            let ids = parse_ids(&read_input(&args)?)?;
+            if let Some(&id) = ids.iter().find(|&&id| id >= config.n_tokens()) {
+                return Err(Error::Runtime(format!(
+                    "token id {id} out of range, vocab size is {}",
+                    config.n_tokens()
+                )));
+            }
+            let text = config.detokenize(&ids, args.skip_special, !args.skip_special);
+            if args.json {
+                json!({ "text": text }).to_string()
+            } else {
+                text
+            }
+        }
+        Command::Count => {
+            let text = read_input(&args)?;
+            let count = config.count_tokens(&text, args.add_special, args.parse_special);
+            if args.json {
+                json!({ "count": count }).to_string()
+            } else {
+                count.to_string()
+            }
+        }
+        Command::Inspect | Command::Edit => {
+            let info = inspect(&config);
+            if args.json {
+                serde_json::to_string_pretty(&info).unwrap()
+            } else {
+                plain(&info, "")
+            }
+        }
+    };
+    // 输出被管道提前关闭（如 `| head`）不算错误
+    match writeln!(io::stdout().lock(), "{output}") {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+            Err(Error::Runtime(format!("stdout: {e}")))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// 目录或 .json 文件按 HF 格式加载，.model 按 SentencePiece 加载，其他按 GGUF 加载
+fn load_tokenizer(path: &Path) -> Result<TokenizerConfig, Error> {
+    let error = |e: &dyn std::fmt::Display| Error::Runtime(format!("{}: {e}", path.display()));
+    if path.is_dir() || path.extension().is_some_and(|ext| ext == "json") {
+        load_hf(path).map_err(|e| error(&e))
+    } else if path.extension().is_some_and(|ext| ext == "model") {
+        load_sentencepiece(path).map_err(|e| error(&e))
+    } else {
+        let file = File::open(path).map_err(|e| error(&e))?;
+        let mmap = unsafe { Mmap::map(&file) }.map_err(|e| error(&e))?;
+        try_load(mmap).map_err(|e| error(&e))
+    }
+}
+
+/// 命令行参数以空格连接，没有参数时读取文件或标准输入
+fn read_input(args: &Args) -> Result<String, Error> {
+    if !args.inputs.is_empty() {
+        return Ok(args.inputs.join(" "));
+    }
+    match &args.file {
+        Some(path) => {
+            fs::read_to_string(path).map_err(|e| Error::Runtime(format!("{}: {e}", path.display())))
+        }
+        None => {
+            let mut text = String::new();
+            io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|e| Error::Runtime(format!("stdin: {e}")))?;
+            Ok(text)
+        }
+    }
+}
+
+/// 标记 id 以空白或逗号分隔，也可以是 JSON 数组或 `tokenize --json` 的输出
+fn parse_ids(input: &str) -> Result<Vec<TokenId>, Error> {
+    let input = input.trim();
+    if input.starts_with('[') || input.starts_with('{') {
+        let value = serde_json::from_str::<Value>(input)
+            .map_err(|e| Error::Runtime(format!("invalid json: {e}")))?;
+        let ids = value.get("ids").unwrap_or(&value);
+        return ids
+            .as_array()
+            .ok_or_else(|| Error::Runtime("expected an array of token ids".into()))?
+            .iter()
+            .map(|id| {
+                id.as_u64()
+                    .and_then(|id| TokenId::try_from(id).ok())
+                    .ok_or_else(|| Error::Runtime(format!("invalid token id `{id}`")))
+            })
+            .collect();
+    }
+    input
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse()
+                .map_err(|_| Error::Runtime(format!("invalid token id `{id}`")))
         })
+        .collect()
+}
+
+fn join(ids: &[TokenId]) -> String {
+    ids.iter()
+        .map(TokenId::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 词表类型、特殊标记、开关和大小
+fn inspect(config: &TokenizerConfig) -> Value {
+    let special = |id: TokenId| {
+        if id == NULL {
+            Value::Null
+        } else {
+            let text = config.id_to_token.get(id as usize).map(|token| &token.text);
+            json!({ "id": id, "text": text })
+        }
+    };
+    let count = |attribute: TokenAttribute| config.tokens_with(attribute).count();
+    json!({
+        "vocab_type": format!("{:?}", config.vocab_type),
+        "sizes": {
+            "tokens": config.n_tokens(),
+            "merges": config.bpe_ranks.len(),
+            "special_tokens": config.special_tokens.len(),
+            "normal": count(TokenAttribute::Normal),
+            "control": count(TokenAttribute::Control),
+            "user_defined": count(TokenAttribute::UserDefined),
+            "byte": count(TokenAttribute::Byte),
+            "unknown": count(TokenAttribute::Unknown),
+            "unused": count(TokenAttribute::Unused),
+        },
+        "special": {
+            "bos": special(config.bos),
+            "eos": special(config.eos),
+            "eot": special(config.eot),
+            "eom": special(config.eom),
+            "unk": special(config.unk),
+            "sep": special(config.sep),
+            "pad": special(config.pad),
+            "mask": special(config.mask),
+            "linefeed": special(config.linefeed),
+            "fim_pre": special(config.fim_pre),
+            "fim_suf": special(config.fim_suf),
+            "fim_mid": special(config.fim_mid),
+            "fim_pad": special(config.fim_pad),
+            "fim_rep": special(config.fim_rep),
+            "fim_sep": special(config.fim_sep),
+        },
+        "flags": {
+            "add_bos": config.add_bos,
+            "add_eos": config.add_eos,
+            "add_space_prefix": config.add_space_prefix,
+            "ignore_merges": config.ignore_merges,
+            "clean_spaces": config.clean_spaces,
+            "remove_extra_whitespaces": config.remove_extra_whitespaces,
+            "escape_whitespaces": config.escape_whitespaces,
+            "treat_whitespace_as_suffix": config.treat_whitespace_as_suffix,
+        },
+        // 只有 BPE 词表使用预分词正则
+        "regex_exprs": match config.vocab_type {
+            VocabType::Bpe => config.session.borrow().regex_exprs().to_vec(),
+            _ => Vec::new(),
+        },
+    })
+}
+
+/// 将 [`inspect`] 的结果按 `key: value` 逐行输出，嵌套的对象缩进
+fn plain(value: &Value, indent: &str) -> String {
+    let Value::Object(map) = value else {
+        return String::new();
+    };
+    let mut lines = Vec::new();
+    for (key, value) in map {
+        match value {
+            Value::Object(object) if object.contains_key("id") => {
+                lines.push(format!(
+                    "{indent}{key}: {} {}",
+                    object["id"], object["text"]
+                ));
+            }
+            Value::Object(_) => {
+                lines.push(format!("{indent}{key}:"));
+                lines.push(plain(value, &format!("{indent}  ")));
+            }
+            Value::Array(items) => {
+                lines.push(format!("{indent}{key}:"));
+                lines.extend(items.iter().map(|item| format!("{indent}  {item}")));
+            }
+            Value::Null => lines.push(format!("{indent}{key}: -")),
+            Value::String(text) => lines.push(format!("{indent}{key}: {text}")),
+            _ => lines.push(format!("{indent}{key}: {value}")),
+        }
     }
+    lines.join("\n")
 }
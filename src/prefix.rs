@@ -0,0 +1,87 @@
+use std::ops::Range;
+
+use crate::{common::TokenId, config::TokenizerConfig};
+
+/// 按还原后的字节排序的标记索引，用于前缀查询
+///
+/// 只包含 [`token_to_piece`](TokenizerConfig::token_to_piece) 不为空的标记，控制标记不在其中。
+/// 字节相同的多个标记按 id 排列。
+#[derive(Clone, Debug, Default)]
+pub struct PrefixIndex {
+    entries: Vec<(Vec<u8>, TokenId)>,
+}
+
+impl PrefixIndex {
+    /// 遍历词表构造索引
+    pub fn new(config: &TokenizerConfig) -> Self {
+        let mut entries = config
+            .vocab()
+            .map(|(id, _)| (config.token_to_piece(id, 0, false), id))
+            .filter(|(piece, _)| !piece.is_empty())
+            .collect::<Vec<_>>();
+        entries.sort_unstable();
+        Self { entries }
+    }
+
+    /// 索引中的标记数
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 按字节顺序遍历所有标记
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&[u8], TokenId)> {
+        self.entries
+            .iter()
+            .map(|(piece, id)| (piece.as_slice(), *id))
+    }
+
+    /// 以 `prefix` 开头的标记，按字节顺序排列
+    pub fn starting_with(&self, prefix: &[u8]) -> impl ExactSizeIterator<Item = (&[u8], TokenId)> {
+        let range = self.range(0..self.entries.len(), prefix);
+        self.entries[range]
+            .iter()
+            .map(|(piece, id)| (piece.as_slice(), *id))
+    }
+
+    /// 是 `text` 前缀的标记，按长度从短到长排列
+    pub fn prefixes_of(&self, text: &[u8]) -> Vec<(&[u8], TokenId)> {
+        let mut result = Vec::new();
+        // 以更长前缀开头的标记总在以较短前缀开头的标记之中，逐步缩小范围
+        let mut range = 0..self.entries.len();
+        for len in 1..=text.len() {
+            range = self.range(range, &text[..len]);
+            if range.is_empty() {
+                break;
+            }
+            // 与前缀完全相同的标记排在范围最前面
+            result.extend(
+                self.entries[range.clone()]
+                    .iter()
+                    .take_while(|(piece, _)| piece.len() == len)
+                    .map(|(piece, id)| (piece.as_slice(), *id)),
+            );
+        }
+        result
+    }
+
+    /// `range` 中以 `prefix` 开头的标记的范围
+    fn range(&self, range: Range<usize>, prefix: &[u8]) -> Range<usize> {
+        let entries = &self.entries[range.clone()];
+        let start = entries.partition_point(|(piece, _)| piece.as_slice() < prefix);
+        let end = start + entries[start..].partition_point(|(piece, _)| piece.starts_with(prefix));
+        range.start + start..range.start + end
+    }
+}
+
+impl TokenizerConfig {
+    /// 词表的前缀索引，第一次调用时构造
+    ///
+    /// 构造之后再修改词表不会更新索引。
+    pub fn prefix_index(&self) -> &PrefixIndex {
+        self.prefix_index.get_or_init(|| PrefixIndex::new(self))
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{HashMap, VecDeque};
+
+use rayon::prelude::*;
+use regex_automata::{
+    Anchored, MatchKind,
+    dfa::{Automaton, StartKind, dense},
+    util::{primitives::StateID, start},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{common::TokenId, config::TokenizerConfig};
+
+/// 构造正则索引失败的原因
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegexIndexError {
+    /// 正则表达式无法编译为字节 DFA，例如语法错误、使用了 Unicode 单词边界或超出大小限制
+    Build(String),
+}
+
+impl std::fmt::Display for RegexIndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Build(msg) => write!(f, "failed to build regex DFA: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for RegexIndexError {}
+
+/// 正则约束的标记索引
+///
+/// 正则表达式编译为字节 DFA，只保留从起点可达且还能到达匹配的状态，重新编号为 `0..n_states`。
+/// 对每个状态预先算出还原后的字节不会使 DFA 失配的标记和接受后的状态，解码时逐步查表即可。
+/// 整个文本必须从头匹配正则表达式，匹配完整的状态允许结束生成的标记，接受后状态不变。
+///
+/// 索引与构造时的词表绑定，可以用 serde 序列化后复用。
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegexIndex {
+    pattern: String,
+    n_tokens: u32,
+    /// 每个状态允许的标记，按 id 排序
+    tokens: Vec<Vec<TokenId>>,
+    /// 与 `tokens` 一一对应，接受标记后的状态
+    next: Vec<Vec<u32>>,
+    /// 每个状态是否匹配完整
+    accepting: Vec<bool>,
+}
+
+impl RegexIndex {
+    /// 为 `config` 的词表构造 `pattern` 的索引，按状态并行计算
+    ///
+    /// 标记按 [`token_to_piece`](TokenizerConfig::token_to_piece) 还原的字节匹配，
+    /// 多字节字符可以跨越多个标记，还原为空的标记不允许。
+    pub fn new(config: &TokenizerConfig, pattern: &str) -> Result<Self, RegexIndexError> {
+        let dfa = dense::Builder::new()
+            .configure(
+                dense::DFA::config()
+                    .start_kind(StartKind::Anchored)
+                    .match_kind(MatchKind::All),
+            )
+            .build(pattern)
+            .map_err(|e| RegexIndexError::Build(e.to_string()))?;
+        let start = dfa
+            .start_state(&start::Config::new().anchored(Anchored::Yes))
+            .map_err(|e| RegexIndexError::Build(e.to_string()))?;
+
+        // 从起点遍历可达的状态，记录反向的边
+        let mut order = vec![start];
+        let mut seen = HashMap::from([(start, 0)]);
+        let mut reverse = vec![Vec::new()];
+        let mut i = 0;
+        while i < order.len() {
+            let state = order[i];
+            for byte in 0..=255 {
+                let next = dfa.next_state(state, byte);
+                if dfa.is_dead_state(next) || dfa.is_quit_state(next) {
+                    continue;
+                }
+                let j = *seen.entry(next).or_insert_with(|| {
+                    order.push(next);
+                    reverse.push(Vec::new());
+                    order.len() - 1
+                });
+                reverse[j].push(i);
+            }
+            i += 1;
+        }
+        let accepting = order
+            .iter()
+            .map(|&state| dfa.is_match_state(dfa.next_eoi_state(state)))
+            .collect::<Vec<_>>();
+
+        // 能到达匹配的状态才保留，起点总是保留为 0 号状态
+        let mut alive = accepting.clone();
+        let mut queue = (0..order.len())
+            .filter(|&i| alive[i])
+            .collect::<VecDeque<_>>();
+        while let Some(i) = queue.pop_front() {
+            for &j in &reverse[i] {
+                if !alive[j] {
+                    alive[j] = true;
+                    queue.push_back(j);
+                }
+            }
+        }
+        let kept = (0..order.len())
+            .filter(|&i| i == 0 || alive[i])
+            .collect::<Vec<_>>();
+        let renumber = kept
+            .iter()
+            .enumerate()
+            .filter(|&(_, &i)| alive[i])
+            .map(|(new, &i)| (order[i], new as u32))
+            .collect::<HashMap<StateID, u32>>();
+
+        let eog = {
+            let mut eog = config.special_eog_ids.iter().copied().collect::<Vec<_>>();
+            eog.sort_unstable();
+            eog
+        };
+        let pieces = config
+            .prefix_index()
+            .iter()
+            .filter(|&(_, id)| !config.is_eog(id))
+            .collect::<Vec<_>>();
+        let (tokens, next) = kept
+            .par_iter()
+            .enumerate()
+            .map(|(new, &i)| {
+                let mut allowed = walk(&dfa, order[i], &pieces, &renumber);
+                if accepting[i] {
+                    allowed.extend(eog.iter().map(|&id| (id, new as u32)));
+                }
+                allowed.sort_unstable();
+                allowed.into_iter().unzip::<_, _, Vec<_>, Vec<_>>()
+            })
+            .unzip();
+        Ok(Self {
+            pattern: pattern.into(),
+            n_tokens: config.n_tokens(),
+            tokens,
+            next,
+            accepting: kept.iter().map(|&i| accepting[i]).collect(),
+        })
+    }
+
+    /// 构造索引的正则表达式
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// 构造索引时的词表大小，用于检查反序列化的索引是否匹配词表
+    pub fn n_tokens(&self) -> u32 {
+        self.n_tokens
+    }
+
+    /// 状态数
+    pub fn n_states(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// 起始状态
+    pub fn initial_state(&self) -> u32 {
+        0
+    }
+
+    /// 状态是否匹配完整
+    pub fn is_accepting(&self, state: u32) -> bool {
+        self.accepting[state as usize]
+    }
+
+    /// 状态允许的标记，按 id 排序
+    pub fn allowed_tokens(&self, state: u32) -> &[TokenId] {
+        &self.tokens[state as usize]
+    }
+
+    /// 接受标记后的状态，不允许时返回 `None`
+    pub fn next_state(&self, state: u32, id: TokenId) -> Option<u32> {
+        let tokens = &self.tokens[state as usize];
+        tokens
+            .binary_search(&id)
+            .ok()
+            .map(|i| self.next[state as usize][i])
+    }
+}
+
+/// 从 `state` 开始逐个匹配按字节排序的标记，相同前缀的标记共享已走过的状态
+fn walk(
+    dfa: &dense::DFA<Vec<u32>>,
+    state: StateID,
+    pieces: &[(&[u8], TokenId)],
+    renumber: &HashMap<StateID, u32>,
+) -> Vec<(TokenId, u32)> {
+    let mut allowed = Vec::new();
+    // states[i] 是接受 prev[..i] 后的状态，失配后不再增长
+    let mut states = vec![state];
+    let mut prev: &[u8] = &[];
+    for &(piece, id) in pieces {
+        let common = prev.iter().zip(piece).take_while(|(a, b)| a == b).count();
+        states.truncate(common + 1);
+        prev = piece;
+        if states.len() <= common {
+            continue;
+        }
+        while states.len() <= piece.len() {
+            let next = dfa.next_state(*states.last().unwrap(), piece[states.len() - 1]);
+            if !renumber.contains_key(&next) {
+                break;
+            }
+            states.push(next);
+        }
+        if states.len() > piece.len() {
+            allowed.push((id, renumber[states.last().unwrap()]));
+        }
+    }
+    allowed
+}
@@ -0,0 +1,278 @@
+use std::{fs, path::Path};
+
+use crate::{
+    common::{NULL, TokenAttribute, TokenData, TokenId},
+    config::{LoadError, TokenizerConfig, VocabType},
+    ugm::Charsmap,
+};
+
+/// 加载 SentencePiece 的 `.model` 文件（`ModelProto` protobuf）
+pub fn load_sentencepiece(path: impl AsRef<Path>) -> Result<TokenizerConfig, LoadError> {
+    let data = fs::read(path)?;
+    load_sentencepiece_bytes(&data)
+}
+
+/// 从 `ModelProto` 的二进制内容加载分词器
+///
+/// BPE 模型对应 [`VocabType::Spm`]，Unigram 模型对应 [`VocabType::Ugm`]，Word 和 Char 模型返回错误。
+/// Unigram 模型按 `precompiled_charsmap`（如 `nmt_nfkc`）规范化，与 llama.cpp 的 T5 相同，
+/// 不添加 BOS，结尾添加 EOS。BPE 模型带 `precompiled_charsmap` 时返回错误，
+/// llama.cpp 的 SPM 分词不做这种规范化。
+pub fn load_sentencepiece_bytes(data: &[u8]) -> Result<TokenizerConfig, LoadError> {
+    let model = ModelProto::parse(data)?;
+    let mut config = TokenizerConfig::new();
+    match model.trainer.model_type {
+        MODEL_TYPE_UNIGRAM => {
+            config.vocab_type = VocabType::Ugm;
+            config.add_bos = false;
+            config.add_eos = true;
+            Charsmap::check(&model.normalizer.precompiled_charsmap)?;
+            config.precompiled_charsmap = model.normalizer.precompiled_charsmap;
+        }
+        MODEL_TYPE_BPE if model.normalizer.precompiled_charsmap.is_empty() => {
+            config.vocab_type = VocabType::Spm;
+        }
+        MODEL_TYPE_BPE => {
+            return Err(LoadError::Format(
+                "unsupported sentencepiece BPE normalizer with precompiled_charsmap".into(),
+            ));
+        }
+        model_type => {
+            return Err(LoadError::Format(format!(
+                "unsupported sentencepiece model type {model_type}"
+            )));
+        }
+    }
+    let id = |id: i32| if id < 0 { NULL } else { id as TokenId };
+    config.unk = id(model.trainer.unk_id);
+    config.bos = id(model.trainer.bos_id);
+    config.eos = id(model.trainer.eos_id);
+    config.pad = id(model.trainer.pad_id);
+    config.sep = NULL;
+    config.mask = NULL;
+    config.treat_whitespace_as_suffix = model.trainer.treat_whitespace_as_suffix;
+    config.add_space_prefix = model.normalizer.add_dummy_prefix;
+    config.remove_extra_whitespaces = model.normalizer.remove_extra_whitespaces;
+    config.escape_whitespaces = model.normalizer.escape_whitespaces;
+
+    // 与 GGUF 的 token_type 取值相同
+    config.id_to_token = model
+        .pieces
+        .into_iter()
+        .map(|piece| TokenData {
+            text: piece.piece.into(),
+            score: piece.score,
+            attribute: match piece.ty {
+                1 => TokenAttribute::Normal,
+                2 => TokenAttribute::Unknown,
+                3 => TokenAttribute::Control,
+                4 => TokenAttribute::UserDefined,
+                5 => TokenAttribute::Unused,
+                6 => TokenAttribute::Byte,
+                _ => TokenAttribute::Undefined,
+            },
+        })
+        .collect();
+    config.token_to_id = config
+        .id_to_token
+        .iter()
+        .enumerate()
+        .map(|(id, token)| (token.text.clone(), id as TokenId))
+        .collect();
+
+    config.init_special_tokens();
+    Ok(config)
+}
+
+const MODEL_TYPE_UNIGRAM: i32 = 1;
+const MODEL_TYPE_BPE: i32 = 2;
+
+/// `ModelProto` 中用到的字段
+#[derive(Default)]
+struct ModelProto {
+    pieces: Vec<SentencePiece>,
+    trainer: TrainerSpec,
+    normalizer: NormalizerSpec,
+}
+
+/// `ModelProto.SentencePiece`
+struct SentencePiece {
+    piece: String,
+    score: f32,
+    ty: i32,
+}
+
+/// `TrainerSpec` 中用到的字段，默认值与 sentencepiece_model.proto 一致
+struct TrainerSpec {
+    model_type: i32,
+    treat_whitespace_as_suffix: bool,
+    unk_id: i32,
+    bos_id: i32,
+    eos_id: i32,
+    pad_id: i32,
+}
+
+impl Default for TrainerSpec {
+    fn default() -> Self {
+        Self {
+            model_type: MODEL_TYPE_UNIGRAM,
+            treat_whitespace_as_suffix: false,
+            unk_id: 0,
+            bos_id: 1,
+            eos_id: 2,
+            pad_id: -1,
+        }
+    }
+}
+
+/// `NormalizerSpec` 中用到的字段，默认值与 sentencepiece_model.proto 一致
+struct NormalizerSpec {
+    precompiled_charsmap: Vec<u8>,
+    add_dummy_prefix: bool,
+    remove_extra_whitespaces: bool,
+    escape_whitespaces: bool,
+}
+
+impl Default for NormalizerSpec {
+    fn default() -> Self {
+        Self {
+            precompiled_charsmap: Vec::new(),
+            add_dummy_prefix: true,
+            remove_extra_whitespaces: true,
+            escape_whitespaces: true,
+        }
+    }
+}
+
+impl ModelProto {
+    fn parse(data: &[u8]) -> Result<Self, LoadError> {
+        let mut model = Self::default();
+        let mut reader = ProtoReader(data);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (1, WireValue::Bytes(bytes)) => model.pieces.push(SentencePiece::parse(bytes)?),
+                (2, WireValue::Bytes(bytes)) => model.trainer = TrainerSpec::parse(bytes)?,
+                (3, WireValue::Bytes(bytes)) => model.normalizer = NormalizerSpec::parse(bytes)?,
+                _ => {}
+            }
+        }
+        Ok(model)
+    }
+}
+
+impl SentencePiece {
+    fn parse(data: &[u8]) -> Result<Self, LoadError> {
+        let mut piece = Self {
+            piece: String::new(),
+            score: 0.0,
+            ty: 1,
+        };
+        let mut reader = ProtoReader(data);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (1, WireValue::Bytes(bytes)) => {
+                    piece.piece = String::from_utf8(bytes.to_vec())
+                        .map_err(|_| LoadError::Format("piece is not valid utf-8".into()))?
+                }
+                (2, WireValue::Fixed32(bits)) => piece.score = f32::from_bits(bits),
+                (3, WireValue::Varint(ty)) => piece.ty = ty as i32,
+                _ => {}
+            }
+        }
+        Ok(piece)
+    }
+}
+
+impl TrainerSpec {
+    fn parse(data: &[u8]) -> Result<Self, LoadError> {
+        let mut spec = Self::default();
+        let mut reader = ProtoReader(data);
+        while let Some((field, value)) = reader.next_field()? {
+            // int32 的负数以 10 字节 varint 编码，截断即可还原
+            match (field, value) {
+                (3, WireValue::Varint(v)) => spec.model_type = v as i32,
+                (24, WireValue::Varint(v)) => spec.treat_whitespace_as_suffix = v != 0,
+                (40, WireValue::Varint(v)) => spec.unk_id = v as i32,
+                (41, WireValue::Varint(v)) => spec.bos_id = v as i32,
+                (42, WireValue::Varint(v)) => spec.eos_id = v as i32,
+                (43, WireValue::Varint(v)) => spec.pad_id = v as i32,
+                _ => {}
+            }
+        }
+        Ok(spec)
+    }
+}
+
+impl NormalizerSpec {
+    fn parse(data: &[u8]) -> Result<Self, LoadError> {
+        let mut spec = Self::default();
+        let mut reader = ProtoReader(data);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (2, WireValue::Bytes(bytes)) => spec.precompiled_charsmap = bytes.to_vec(),
+                (3, WireValue::Varint(v)) => spec.add_dummy_prefix = v != 0,
+                (4, WireValue::Varint(v)) => spec.remove_extra_whitespaces = v != 0,
+                (5, WireValue::Varint(v)) => spec.escape_whitespaces = v != 0,
+                _ => {}
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// protobuf 线格式中的值
+enum WireValue<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// 最小的 protobuf 线格式读取器
+struct ProtoReader<'a>(&'a [u8]);
+
+impl<'a> ProtoReader<'a> {
+    /// 读取下一个字段的编号和值，读完时返回 `None`
+    fn next_field(&mut self) -> Result<Option<(u32, WireValue<'a>)>, LoadError> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let key = self.read_varint()?;
+        let field = (key >> 3) as u32;
+        let value = match key & 7 {
+            0 => WireValue::Varint(self.read_varint()?),
+            1 => {
+                self.take(8)?;
+                WireValue::Fixed64
+            }
+            2 => {
+                let len = self.read_varint()? as usize;
+                WireValue::Bytes(self.take(len)?)
+            }
+            5 => WireValue::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            ty => return Err(LoadError::Format(format!("unsupported wire type {ty}"))),
+        };
+        Ok(Some((field, value)))
+    }
+
+    fn read_varint(&mut self) -> Result<u64, LoadError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(LoadError::Format("varint too long".into()))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        let (head, tail) = self
+            .0
+            .split_at_checked(len)
+            .ok_or_else(|| LoadError::Format("unexpected end of protobuf".into()))?;
+        self.0 = tail;
+        Ok(head)
+    }
+}
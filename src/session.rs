@@ -1,12 +1,15 @@
 use std::{
     cmp::{Ordering, Reverse},
     collections::{BinaryHeap, HashMap, VecDeque},
+    sync::Arc,
 };
 
+use fancy_regex::Regex;
+
 use crate::{
     common::{NULL, TokenId},
     config::TokenizerConfig,
-    unicode::{unicode_len_utf8, unicode_regex_split},
+    unicode::{compiled_regex, unicode_byte_encode, unicode_len_utf8, unicode_regex_split_iter},
 };
 
 /// 符号结构体，表示文本中的一个符号
@@ -22,15 +25,28 @@ pub struct LlmSymbol {
     pub n: usize,
 }
 
+/// SPM 使用的符号，只记录在原始文本中的位置，避免为每个符号复制文本
+#[derive(Clone, Debug)]
+pub struct LlmSymbolSpm {
+    /// 前一个符号的索引
+    pub prev: i32,
+    /// 下一个符号的索引
+    pub next: i32,
+    /// 在原始文本中的字节偏移
+    pub offset: usize,
+    /// 符号的长度
+    pub n: usize,
+}
+
 /// BPE 标记器会话结构体
 
 pub struct LlmTokenizerBpeSession {
     /// 标记器引用
     tokenizer: LlmTokenizerBpe,
+    /// 编译好的预分词正则表达式，创建会话时从缓存取出
+    regexes: Vec<Arc<Regex>>,
     /// 符号列表
     symbols: Vec<LlmSymbol>,
-    /// 最终符号列表
-    symbols_final: Vec<LlmSymbol>,
     /// 工作队列
     work_queue: LlmBigramBpe,
 }
@@ -38,14 +54,24 @@ pub struct LlmTokenizerBpeSession {
 impl LlmTokenizerBpeSession {
     /// 创建一个新的 BPE 标记器会话
     pub fn new(tokenizer: LlmTokenizerBpe) -> Self {
+        let regexes = tokenizer
+            .regex_exprs
+            .iter()
+            .map(|regex_expr| compiled_regex(regex_expr))
+            .collect();
         Self {
             tokenizer,
+            regexes,
             symbols: Vec::new(),
-            symbols_final: Vec::new(),
             work_queue: LlmBigramBpe::new(),
         }
     }
 
+    /// 预分词使用的正则表达式
+    pub fn regex_exprs(&self) -> &[String] {
+        &self.tokenizer.regex_exprs
+    }
+
     /// 添加标记到输出
     pub fn append(token_id: TokenId, output: &mut Vec<TokenId>) {
         output.push(token_id);
@@ -53,129 +79,124 @@ impl LlmTokenizerBpeSession {
 
     /// 标记化文本
     pub fn tokenize(&mut self, text: &str, output: &mut Vec<TokenId>, config: &TokenizerConfig) {
-        let mut final_prev_index = -1;
-        let word_collection = unicode_regex_split(text, &self.tokenizer.regex_exprs);
-        self.symbols_final.clear();
-
-        for word in word_collection {
-            self.work_queue = LlmBigramBpe::new();
-            self.symbols.clear();
-            // 如果词汇表忽略合并且单词已经在词汇表中
-            if config.ignore_merges && config.text_to_token(&word) != NULL {
-                todo!();
-                self.symbols.push(LlmSymbol {
-                    prev: -1,
-                    next: -1,
-                    text: word.to_string(),
-                    n: word.len(),
-                });
+        let regexes = self.regexes.clone();
+        for word in unicode_regex_split_iter(text, &regexes) {
+            self.tokenize_word(&unicode_byte_encode(word), output, config);
+        }
+    }
+
+    /// 统计文本的标记数，不保存标记
+    ///
+    /// 按顺序逐个预分词，重复的词只合并一次。超过 `limit` 后不再处理剩余的文本，
+    /// 此时返回值大于 `limit` 但可能小于实际的标记数。
+    pub fn count(&mut self, text: &str, limit: usize, config: &TokenizerConfig) -> usize {
+        let regexes = self.regexes.clone();
+        let mut cache = HashMap::<&str, usize>::new();
+        let mut output = Vec::new();
+        let mut count = 0;
+        for word in unicode_regex_split_iter(text, &regexes) {
+            count += match cache.get(word) {
+                Some(&n) => n,
+                None => {
+                    output.clear();
+                    self.tokenize_word(&unicode_byte_encode(word), &mut output, config);
+                    cache.insert(word, output.len());
+                    output.len()
+                }
+            };
+            if count > limit {
+                break;
             }
+        }
+        count
+    }
 
+    /// 对预分词得到的一个词进行合并并输出标记
+    fn tokenize_word(&mut self, word: &str, output: &mut Vec<TokenId>, config: &TokenizerConfig) {
+        self.work_queue = LlmBigramBpe::new();
+        self.symbols.clear();
+        // 如果词汇表忽略合并且单词已经在词汇表中，整个单词直接作为一个符号
+        if config.ignore_merges && config.text_to_token(word) != NULL {
+            self.symbols.push(LlmSymbol {
+                prev: -1,
+                next: -1,
+                text: word.to_string(),
+                n: word.len(),
+            });
+        } else {
             // 将单词分割为 UTF-8 字符
+            let n_chars = word.chars().count();
             for (i, c) in word.chars().enumerate() {
                 let sym = LlmSymbol {
                     text: c.to_string(),
                     n: c.len_utf8(),
                     prev: i as i32 - 1,
-                    next: if i == word.chars().count() - 1 {
-                        -1
-                    } else {
-                        i as i32 + 1
-                    },
+                    next: if i == n_chars - 1 { -1 } else { i as i32 + 1 },
                 };
                 self.symbols.push(sym);
             }
+        }
 
-            // 添加所有可能的二元组
-            for i in 1..(self.symbols.len() as i32) {
-                self.add_new_bigram(i - 1, i, config);
-            }
-            // 构建标记
-            while let Some(bigram) = self.work_queue.pop_move() {
-                let left_idx = bigram.left as usize;
-                let right_idx = bigram.right as usize;
-
-                // 获取左右符号的引用
-                let left_symbol = &self.symbols[left_idx];
-                let right_symbol = &self.symbols[right_idx];
-                let flag = format!("{}{}", &left_symbol.text, &right_symbol.text);
-
-                // 如果其中一个符号已经被合并，跳过它
-                if left_symbol.n == 0 || right_symbol.n == 0 {
-                    continue;
-                }
-
-                // 检查二元组是否过时
-                if flag != bigram.text {
-                    continue;
-                }
+        // 添加所有可能的二元组
+        for i in 1..(self.symbols.len() as i32) {
+            self.add_new_bigram(i - 1, i, config);
+        }
+        // 构建标记
+        while let Some(bigram) = self.work_queue.pop_move() {
+            let left_idx = bigram.left as usize;
+            let right_idx = bigram.right as usize;
 
-                // 合并右符号到左符号
-                self.symbols[left_idx].n += self.symbols[right_idx].n;
+            // 获取左右符号的引用
+            let left_symbol = &self.symbols[left_idx];
+            let right_symbol = &self.symbols[right_idx];
 
-                // 将右符号标记为已合并
-                self.symbols[right_idx].n = 0;
+            // 如果其中一个符号已经被合并，跳过它
+            if left_symbol.n == 0 || right_symbol.n == 0 {
+                continue;
+            }
 
-                // 从链中移除右符号
-                let right_next = self.symbols[right_idx].next;
-                self.symbols[left_idx].next = right_next;
-                self.symbols[left_idx].text = flag;
-                if right_next >= 0 {
-                    self.symbols[right_next as usize].prev = bigram.left;
-                }
-                // 寻找更多合并
-                self.add_new_bigram(self.symbols[left_idx].prev, bigram.left, config);
-                self.add_new_bigram(bigram.left, self.symbols[left_idx].next, config);
+            // 检查二元组是否过时
+            let flag = format!("{}{}", left_symbol.text, right_symbol.text);
+            if flag != bigram.text {
+                continue;
             }
 
-            // 将完成的标记添加到最终列表，保持正确的顺序
-            for sym in &self.symbols {
-                if sym.n > 0 {
-                    let mut new_sym = sym.clone();
-                    new_sym.prev = final_prev_index;
-                    new_sym.next = -1;
+            // 合并右符号到左符号
+            self.symbols[left_idx].n += self.symbols[right_idx].n;
 
-                    if final_prev_index != -1 {
-                        self.symbols_final[final_prev_index as usize].next =
-                            self.symbols_final.len() as i32;
-                    }
+            // 将右符号标记为已合并
+            self.symbols[right_idx].n = 0;
 
-                    self.symbols_final.push(new_sym);
-                    final_prev_index = (self.symbols_final.len() - 1) as i32;
-                }
+            // 从链中移除右符号
+            let right_next = self.symbols[right_idx].next;
+            self.symbols[left_idx].next = right_next;
+            self.symbols[left_idx].text = flag;
+            if right_next >= 0 {
+                self.symbols[right_next as usize].prev = bigram.left;
             }
+            // 寻找更多合并
+            self.add_new_bigram(self.symbols[left_idx].prev, bigram.left, config);
+            self.add_new_bigram(bigram.left, self.symbols[left_idx].next, config);
         }
 
-        // 使用最终符号列表
-        self.symbols = self.symbols_final.clone();
-
-        // 处理所有符号
-        if !self.symbols.is_empty() {
-            let mut i = 0;
-            while i != -1 {
-                let symbol = &self.symbols[i as usize];
-                if symbol.n > 0 {
-                    // 创建符号的字符串
-                    let str =
-                        String::from_utf8_lossy(&symbol.text.as_bytes()[..symbol.n]).to_string();
-                    let token = config.text_to_token(&str);
-
-                    if token == NULL {
-                        // 如果找不到标记，将每个字节作为单独的标记输出
-                        for byte in str.bytes() {
-                            let byte_str = String::from(byte as char);
-                            let token_multibyte = config.text_to_token(&byte_str);
-                            if token_multibyte != NULL {
-                                output.push(token_multibyte);
-                            }
-                        }
-                    } else {
-                        // 添加找到的标记
-                        output.push(token);
+        // 按链表顺序输出合并后的符号，已合并的符号长度为 0，不在链表中
+        let mut i = if self.symbols.is_empty() { -1 } else { 0 };
+        while i != -1 {
+            let symbol = &self.symbols[i as usize];
+            let token = config.text_to_token(&symbol.text);
+            if token == NULL {
+                // 如果找不到标记，将每个字节（字节级编码的字符）作为单独的标记输出
+                for ch in symbol.text.chars() {
+                    let token_multibyte = config.text_to_token(ch.encode_utf8(&mut [0; 4]));
+                    if token_multibyte != NULL {
+                        output.push(token_multibyte);
                     }
                 }
-                i = symbol.next;
+            } else {
+                // 添加找到的标记
+                output.push(token);
             }
+            i = symbol.next;
         }
     }
 
@@ -231,7 +252,7 @@ pub struct LlmBigramBpe {
 /// 为 LlmBigramBpeItem 实现 PartialEq
 impl PartialEq for LlmBigramBpeItem {
     fn eq(&self, other: &Self) -> bool {
-        self.rank == other.rank
+        self.rank == other.rank && self.left == other.left
     }
 }
 
@@ -246,9 +267,11 @@ impl PartialOrd for LlmBigramBpeItem {
 }
 
 /// 为 LlmBigramBpeItem 实现 Ord，用于优先队列
+///
+/// 排名相同时先合并靠左的二元组，与 llama.cpp 一致
 impl Ord for LlmBigramBpeItem {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.rank.cmp(&other.rank)
+        (self.rank, self.left).cmp(&(other.rank, other.left))
     }
 }
 
@@ -312,15 +335,13 @@ impl PartialOrd for LlmBigramSpm {
 }
 
 /// 为 LlmBigramSpm 实现 Ord，用于优先队列
+///
+/// 分数最高的先出队，分数相同时先合并靠左的二元组，与 llama.cpp 一致
 impl Ord for LlmBigramSpm {
     fn cmp(&self, other: &Self) -> Ordering {
-        // 注意：这里是反向比较，因为我们需要最大堆
-        // 首先比较分数，然后比较左侧索引
-        match other.score.partial_cmp(&self.score) {
-            Some(Ordering::Equal) => other.left.cmp(&self.left),
-            Some(ord) => ord,
-            None => Ordering::Equal, // 处理 NaN 情况
-        }
+        self.score
+            .total_cmp(&other.score)
+            .then(other.left.cmp(&self.left))
     }
 }
 
@@ -328,7 +349,7 @@ impl Ord for LlmBigramSpm {
 #[derive(Debug)]
 pub struct LlmTokenizerSpmSession {
     /// 符号列表
-    symbols: Vec<LlmSymbol>,
+    symbols: Vec<LlmSymbolSpm>,
     /// 工作队列
     work_queue: BinaryHeap<LlmBigramSpm>,
     /// 反向合并映射
@@ -358,8 +379,8 @@ impl<'a> LlmTokenizerSpmSession {
             let len = unicode_len_utf8(text.as_bytes()[offs]);
 
             // 创建新的符号
-            let sym = LlmSymbol {
-                text: text.to_string(),
+            let sym = LlmSymbolSpm {
+                offset: offs,
                 n: std::cmp::min(len, text.len() - offs),
                 prev: index - 1,
                 next: if offs + len >= text.len() {
@@ -375,8 +396,10 @@ impl<'a> LlmTokenizerSpmSession {
         }
 
         // 用所有可能的 2 字符标记初始化工作队列
+        self.work_queue.clear();
+        self.rev_merge.clear();
         for i in 1..self.symbols.len() {
-            self.try_add_bigram(i as i32 - 1, i as i32, config);
+            self.try_add_bigram(text, i as i32 - 1, i as i32, config);
         }
 
         // 持续替换频率最高的对，直到不能再替换
@@ -407,21 +430,21 @@ impl<'a> LlmTokenizerSpmSession {
             }
 
             // 寻找更多替换
-            self.try_add_bigram(self.symbols[left_idx].prev, bigram.left, config);
-            self.try_add_bigram(bigram.left, self.symbols[left_idx].next, config);
+            self.try_add_bigram(text, self.symbols[left_idx].prev, bigram.left, config);
+            self.try_add_bigram(text, bigram.left, self.symbols[left_idx].next, config);
         }
 
         // 处理最终的符号
-        let mut i = 0;
+        let mut i = if self.symbols.is_empty() { -1 } else { 0 };
         while i != -1 {
             let symbol = &self.symbols[i as usize];
-            self.resegment(symbol, output, config);
+            self.resegment(text, symbol, output, config);
             i = symbol.next;
         }
     }
 
     /// 尝试添加新的二元组
-    fn try_add_bigram(&mut self, left: i32, right: i32, config: &TokenizerConfig) {
+    fn try_add_bigram(&mut self, text: &str, left: i32, right: i32, config: &TokenizerConfig) {
         if left == -1 || right == -1 {
             return;
         }
@@ -431,12 +454,13 @@ impl<'a> LlmTokenizerSpmSession {
         let right_sym = &self.symbols[right as usize];
 
         // 构建完整的文本
-        let left_text = &left_sym.text[..left_sym.n];
-        let right_text = &right_sym.text[..right_sym.n];
-        let text = format!("{}{}", left_text, right_text);
+        let left_text = &text[left_sym.offset..][..left_sym.n];
+        let right_text = &text[right_sym.offset..][..right_sym.n];
+        // 左右符号在原始文本中相邻
+        let text = &text[left_sym.offset..][..left_sym.n + right_sym.n];
 
         // 查找标记
-        let token = config.text_to_token(&text);
+        let token = config.text_to_token(text);
 
         if token == NULL {
             return;
@@ -446,14 +470,23 @@ impl<'a> LlmTokenizerSpmSession {
             return;
         }
 
-        // 获取标记数据
-        let tok_data = config.get_token_data(token);
+        // 有合并表时（如 HF 中带字节回退的 BPE）只按合并规则合并，排名越靠前分数越高，
+        // 否则使用标记的分数
+        let score = if config.bpe_ranks.is_empty() {
+            config.get_token_data(token).score
+        } else {
+            let rank = config.find_bpe_rank(left_text, right_text);
+            if rank < 0 {
+                return;
+            }
+            -(rank as f32)
+        };
 
         // 创建新的二元组
         let bigram = LlmBigramSpm {
             left,
             right,
-            score: tok_data.score,
+            score,
             size: text.len(),
         };
 
@@ -461,13 +494,20 @@ impl<'a> LlmTokenizerSpmSession {
         self.work_queue.push(bigram);
 
         // 添加到反向合并映射
-        self.rev_merge.insert(text, (left, right));
+        self.rev_merge.insert(text.to_string(), (left, right));
     }
 
     /// 重新分割符号
-    fn resegment(&self, symbol: &LlmSymbol, output: &mut Vec<u32>, config: &TokenizerConfig) {
+    fn resegment(
+        &self,
+        text: &str,
+        symbol: &LlmSymbolSpm,
+        output: &mut Vec<u32>,
+        config: &TokenizerConfig,
+    ) {
         // 获取符号的文本
-        let text = &symbol.text[..symbol.n];
+        let full_text = text;
+        let text = &text[symbol.offset..][..symbol.n];
 
         // 尝试将文本转换为标记
         let token = config.text_to_token(text);
@@ -481,17 +521,20 @@ impl<'a> LlmTokenizerSpmSession {
         // 查找反向合并映射
         if let Some(&(left, right)) = self.rev_merge.get(text) {
             // 递归处理左右符号
-            self.resegment(&self.symbols[left as usize], output, config);
-            self.resegment(&self.symbols[right as usize], output, config);
+            self.resegment(full_text, &self.symbols[left as usize], output, config);
+            self.resegment(full_text, &self.symbols[right as usize], output, config);
             return;
         }
 
         // 如果没有找到映射，将每个字节作为单独的标记输出
-        for i in 0..symbol.n {
-            if let Some(byte) = symbol.text.as_bytes().get(i) {
-                let id = config.byte_to_token(*byte);
-                output.push(id);
+        let ids = text.bytes().map(|byte| config.byte_to_token(byte));
+        if ids.clone().any(|id| id == config.unk) {
+            // 没有字节标记时整个符号是一个未知标记，词表也没有未知标记时跳过
+            if config.unk != NULL {
+                output.push(config.unk);
             }
+            return;
         }
+        output.extend(ids);
     }
 }
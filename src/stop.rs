@@ -0,0 +1,138 @@
+use crate::{common::TokenId, config::TokenizerConfig};
+
+/// 停止生成的原因
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// 匹配到第几个停止字符串
+    String(usize),
+    /// 结束生成的标记
+    Eog(TokenId),
+}
+
+/// 匹配到停止条件
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StopMatch {
+    pub reason: StopReason,
+    /// 已输入的全部文本末尾应删除的字节数，即停止字符串及同一标记中其后的内容
+    pub trim: usize,
+}
+
+/// [`StopMatcher::feed`] 的结果
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StopStep {
+    /// 可以输出的文本，不含停止字符串和可能是其开头的部分
+    pub text: String,
+    pub stop: Option<StopMatch>,
+}
+
+/// 逐个接收生成的标记，检测跨越多个标记的停止字符串
+///
+/// 标记按 [`token_to_piece`](TokenizerConfig::token_to_piece) 还原。
+/// 末尾可能是停止字符串开头的文本和不完整的 UTF-8 字符暂不输出，
+/// 确定不会匹配后再随后续的文本输出。结束生成的标记（见 [`TokenizerConfig::is_eog`]）也会停止。
+/// 停止后缓存清空，可以继续接收新的生成。
+#[derive(Clone, Debug, Default)]
+pub struct StopMatcher {
+    stops: Vec<Vec<u8>>,
+    /// 已输入但还没有输出的字节
+    pending: Vec<u8>,
+}
+
+impl StopMatcher {
+    /// 空的停止字符串不会匹配
+    pub fn new<S: AsRef<str>>(stops: &[S]) -> Self {
+        Self {
+            stops: stops
+                .iter()
+                .map(|stop| stop.as_ref().as_bytes().to_vec())
+                .collect(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// 暂不输出的字节
+    pub fn pending(&self) -> &[u8] {
+        &self.pending
+    }
+
+    /// 接收一个标记
+    pub fn feed(&mut self, config: &TokenizerConfig, id: TokenId) -> StopStep {
+        if config.is_eog(id) {
+            return StopStep {
+                text: self.finish(),
+                stop: Some(StopMatch {
+                    reason: StopReason::Eog(id),
+                    trim: 0,
+                }),
+            };
+        }
+        self.pending.extend(config.token_to_piece(id, 0, false));
+
+        // 最早出现的停止字符串，起点相同时取靠前的
+        let found = self
+            .stops
+            .iter()
+            .enumerate()
+            .filter(|(_, stop)| !stop.is_empty())
+            .filter_map(|(i, stop)| {
+                self.pending
+                    .windows(stop.len())
+                    .position(|window| window == stop.as_slice())
+                    .map(|pos| (pos, i))
+            })
+            .min();
+        if let Some((pos, i)) = found {
+            let trim = self.pending.len() - pos;
+            self.pending.truncate(pos);
+            return StopStep {
+                text: self.finish(),
+                stop: Some(StopMatch {
+                    reason: StopReason::String(i),
+                    trim,
+                }),
+            };
+        }
+
+        // 保留可能是停止字符串开头的最长后缀
+        let len = self.pending.len();
+        let held = (1..=len.min(self.max_stop_len().saturating_sub(1)))
+            .rev()
+            .find(|&k| {
+                let suffix = &self.pending[len - k..];
+                self.stops.iter().any(|stop| stop.starts_with(suffix))
+            })
+            .unwrap_or(0);
+        let end = incomplete_utf8_start(&self.pending[..len - held]);
+        let text = String::from_utf8_lossy(&self.pending[..end]).into_owned();
+        self.pending.drain(..end);
+        StopStep { text, stop: None }
+    }
+
+    /// 生成结束，输出暂存的全部文本
+    pub fn finish(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        text
+    }
+
+    fn max_stop_len(&self) -> usize {
+        self.stops.iter().map(Vec::len).max().unwrap_or(0)
+    }
+}
+
+/// 末尾不完整的 UTF-8 字符的起点，没有时返回长度
+fn incomplete_utf8_start(bytes: &[u8]) -> usize {
+    let len = bytes.len();
+    for i in (len.saturating_sub(3)..len).rev() {
+        let need = match bytes[i] {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            // 后续字节继续向前找首字节
+            0x80..=0xbf => continue,
+            _ => return len,
+        };
+        return if len - i < need { i } else { len };
+    }
+    len
+}
@@ -0,0 +1,210 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fs,
+    path::Path,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+
+use crate::{
+    common::{NULL, TokenAttribute, TokenData, TokenId},
+    config::{LoadError, TokenizerConfig, VocabType},
+    unicode::unicode_byte_to_utf8,
+};
+
+/// 加载 OpenAI 风格的 `.tiktoken` 排名文件
+///
+/// 文件每行为 `base64 编码的标记 排名`，排名是从 0 开始的连续整数。
+/// 预分词正则与特殊标记由调用者提供，例如 cl100k 可使用 [`LLAMA3`](crate::common::LLAMA3)。
+/// BOS/EOS 不会自动设置。
+pub fn load_tiktoken(
+    path: impl AsRef<Path>,
+    regex_expr: &str,
+    special_tokens: &HashMap<String, TokenId>,
+) -> Result<TokenizerConfig, LoadError> {
+    let text = fs::read_to_string(path)?;
+    load_tiktoken_str(&text, regex_expr, special_tokens)
+}
+
+/// 从 `.tiktoken` 文件的文本加载分词器
+pub fn load_tiktoken_str(
+    text: &str,
+    regex_expr: &str,
+    special_tokens: &HashMap<String, TokenId>,
+) -> Result<TokenizerConfig, LoadError> {
+    // 读取排名表
+    let mut ranks = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let format_error = || LoadError::Format(format!("invalid line {}: {line}", i + 1));
+        let (token, rank) = line.split_once(' ').ok_or_else(format_error)?;
+        let token = STANDARD.decode(token).map_err(|_| format_error())?;
+        let rank = rank.parse::<TokenId>().map_err(|_| format_error())?;
+        if ranks.insert(token, rank).is_some() {
+            return Err(LoadError::Format(format!(
+                "duplicate token on line {}",
+                i + 1
+            )));
+        }
+    }
+    // 排名必须是 0..n 的排列，词表大小由文件内容决定，而不是由其中最大的排名决定
+    let mut seen = vec![false; ranks.len()];
+    for &rank in ranks.values() {
+        match seen.get_mut(rank as usize) {
+            Some(seen) if !*seen => *seen = true,
+            _ => return Err(LoadError::Format(format!("ranks are not dense: {rank}"))),
+        }
+    }
+
+    let mut config = TokenizerConfig::new();
+    config.vocab_type = VocabType::Bpe;
+    config.bos = NULL;
+    config.eos = NULL;
+    config.unk = NULL;
+    config.sep = NULL;
+    config.pad = NULL;
+    config.mask = NULL;
+    config.add_bos = false;
+    config.clean_spaces = true;
+    // tiktoken 对已在词表中的整个预分词结果直接返回，等同于忽略合并
+    config.ignore_merges = true;
+    config.set_regex_exprs(&[regex_expr]);
+
+    // 构造词表，标记文本使用与 GGUF 相同的字节级编码。
+    // 特殊标记的编号可以不连续（如 cl100k），空出的编号用占位标记填充，但不能多于实际的标记
+    let n_entries = ranks.len() + special_tokens.len();
+    let n_tokens = special_tokens
+        .values()
+        .map(|&id| id as usize + 1)
+        .max()
+        .unwrap_or(0)
+        .max(ranks.len());
+    if n_tokens > 2 * n_entries {
+        return Err(LoadError::Format(format!(
+            "special token id {} is too far beyond {} tokens",
+            n_tokens - 1,
+            n_entries
+        )));
+    }
+    let mut id_to_token = (0..n_tokens)
+        .map(|i| TokenData {
+            text: format!("[PAD{i}]").into(),
+            score: 0.0,
+            attribute: TokenAttribute::Unused,
+        })
+        .collect::<Vec<_>>();
+    for (bytes, &rank) in &ranks {
+        id_to_token[rank as usize] = TokenData {
+            text: byte_encode(bytes).into(),
+            score: -(rank as f32),
+            attribute: TokenAttribute::Normal,
+        };
+    }
+    for (text, &id) in special_tokens {
+        // 特殊标记不能占用已有标记的编号
+        if id_to_token[id as usize].attribute != TokenAttribute::Unused {
+            return Err(LoadError::Format(format!(
+                "special token `{text}` collides with token {id}"
+            )));
+        }
+        id_to_token[id as usize] = TokenData {
+            text: text.as_str().into(),
+            score: 0.0,
+            attribute: TokenAttribute::Control,
+        };
+    }
+    // 占位标记不能通过文本查到，也不会遮住同名的真实标记
+    config.token_to_id = id_to_token
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| token.attribute != TokenAttribute::Unused)
+        .map(|(id, token)| (token.text.clone(), id as TokenId))
+        .collect();
+    config.id_to_token = id_to_token;
+
+    // 由排名还原合并表：用更低排名的合并切分每个多字节标记，必然得到两部分
+    let mut sorted = ranks
+        .iter()
+        .filter(|(bytes, _)| bytes.len() > 1)
+        .collect::<Vec<_>>();
+    sorted.sort_unstable_by_key(|&(_, &rank)| rank);
+    let mut bpe_ranks = HashMap::with_capacity(sorted.len());
+    for (bytes, &rank) in sorted {
+        let parts = bpe_split(&ranks, bytes, rank);
+        let id = |bytes: &[u8]| config.token_to_id.get(byte_encode(bytes).as_str()).copied();
+        let [left, right] = &parts[..] else {
+            return Err(LoadError::Format(format!(
+                "token with rank {rank} cannot be built from lower ranks"
+            )));
+        };
+        let (Some(left), Some(right)) = (id(left), id(right)) else {
+            return Err(LoadError::Format(format!(
+                "merge for token with rank {rank} uses a token missing from the vocabulary"
+            )));
+        };
+        let merge = (left, right);
+        let n = bpe_ranks.len();
+        bpe_ranks.entry(merge).or_insert(n);
+    }
+    config.bpe_ranks = bpe_ranks;
+
+    config.init_special_tokens();
+    Ok(config)
+}
+
+/// 只使用排名低于 `max_rank` 的合并对字节串做 BPE
+///
+/// 片段以起点标识，候选合并放在最小堆中，合并后只更新相邻的两个候选，
+/// 过期的候选在出堆时丢弃。排名相同时先合并靠左的。
+fn bpe_split(ranks: &HashMap<Vec<u8>, TokenId>, bytes: &[u8], max_rank: TokenId) -> Vec<Vec<u8>> {
+    let n = bytes.len();
+    let rank = |start: usize, stop: usize| {
+        ranks
+            .get(&bytes[start..stop])
+            .copied()
+            .filter(|&rank| rank < max_rank)
+    };
+    // end[i] 是以 i 开始的片段的终点，prev[i] 是前一个片段的起点
+    let mut end = (1..=n).collect::<Vec<_>>();
+    let mut prev = (0..n).map(|i| i.checked_sub(1)).collect::<Vec<_>>();
+    let mut alive = vec![true; n];
+    let mut heap = (0..n.saturating_sub(1))
+        .filter_map(|i| rank(i, i + 2).map(|rank| Reverse((rank, i, i + 2))))
+        .collect::<BinaryHeap<_>>();
+    while let Some(Reverse((_, start, stop))) = heap.pop() {
+        let mid = end[start];
+        if !alive[start] || mid >= n || end[mid] != stop {
+            continue;
+        }
+        alive[mid] = false;
+        end[start] = stop;
+        if stop < n {
+            prev[stop] = Some(start);
+            if let Some(rank) = rank(start, end[stop]) {
+                heap.push(Reverse((rank, start, end[stop])));
+            }
+        }
+        if let Some(left) = prev[start]
+            && let Some(rank) = rank(left, stop)
+        {
+            heap.push(Reverse((rank, left, stop)));
+        }
+    }
+
+    let mut parts = Vec::new();
+    let mut start = 0;
+    while start < n {
+        parts.push(bytes[start..end[start]].to_vec());
+        start = end[start];
+    }
+    parts
+}
+
+/// 将原始字节转换为 GPT-2 字节级编码的字符串
+fn byte_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| unicode_byte_to_utf8(b)).collect()
+}
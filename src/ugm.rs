@@ -0,0 +1,335 @@
+use crate::{
+    common::{NULL, TokenAttribute, TokenId},
+    config::{LoadError, TokenizerConfig},
+    unicode::unicode_len_utf8,
+};
+
+/// 未知字符比分数最低的普通标记再低的分数，与 llama.cpp 相同
+const UNKNOWN_TOKEN_SCORE_PENALTY: f32 = 10.0;
+
+/// Unigram（UGM）分词器，与 llama.cpp 的 `llm_tokenizer_ugm` 相同
+///
+/// 先按 `precompiled_charsmap` 和空白选项规范化文本，再用 Viterbi 算法找到分数之和最大的切分。
+#[derive(Debug)]
+pub(crate) struct LlmTokenizerUgm {
+    /// 普通、用户定义和未使用的标记
+    token_matcher: Trie,
+    /// 用户定义的标记，规范化时原样保留
+    user_defined_token_matcher: Trie,
+    unknown_token_score: f32,
+}
+
+impl LlmTokenizerUgm {
+    pub fn new(config: &TokenizerConfig) -> Self {
+        let mut token_matcher = Trie::default();
+        let mut user_defined_token_matcher = Trie::default();
+        let mut min_score = f32::MAX;
+        for (id, token) in config.id_to_token.iter().enumerate() {
+            let attribute = token.attribute;
+            if attribute.contains(TokenAttribute::Normal) {
+                min_score = min_score.min(token.score);
+            }
+            if attribute.intersects(
+                TokenAttribute::Normal | TokenAttribute::UserDefined | TokenAttribute::Unused,
+            ) {
+                token_matcher.insert(token.text.as_bytes(), id as TokenId);
+            }
+            if attribute.contains(TokenAttribute::UserDefined) {
+                user_defined_token_matcher.insert(token.text.as_bytes(), id as TokenId);
+            }
+        }
+        Self {
+            token_matcher,
+            user_defined_token_matcher,
+            unknown_token_score: min_score - UNKNOWN_TOKEN_SCORE_PENALTY,
+        }
+    }
+
+    /// 标记化文本
+    ///
+    /// 连续的未知字符合并为一个未知标记。词表没有未知标记时跳过这些字符。
+    pub fn tokenize(&self, text: &str, output: &mut Vec<TokenId>, config: &TokenizerConfig) {
+        let normalized = self.normalize(text.as_bytes(), config);
+        let input_len = normalized.len();
+        if input_len == 0 {
+            return;
+        }
+
+        // 每个位置上以此结束的最优切分：最后一个标记、它的起点和分数之和
+        let mut best = vec![(config.unk, 0, f64::MIN); input_len + 1];
+        best[0].2 = 0.;
+        let mut offset = 0;
+        while offset < input_len {
+            let n_utf8 = unicode_len_utf8(normalized[offset]).min(input_len - offset);
+            let score_sum = best[offset].2;
+            let mut single_codepoint_token_found = false;
+            let mut node = Trie::ROOT;
+            for end in offset + 1..=input_len {
+                let Some(next) = self.token_matcher.child(node, normalized[end - 1]) else {
+                    break;
+                };
+                node = next;
+                let Some(id) = self.token_matcher.value(node) else {
+                    continue;
+                };
+                if end - offset == n_utf8 {
+                    single_codepoint_token_found = true;
+                }
+                // 用户定义的标记分数视为 0
+                let token = &config.id_to_token[id as usize];
+                let score = if token.attribute.contains(TokenAttribute::UserDefined) {
+                    0.
+                } else {
+                    token.score
+                };
+                let challenger = score_sum + f64::from(score);
+                if challenger > best[end].2 {
+                    best[end] = (id, offset, challenger);
+                }
+            }
+            // 没有覆盖当前字符的标记时按未知字符处理
+            if !single_codepoint_token_found {
+                let end = offset + n_utf8;
+                let challenger = score_sum + f64::from(self.unknown_token_score);
+                if challenger > best[end].2 {
+                    best[end] = (config.unk, offset, challenger);
+                }
+            }
+            offset += n_utf8;
+        }
+
+        // 从末尾回溯，连续的未知标记只保留一个
+        let start = output.len();
+        let mut is_prev_unknown = false;
+        let mut end = input_len;
+        loop {
+            let (id, offset, _) = best[end];
+            let is_unknown = id == config.unk;
+            if !(is_prev_unknown && is_unknown) && id != NULL {
+                output.push(id);
+            }
+            if offset == 0 {
+                break;
+            }
+            is_prev_unknown = is_unknown;
+            end = offset;
+        }
+        output[start..].reverse();
+    }
+
+    /// 规范化文本，与 llama.cpp 相同
+    ///
+    /// 每段非空格文本前补一个空格（`remove_extra_whitespaces` 时合并连续的空格并去掉末尾的空格），
+    /// `escape_whitespaces` 时空格写为 U+2581。
+    fn normalize(&self, input: &[u8], config: &TokenizerConfig) -> Vec<u8> {
+        let space: &[u8] = if config.escape_whitespaces {
+            "\u{2581}".as_bytes()
+        } else {
+            b" "
+        };
+        let shall_prepend_space = !config.treat_whitespace_as_suffix && config.add_space_prefix;
+        let shall_append_space = config.treat_whitespace_as_suffix && config.add_space_prefix;
+        let shall_merge_spaces = config.remove_extra_whitespaces;
+        let charsmap = Charsmap::new(&config.precompiled_charsmap);
+
+        let mut normalized = Vec::with_capacity(input.len() * 3);
+        let mut is_space_prepended = false;
+        let mut processing_non_ws = false;
+        let mut offset = 0;
+        while offset < input.len() {
+            let (prefix, consumed) = self.normalize_prefix(&input[offset..], charsmap.as_ref());
+            for &c in prefix {
+                if c != b' ' {
+                    if !processing_non_ws {
+                        processing_non_ws = true;
+                        if (shall_prepend_space && !is_space_prepended) || shall_merge_spaces {
+                            normalized.extend_from_slice(space);
+                            is_space_prepended = true;
+                        }
+                    }
+                    normalized.push(c);
+                } else {
+                    processing_non_ws = false;
+                    if !shall_merge_spaces {
+                        normalized.extend_from_slice(space);
+                    }
+                }
+            }
+            offset += consumed;
+        }
+        if shall_append_space {
+            normalized.extend_from_slice(space);
+        }
+        normalized
+    }
+
+    /// 规范化 `input` 开头的一段，返回结果和消耗的字节数
+    ///
+    /// 用户定义的标记原样保留，其次按 charsmap 替换最长的匹配，都没有时保留一个字符，
+    /// 不是合法 UTF-8 时消耗一个字节并替换为 U+FFFD。
+    fn normalize_prefix<'a>(
+        &self,
+        input: &'a [u8],
+        charsmap: Option<&Charsmap<'a>>,
+    ) -> (&'a [u8], usize) {
+        let len = self.user_defined_token_matcher.longest_prefix(input);
+        if len > 0 {
+            return (&input[..len], len);
+        }
+        if let Some((len, replacement)) =
+            charsmap.and_then(|charsmap| charsmap.longest_prefix(input))
+        {
+            return (replacement, len);
+        }
+        let len = unicode_len_utf8(input[0]).min(input.len());
+        match std::str::from_utf8(&input[..len]) {
+            Ok(_) => (&input[..len], len),
+            Err(_) => ("\u{fffd}".as_bytes(), 1),
+        }
+    }
+}
+
+/// 按字节查找的前缀树
+#[derive(Debug, Default)]
+struct Trie {
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: Vec<(u8, usize)>,
+    value: Option<TokenId>,
+}
+
+impl Trie {
+    const ROOT: usize = 0;
+
+    /// 插入 `key`，重复的键保留后插入的值
+    fn insert(&mut self, key: &[u8], value: TokenId) {
+        if self.nodes.is_empty() {
+            self.nodes.push(TrieNode::default());
+        }
+        let mut node = Self::ROOT;
+        for &byte in key {
+            node = match self.child(node, byte) {
+                Some(child) => child,
+                None => {
+                    self.nodes.push(TrieNode::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children.push((byte, child));
+                    child
+                }
+            };
+        }
+        self.nodes[node].value = Some(value);
+    }
+
+    fn child(&self, node: usize, byte: u8) -> Option<usize> {
+        self.nodes
+            .get(node)?
+            .children
+            .iter()
+            .find(|&&(b, _)| b == byte)
+            .map(|&(_, child)| child)
+    }
+
+    fn value(&self, node: usize) -> Option<TokenId> {
+        self.nodes[node].value
+    }
+
+    /// `input` 最长的是某个键的前缀的长度，没有时为 0
+    fn longest_prefix(&self, input: &[u8]) -> usize {
+        let mut node = Self::ROOT;
+        let mut longest = 0;
+        for (i, &byte) in input.iter().enumerate() {
+            match self.child(node, byte) {
+                Some(child) => node = child,
+                None => break,
+            }
+            if self.value(node).is_some() {
+                longest = i + 1;
+            }
+        }
+        longest
+    }
+}
+
+/// SentencePiece 的 `precompiled_charsmap`
+///
+/// 依次为 XOR 压缩的 darts-clone 双数组（前 4 字节是它的字节数）和以 0 结尾的替换字符串。
+/// 双数组把原文映射为替换字符串在后一部分中的偏移。
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Charsmap<'a> {
+    units: &'a [u8],
+    replacements: &'a [u8],
+}
+
+impl<'a> Charsmap<'a> {
+    /// 解析 charsmap，为空时返回 `None`
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let (len, rest) = data.split_first_chunk::<4>()?;
+        let len = u32::from_le_bytes(*len) as usize;
+        if !len.is_multiple_of(4) || len > rest.len() {
+            return None;
+        }
+        let (units, replacements) = rest.split_at(len);
+        Some(Self {
+            units,
+            replacements,
+        })
+    }
+
+    /// 检查 charsmap 的格式，空的 charsmap 表示不替换
+    pub fn check(data: &[u8]) -> Result<(), LoadError> {
+        if data.is_empty() || Charsmap::new(data).is_some() {
+            Ok(())
+        } else {
+            Err(LoadError::Format("invalid precompiled_charsmap".into()))
+        }
+    }
+
+    /// `input` 最长的有替换的前缀的长度和替换结果
+    ///
+    /// 越界的节点或偏移视为没有匹配，格式错误的 charsmap 不会 panic。
+    fn longest_prefix(&self, input: &[u8]) -> Option<(usize, &'a [u8])> {
+        let mut longest = None;
+        let mut node = self.base(0)?;
+        for (i, &c) in input.iter().enumerate() {
+            if c == 0 {
+                break;
+            }
+            node ^= u32::from(c);
+            let unit = self.unit(node)?;
+            // 标签不同说明不是上一个节点的子节点
+            if unit & (1 << 31 | 0xff) != u32::from(c) {
+                break;
+            }
+            let is_leaf = (unit >> 8) & 1 == 1;
+            node ^= Self::offset(unit);
+            if is_leaf {
+                longest = Some((i + 1, self.unit(node)? & ((1 << 31) - 1)));
+            }
+        }
+        let (len, offset) = longest?;
+        let replacement = self.replacements.get(offset as usize..)?;
+        let end = replacement
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(replacement.len());
+        Some((len, &replacement[..end]))
+    }
+
+    fn unit(&self, index: u32) -> Option<u32> {
+        let start = index as usize * 4;
+        let bytes = self.units.get(start..start + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn base(&self, index: u32) -> Option<u32> {
+        self.unit(index).map(Self::offset)
+    }
+
+    fn offset(unit: u32) -> u32 {
+        (unit >> 10) << ((unit & (1 << 9)) >> 6)
+    }
+}
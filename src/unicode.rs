@@ -1,467 +1,92 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Range,
+    sync::{Arc, LazyLock, Mutex},
+};
 
-/// 将文本按照正则表达式分割成多个部分
-pub fn unicode_regex_split(text: &str, regex_exprs: &[String]) -> Vec<String> {
-    // Unicode 类别
-    let k_ucat_enum: HashMap<&str, u32> = [
-        ("\\p{N}", unicode_cpt_flags::NUMBER),
-        ("\\p{L}", unicode_cpt_flags::LETTER),
-        ("\\p{P}", unicode_cpt_flags::PUNCTUATION),
-        ("\\p{M}", unicode_cpt_flags::ACCENT_MARK),
-        ("\\p{S}", unicode_cpt_flags::SYMBOL),
-    ]
-    .iter()
-    .cloned()
-    .collect();
-
-    let k_ucat_cpt: HashMap<u32, u8> = [
-        (unicode_cpt_flags::NUMBER, 0xD1),
-        (unicode_cpt_flags::LETTER, 0xD2),
-        (unicode_cpt_flags::PUNCTUATION, 0xD3),
-        (unicode_cpt_flags::ACCENT_MARK, 0xD4),
-        (unicode_cpt_flags::SYMBOL, 0xD5),
-    ]
-    .iter()
-    .cloned()
-    .collect();
-
-    let k_ucat_map: HashMap<u32, &str> = [
-        (unicode_cpt_flags::NUMBER, "0-9"),
-        (unicode_cpt_flags::LETTER, "A-Za-z"),
-        (
-            unicode_cpt_flags::PUNCTUATION,
-            "!-#%-*,-/:-;?-@\\[-\\]_\\{\\}",
-        ),
-        (unicode_cpt_flags::ACCENT_MARK, ""),
-        (unicode_cpt_flags::SYMBOL, "\\$+<=>^`\\|"),
-    ]
-    .iter()
-    .cloned()
-    .collect();
-
-    let tmp = process_regex(
-        &regex_exprs[0],
-        text,
-        &k_ucat_enum,
-        &k_ucat_cpt,
-        &k_ucat_map,
-    );
-    unicode_byte_encoding_process(&tmp)
-}
-
-/// 处理正则表达式
-fn process_regex(
-    regex_expr: &str,
-    text: &str,
-    k_ucat_enum: &HashMap<&str, u32>,
-    k_ucat_cpt: &HashMap<u32, u8>,
-    k_ucat_map: &HashMap<u32, &str>,
-) -> Vec<String> {
-    // 生成正则表达式的折叠表示
-    let mut regex_expr_collapsed = String::new();
-
-    let mut inside = false;
-    let mut i = 0;
-    while i < regex_expr.len() {
-        let c = regex_expr.chars().nth(i).unwrap();
-
-        if c == '[' && (i == 0 || regex_expr.chars().nth(i - 1).unwrap() != '\\') {
-            regex_expr_collapsed.push('[');
-            inside = true;
-            i += 1;
-            continue;
-        }
-
-        if inside && c == ']' && regex_expr.chars().nth(i - 1).unwrap() != '\\' {
-            regex_expr_collapsed.push(']');
-            inside = false;
-            i += 1;
-            continue;
-        }
-
-        if i + 4 < regex_expr.len()
-            && regex_expr.chars().nth(i).unwrap() == '\\'
-            && regex_expr.chars().nth(i + 1).unwrap() == 'p'
-            && regex_expr.chars().nth(i + 2).unwrap() == '{'
-            && regex_expr.chars().nth(i + 4).unwrap() == '}'
-        {
-            let pat = format!("\\p{{{}}}", regex_expr.chars().nth(i + 3).unwrap());
-            if let Some(&cat_flag) = k_ucat_enum.get(pat.as_str()) {
-                if !inside {
-                    regex_expr_collapsed.push('[');
-                }
-
-                if let Some(&cat_char) = k_ucat_cpt.get(&cat_flag) {
-                    regex_expr_collapsed.push(cat_char as char);
-                }
-
-                if let Some(&cat_map) = k_ucat_map.get(&cat_flag) {
-                    regex_expr_collapsed.push_str(cat_map);
-                }
-
-                if !inside {
-                    regex_expr_collapsed.push(']');
-                }
-
-                i += 5;
-                continue;
-            }
-        }
-        regex_expr_collapsed.push(c);
-        i += 1;
-    }
-
-    // 使用折叠的文本和正则表达式
-    use fancy_regex::Regex;
-
-    match Regex::new(&regex_expr_collapsed) {
-        Ok(re) => {
-            // 使用正则表达式分割文本
-            let mut result = Vec::new();
-            let mut last_end = 0;
-
-            for cap_result in re.captures_iter(text) {
-                if let Ok(cap) = cap_result {
-                    if let Some(m) = cap.get(0) {
-                        // 如果匹配前有未匹配的文本，添加到结果中
-                        if m.start() > last_end {
-                            result.push(text[last_end..m.start()].to_string());
-                        }
-                        // 添加匹配的文本
-                        result.push(text[m.start()..m.end()].to_string());
-                        last_end = m.end();
-                    }
-                }
-            }
+use fancy_regex::Regex;
 
-            // 添加最后一部分未匹配的文本
-            if last_end < text.len() {
-                result.push(text[last_end..].to_string());
-            }
+use crate::unicode_data;
 
-            result
-        }
-        Err(_) => panic!(), // 返回空向量表示正则表达式错误
-    }
+/// 将文本按照正则表达式分割成多个部分，结果为字节级编码的字符串
+///
+/// 与 llama.cpp 相同，依次用每个表达式分割上一步的结果，匹配之间的文本也作为一段保留，
+/// 因此所有片段拼接后与原文相同。没有表达式时整个文本作为一段。
+pub fn unicode_regex_split(text: &str, regex_exprs: &[String]) -> Vec<String> {
+    let regexes = regex_exprs
+        .iter()
+        .map(|regex_expr| compiled_regex(regex_expr))
+        .collect::<Vec<_>>();
+    unicode_regex_split_iter(text, &regexes)
+        .map(unicode_byte_encode)
+        .collect()
 }
 
-/// 自定义正则表达式分割实现
-fn unicode_regex_split_custom(text: &str, regex_expr: &str, offsets: &[usize]) -> Vec<usize> {
-    if regex_expr == "'s|'t|'re|'ve|'m|'ll|'d| ?\\p{L}+| ?\\p{N}+| ?[^\\s\\p{L}\\p{N}]+|\\s+(?!\\S)"
-    {
-        unicode_regex_split_custom_gpt2(text, offsets)
-    } else if regex_expr
-        == "(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\\r\\n\\p{L}\\p{N}]?\\p{L}+|\\p{N}{1,3}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+"
-        || regex_expr
-            == "(?:'[sS]|'[tT]|'[rR][eE]|'[vV][eE]|'[mM]|'[lL][lL]|'[dD])|[^\\r\\n\\p{L}\\p{N}]?\\p{L}+|\\p{N}{1,3}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+"
-    {
-        unicode_regex_split_custom_llama3(text, offsets)
-    } else {
-        Vec::new()
+/// 与 [`unicode_regex_split`] 相同，但按顺序逐个产生未编码的片段，可以提前结束
+pub(crate) fn unicode_regex_split_iter<'a>(
+    text: &'a str,
+    regexes: &'a [Arc<Regex>],
+) -> Box<dyn Iterator<Item = &'a str> + 'a> {
+    let mut words: Box<dyn Iterator<Item = &'a str>> = Box::new(std::iter::once(text));
+    for regex in regexes {
+        words = Box::new(words.flat_map(move |word| process_regex(regex, word)));
     }
+    words
 }
 
-/// GPT2 系统正则表达式分割实现
-fn unicode_regex_split_custom_gpt2(text: &str, offsets: &[usize]) -> Vec<usize> {
-    let cpts = unicode_cpts_from_utf8(text);
-    let mut bpe_offsets = Vec::with_capacity(offsets.len());
-
-    let mut start = 0;
-    for &offset in offsets {
-        let offset_ini = start;
-        let offset_end = start + offset;
-        assert!(offset_end <= cpts.len());
-        start = offset_end;
-
-        const OUT_OF_RANGE: u32 = 0xFFFFFFFF;
-
-        let get_cpt = |pos: usize| -> u32 {
-            if offset_ini <= pos && pos < offset_end {
-                cpts[pos]
-            } else {
-                OUT_OF_RANGE
-            }
-        };
-
-        let get_flags = |pos: usize| -> unicode_cpt_flags {
-            if offset_ini <= pos && pos < offset_end {
-                unicode_cpt_flags_from_cpt(cpts[pos])
-            } else {
-                unicode_cpt_flags::default()
-            }
-        };
-
-        let mut prev_end = offset_ini;
-        let mut pos = offset_ini;
-
-        while pos < offset_end {
-            let cpt = get_cpt(pos);
-            let flags = get_flags(pos);
-
-            // 添加标记并返回长度
-            let mut add_token = |end: usize| -> usize {
-                assert!(prev_end <= end && end <= offset_end);
-                let len = end - prev_end;
-                if len > 0 {
-                    bpe_offsets.push(len);
-                }
-                prev_end = end;
-                len
-            };
-
-            // 正则表达式: 's|'t|'re|'ve|'m|'ll|'d
-            if cpt == '\'' as u32 && pos + 1 < offset_end {
-                let cpt_next = get_cpt(pos + 1);
-                if cpt_next == 's' as u32
-                    || cpt_next == 't' as u32
-                    || cpt_next == 'm' as u32
-                    || cpt_next == 'd' as u32
-                {
-                    pos += add_token(pos + 2);
-                    continue;
-                }
-                if pos + 2 < offset_end {
-                    let cpt_next_next = get_cpt(pos + 2);
-                    if (cpt_next == 'r' as u32 && cpt_next_next == 'e' as u32)
-                        || (cpt_next == 'v' as u32 && cpt_next_next == 'e' as u32)
-                        || (cpt_next == 'l' as u32 && cpt_next_next == 'l' as u32)
-                    {
-                        pos += add_token(pos + 3);
-                        continue;
-                    }
-                }
-            }
-
-            let flags2 = if cpt == ' ' as u32 {
-                get_flags(pos + 1)
-            } else {
-                flags
-            };
-
-            // 正则表达式: <space>?\p{L}+
-            if flags2.is_letter {
-                pos += (cpt == ' ' as u32) as usize;
-                while get_flags(pos).is_letter {
-                    pos += 1;
-                }
-                add_token(pos);
-                continue;
-            }
-
-            // 正则表达式: <space>?\p{N}+
-            if flags2.is_number {
-                pos += (cpt == ' ' as u32) as usize;
-                while get_flags(pos).is_number {
-                    pos += 1;
-                }
-                add_token(pos);
-                continue;
-            }
-
-            // 正则表达式: <space>?[^\s\p{L}\p{N}]+
-            if !(flags2.is_whitespace | flags2.is_letter | flags2.is_number) && flags.as_uint() != 0
-            {
-                pos += (cpt == ' ' as u32) as usize;
-                while !(get_flags(pos).is_whitespace
-                    | get_flags(pos).is_letter
-                    | get_flags(pos).is_number)
-                    && get_flags(pos).as_uint() != 0
-                {
-                    pos += 1;
-                }
-                add_token(pos);
-                continue;
-            }
-
-            let mut num_whitespaces = 0;
-            while get_flags(pos + num_whitespaces).is_whitespace {
-                num_whitespaces += 1;
-            }
-
-            // 正则表达式: \s+(?!\S)
-            if num_whitespaces > 1 && get_cpt(pos + num_whitespaces) != OUT_OF_RANGE {
-                pos += num_whitespaces - 1;
-                add_token(pos);
-                continue;
-            }
-
-            // 正则表达式: \s+
-            if num_whitespaces > 0 {
-                pos += num_whitespaces;
-                add_token(pos);
-                continue;
-            }
+/// 编译过的预分词正则表达式，按表达式文本缓存
+///
+/// 返回共享的 [`Arc`]，命中缓存时不复制编译结果，锁只在查表时持有。
+pub(crate) fn compiled_regex(regex_expr: &str) -> Arc<Regex> {
+    try_compiled_regex(regex_expr)
+        .unwrap_or_else(|e| panic!("invalid pre-tokenizer regex {regex_expr:?}: {e}"))
+}
 
-            // 没有匹配项
-            add_token(pos + 1);
-            pos += 1;
-        }
+/// 与 [`compiled_regex`] 相同，表达式无效时返回错误
+pub(crate) fn try_compiled_regex(regex_expr: &str) -> Result<Arc<Regex>, Box<fancy_regex::Error>> {
+    static CACHE: LazyLock<Mutex<HashMap<String, Arc<Regex>>>> = LazyLock::new(Default::default);
+    let mut cache = CACHE.lock().unwrap();
+    if let Some(regex) = cache.get(regex_expr) {
+        return Ok(regex.clone());
     }
-
-    bpe_offsets
+    // fancy-regex 直接支持 \p{L} 等 Unicode 类别和前瞻断言
+    let regex = Arc::new(Regex::new(regex_expr).map_err(Box::new)?);
+    cache.insert(regex_expr.to_string(), regex.clone());
+    Ok(regex)
 }
 
-/// LLAMA3 系统正则表达式分割实现
-fn unicode_regex_split_custom_llama3(text: &str, offsets: &[usize]) -> Vec<usize> {
-    let cpts = unicode_cpts_from_utf8(text);
-    let mut bpe_offsets = Vec::with_capacity(offsets.len());
-
-    let mut start = 0;
-    for &offset in offsets {
-        let offset_ini = start;
-        let offset_end = start + offset;
-        assert!(offset_end <= cpts.len());
-        start = offset_end;
-
-        const OUT_OF_RANGE: u32 = 0xFFFFFFFF;
-
-        let get_cpt = |pos: usize| -> u32 {
-            if offset_ini <= pos && pos < offset_end {
-                cpts[pos]
-            } else {
-                OUT_OF_RANGE
-            }
-        };
-
-        let get_flags = |pos: usize| -> unicode_cpt_flags {
-            if offset_ini <= pos && pos < offset_end {
-                unicode_cpt_flags_from_cpt(cpts[pos])
-            } else {
-                unicode_cpt_flags::default()
-            }
-        };
-
-        let mut prev_end = offset_ini;
-        let mut pos = offset_ini;
-
-        while pos < offset_end {
-            let cpt = get_cpt(pos);
-            let flags = get_flags(pos);
-
-            // 添加标记并返回长度
-            let mut add_token = |end: usize| -> usize {
-                assert!(prev_end <= end && end <= offset_end);
-                let len = end - prev_end;
-                if len > 0 {
-                    bpe_offsets.push(len);
-                }
-                prev_end = end;
-                len
-            };
-
-            // 正则表达式: (?i:'s|'t|'re|'ve|'m|'ll|'d) // 不区分大小写
-            if cpt == '\'' as u32 && pos + 1 < offset_end {
-                let cpt_next = unicode_tolower(get_cpt(pos + 1));
-                if cpt_next == 's' as u32
-                    || cpt_next == 't' as u32
-                    || cpt_next == 'm' as u32
-                    || cpt_next == 'd' as u32
-                {
-                    pos += add_token(pos + 2);
-                    continue;
-                }
-                if pos + 2 < offset_end {
-                    let cpt_next_next = unicode_tolower(get_cpt(pos + 2));
-                    if (cpt_next == 'r' as u32 && cpt_next_next == 'e' as u32)
-                        || (cpt_next == 'v' as u32 && cpt_next_next == 'e' as u32)
-                        || (cpt_next == 'l' as u32 && cpt_next_next == 'l' as u32)
-                    {
-                        pos += add_token(pos + 3);
-                        continue;
-                    }
-                }
-            }
-
-            // 正则表达式: [^\r\n\p{L}\p{N}]?\p{L}+
-            if !(cpt == '\r' as u32 || cpt == '\n' as u32 || flags.is_number) {
-                if flags.is_letter || get_flags(pos + 1).is_letter {
-                    // 一个或多个字母
-                    pos += 1;
-                    while get_flags(pos).is_letter {
-                        pos += 1;
-                    }
-                    add_token(pos);
-                    continue;
-                }
-            }
-
-            // 正则表达式: \p{N}{1,3}
-            if flags.is_number {
-                let mut ini = pos;
-                while get_flags(pos).is_number {
-                    if pos - ini >= 3 {
-                        add_token(pos);
-                        ini = pos;
-                    }
-                    pos += 1;
-                }
-                add_token(pos);
-                continue;
-            }
-
-            // 正则表达式: <space>?[^\s\p{L}\p{N}]+[\r\n]*
-            let flags2 = if cpt == ' ' as u32 {
-                get_flags(pos + 1)
-            } else {
-                flags
-            };
-            if !(flags2.is_whitespace | flags2.is_letter | flags2.is_number) && flags.as_uint() != 0
-            {
-                pos += (cpt == ' ' as u32) as usize;
-                while !(get_flags(pos).is_whitespace
-                    | get_flags(pos).is_letter
-                    | get_flags(pos).is_number)
-                    && get_flags(pos).as_uint() != 0
-                {
-                    pos += 1;
-                }
-                let mut cpt2 = get_cpt(pos);
-                while cpt2 == '\r' as u32 || cpt2 == '\n' as u32 {
-                    pos += 1;
-                    cpt2 = get_cpt(pos);
-                }
-                add_token(pos);
-                continue;
-            }
-
-            let mut num_whitespaces = 0;
-            let mut last_end_r_or_n = 0;
-            while get_flags(pos + num_whitespaces).is_whitespace {
-                let cpt2 = get_cpt(pos + num_whitespaces);
-                if cpt2 == '\r' as u32 || cpt2 == '\n' as u32 {
-                    last_end_r_or_n = pos + num_whitespaces + 1;
+/// 用正则表达式分割文本，匹配和未匹配的部分都保留
+fn process_regex<'a>(re: &'a Regex, text: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    // 回溯超限等匹配错误时跳过，剩余文本仍作为未匹配部分保留
+    let mut matches = re.find_iter(text).filter_map(Result::ok);
+    let mut last_end = 0;
+    let mut pending = None;
+    std::iter::from_fn(move || {
+        if let Some(word) = pending.take() {
+            return Some(word);
+        }
+        while let Some(m) = matches.next() {
+            // 如果匹配前有未匹配的文本，先产生未匹配的部分
+            let unmatched = (m.start() > last_end).then(|| &text[last_end..m.start()]);
+            let matched = (m.end() > m.start()).then(|| m.as_str());
+            last_end = m.end();
+            match (unmatched, matched) {
+                (Some(unmatched), matched) => {
+                    pending = matched;
+                    return Some(unmatched);
                 }
-                num_whitespaces += 1;
-            }
-
-            // 正则表达式: \s*[\r\n]+
-            if last_end_r_or_n > 0 {
-                pos = last_end_r_or_n;
-                add_token(pos);
-                continue;
-            }
-
-            // 正则表达式: \s+(?!\S)
-            if num_whitespaces > 1 && get_cpt(pos + num_whitespaces) != OUT_OF_RANGE {
-                pos += num_whitespaces - 1;
-                add_token(pos);
-                continue;
-            }
-
-            // 正则表达式: \s+
-            if num_whitespaces > 0 {
-                pos += num_whitespaces;
-                add_token(pos);
-                continue;
+                (None, Some(matched)) => return Some(matched),
+                (None, None) => {}
             }
-
-            // 没有匹配项
-            add_token(pos + 1);
-            pos += 1;
         }
-    }
-
-    bpe_offsets
+        // 最后一部分未匹配的文本
+        (last_end < text.len()).then(|| {
+            let rest = &text[last_end..];
+            last_end = text.len();
+            rest
+        })
+    })
 }
 
 /// Unicode 代码点标志结构体
@@ -475,6 +100,7 @@ pub struct unicode_cpt_flags {
     pub is_accent_mark: bool,
     pub is_lowercase: bool,
     pub is_uppercase: bool,
+    /// 码点在 NFD 中保持不变
     pub is_nfd: bool,
 }
 
@@ -541,54 +167,12 @@ impl unicode_cpt_flags {
     }
 }
 
-// 以下是辅助函数的声明，这些函数在原始代码中被调用但未在片段中定义
-// 在实际实现中，您需要提供这些函数的完整实现
-
-fn unicode_cpts_from_utf8(text: &str) -> Vec<u32> {
-    text.chars().map(|c| c as u32).collect()
-}
-/// 将 Unicode 码点转换为 UTF-8 编码的字符串
-fn unicode_cpt_to_utf8(cpt: u32) -> Result<String, &'static str> {
-    let mut result = String::new();
-
-    if cpt <= 0x7f {
-        // 单字节 ASCII 字符
-        result.push(cpt as u8 as char);
-        return Ok(result);
-    }
-
-    if cpt >= 0x80 && cpt <= 0x7ff {
-        // 双字节字符
-        result.push((0xc0 | ((cpt >> 6) & 0x1f)) as u8 as char);
-        result.push((0x80 | (cpt & 0x3f)) as u8 as char);
-        return Ok(result);
-    }
-
-    if cpt >= 0x800 && cpt <= 0xffff {
-        // 三字节字符
-        result.push((0xe0 | ((cpt >> 12) & 0x0f)) as u8 as char);
-        result.push((0x80 | ((cpt >> 6) & 0x3f)) as u8 as char);
-        result.push((0x80 | (cpt & 0x3f)) as u8 as char);
-        return Ok(result);
-    }
-
-    if cpt >= 0x10000 && cpt <= 0x10ffff {
-        // 四字节字符
-        result.push((0xf0 | ((cpt >> 18) & 0x07)) as u8 as char);
-        result.push((0x80 | ((cpt >> 12) & 0x3f)) as u8 as char);
-        result.push((0x80 | ((cpt >> 6) & 0x3f)) as u8 as char);
-        result.push((0x80 | (cpt & 0x3f)) as u8 as char);
-        return Ok(result);
-    }
-
-    // 无效的码点
-    Err("无效的码点")
-}
-
-fn unicode_cpt_flags_from_cpt(cpt: u32) -> unicode_cpt_flags {
+/// 码点的标志，`is_nfd` 来自生成的规范分解表
+pub fn unicode_cpt_flags_from_cpt(cpt: u32) -> unicode_cpt_flags {
     // 这里需要实现从代码点获取标志的逻辑
     // 在实际实现中，您可能需要查询 Unicode 数据表
     let mut flags = unicode_cpt_flags::default();
+    flags.is_nfd = char::from_u32(cpt).is_some_and(is_nfd);
 
     if (cpt >= '0' as u32 && cpt <= '9' as u32) {
         flags.is_number = true;
@@ -612,17 +196,19 @@ fn unicode_cpt_flags_from_cpt(cpt: u32) -> unicode_cpt_flags {
     flags
 }
 
-fn unicode_tolower(cpt: u32) -> u32 {
-    // 简单的小写转换实现
-    if cpt >= 'A' as u32 && cpt <= 'Z' as u32 {
-        return cpt + ('a' as u32 - 'A' as u32);
-    }
-    cpt
-}
-
 pub fn unicode_byte_to_utf8(byte: u8) -> String {
-    let map = unicode_byte_to_utf8_map();
-    map.get(&byte).unwrap().to_string()
+    static MAP: LazyLock<HashMap<u8, char>> = LazyLock::new(unicode_byte_to_utf8_map);
+    MAP.get(&byte).unwrap().to_string()
+}
+/// [`unicode_byte_to_utf8`] 的逆映射，不在映射中的字符返回 `None`
+pub fn unicode_utf8_to_byte(ch: char) -> Option<u8> {
+    static MAP: LazyLock<HashMap<char, u8>> = LazyLock::new(|| {
+        unicode_byte_to_utf8_map()
+            .into_iter()
+            .map(|(byte, ch)| (ch, byte))
+            .collect()
+    });
+    MAP.get(&ch).copied()
 }
 /// 创建一个从字节到 UTF-8 字符串的映射
 fn unicode_byte_to_utf8_map() -> HashMap<u8, char> {
@@ -654,15 +240,13 @@ fn unicode_byte_to_utf8_map() -> HashMap<u8, char> {
 
     map
 }
-fn unicode_byte_encoding_process(bpe_words: &[String]) -> Vec<String> {
-    bpe_words
-        .into_iter()
-        .map(|word: &String| {
-            word.chars()
-                .map(|ch| unicode_byte_to_utf8(ch as u8))
-                .collect()
-        })
-        .collect()
+/// 按字节编码片段，每个字节映射为一个可见字符
+pub(crate) fn unicode_byte_encode(word: &str) -> String {
+    static TABLE: LazyLock<[char; 256]> = LazyLock::new(|| {
+        let map = unicode_byte_to_utf8_map();
+        std::array::from_fn(|byte| map[&(byte as u8)])
+    });
+    word.bytes().map(|byte| TABLE[byte as usize]).collect()
 }
 
 /// 获取 UTF-8 字符的长度
@@ -679,3 +263,293 @@ pub fn unicode_len_utf8(byte: u8) -> usize {
         1 // 无效的 UTF-8 序列，返回 1
     }
 }
+
+/// Unicode 规范化形式
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum NormalizationForm {
+    /// 规范分解后再组合
+    Nfc,
+    /// 规范分解
+    Nfd,
+    /// 兼容分解后再组合
+    Nfkc,
+    /// 兼容分解
+    Nfkd,
+}
+
+/// HF `tokenizers` 中分词前的规范化步骤
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Normalizer {
+    /// `NFC`、`NFD`、`NFKC` 或 `NFKD`
+    Form(NormalizationForm),
+    /// `Lowercase`：逐个字符转为小写
+    Lowercase,
+    /// `StripAccents`：去掉非间距标记（Mn），不先做 NFD
+    StripAccents,
+}
+
+/// 将文本规范化为 `form`
+pub fn unicode_normalize(text: &str, form: NormalizationForm) -> String {
+    NormalizedText::new(text).normalize(form).into_string()
+}
+
+/// 去除重音：先做 NFD，再去掉非间距标记（Mn），与 BERT 和 HF 的 `StripAccents` 相同
+pub fn unicode_strip_accents(text: &str) -> String {
+    NormalizedText::new(text)
+        .normalize(NormalizationForm::Nfd)
+        .strip_accents()
+        .into_string()
+}
+
+/// 规范化后的文本，记录每个字符对应原文中的字节范围
+///
+/// 分解得到的字符都对应被分解字符的范围，组合得到的字符对应被组合的字符范围的并集，
+/// 因此规范化后的片段总能映射回原文。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NormalizedText {
+    text: String,
+    /// 每个字符在 `text` 中的起始字节和在原文中的字节范围
+    alignments: Vec<(usize, Range<usize>)>,
+    original_len: usize,
+}
+
+impl NormalizedText {
+    /// 未经规范化的文本，每个字符对应自身
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            alignments: text
+                .char_indices()
+                .map(|(i, ch)| (i, i..i + ch.len_utf8()))
+                .collect(),
+            original_len: text.len(),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+
+    /// 规范化为 `form`
+    pub fn normalize(self, form: NormalizationForm) -> Self {
+        let compat = matches!(form, NormalizationForm::Nfkc | NormalizationForm::Nfkd);
+        let mut chars = self.decompose(compat);
+        if matches!(form, NormalizationForm::Nfc | NormalizationForm::Nfkc) {
+            chars = compose(chars);
+        }
+        Self::from_chars(chars, self.original_len)
+    }
+
+    /// 去掉非间距标记（Mn），通常在 NFD 之后使用
+    pub fn strip_accents(self) -> Self {
+        let chars = self
+            .alignments()
+            .filter(|(ch, _)| !is_nonspacing_mark(*ch))
+            .collect();
+        Self::from_chars(chars, self.original_len)
+    }
+
+    /// 逐个字符转为小写，与 HF 的 `Lowercase` 相同，不处理词尾的 Σ
+    pub fn lowercase(self) -> Self {
+        let chars = self
+            .alignments()
+            .flat_map(|(ch, original)| ch.to_lowercase().map(move |ch| (ch, original.clone())))
+            .collect();
+        Self::from_chars(chars, self.original_len)
+    }
+
+    /// 执行一个规范化步骤
+    pub fn apply(self, normalizer: Normalizer) -> Self {
+        match normalizer {
+            Normalizer::Form(form) => self.normalize(form),
+            Normalizer::Lowercase => self.lowercase(),
+            Normalizer::StripAccents => self.strip_accents(),
+        }
+    }
+
+    /// 规范化后文本中 `range` 对应原文中的字节范围
+    ///
+    /// `range` 不在字符边界上或越界时返回 `None`。空范围映射为原文中的一个位置。
+    pub fn original_range(&self, range: Range<usize>) -> Option<Range<usize>> {
+        if range.start > range.end
+            || !self.text.is_char_boundary(range.start)
+            || !self.text.is_char_boundary(range.end)
+        {
+            return None;
+        }
+        let first = self.alignments.partition_point(|(i, _)| *i < range.start);
+        let last = self.alignments.partition_point(|(i, _)| *i < range.end);
+        if first == last {
+            let pos = self
+                .alignments
+                .get(first)
+                .map_or(self.original_len, |(_, original)| original.start);
+            return Some(pos..pos);
+        }
+        let aligned = &self.alignments[first..last];
+        let start = aligned.iter().map(|(_, r)| r.start).min().unwrap();
+        let end = aligned.iter().map(|(_, r)| r.end).max().unwrap();
+        Some(start..end)
+    }
+
+    /// 每个字符和它在原文中的字节范围
+    pub fn alignments(&self) -> impl Iterator<Item = (char, Range<usize>)> + '_ {
+        self.text
+            .chars()
+            .zip(&self.alignments)
+            .map(|(ch, (_, original))| (ch, original.clone()))
+    }
+
+    fn from_chars(chars: Vec<(char, Range<usize>)>, original_len: usize) -> Self {
+        let mut text = String::with_capacity(chars.len());
+        let mut alignments = Vec::with_capacity(chars.len());
+        for (ch, original) in chars {
+            alignments.push((text.len(), original));
+            text.push(ch);
+        }
+        Self {
+            text,
+            alignments,
+            original_len,
+        }
+    }
+
+    /// 完全分解后按规范组合类稳定排序
+    fn decompose(&self, compat: bool) -> Vec<(char, Range<usize>)> {
+        let mut chars = Vec::with_capacity(self.text.len());
+        for (ch, original) in self.alignments() {
+            decompose_char(ch, compat, |ch| chars.push((ch, original.clone())));
+        }
+        reorder(&mut chars);
+        chars
+    }
+}
+
+impl fmt::Display for NormalizedText {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+const HANGUL_S_BASE: u32 = 0xAC00;
+const HANGUL_L_BASE: u32 = 0x1100;
+const HANGUL_V_BASE: u32 = 0x1161;
+const HANGUL_T_BASE: u32 = 0x11A7;
+const HANGUL_L_COUNT: u32 = 19;
+const HANGUL_V_COUNT: u32 = 21;
+const HANGUL_T_COUNT: u32 = 28;
+const HANGUL_N_COUNT: u32 = HANGUL_V_COUNT * HANGUL_T_COUNT;
+const HANGUL_S_COUNT: u32 = HANGUL_L_COUNT * HANGUL_N_COUNT;
+
+/// 完全分解一个字符，韩文音节按算法分解
+fn decompose_char(ch: char, compat: bool, mut push: impl FnMut(char)) {
+    let s_index = (ch as u32).wrapping_sub(HANGUL_S_BASE);
+    if s_index < HANGUL_S_COUNT {
+        push(char::from_u32(HANGUL_L_BASE + s_index / HANGUL_N_COUNT).unwrap());
+        push(char::from_u32(HANGUL_V_BASE + s_index % HANGUL_N_COUNT / HANGUL_T_COUNT).unwrap());
+        if !s_index.is_multiple_of(HANGUL_T_COUNT) {
+            push(char::from_u32(HANGUL_T_BASE + s_index % HANGUL_T_COUNT).unwrap());
+        }
+        return;
+    }
+    let lookup = |table: &[(char, &'static [char])]| {
+        table
+            .binary_search_by_key(&ch, |&(cpt, _)| cpt)
+            .ok()
+            .map(|i| table[i].1)
+    };
+    let decomposed = compat
+        .then(|| lookup(unicode_data::COMPATIBILITY_DECOMPOSITION))
+        .flatten()
+        .or_else(|| lookup(unicode_data::CANONICAL_DECOMPOSITION));
+    match decomposed {
+        Some(chars) => chars.iter().copied().for_each(push),
+        None => push(ch),
+    }
+}
+
+/// 按规范组合类稳定排序连续的非起始字符
+fn reorder(chars: &mut [(char, Range<usize>)]) {
+    for run in chars.split_mut(|(ch, _)| unicode_combining_class(*ch) == 0) {
+        run.sort_by_key(|(ch, _)| unicode_combining_class(*ch));
+    }
+}
+
+/// 规范组合，组合得到的字符对应两个字符在原文中范围的并集
+fn compose(chars: Vec<(char, Range<usize>)>) -> Vec<(char, Range<usize>)> {
+    let mut output: Vec<(char, Range<usize>)> = Vec::with_capacity(chars.len());
+    let mut starter: Option<usize> = None;
+    let mut last_class = 0;
+    for (ch, original) in chars {
+        let class = unicode_combining_class(ch);
+        if let Some(i) = starter {
+            // 与起始字符之间没有组合类相同或更高的字符时才能组合
+            let blocked = i + 1 != output.len() && last_class >= class;
+            if !blocked && let Some(composite) = compose_pair(output[i].0, ch) {
+                let range = &mut output[i].1;
+                *range = range.start.min(original.start)..range.end.max(original.end);
+                output[i].0 = composite;
+                continue;
+            }
+        }
+        if class == 0 {
+            starter = Some(output.len());
+        }
+        last_class = class;
+        output.push((ch, original));
+    }
+    output
+}
+
+/// 两个字符的主组合字符，韩文音节按算法组合
+fn compose_pair(first: char, second: char) -> Option<char> {
+    let (a, b) = (first as u32, second as u32);
+    let l_index = a.wrapping_sub(HANGUL_L_BASE);
+    let v_index = b.wrapping_sub(HANGUL_V_BASE);
+    if l_index < HANGUL_L_COUNT && v_index < HANGUL_V_COUNT {
+        return char::from_u32(
+            HANGUL_S_BASE + (l_index * HANGUL_V_COUNT + v_index) * HANGUL_T_COUNT,
+        );
+    }
+    let s_index = a.wrapping_sub(HANGUL_S_BASE);
+    let t_index = b.wrapping_sub(HANGUL_T_BASE);
+    if s_index < HANGUL_S_COUNT
+        && s_index.is_multiple_of(HANGUL_T_COUNT)
+        && 0 < t_index
+        && t_index < HANGUL_T_COUNT
+    {
+        return char::from_u32(a + t_index);
+    }
+    unicode_data::COMPOSITION
+        .binary_search_by_key(&(first, second), |&(a, b, _)| (a, b))
+        .ok()
+        .map(|i| unicode_data::COMPOSITION[i].2)
+}
+
+/// 规范组合类，起始字符为 0
+pub fn unicode_combining_class(ch: char) -> u8 {
+    let table = unicode_data::COMBINING_CLASS;
+    let i = table.partition_point(|&(_, last, _)| last < ch);
+    match table.get(i) {
+        Some(&(first, _, class)) if first <= ch => class,
+        _ => 0,
+    }
+}
+
+fn is_nonspacing_mark(ch: char) -> bool {
+    let table = unicode_data::NONSPACING_MARK;
+    let i = table.partition_point(|&(_, last)| last < ch);
+    table.get(i).is_some_and(|&(first, _)| first <= ch)
+}
+
+/// 字符在 NFD 中保持不变，即没有规范分解
+fn is_nfd(ch: char) -> bool {
+    (ch as u32).wrapping_sub(HANGUL_S_BASE) >= HANGUL_S_COUNT
+        && unicode_data::CANONICAL_DECOMPOSITION
+            .binary_search_by_key(&ch, |&(cpt, _)| cpt)
+            .is_err()
+}
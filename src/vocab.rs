@@ -0,0 +1,61 @@
+use std::fmt::Write;
+
+use crate::{
+    common::{TokenAttribute, TokenData, TokenId},
+    config::TokenizerConfig,
+};
+
+impl TokenizerConfig {
+    /// 按 id 顺序遍历词表
+    pub fn vocab(&self) -> impl Iterator<Item = (TokenId, &TokenData)> {
+        self.id_to_token
+            .iter()
+            .enumerate()
+            .map(|(id, token)| (id as TokenId, token))
+    }
+
+    /// 带有 `attribute` 全部位的标记，例如 [`TokenAttribute::Control`]、[`TokenAttribute::Byte`]
+    ///
+    /// [`TokenAttribute::Undefined`] 没有任何位，匹配所有标记。
+    pub fn tokens_with(
+        &self,
+        attribute: TokenAttribute,
+    ) -> impl Iterator<Item = (TokenId, &TokenData)> {
+        self.vocab()
+            .filter(move |(_, token)| token.attribute.contains(attribute))
+    }
+
+    /// 便于阅读的标记文本
+    ///
+    /// 与 [`token_to_piece`](Self::token_to_piece) 相同，控制标记输出原文，
+    /// 字节级编码和 SPM 的空格都已还原。不是合法 UTF-8 的字节写为 `<0xXY>`。
+    pub fn decoded_piece(&self, id: TokenId) -> String {
+        let piece = self.token_to_piece(id, 0, true);
+        let mut text = String::new();
+        for chunk in piece.utf8_chunks() {
+            text.push_str(chunk.valid());
+            for byte in chunk.invalid() {
+                write!(text, "<0x{byte:02X}>").unwrap();
+            }
+        }
+        text
+    }
+
+    /// 还原后的文本以 `prefix` 开头的标记
+    pub fn find_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (TokenId, &'a TokenData)> {
+        self.vocab()
+            .filter(move |&(id, _)| self.decoded_piece(id).starts_with(prefix))
+    }
+
+    /// 还原后的文本包含 `needle` 的标记
+    pub fn find_substring<'a>(
+        &'a self,
+        needle: &'a str,
+    ) -> impl Iterator<Item = (TokenId, &'a TokenData)> {
+        self.vocab()
+            .filter(move |&(id, _)| self.decoded_piece(id).contains(needle))
+    }
+}
@@ -0,0 +1,278 @@
+//! 分词器的二进制缓存
+
+mod common;
+
+use std::{
+    fs::{self, File},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use common::{
+    CONTROL, USER_DEFINED, bpe_gguf, load_gguf, precompiled_charsmap, spm_gguf, spm_vocab,
+    ugm_gguf, ugm_vocab,
+};
+use memmap2::Mmap;
+use proptest::prelude::*;
+use try_tokenize::{
+    CACHE_VERSION, CacheView, NormalizationForm, Normalizer, TokenizerConfig, gguf_tokenizer_key,
+    load_cached, save_cache, to_cache_bytes,
+};
+
+/// 每个测试使用不同的临时路径
+fn temp_path(ext: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "try-tokenize-cache-{}-{}.{ext}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+    ))
+}
+
+fn mmap(bytes: &[u8]) -> Mmap {
+    let path = temp_path("gguf");
+    fs::write(&path, bytes).unwrap();
+    let file = File::open(&path).unwrap();
+    let mmap = unsafe { Mmap::map(&file) }.unwrap();
+    fs::remove_file(&path).unwrap();
+    mmap
+}
+
+const TEXTS: &[&str] = &[
+    "Hello world",
+    " Hello  the tokenizer\n\n123 café",
+    "你好 привет 中",
+    "<|im_start|>user\n<tool_call>Hello<|im_end|><|endoftext|>",
+];
+
+/// 两个分词器的词表、特殊标记和分词结果都相同
+fn assert_same(a: &TokenizerConfig, b: &TokenizerConfig) {
+    assert_same_vocab(a, b);
+    assert_eq!(
+        [a.bos, a.eos, a.eot, a.unk, a.linefeed],
+        [b.bos, b.eos, b.eot, b.unk, b.linefeed]
+    );
+}
+
+/// 两个分词器的词表和分词结果相同
+///
+/// 有多个候选时 EOT 等特殊标记按哈希表的遍历顺序识别，分别解析同一个 GGUF 的结果可能不同，这里不比较。
+fn assert_same_vocab(a: &TokenizerConfig, b: &TokenizerConfig) {
+    assert_eq!(a.vocab_type, b.vocab_type);
+    assert_eq!(a.n_tokens(), b.n_tokens());
+    for ((id, x), (_, y)) in a.vocab().zip(b.vocab()) {
+        assert_eq!(x.text, y.text, "{id}");
+        assert_eq!(x.score.to_bits(), y.score.to_bits(), "{id}");
+        assert_eq!(x.attribute, y.attribute, "{id}");
+    }
+    assert_eq!(a.token_to_id, b.token_to_id);
+    assert_eq!(a.special_tokens, b.special_tokens);
+    assert_eq!(a.special_eog_ids, b.special_eog_ids);
+    assert_eq!(a.bpe_ranks, b.bpe_ranks);
+    assert_eq!(a.precompiled_charsmap, b.precompiled_charsmap);
+    assert_eq!(a.normalizers, b.normalizers);
+    assert_eq!(
+        [a.add_space_prefix, a.add_bos, a.add_eos, a.ignore_merges],
+        [b.add_space_prefix, b.add_bos, b.add_eos, b.ignore_merges]
+    );
+    for text in TEXTS {
+        for (add_special, parse_special) in [(false, false), (true, true)] {
+            let ids = a.tokenize(text, add_special, parse_special);
+            assert_eq!(ids, b.tokenize(text, add_special, parse_special), "{text}");
+            assert_eq!(
+                a.detokenize(&ids, false, true),
+                b.detokenize(&ids, false, true)
+            );
+        }
+    }
+}
+
+#[test]
+fn round_trip() {
+    let charsmap = precompiled_charsmap(&[("Ａ", "A"), ("ﬁ", "fi")]);
+    for gguf in [
+        bpe_gguf("qwen2"),
+        bpe_gguf("gpt2"),
+        spm_gguf(&spm_vocab()),
+        ugm_gguf(&ugm_vocab(), &charsmap),
+    ] {
+        let config = load_gguf(&gguf);
+        let key = gguf_tokenizer_key(&gguf).unwrap();
+        let bytes = to_cache_bytes(&config, key);
+        let view = CacheView::new(&bytes).unwrap();
+        assert_eq!(view.key(), key);
+        assert_eq!(view.n_tokens(), config.n_tokens() as usize);
+        for (id, token) in config.vocab() {
+            assert_eq!(view.text(id), token.text);
+            assert_eq!(view.attribute(id), token.attribute);
+        }
+        assert_eq!(view.merges().count(), config.bpe_ranks.len());
+        assert_same(&config, &view.to_config());
+    }
+}
+
+#[test]
+fn normalizers() {
+    let gguf = bpe_gguf("qwen2");
+    let mut config = load_gguf(&gguf);
+    config.normalizers = vec![
+        Normalizer::Form(NormalizationForm::Nfkc),
+        Normalizer::Lowercase,
+        Normalizer::StripAccents,
+    ];
+    let bytes = to_cache_bytes(&config, 0);
+    let cached = CacheView::new(&bytes).unwrap().to_config();
+    assert_same(&config, &cached);
+    // 规范化改变了分词结果，缓存后仍然一致
+    let text = "Ｈello ＷORLD café";
+    assert_ne!(
+        config.tokenize(text, false, false),
+        load_gguf(&gguf).tokenize(text, false, false)
+    );
+    assert_eq!(
+        cached.tokenize(text, false, false),
+        config.tokenize(text, false, false)
+    );
+}
+
+#[test]
+fn special_tokens() {
+    let gguf = bpe_gguf("qwen2");
+    let config = CacheView::new(&to_cache_bytes(&load_gguf(&gguf), 0))
+        .unwrap()
+        .to_config();
+    for text in CONTROL.iter().chain(USER_DEFINED) {
+        assert_eq!(config.tokenize(text, false, true).len(), 1, "{text}");
+    }
+    assert!(config.is_eog(config.text_to_token("<|endoftext|>")));
+}
+
+#[test]
+fn key_depends_on_tokenizer() {
+    let bpe = gguf_tokenizer_key(&bpe_gguf("qwen2")).unwrap();
+    assert_eq!(bpe, gguf_tokenizer_key(&bpe_gguf("qwen2")).unwrap());
+    assert_ne!(bpe, gguf_tokenizer_key(&bpe_gguf("gpt2")).unwrap());
+    assert_ne!(bpe, gguf_tokenizer_key(&spm_gguf(&spm_vocab())).unwrap());
+    assert!(gguf_tokenizer_key(b"not a gguf").is_err());
+}
+
+#[test]
+fn load_writes_and_reuses_cache() {
+    let gguf = bpe_gguf("qwen2");
+    let key = gguf_tokenizer_key(&gguf).unwrap();
+    let path = temp_path("cache");
+
+    let config = load_cached(mmap(&gguf), &path).unwrap();
+    let bytes = fs::read(&path).unwrap();
+    let view = CacheView::new(&bytes).unwrap();
+    assert_eq!(view.key(), key);
+    assert_same(&config, &view.to_config());
+    assert_same_vocab(&config, &load_gguf(&gguf));
+
+    // 命中缓存时标记的文本借用缓存文件的映射
+    let cached = load_cached(mmap(&gguf), &path).unwrap();
+    assert_same(&cached, &config);
+    assert!(cached.vocab().all(|(_, token)| token.text.is_mapped()));
+
+    // 键相同时直接使用缓存的内容，即使它来自另一个词表
+    let spm = load_gguf(&spm_gguf(&spm_vocab()));
+    save_cache(&spm, key, &path).unwrap();
+    assert_same(&load_cached(mmap(&gguf), &path).unwrap(), &spm);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn stale_cache_falls_back() {
+    let gguf = bpe_gguf("qwen2");
+    let key = gguf_tokenizer_key(&gguf).unwrap();
+    let expected = load_gguf(&gguf);
+    let path = temp_path("cache");
+
+    let mut bad_version = to_cache_bytes(&expected, key);
+    bad_version[8..12].copy_from_slice(&(CACHE_VERSION + 1).to_le_bytes());
+    let mut truncated = to_cache_bytes(&expected, key);
+    truncated.truncate(truncated.len() / 2);
+    for stale in [
+        to_cache_bytes(&load_gguf(&bpe_gguf("gpt2")), key ^ 1),
+        bad_version,
+        truncated,
+        b"garbage".to_vec(),
+    ] {
+        fs::write(&path, stale).unwrap();
+        let config = load_cached(mmap(&gguf), &path).unwrap();
+        assert_same_vocab(&config, &expected);
+        // 重新写入了有效的缓存
+        let bytes = fs::read(&path).unwrap();
+        let view = CacheView::new(&bytes).unwrap();
+        assert_eq!(view.key(), key);
+        assert_same(&config, &view.to_config());
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn bad_ids_are_rejected() {
+    const HEADER_LEN: usize = 120;
+    let config = load_gguf(&bpe_gguf("qwen2"));
+    let n_tokens = config.n_tokens();
+    let bytes = to_cache_bytes(&config, 0);
+    let special = HEADER_LEN + (2 * n_tokens as usize + config.token_to_id.len()) * 4;
+    let eog = special + config.special_tokens.len() * 4;
+    let merges = eog + config.special_eog_ids.len() * 4;
+    assert!(!config.bpe_ranks.is_empty());
+    // 头部的 BOS、特殊标记段、结束标记段和合并规则的左右标记
+    for pos in [24, special, eog, merges, merges + 4] {
+        let mut bad = bytes.clone();
+        bad[pos..pos + 4].copy_from_slice(&n_tokens.to_le_bytes());
+        let err = CacheView::new(&bad).unwrap_err();
+        assert!(
+            err.to_string().contains("token id out of range"),
+            "{pos}: {err}"
+        );
+    }
+
+    let mut config = config;
+    config.normalizers = vec![Normalizer::Lowercase];
+    let mut bad = to_cache_bytes(&config, 0);
+    let normalizers = merges + config.bpe_ranks.len() * 12;
+    assert_eq!(bad[normalizers..normalizers + 4], 4u32.to_le_bytes());
+    bad[normalizers..normalizers + 4].copy_from_slice(&6u32.to_le_bytes());
+    let err = CacheView::new(&bad).unwrap_err();
+    assert!(err.to_string().contains("unknown normalizer"), "{err}");
+}
+
+#[test]
+fn invalid_gguf_is_an_error() {
+    let path = temp_path("cache");
+    for gguf in [b"not a gguf".to_vec(), bpe_gguf("no-such-pre")] {
+        assert!(load_cached(mmap(&gguf), &path).is_err());
+        assert!(!path.exists());
+    }
+}
+
+thread_local! {
+    static BPE: (TokenizerConfig, TokenizerConfig) = {
+        let config = load_gguf(&bpe_gguf("llama3"));
+        let cached = CacheView::new(&to_cache_bytes(&config, 0)).unwrap().to_config();
+        (config, cached)
+    };
+    static SPM: (TokenizerConfig, TokenizerConfig) = {
+        let config = load_gguf(&spm_gguf(&spm_vocab()));
+        let cached = CacheView::new(&to_cache_bytes(&config, 0)).unwrap().to_config();
+        (config, cached)
+    };
+}
+
+proptest! {
+    #[test]
+    fn same_tokens(text in "[a-z ,.你好中é\n]{0,24}|\\PC{0,12}") {
+        BPE.with(|(config, cached)| {
+            prop_assert_eq!(config.tokenize(&text, true, true), cached.tokenize(&text, true, true));
+            Ok(())
+        })?;
+        SPM.with(|(config, cached)| {
+            prop_assert_eq!(config.tokenize(&text, true, true), cached.tokenize(&text, true, true));
+            Ok(())
+        })?;
+    }
+}
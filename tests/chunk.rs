@@ -0,0 +1,163 @@
+//! 标记的原文范围和滑动窗口分块
+
+mod common;
+
+use common::{CONTROL, USER_DEFINED, bpe, spm, ugm};
+use proptest::prelude::*;
+use try_tokenize::{
+    ChunkError, ChunkOptions, NormalizationForm, Normalizer, TokenizerConfig, VocabType,
+};
+
+fn options(max_tokens: usize, stride: usize) -> ChunkOptions {
+    ChunkOptions {
+        max_tokens,
+        stride,
+        add_special: true,
+        parse_special: false,
+    }
+}
+
+#[test]
+fn offsets() {
+    for config in [bpe("qwen2"), spm()] {
+        let text = format!(" Hello{} world{}\n\n你好 café", CONTROL[1], USER_DEFINED[0]);
+        let tokens = config.tokenize_with_offsets(&text, true, true);
+        let ids = tokens.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(ids, config.tokenize(&text, true, true));
+        // 范围首尾相接并覆盖全文
+        let mut pos = 0;
+        for (id, range) in &tokens {
+            assert_eq!(range.start, pos, "{id}");
+            pos = range.end;
+        }
+        assert_eq!(pos, text.len());
+        let special = config.text_to_token(CONTROL[1]);
+        let (_, range) = tokens.iter().find(|(id, _)| *id == special).unwrap();
+        assert_eq!(&text[range.clone()], CONTROL[1]);
+    }
+}
+
+#[test]
+fn normalized_offsets() {
+    let mut config = bpe("qwen2");
+    config.normalizers = vec![
+        Normalizer::Form(NormalizationForm::Nfkc),
+        Normalizer::Lowercase,
+    ];
+    let text = "Ｈello ＷORLD the ﬁ";
+    let tokens = config.tokenize_with_offsets(text, false, false);
+    let ids = tokens.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    assert_eq!(ids, config.tokenize(text, false, false));
+    let mut pos = 0;
+    for (id, range) in &tokens {
+        assert_eq!(range.start, pos, "{id}");
+        pos = range.end;
+    }
+    assert_eq!(pos, text.len());
+    // 规范化后的 " world" 对应原文中的全角字母
+    let world = config.text_to_token("Ġworld");
+    let (_, range) = tokens.iter().find(|(id, _)| *id == world).unwrap();
+    assert_eq!(&text[range.clone()], " ＷORLD");
+}
+
+#[test]
+fn unsupported_vocab() {
+    let config = ugm();
+    assert_eq!(
+        config.chunk("Hello world", &options(4, 2)),
+        Err(ChunkError::UnsupportedVocab(VocabType::Ugm))
+    );
+}
+
+#[test]
+fn windows() {
+    let config = bpe("qwen2");
+    let text = " Hello world the tokenizer".repeat(10);
+    let n = config.tokenize(&text, false, false).len();
+    let chunks = config.chunk(&text, &options(7, 4)).unwrap();
+    for chunk in &chunks {
+        assert!(chunk.ids.len() <= 7);
+        assert_eq!(chunk.ids[0], config.bos);
+    }
+    // 每个窗口 6 个文本标记，步长为 4
+    assert_eq!(chunks.len(), (n - 6).div_ceil(4) + 1);
+    assert_eq!(chunks[0].range.start, 0);
+    assert_eq!(chunks.last().unwrap().range.end, text.len());
+    for pair in chunks.windows(2) {
+        assert!(pair[1].range.start < pair[0].range.end);
+    }
+}
+
+#[test]
+fn paragraph_boundary() {
+    let config = bpe("qwen2");
+    let text = " Hello world the\n\n tokenizer Hello world";
+    let chunks = config.chunk(text, &options(7, 6)).unwrap();
+    assert_eq!(&text[chunks[0].range.clone()], " Hello world the\n\n");
+    assert!(text[chunks[1].range.clone()].starts_with(" tokenizer"));
+}
+
+#[test]
+fn sentence_boundary() {
+    let config = spm();
+    let text = "Hello world. the tokenizer Hello world";
+    let chunks = config.chunk(text, &options(5, 4)).unwrap();
+    assert!(text[chunks[0].range.clone()].ends_with("world."));
+}
+
+#[test]
+fn invalid_options() {
+    let config = spm();
+    assert_eq!(
+        config.chunk("Hello", &options(1, 1)),
+        Err(ChunkError::WindowTooSmall {
+            max_tokens: 1,
+            special: 1
+        })
+    );
+    for stride in [0, 4] {
+        assert_eq!(
+            config.chunk("Hello", &options(4, stride)),
+            Err(ChunkError::InvalidStride { stride, window: 3 })
+        );
+    }
+    assert_eq!(config.chunk("", &options(4, 2)), Ok(Vec::new()));
+}
+
+thread_local! {
+    static CONFIGS: Vec<TokenizerConfig> = {
+        let mut lowercase = bpe("gpt2");
+        lowercase.normalizers = vec![Normalizer::Lowercase];
+        vec![bpe("gpt2"), spm(), lowercase]
+    };
+}
+
+proptest! {
+    #[test]
+    fn chunks_cover_text(
+        text in "[ a-zA-Z0-9.\n]{1,80}",
+        max_tokens in 2usize..12,
+        stride in 1usize..12,
+    ) {
+        CONFIGS.with(|configs| {
+            for config in configs {
+                let options = ChunkOptions { add_special: false, ..options(max_tokens, stride.min(max_tokens)) };
+                let chunks = config.chunk(&text, &options).unwrap();
+                let ids = config.tokenize(&text, false, false);
+                prop_assert!(!chunks.is_empty());
+                prop_assert_eq!(chunks[0].range.start, 0);
+                prop_assert_eq!(chunks.last().unwrap().range.end, text.len());
+                // 每个窗口是原文分词结果的连续子串，相邻窗口之间没有遗漏
+                let mut covered = 0;
+                for chunk in &chunks {
+                    prop_assert!(!chunk.ids.is_empty() && chunk.ids.len() <= max_tokens);
+                    let start = (0..=covered).rev().find(|&i| ids[i..].starts_with(&chunk.ids));
+                    prop_assert!(start.is_some(), "{:?} {:?}", ids, chunk.ids);
+                    covered = start.unwrap() + chunk.ids.len();
+                }
+                prop_assert_eq!(covered, ids.len());
+            }
+            Ok(())
+        })?;
+    }
+}
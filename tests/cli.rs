@@ -0,0 +1,220 @@
+//! 命令行工具的各个子命令和退出码
+
+mod common;
+
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+};
+
+use common::{CONTROL, bpe_gguf};
+use serde_json::Value;
+
+/// 写入临时目录的 GGUF 文件，测试结束时删除
+struct Model(PathBuf);
+
+impl Model {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "try-tokenize-cli-{name}-{}.gguf",
+            std::process::id()
+        ));
+        fs::write(&path, bpe_gguf("qwen2")).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for Model {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_try-tokenize"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn tokenize_and_detokenize() {
+    let model = Model::new("roundtrip");
+    let path = model.0.to_str().unwrap();
+    let special = format!("Hello world{}", CONTROL[2]);
+
+    let plain = stdout(&run(&["tokenize", path, "--parse-special", &special], ""));
+    let json = stdout(&run(
+        &["tokenize", path, "--json", "--parse-special"],
+        &special,
+    ));
+    let ids = serde_json::from_str::<Value>(&json).unwrap()["ids"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(plain.trim(), ids.join(" "));
+
+    // tokenize --json 的输出可以直接作为 detokenize 的输入
+    let text = stdout(&run(&["detokenize", path], &json));
+    assert_eq!(text, format!("{}{special}\n", CONTROL[0]));
+    let text = stdout(&run(
+        &["detokenize", path, "--skip-special", "--json"],
+        &json,
+    ));
+    assert_eq!(
+        serde_json::from_str::<Value>(&text).unwrap()["text"],
+        "Hello world"
+    );
+}
+
+#[test]
+fn no_bos_and_count() {
+    let model = Model::new("count");
+    let path = model.0.to_str().unwrap();
+    let with_bos = stdout(&run(&["count", path, "Hello", "world"], ""));
+    let without_bos = stdout(&run(
+        &["count", path, "--no-bos", "--", "Hello", "world"],
+        "",
+    ));
+    assert_eq!(with_bos.trim(), "3");
+    assert_eq!(without_bos.trim(), "2");
+    let json = stdout(&run(&["count", path, "--json"], "Hello world"));
+    assert_eq!(serde_json::from_str::<Value>(&json).unwrap()["count"], 3);
+}
+
+#[test]
+fn input_file() {
+    let model = Model::new("file");
+    let path = model.0.to_str().unwrap();
+    let input = model.0.with_extension("txt");
+    fs::write(&input, "Hello world").unwrap();
+    let from_file = stdout(&run(
+        &["tokenize", path, "--file", input.to_str().unwrap()],
+        "",
+    ));
+    fs::remove_file(&input).unwrap();
+    let from_args = stdout(&run(&["tokenize", path, "Hello world"], ""));
+    assert_eq!(from_file, from_args);
+}
+
+#[test]
+fn inspect() {
+    let model = Model::new("inspect");
+    let path = model.0.to_str().unwrap();
+    let json = stdout(&run(&["inspect", path, "--json"], ""));
+    let info = serde_json::from_str::<Value>(&json).unwrap();
+    assert_eq!(info["vocab_type"], "Bpe");
+    assert_eq!(info["special"]["bos"]["text"], CONTROL[0]);
+    assert_eq!(info["special"]["pad"], Value::Null);
+    assert_eq!(info["flags"]["clean_spaces"], false);
+    assert_eq!(info["sizes"]["control"], CONTROL.len());
+
+    let plain = stdout(&run(&["inspect", path], ""));
+    assert!(plain.contains("vocab_type: Bpe"), "{plain}");
+    assert!(plain.contains("pad: -"), "{plain}");
+}
+
+#[test]
+fn edit() {
+    let model = Model::new("edit");
+    let path = model.0.to_str().unwrap();
+    let output = Model::new("edited");
+    let template = output.0.with_extension("jinja");
+    fs::write(&template, "{{ messages }}").unwrap();
+    let json = stdout(&run(
+        &[
+            "edit",
+            path,
+            "--output",
+            output.0.to_str().unwrap(),
+            "--set-id",
+            "eos=2",
+            "--add-bos",
+            "false",
+            "--chat-template",
+            template.to_str().unwrap(),
+            "--json",
+        ],
+        "",
+    ));
+    fs::remove_file(&template).unwrap();
+    let info = serde_json::from_str::<Value>(&json).unwrap();
+    assert_eq!(info["special"]["eos"]["id"], 2);
+    assert_eq!(info["flags"]["add_bos"], false);
+    // 原文件不变
+    let json = stdout(&run(&["inspect", path, "--json"], ""));
+    let info = serde_json::from_str::<Value>(&json).unwrap();
+    assert_eq!(info["special"]["eos"]["text"], CONTROL[0]);
+
+    for (args, code) in [
+        (&["edit", path][..], 2),
+        (&["edit", path, "--output", path, "--set-id", "eos"], 2),
+        (&["edit", path, "--output", path, "--add-bos", "yes"], 2),
+        (&["tokenize", path, "--pre", "gpt2", "x"], 2),
+        (
+            &["edit", path, "--output", path, "--set-id", "eos=99999"],
+            1,
+        ),
+        (&["edit", path, "--output", path, "--pre", "bogus"], 1),
+    ] {
+        let output = run(args, "");
+        assert_eq!(output.status.code(), Some(code), "{args:?}");
+        assert!(!output.stderr.is_empty(), "{args:?}");
+    }
+}
+
+#[test]
+fn exit_codes() {
+    let model = Model::new("errors");
+    let path = model.0.to_str().unwrap();
+    for (args, code) in [
+        (&["frobnicate", path][..], 2),
+        (&["tokenize", path, "--bogus"], 2),
+        (&["tokenize"], 2),
+        (&["inspect", path, "extra"], 2),
+        (&["tokenize", "/nonexistent/model.gguf", "x"], 1),
+        (&["detokenize", path, "1", "x"], 1),
+        (&["detokenize", path, "4294967295"], 1),
+    ] {
+        let output = run(args, "");
+        assert_eq!(output.status.code(), Some(code), "{args:?}");
+        assert!(!output.stderr.is_empty(), "{args:?}");
+    }
+}
+
+#[test]
+fn corrupt_model() {
+    let model = Model::new("corrupt");
+    let bytes = fs::read(&model.0).unwrap();
+    for (bytes, message) in [
+        (bytes[..bytes.len() / 2].to_vec(), "gguf"),
+        (bpe_gguf("no-such-pre"), "no-such-pre"),
+        (b"not a gguf file".to_vec(), "gguf"),
+    ] {
+        fs::write(&model.0, bytes).unwrap();
+        let output = run(&["tokenize", model.0.to_str().unwrap(), "x"], "");
+        assert_eq!(output.status.code(), Some(1), "{message}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(message), "{stderr}");
+        assert!(!stderr.contains("panicked"), "{stderr}");
+    }
+}
@@ -0,0 +1,644 @@
+//! 测试用的小词表，在内存中构造 GGUF 后写入临时文件，再通过 `load` 加载
+//!
+//! 同一个词表也可以写成 tokenizer.json 和 SentencePiece `.model`，用来测试其他加载器。
+
+#![allow(dead_code)]
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use memmap2::Mmap;
+use serde_json::{Value as Json, json};
+use try_tokenize::{
+    TokenizerConfig, load, load_hf_json, load_sentencepiece_bytes, unicode::unicode_byte_to_utf8,
+};
+
+/// 构造合并规则用到的词，覆盖 ASCII、拉丁扩展、CJK 和西里尔字母
+const WORDS: &[&str] = &[
+    "Hello",
+    " Hello",
+    " world",
+    " the",
+    "ing",
+    " tokenizer",
+    "123",
+    "\n\n",
+    " café",
+    "你好",
+    " 你好",
+    " привет",
+    "  ",
+];
+
+/// 在词表中但没有合并规则能得到的词，只有 `ignore_merges` 时才会整体输出
+pub const UNMERGED: &str = " unmerged";
+
+/// 控制标记，`parse_special` 为真时才会匹配
+pub const CONTROL: &[&str] = &["<|endoftext|>", "<|im_start|>", "<|im_end|>"];
+/// 用户定义的标记，总是会匹配
+pub const USER_DEFINED: &[&str] = &["<tool_call>"];
+
+/// 测试词表的内容，GGUF 和 tokenizer.json 两种形式都由它生成
+pub struct Vocab {
+    pub tokens: Vec<String>,
+    /// GGUF 的 token_type
+    pub types: Vec<i32>,
+    pub scores: Vec<f32>,
+    /// 空格分隔的合并规则，只有 BPE 词表有
+    pub merges: Vec<String>,
+}
+
+/// 字节级 BPE 词表的内容
+pub fn bpe_vocab() -> Vocab {
+    let mut tokens = (0..=255).map(unicode_byte_to_utf8).collect::<Vec<_>>();
+    let mut merges = Vec::new();
+    for word in WORDS {
+        let chars = word.bytes().map(unicode_byte_to_utf8).collect::<Vec<_>>();
+        let mut cur = chars[0].clone();
+        for ch in &chars[1..] {
+            let next = format!("{cur}{ch}");
+            if !tokens.contains(&next) {
+                merges.push(format!("{cur} {ch}"));
+                tokens.push(next.clone());
+            }
+            cur = next;
+        }
+    }
+    tokens.push(UNMERGED.bytes().map(unicode_byte_to_utf8).collect());
+    let mut types = vec![1; tokens.len()];
+    push_specials(&mut tokens, &mut types);
+    Vocab {
+        scores: vec![0.; tokens.len()],
+        tokens,
+        types,
+        merges,
+    }
+}
+
+/// 字节级 BPE 词表，`pre` 为 `tokenizer.ggml.pre`
+pub fn bpe(pre: &str) -> TokenizerConfig {
+    load_gguf(&bpe_gguf(pre))
+}
+
+/// [`bpe`] 词表的 GGUF 文件内容
+pub fn bpe_gguf(pre: &str) -> Vec<u8> {
+    bpe_vocab_gguf(&bpe_vocab(), pre)
+}
+
+/// 字节级 BPE 词表的 GGUF 文件内容，`vocab` 中必须有 [`CONTROL`] 中的标记
+pub fn bpe_vocab_gguf(vocab: &Vocab, pre: &str) -> Vec<u8> {
+    let Vocab {
+        tokens,
+        types,
+        merges,
+        ..
+    } = vocab;
+    let bos = tokens.iter().position(|t| t == CONTROL[0]).unwrap() as u32;
+    gguf(&[
+        ("general.architecture", Value::Str("llama")),
+        ("tokenizer.ggml.model", Value::Str("gpt2")),
+        ("tokenizer.ggml.pre", Value::Str(pre)),
+        ("tokenizer.ggml.tokens", Value::Strs(tokens)),
+        ("tokenizer.ggml.token_type", Value::I32s(types)),
+        ("tokenizer.ggml.merges", Value::Strs(merges)),
+        ("tokenizer.ggml.bos_token_id", Value::U32(bos)),
+        ("tokenizer.ggml.eos_token_id", Value::U32(bos)),
+    ])
+}
+
+/// SentencePiece（LLaMA）词表的内容，带字节回退
+pub fn spm_vocab() -> Vocab {
+    let mut tokens = vec!["<unk>".to_string(), "<s>".into(), "</s>".into()];
+    let mut types = vec![2, 3, 3];
+    tokens.extend((0..=255).map(|b| format!("<0x{b:02X}>")));
+    types.extend([6; 256]);
+
+    // 先加入所有字符，再加入每个词的前缀，越长的片段分数越低
+    let words = WORDS
+        .iter()
+        .map(|word| word.replace(' ', "\u{2581}"))
+        .collect::<Vec<_>>();
+    for word in &words {
+        for ch in word.chars() {
+            let ch = ch.to_string();
+            if !tokens.contains(&ch) {
+                tokens.push(ch);
+            }
+        }
+    }
+    for word in &words {
+        for (end, _) in word.char_indices().skip(1).chain([(word.len(), ' ')]) {
+            let prefix = word[..end].to_string();
+            if !tokens.contains(&prefix) {
+                tokens.push(prefix);
+            }
+        }
+    }
+    types.resize(tokens.len(), 1);
+    push_specials(&mut tokens, &mut types);
+    let scores = (0..tokens.len())
+        .map(|i| if types[i] == 1 { -(i as f32) } else { 0. })
+        .collect::<Vec<_>>();
+    Vocab {
+        tokens,
+        types,
+        scores,
+        merges: Vec::new(),
+    }
+}
+
+/// SentencePiece（LLaMA）词表，带字节回退
+pub fn spm() -> TokenizerConfig {
+    load_gguf(&spm_gguf(&spm_vocab()))
+}
+
+/// SPM 词表的 GGUF 文件内容
+pub fn spm_gguf(vocab: &Vocab) -> Vec<u8> {
+    gguf(&[
+        ("general.architecture", Value::Str("llama")),
+        ("tokenizer.ggml.model", Value::Str("llama")),
+        ("tokenizer.ggml.tokens", Value::Strs(&vocab.tokens)),
+        ("tokenizer.ggml.scores", Value::F32s(&vocab.scores)),
+        ("tokenizer.ggml.token_type", Value::I32s(&vocab.types)),
+    ])
+}
+
+/// T5（Unigram）词表的内容：`<pad>`、`</s>`、`<unk>` 之后是 SPM 词表中的普通片段，没有字节回退
+pub fn ugm_vocab() -> Vocab {
+    let spm = spm_vocab();
+    let mut tokens = vec!["<pad>".to_string(), "</s>".into(), "<unk>".into()];
+    let mut types = vec![3, 3, 2];
+    let mut scores = vec![0.; 3];
+    for ((token, &ty), &score) in spm.tokens.iter().zip(&spm.types).zip(&spm.scores) {
+        if ty == 1 {
+            tokens.push(token.clone());
+            types.push(1);
+            scores.push(score);
+        }
+    }
+    push_specials(&mut tokens, &mut types);
+    scores.resize(tokens.len(), 0.);
+    Vocab {
+        tokens,
+        types,
+        scores,
+        merges: Vec::new(),
+    }
+}
+
+/// T5 词表的 GGUF 文件内容，`charsmap` 为空时不写入
+pub fn ugm_gguf(vocab: &Vocab, charsmap: &[u8]) -> Vec<u8> {
+    let mut kvs = vec![
+        ("general.architecture", Value::Str("t5")),
+        ("tokenizer.ggml.model", Value::Str("t5")),
+        ("tokenizer.ggml.tokens", Value::Strs(&vocab.tokens)),
+        ("tokenizer.ggml.scores", Value::F32s(&vocab.scores)),
+        ("tokenizer.ggml.token_type", Value::I32s(&vocab.types)),
+        ("tokenizer.ggml.add_space_prefix", Value::Bool(true)),
+        ("tokenizer.ggml.remove_extra_whitespaces", Value::Bool(true)),
+    ];
+    if !charsmap.is_empty() {
+        kvs.push(("tokenizer.ggml.precompiled_charsmap", Value::U8s(charsmap)));
+    }
+    gguf(&kvs)
+}
+
+/// T5 词表
+pub fn ugm() -> TokenizerConfig {
+    load_gguf(&ugm_gguf(&ugm_vocab(), &[]))
+}
+
+/// 构造 SentencePiece 的 `precompiled_charsmap`，把每条规则的原文替换为对应的文本
+///
+/// 按 darts-clone 的 XOR 布局逐个节点放置双数组，每个节点的子节点使用不同的基址，只适合小的规则表。
+pub fn precompiled_charsmap(rules: &[(&str, &str)]) -> Vec<u8> {
+    #[derive(Default)]
+    struct Node {
+        children: BTreeMap<u8, Node>,
+        /// 替换文本在字符串表中的偏移
+        value: Option<u32>,
+    }
+
+    struct Units {
+        units: Vec<u32>,
+        used: Vec<bool>,
+        offsets: HashSet<u32>,
+    }
+
+    impl Units {
+        fn place(&mut self, node: &Node, index: usize) {
+            let mut labels = node.children.keys().map(|&b| b as u32).collect::<Vec<_>>();
+            if node.value.is_some() {
+                // 值放在标签为 0 的位置上
+                labels.push(0);
+            }
+            if labels.is_empty() {
+                return;
+            }
+            let free = |units: &Self, offset: u32| {
+                !units.offsets.contains(&offset)
+                    && labels.iter().all(|&label| {
+                        !units
+                            .used
+                            .get((offset ^ label) as usize)
+                            .copied()
+                            .unwrap_or(false)
+                    })
+            };
+            let offset = (1..).find(|&offset| free(self, offset)).unwrap();
+            // `offset` 是子节点的基址
+            self.offsets.insert(offset);
+            let len = labels
+                .iter()
+                .map(|&label| (offset ^ label) as usize + 1)
+                .max()
+                .unwrap();
+            if self.units.len() < len {
+                self.units.resize(len, 0);
+                self.used.resize(len, false);
+            }
+            // 单元里存的是相对于节点自身下标的 XOR 偏移
+            self.units[index] |=
+                (index as u32 ^ offset) << 10 | u32::from(node.value.is_some()) << 8;
+            if let Some(value) = node.value {
+                self.units[offset as usize] = 1 << 31 | value;
+                self.used[offset as usize] = true;
+            }
+            for &label in node.children.keys() {
+                let child = (offset ^ label as u32) as usize;
+                self.units[child] = label as u32;
+                self.used[child] = true;
+            }
+            for (&label, child) in &node.children {
+                self.place(child, (offset ^ label as u32) as usize);
+            }
+        }
+    }
+
+    let mut root = Node::default();
+    let mut replacements = Vec::new();
+    for (from, to) in rules {
+        let node = from
+            .bytes()
+            .fold(&mut root, |node, b| node.children.entry(b).or_default());
+        node.value = Some(replacements.len() as u32);
+        replacements.extend(to.as_bytes());
+        replacements.push(0);
+    }
+    let mut units = Units {
+        units: vec![0],
+        used: vec![true],
+        offsets: HashSet::new(),
+    };
+    units.place(&root, 0);
+
+    let mut data = ((units.units.len() * 4) as u32).to_le_bytes().to_vec();
+    units
+        .units
+        .iter()
+        .for_each(|unit| data.extend(unit.to_le_bytes()));
+    data.extend(replacements);
+    data
+}
+
+/// 尚未实现分词的词表类型（`bert`、`rwkv`、`no_vocab`）的 GGUF 文件内容
+pub fn unsupported_gguf(model: &str) -> Vec<u8> {
+    let tokens = ["[PAD]", "[UNK]", "[CLS]", "[SEP]", "hello", "##s"].map(String::from);
+    let types = [3, 2, 3, 3, 1, 1];
+    gguf(&[
+        ("general.architecture", Value::Str(model)),
+        ("tokenizer.ggml.model", Value::Str(model)),
+        ("tokenizer.ggml.tokens", Value::Strs(&tokens)),
+        ("tokenizer.ggml.token_type", Value::I32s(&types)),
+    ])
+}
+
+/// GGUF 文件内容写入临时文件，映射后加载
+pub fn load_gguf(bytes: &[u8]) -> TokenizerConfig {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "try-tokenize-{}-{}.gguf",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+    ));
+    fs::write(&path, bytes).unwrap();
+    let file = File::open(&path).unwrap();
+    let mmap = unsafe { Mmap::map(&file) }.unwrap();
+    fs::remove_file(&path).unwrap();
+    load(mmap)
+}
+
+/// HF 中的原始正则，llama.cpp 将其中的 `(?i:...)` 改写为了字符类
+pub const QWEN_HF: &str = "(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\\r\\n\\p{L}\\p{N}]?\\p{L}+|\\p{N}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+";
+pub const LLAMA3_HF: &str = "(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\\r\\n\\p{L}\\p{N}]?\\p{L}+|\\p{N}{1,3}| ?[^\\s\\p{L}\\p{N}]+[\\r\\n]*|\\s*[\\r\\n]+|\\s+(?!\\S)|\\s+";
+
+/// GGUF 中的未知、控制和用户定义标记在 tokenizer.json 中都是 added_tokens
+pub fn added_tokens(vocab: &Vocab) -> Vec<Json> {
+    vocab
+        .tokens
+        .iter()
+        .zip(&vocab.types)
+        .enumerate()
+        .filter(|&(_, (_, &ty))| matches!(ty, 2..=4))
+        .map(|(id, (text, &ty))| {
+            json!({
+                "id": id,
+                "content": text,
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": false,
+                "special": ty != 4,
+            })
+        })
+        .collect()
+}
+
+/// 与 [`bpe`] 相同的词表，按 HF 中对应模型的写法构造
+pub fn bpe_hf(pre: &str) -> (String, String) {
+    let vocab = bpe_vocab();
+    let split = |regex: &str| json!({ "type": "Split", "pattern": { "Regex": regex }, "behavior": "Isolated", "invert": false });
+    let byte_level = |use_regex: bool| json!({ "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true, "use_regex": use_regex });
+    let sequence =
+        |regex| json!({ "type": "Sequence", "pretokenizers": [split(regex), byte_level(false)] });
+    let (pre_tokenizer, ignore_merges, clean_spaces) = match pre {
+        "gpt2" => (byte_level(true), false, true),
+        "qwen2" => (sequence(QWEN_HF), false, false),
+        "llama-bpe" => (sequence(LLAMA3_HF), true, true),
+        _ => unreachable!(),
+    };
+    let ids = vocab
+        .tokens
+        .iter()
+        .enumerate()
+        .map(|(id, text)| (text.clone(), json!(id)))
+        .collect::<serde_json::Map<_, _>>();
+    let tokenizer = json!({
+        "version": "1.0",
+        "added_tokens": added_tokens(&vocab),
+        "normalizer": null,
+        "pre_tokenizer": pre_tokenizer,
+        "decoder": { "type": "ByteLevel" },
+        "model": {
+            "type": "BPE",
+            "vocab": ids,
+            "merges": vocab.merges,
+            "byte_fallback": false,
+            "ignore_merges": ignore_merges,
+        },
+    });
+    let tokenizer_config = json!({
+        "add_bos_token": true,
+        "bos_token": CONTROL[0],
+        "eos_token": CONTROL[0],
+        "clean_up_tokenization_spaces": clean_spaces,
+    });
+    (tokenizer.to_string(), tokenizer_config.to_string())
+}
+
+/// 与 [`spm`] 相同的词表，按 transformers 的 LlamaConverter 的写法构造
+///
+/// 合并表包含所有能拆成两个词表内标记的普通标记，按合并结果的分数从高到低排列。
+pub fn spm_hf(vocab: &Vocab) -> (String, String) {
+    let known = vocab.tokens.iter().collect::<HashSet<_>>();
+    let mut merges = vocab
+        .tokens
+        .iter()
+        .enumerate()
+        .filter(|&(id, _)| vocab.types[id] == 1)
+        .flat_map(|(id, text)| {
+            text.char_indices()
+                .skip(1)
+                .map(move |(i, _)| (vocab.scores[id], id, text.split_at(i)))
+        })
+        .filter(|(.., (left, right))| {
+            known.contains(&left.to_string()) && known.contains(&right.to_string())
+        })
+        .collect::<Vec<_>>();
+    merges.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    let merges = merges
+        .into_iter()
+        .map(|(.., (left, right))| json!([left, right]))
+        .collect::<Vec<_>>();
+    let ids = vocab
+        .tokens
+        .iter()
+        .enumerate()
+        .map(|(id, text)| (text.clone(), json!(id)))
+        .collect::<serde_json::Map<_, _>>();
+    let tokenizer = json!({
+        "version": "1.0",
+        "added_tokens": added_tokens(vocab),
+        "normalizer": {
+            "type": "Sequence",
+            "normalizers": [
+                { "type": "Prepend", "prepend": "\u{2581}" },
+                { "type": "Replace", "pattern": { "String": " " }, "content": "\u{2581}" },
+            ],
+        },
+        "pre_tokenizer": null,
+        "model": {
+            "type": "BPE",
+            "vocab": ids,
+            "merges": merges,
+            "unk_token": "<unk>",
+            "byte_fallback": true,
+        },
+    });
+    let tokenizer_config = json!({
+        "add_bos_token": true,
+        "add_eos_token": false,
+        "bos_token": "<s>",
+        "eos_token": "</s>",
+        "unk_token": "<unk>",
+        "clean_up_tokenization_spaces": false,
+    });
+    (tokenizer.to_string(), tokenizer_config.to_string())
+}
+
+/// 与 [`ugm_gguf`] 相同的 T5 词表，按 HF 的 T5 转换结果构造 Unigram 模型的 tokenizer.json
+pub fn ugm_hf(vocab: &Vocab, charsmap: &[u8]) -> (String, String) {
+    let pieces = vocab
+        .tokens
+        .iter()
+        .zip(&vocab.scores)
+        .map(|(text, &score)| json!([text, score]))
+        .collect::<Vec<_>>();
+    let tokenizer = json!({
+        "version": "1.0",
+        "added_tokens": added_tokens(vocab),
+        "normalizer": {
+            "type": "Sequence",
+            "normalizers": [
+                { "type": "Precompiled", "precompiled_charsmap": STANDARD.encode(charsmap) },
+                { "type": "Replace", "pattern": { "Regex": " {2,}" }, "content": " " },
+            ],
+        },
+        "pre_tokenizer": { "type": "Metaspace", "replacement": "\u{2581}", "prepend_scheme": "always", "split": false },
+        "post_processor": {
+            "type": "TemplateProcessing",
+            "single": [
+                { "Sequence": { "id": "A", "type_id": 0 } },
+                { "SpecialToken": { "id": "</s>", "type_id": 0 } },
+            ],
+        },
+        "model": { "type": "Unigram", "unk_id": 2, "vocab": pieces, "byte_fallback": false },
+    });
+    let tokenizer_config = json!({
+        "eos_token": "</s>",
+        "unk_token": "<unk>",
+        "pad_token": "<pad>",
+        "clean_up_tokenization_spaces": false,
+    });
+    (tokenizer.to_string(), tokenizer_config.to_string())
+}
+
+/// 从 [`bpe_hf`] 或 [`spm_hf`] 生成的 tokenizer.json 加载
+pub fn hf((tokenizer, tokenizer_config): (String, String)) -> TokenizerConfig {
+    load_hf_json(&tokenizer, Some(&tokenizer_config)).unwrap()
+}
+
+/// `.tiktoken` 排名文件，`tokens` 中的序号即排名
+pub fn tiktoken_ranks(tokens: &[Vec<u8>]) -> String {
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(rank, bytes)| format!("{} {rank}\n", STANDARD.encode(bytes)))
+        .collect()
+}
+
+/// SentencePiece `ModelProto`，`pieces` 为 (文本, 分数, 类型)，类型与 GGUF 的 token_type 相同
+///
+/// `model_type` 为 `None` 时使用 proto 中的默认值（unigram）。
+pub fn sentencepiece_model(
+    pieces: &[(&str, f32, u64)],
+    model_type: Option<u64>,
+    add_dummy_prefix: bool,
+) -> Vec<u8> {
+    fn varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+    fn field_varint(out: &mut Vec<u8>, field: u64, value: u64) {
+        varint(out, field << 3);
+        varint(out, value);
+    }
+    fn field_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+        varint(out, field << 3 | 2);
+        varint(out, bytes.len() as u64);
+        out.extend_from_slice(bytes);
+    }
+
+    let mut out = Vec::new();
+    for &(text, score, ty) in pieces {
+        let mut piece = Vec::new();
+        field_bytes(&mut piece, 1, text.as_bytes());
+        varint(&mut piece, 2 << 3 | 5);
+        piece.extend_from_slice(&score.to_le_bytes());
+        field_varint(&mut piece, 3, ty);
+        field_bytes(&mut out, 1, &piece);
+    }
+    let mut trainer = Vec::new();
+    if let Some(model_type) = model_type {
+        field_varint(&mut trainer, 3, model_type);
+    }
+    field_bytes(&mut out, 2, &trainer);
+    let mut normalizer = Vec::new();
+    field_varint(&mut normalizer, 3, add_dummy_prefix as u64);
+    field_bytes(&mut out, 3, &normalizer);
+    out
+}
+
+/// SPM 词表写成 BPE 类型的 SentencePiece `.model` 后加载
+pub fn spm_sentencepiece(vocab: &Vocab) -> TokenizerConfig {
+    let pieces = vocab
+        .tokens
+        .iter()
+        .zip(&vocab.scores)
+        .zip(&vocab.types)
+        .map(|((text, &score), &ty)| (text.as_str(), score, ty as u64))
+        .collect::<Vec<_>>();
+    load_sentencepiece_bytes(&sentencepiece_model(&pieces, Some(2), true)).unwrap()
+}
+
+fn push_specials(tokens: &mut Vec<String>, types: &mut Vec<i32>) {
+    for &text in CONTROL {
+        tokens.push(text.into());
+        types.push(3);
+    }
+    for &text in USER_DEFINED {
+        tokens.push(text.into());
+        types.push(4);
+    }
+}
+
+enum Value<'a> {
+    Str(&'a str),
+    U32(u32),
+    Bool(bool),
+    U8s(&'a [u8]),
+    Strs(&'a [String]),
+    I32s(&'a [i32]),
+    F32s(&'a [f32]),
+}
+
+/// 只有元数据的 GGUF 文件
+fn gguf(kvs: &[(&str, Value)]) -> Vec<u8> {
+    fn str(buf: &mut Vec<u8>, s: &str) {
+        buf.extend((s.len() as u64).to_le_bytes());
+        buf.extend(s.as_bytes());
+    }
+    fn arr(buf: &mut Vec<u8>, ty: u32, len: usize) {
+        buf.extend(9u32.to_le_bytes());
+        buf.extend(ty.to_le_bytes());
+        buf.extend((len as u64).to_le_bytes());
+    }
+
+    let mut buf = b"GGUF".to_vec();
+    buf.extend(3u32.to_le_bytes());
+    buf.extend(0u64.to_le_bytes());
+    buf.extend((kvs.len() as u64).to_le_bytes());
+    for (key, value) in kvs {
+        str(&mut buf, key);
+        match value {
+            Value::Str(s) => {
+                buf.extend(8u32.to_le_bytes());
+                str(&mut buf, s);
+            }
+            Value::U32(v) => {
+                buf.extend(4u32.to_le_bytes());
+                buf.extend(v.to_le_bytes());
+            }
+            Value::Bool(v) => {
+                buf.extend(7u32.to_le_bytes());
+                buf.push(u8::from(*v));
+            }
+            Value::U8s(items) => {
+                arr(&mut buf, 0, items.len());
+                buf.extend(*items);
+            }
+            Value::Strs(items) => {
+                arr(&mut buf, 8, items.len());
+                for s in *items {
+                    str(&mut buf, s);
+                }
+            }
+            Value::I32s(items) => {
+                arr(&mut buf, 5, items.len());
+                items.iter().for_each(|v| buf.extend(v.to_le_bytes()));
+            }
+            Value::F32s(items) => {
+                arr(&mut buf, 6, items.len());
+                items.iter().for_each(|v| buf.extend(v.to_le_bytes()));
+            }
+        }
+    }
+    buf
+}
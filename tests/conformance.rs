@@ -0,0 +1,236 @@
+//! 与 llama.cpp `test-tokenizer-0` 相同的一致性测试
+//!
+//! llama.cpp 的 `models/` 目录中有 `ggml-vocab-*.gguf` 和对应的 `.gguf.inp`、`.gguf.out`：
+//! `.inp` 是以 `__ggml_vocab_test__` 分隔的输入，`.out` 每行是一个输入的标记 id。
+//! 设置环境变量 `LLAMA_CPP_VOCAB_DIR` 指向这个目录后运行
+//! `cargo test --test conformance -- --nocapture`，逐个模型比较分词结果，不一致时输出 id 和片段的差异。
+
+mod common;
+
+use std::{
+    fmt::Write,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use common::bpe_gguf;
+use memmap2::Mmap;
+use try_tokenize::{TokenId, TokenizerConfig, try_load};
+
+/// `.inp` 中分隔输入的行
+const SEPARATOR: &str = "\n__ggml_vocab_test__\n";
+
+/// 读取 `.inp` 和 `.out` 中的输入和期望的标记
+fn read_cases(inp: &Path, out: &Path) -> Result<Vec<(String, Vec<TokenId>)>, String> {
+    let inp = fs::read_to_string(inp).map_err(|e| format!("{}: {e}", inp.display()))?;
+    let out = fs::read_to_string(out).map_err(|e| format!("{}: {e}", out.display()))?;
+    // 文件以分隔行结尾，最后一段为空
+    let mut inputs = inp.split(SEPARATOR).collect::<Vec<_>>();
+    if inputs.last() == Some(&"") {
+        inputs.pop();
+    }
+    let outputs = out.lines().collect::<Vec<_>>();
+    if inputs.len() != outputs.len() {
+        return Err(format!(
+            "{} inputs but {} outputs",
+            inputs.len(),
+            outputs.len()
+        ));
+    }
+    inputs
+        .into_iter()
+        .zip(outputs)
+        .map(|(input, output)| {
+            let ids = output
+                .split_whitespace()
+                .map(|id| id.parse().map_err(|_| format!("invalid token id `{id}`")))
+                .collect::<Result<_, _>>()?;
+            Ok((input.to_string(), ids))
+        })
+        .collect()
+}
+
+/// 一个模型的比较结果
+struct Report {
+    passed: usize,
+    total: usize,
+    /// 不一致的输入的差异
+    diff: String,
+}
+
+/// 与 `test-tokenizer-0` 相同，不添加 BOS/EOS，不匹配控制标记
+fn check(config: &TokenizerConfig, cases: &[(String, Vec<TokenId>)]) -> Report {
+    let mut report = Report {
+        passed: 0,
+        total: cases.len(),
+        diff: String::new(),
+    };
+    let pieces = |ids: &[TokenId]| {
+        ids.iter()
+            .map(|&id| {
+                if id < config.n_tokens() {
+                    String::from_utf8_lossy(&config.token_to_piece(id, 0, true)).into_owned()
+                } else {
+                    format!("<{id} out of range>")
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    for (i, (text, expected)) in cases.iter().enumerate() {
+        let actual = config.tokenize(text, false, false);
+        if &actual == expected {
+            report.passed += 1;
+            continue;
+        }
+        let first = actual
+            .iter()
+            .zip(expected)
+            .take_while(|(a, b)| a == b)
+            .count();
+        writeln!(report.diff, "  case {i}: {text:?}").unwrap();
+        writeln!(report.diff, "    expected: {expected:?}").unwrap();
+        writeln!(report.diff, "              {:?}", pieces(expected)).unwrap();
+        writeln!(report.diff, "    actual:   {actual:?}").unwrap();
+        writeln!(report.diff, "              {:?}", pieces(&actual)).unwrap();
+        writeln!(report.diff, "    first difference at token {first}").unwrap();
+    }
+    report
+}
+
+/// 加载 `gguf` 并比较，加载失败（包括不支持的词表类型）时返回错误
+fn check_model(gguf: &Path) -> Result<Report, String> {
+    let mut inp = gguf.as_os_str().to_owned();
+    inp.push(".inp");
+    let mut out = gguf.as_os_str().to_owned();
+    out.push(".out");
+    let cases = read_cases(Path::new(&inp), Path::new(&out))?;
+    let file = File::open(gguf).map_err(|e| e.to_string())?;
+    let mmap = unsafe { Mmap::map(&file) }.map_err(|e| e.to_string())?;
+    let config = try_load(mmap).map_err(|e| format!("failed to load: {e}"))?;
+    Ok(check(&config, &cases))
+}
+
+/// 目录中有 `.inp` 和 `.out` 的 GGUF，按文件名排序
+fn vocab_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == "gguf")
+                && path.with_extension("gguf.inp").exists()
+                && path.with_extension("gguf.out").exists()
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// 逐个模型比较，返回汇总和是否全部一致
+fn run(dir: &Path) -> (String, bool) {
+    let mut summary = String::new();
+    let mut ok = true;
+    for path in vocab_files(dir) {
+        let name = path.file_name().unwrap().to_string_lossy();
+        match check_model(&path) {
+            Ok(report) => {
+                let status = if report.passed == report.total {
+                    "ok"
+                } else {
+                    "FAILED"
+                };
+                writeln!(
+                    summary,
+                    "{name}: {status} {}/{}",
+                    report.passed, report.total
+                )
+                .unwrap();
+                summary.push_str(&report.diff);
+                ok &= report.passed == report.total;
+            }
+            Err(e) => {
+                writeln!(summary, "{name}: ERROR {e}").unwrap();
+                ok = false;
+            }
+        }
+    }
+    (summary, ok)
+}
+
+#[test]
+fn llama_cpp_vocabs() {
+    let Some(dir) = std::env::var_os("LLAMA_CPP_VOCAB_DIR") else {
+        eprintln!("LLAMA_CPP_VOCAB_DIR is not set, skipping llama.cpp conformance tests");
+        return;
+    };
+    let dir = PathBuf::from(dir);
+    assert!(
+        !vocab_files(&dir).is_empty(),
+        "no ggml-vocab-*.gguf with .inp/.out in {}",
+        dir.display()
+    );
+    let (summary, ok) = run(&dir);
+    println!("{summary}");
+    assert!(ok, "{summary}");
+}
+
+/// 用测试词表生成的夹具检查比较和报告本身
+#[test]
+fn harness_reports_mismatches() {
+    let dir = std::env::temp_dir().join(format!("try-tokenize-conformance-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let gguf = bpe_gguf("qwen2");
+    let config = common::load_gguf(&gguf);
+    let inputs = ["Hello world", " 你好\n\n", "<|endoftext|> 123"];
+    let inp = inputs
+        .iter()
+        .map(|text| format!("{text}{SEPARATOR}"))
+        .collect::<String>();
+    let out = |ids: &dyn Fn(usize, Vec<TokenId>) -> Vec<TokenId>| {
+        inputs
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let ids = ids(i, config.tokenize(text, false, false));
+                let ids = ids.iter().map(|id| format!(" {id}")).collect::<String>();
+                format!("{ids}\n")
+            })
+            .collect::<String>()
+    };
+    for name in [
+        "ggml-vocab-good.gguf",
+        "ggml-vocab-bad.gguf",
+        "ggml-vocab-broken.gguf",
+    ] {
+        fs::write(dir.join(name), &gguf).unwrap();
+        fs::write(dir.join(format!("{name}.inp")), &inp).unwrap();
+    }
+    fs::write(dir.join("ggml-vocab-good.gguf.out"), out(&|_, ids| ids)).unwrap();
+    // 第二个输入的最后一个标记不同
+    let bad = out(&|i, mut ids| {
+        if i == 1 {
+            *ids.last_mut().unwrap() += 1;
+        }
+        ids
+    });
+    fs::write(dir.join("ggml-vocab-bad.gguf.out"), bad).unwrap();
+    fs::write(dir.join("ggml-vocab-broken.gguf.out"), " 1\n").unwrap();
+    // 没有 .inp/.out 的 GGUF 不参与比较
+    fs::write(dir.join("ggml-vocab-alone.gguf"), &gguf).unwrap();
+
+    let (summary, ok) = run(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(!ok);
+    let lines = summary.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "ggml-vocab-bad.gguf: FAILED 2/3");
+    assert_eq!(lines[1], r#"  case 1: " 你好\n\n""#);
+    assert!(
+        lines[6].ends_with("first difference at token 2"),
+        "{summary}"
+    );
+    assert_eq!(
+        lines[7],
+        "ggml-vocab-broken.gguf: ERROR 3 inputs but 1 outputs"
+    );
+    assert_eq!(lines[8], "ggml-vocab-good.gguf: ok 3/3");
+    assert_eq!(lines.len(), 9);
+}
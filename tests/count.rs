@@ -0,0 +1,100 @@
+//! 不保存分词结果的标记计数
+
+mod common;
+
+use common::{CONTROL, USER_DEFINED, bpe, spm};
+use proptest::prelude::*;
+use try_tokenize::{TokenAttribute, TokenizerConfig};
+
+fn configs() -> Vec<(&'static str, TokenizerConfig)> {
+    // 去除两侧空白的特殊标记，计数时的分割需要与 tokenize 相同
+    let mut strip = bpe("qwen2");
+    for text in [CONTROL[1], USER_DEFINED[0]] {
+        let id = strip.token_to_id[text] as usize;
+        strip.id_to_token[id].attribute |= TokenAttribute::LStrIp | TokenAttribute::RStrIp;
+    }
+    vec![
+        ("gpt2", bpe("gpt2")),
+        ("qwen2", bpe("qwen2")),
+        ("qwen2-strip", strip),
+        ("llama-bpe", bpe("llama-bpe")),
+        ("spm", spm()),
+    ]
+}
+
+#[test]
+fn fits_within() {
+    for (name, config) in configs() {
+        let text = format!("Hello world{} the tokenizer 123456", CONTROL[1]);
+        let n = config.tokenize(&text, true, false).len();
+        assert!(config.fits_within(&text, n), "{name}");
+        assert!(!config.fits_within(&text, n - 1), "{name}");
+        assert!(config.fits_within("", 2), "{name}");
+    }
+}
+
+#[test]
+fn limit_below_specials() {
+    // BOS/EOS 已经超过上限时不再处理文本
+    let config = spm();
+    assert!(!config.fits_within("Hello world", 0));
+    assert!(!config.fits_within("Hello world", 1));
+}
+
+#[test]
+fn fits_within_long_text() {
+    // 超过上限后提前结束，不需要处理完整的文本
+    let config = bpe("qwen2");
+    let text = " Hello world".repeat(100_000);
+    assert!(!config.fits_within(&text, 10));
+}
+
+fn text() -> impl Strategy<Value = String> {
+    let specials = CONTROL
+        .iter()
+        .chain(USER_DEFINED)
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+    prop::collection::vec(
+        prop_oneof![
+            3 => any::<String>(),
+            2 => prop::sample::select(specials),
+            3 => "[ a-zA-Z0-9'\n]{1,12}",
+            1 => "[ \n]{1,4}",
+        ],
+        0..8,
+    )
+    .prop_map(|parts| parts.concat())
+}
+
+thread_local! {
+    static CONFIGS: Vec<(&'static str, TokenizerConfig)> = configs();
+}
+
+proptest! {
+    #[test]
+    fn same_as_tokenize(text in text(), add_special: bool, parse_special: bool) {
+        CONFIGS.with(|configs| {
+            for (name, config) in configs {
+                prop_assert_eq!(
+                    config.count_tokens(&text, add_special, parse_special),
+                    config.tokenize(&text, add_special, parse_special).len(),
+                    "{}",
+                    name
+                );
+            }
+            Ok(())
+        })?;
+    }
+
+    #[test]
+    fn fits_within_matches_count(text in text(), limit in 0usize..16) {
+        CONFIGS.with(|configs| {
+            for (name, config) in configs {
+                let n = config.tokenize(&text, true, false).len();
+                prop_assert_eq!(config.fits_within(&text, limit), n <= limit, "{}", name);
+            }
+            Ok(())
+        })?;
+    }
+}
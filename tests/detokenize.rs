@@ -0,0 +1,83 @@
+//! 标记序列还原为文本，与 llama.cpp 的 `detokenize` 相同
+
+mod common;
+
+use common::{CONTROL, bpe, spm};
+use proptest::prelude::*;
+use try_tokenize::TokenizerConfig;
+
+#[test]
+fn bpe_bytes() {
+    let config = bpe("qwen2");
+    for text in ["Hello world", " 你好 café\n\n", "привет123"] {
+        let ids = config.tokenize(text, false, false);
+        assert_eq!(config.detokenize(&ids, false, false), text);
+    }
+}
+
+#[test]
+fn special_tokens() {
+    let config = bpe("qwen2");
+    let text = format!("{}Hello{}", CONTROL[1], CONTROL[2]);
+    let ids = config.tokenize(&text, true, true);
+    assert_eq!(
+        config.detokenize(&ids, false, true),
+        format!("{}{text}", CONTROL[0])
+    );
+    assert_eq!(config.detokenize(&ids, true, true), text);
+    assert_eq!(config.detokenize(&ids, true, false), "Hello");
+}
+
+#[test]
+fn spm_space_prefix() {
+    let config = spm();
+    let ids = config.tokenize("Hello world", false, false);
+    assert_eq!(config.detokenize(&ids, false, false), "Hello world");
+    // 与 llama.cpp 相同，去除 BOS 时不去除开头补的空格
+    let ids = config.tokenize("Hello world", true, false);
+    assert_eq!(config.detokenize(&ids, true, false), " Hello world");
+}
+
+#[test]
+fn spm_byte_fallback() {
+    let config = spm();
+    let ids = config.tokenize("Hello 😀", false, false);
+    assert_eq!(config.detokenize(&ids, false, false), "Hello 😀");
+}
+
+#[test]
+fn clean_spaces() {
+    let config = bpe("gpt2");
+    assert!(config.clean_spaces);
+    for (text, cleaned) in [
+        ("Hello , world !", "Hello, world!"),
+        ("it ' s", "it's"),
+        ("we 're here", "we're here"),
+        ("don 't", "don 't"),
+    ] {
+        let ids = config.tokenize(text, false, false);
+        assert_eq!(config.detokenize(&ids, false, false), cleaned);
+    }
+}
+
+fn roundtrip(config: &TokenizerConfig, text: &str) -> String {
+    let ids = config.tokenize(text, false, false);
+    config.detokenize(&ids, false, false)
+}
+
+thread_local! {
+    static QWEN: TokenizerConfig = bpe("qwen2");
+    static SPM: TokenizerConfig = spm();
+}
+
+proptest! {
+    #[test]
+    fn bpe_roundtrip(text in any::<String>()) {
+        prop_assert_eq!(QWEN.with(|config| roundtrip(config, &text)), text);
+    }
+
+    #[test]
+    fn spm_roundtrip(text in "[^ ].*") {
+        prop_assert_eq!(SPM.with(|config| roundtrip(config, &text)), text);
+    }
+}
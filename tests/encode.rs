@@ -0,0 +1,191 @@
+//! 按最大长度截断和填充
+
+mod common;
+
+use common::{CONTROL, bpe, spm};
+use proptest::prelude::*;
+use try_tokenize::{EncodeError, EncodeOptions, Padding, PaddingSide, TokenizerConfig, Truncation};
+
+const TEXT: &str = " Hello world the tokenizer 123 café";
+
+/// 加上填充标记的 SPM 词表，自动添加 BOS 和 EOS
+fn config() -> TokenizerConfig {
+    let mut config = spm();
+    config.pad = config.text_to_token(CONTROL[0]);
+    config.add_eos = true;
+    config
+}
+
+fn options(max_length: usize, truncation: Truncation) -> EncodeOptions {
+    EncodeOptions {
+        max_length: Some(max_length),
+        truncation,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn same_as_tokenize() {
+    let config = config();
+    for (add_special, parse_special) in [(true, false), (false, true)] {
+        let options = EncodeOptions {
+            add_special,
+            parse_special,
+            ..Default::default()
+        };
+        let encoding = config.encode(TEXT, &options).unwrap();
+        let ids = config.tokenize(TEXT, add_special, parse_special);
+        assert_eq!(encoding.attention_mask, vec![1; ids.len()]);
+        assert_eq!(encoding.ids, ids);
+        assert_eq!(encoding.truncated, 0);
+    }
+}
+
+#[test]
+fn truncation_keeps_specials() {
+    let config = config();
+    let body = config.tokenize(TEXT, false, false);
+    let n = body.len();
+    let wrap = |ids: &[u32]| [&[config.bos], ids, &[config.eos]].concat();
+
+    let head = config.encode(TEXT, &options(6, Truncation::Head)).unwrap();
+    assert_eq!(head.ids, wrap(&body[..4]));
+    assert_eq!(head.truncated, n - 4);
+
+    let tail = config.encode(TEXT, &options(6, Truncation::Tail)).unwrap();
+    assert_eq!(tail.ids, wrap(&body[n - 4..]));
+    assert_eq!(tail.truncated, n - 4);
+
+    let ellipsis = config.tokenize("...", false, false);
+    let truncation = Truncation::HeadTail {
+        ellipsis: ellipsis.clone(),
+    };
+    let max_length = 2 + ellipsis.len() + 3;
+    let both = config
+        .encode(TEXT, &options(max_length, truncation.clone()))
+        .unwrap();
+    assert_eq!(
+        both.ids,
+        wrap(&[&body[..2], &ellipsis, &body[n - 1..]].concat())
+    );
+    assert_eq!(both.truncated, n - 3);
+
+    // 放不下省略标记时只保留开头
+    let short = config.encode(TEXT, &options(3, truncation)).unwrap();
+    assert_eq!(short.ids, wrap(&body[..1]));
+
+    // 不需要截断时不插入省略标记
+    let fits = config
+        .encode(
+            TEXT,
+            &options(
+                n + 2,
+                Truncation::HeadTail {
+                    ellipsis: ellipsis.clone(),
+                },
+            ),
+        )
+        .unwrap();
+    assert_eq!(fits.ids, wrap(&body));
+    assert_eq!(fits.truncated, 0);
+}
+
+#[test]
+fn max_length_too_small() {
+    let config = config();
+    assert_eq!(
+        config.encode(TEXT, &options(1, Truncation::Head)),
+        Err(EncodeError::MaxLengthTooSmall {
+            max_length: 1,
+            special: 2
+        })
+    );
+    // 只有 BOS/EOS
+    let encoding = config.encode(TEXT, &options(2, Truncation::Tail)).unwrap();
+    assert_eq!(encoding.ids, [config.bos, config.eos]);
+}
+
+#[test]
+fn padding() {
+    let config = config();
+    let ids = config.tokenize("Hello", true, false);
+    let n = ids.len();
+    let pad = |padding, padding_side| {
+        let options = EncodeOptions {
+            padding,
+            padding_side,
+            ..Default::default()
+        };
+        config.encode("Hello", &options).unwrap()
+    };
+
+    let right = pad(Padding::Fixed(n + 2), PaddingSide::Right);
+    assert_eq!(right.ids, [&ids[..], &[config.pad; 2]].concat());
+    assert_eq!(right.attention_mask, [vec![1; n], vec![0; 2]].concat());
+
+    let left = pad(Padding::Fixed(n + 2), PaddingSide::Left);
+    assert_eq!(left.ids, [&[config.pad; 2], &ids[..]].concat());
+    assert_eq!(left.attention_mask, [vec![0; 2], vec![1; n]].concat());
+
+    // 已经超过固定长度时不填充
+    assert_eq!(pad(Padding::Fixed(1), PaddingSide::Right).ids, ids);
+
+    let multiple = pad(Padding::Multiple(8), PaddingSide::Right);
+    assert_eq!(multiple.ids.len(), n.div_ceil(8) * 8);
+}
+
+#[test]
+fn padding_errors() {
+    let options = EncodeOptions {
+        padding: Padding::Fixed(16),
+        ..Default::default()
+    };
+    assert_eq!(
+        bpe("gpt2").encode(TEXT, &options),
+        Err(EncodeError::NoPadToken)
+    );
+    let options = EncodeOptions {
+        padding: Padding::Multiple(0),
+        ..Default::default()
+    };
+    assert_eq!(
+        config().encode(TEXT, &options),
+        Err(EncodeError::ZeroMultiple)
+    );
+}
+
+thread_local! {
+    static CONFIG: TokenizerConfig = config();
+}
+
+proptest! {
+    #[test]
+    fn truncated_length(
+        text in "[ a-zA-Z0-9.]{0,64}",
+        max_length in 2usize..32,
+        truncation in prop_oneof![
+            Just(Truncation::Head),
+            Just(Truncation::Tail),
+            prop::collection::vec(0u32..300, 0..4).prop_map(|ellipsis| Truncation::HeadTail { ellipsis }),
+        ],
+        multiple in 1usize..8,
+    ) {
+        CONFIG.with(|config| {
+            let full = config.tokenize(&text, true, false);
+            let options = EncodeOptions {
+                max_length: Some(max_length),
+                truncation,
+                padding: Padding::Multiple(multiple),
+                ..Default::default()
+            };
+            let encoding = config.encode(&text, &options).unwrap();
+            let len = encoding.attention_mask.iter().filter(|&&m| m == 1).count();
+            prop_assert_eq!(len, full.len().min(max_length));
+            prop_assert_eq!(encoding.ids.len() % multiple, 0);
+            prop_assert_eq!(encoding.ids.first(), Some(&config.bos));
+            prop_assert_eq!(encoding.ids[len - 1], config.eos);
+            prop_assert_eq!(encoding.truncated == 0, full.len() <= max_length);
+            Ok(())
+        })?;
+    }
+}
@@ -0,0 +1,445 @@
+//! 从内存、`Read + Seek` 和分片加载 GGUF
+
+mod common;
+
+use std::{
+    fs,
+    io::{Cursor, Read, Seek, SeekFrom},
+};
+
+use common::{bpe_gguf, load_gguf, spm_gguf, spm_vocab, unsupported_gguf};
+use memmap2::Mmap;
+use proptest::prelude::*;
+use try_tokenize::{
+    GGufEdit, LoadError, TokenizerConfig, edit_gguf, edit_gguf_bytes, load, load_gguf_bytes,
+    load_gguf_reader, load_gguf_split, read_gguf_metadata, try_load, vocab_only_gguf,
+    write_vocab_only,
+};
+
+/// 追加的元数据的值
+enum Kv<'a> {
+    U16(u16),
+    Str(&'a str),
+}
+
+/// 在只有元数据的 GGUF 后追加元数据和一个 `len` 字节的张量
+fn with_tensor(gguf: &[u8], kvs: &[(&str, Kv)], len: usize) -> Vec<u8> {
+    let mut buf = gguf.to_vec();
+    let n_kvs = u64::from_le_bytes(buf[16..24].try_into().unwrap()) + kvs.len() as u64;
+    buf[8..16].copy_from_slice(&1u64.to_le_bytes());
+    buf[16..24].copy_from_slice(&n_kvs.to_le_bytes());
+    for (key, value) in kvs {
+        buf.extend((key.len() as u64).to_le_bytes());
+        buf.extend(key.as_bytes());
+        match value {
+            Kv::U16(v) => {
+                buf.extend(2u32.to_le_bytes());
+                buf.extend(v.to_le_bytes());
+            }
+            Kv::Str(s) => {
+                buf.extend(8u32.to_le_bytes());
+                buf.extend((s.len() as u64).to_le_bytes());
+                buf.extend(s.as_bytes());
+            }
+        }
+    }
+    // 一维 I8 张量
+    let name = "token_embd.weight";
+    buf.extend((name.len() as u64).to_le_bytes());
+    buf.extend(name.as_bytes());
+    buf.extend(1u32.to_le_bytes());
+    buf.extend((len as u64).to_le_bytes());
+    buf.extend(24u32.to_le_bytes());
+    buf.extend(0u64.to_le_bytes());
+    buf.resize(buf.len().next_multiple_of(32), 0);
+    buf.resize(buf.len() + len, 0xaa);
+    buf
+}
+
+/// 记录读取了多少字节
+struct Counting<R> {
+    inner: R,
+    read: usize,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n;
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Counting<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+fn assert_same(a: &TokenizerConfig, b: &TokenizerConfig) {
+    assert_eq!(a.n_tokens(), b.n_tokens());
+    for text in [
+        "Hello world",
+        "你好 привет 中",
+        "<|im_start|>Hello<|endoftext|>",
+    ] {
+        assert_eq!(
+            a.tokenize(text, true, true),
+            b.tokenize(text, true, true),
+            "{text}"
+        );
+    }
+}
+
+#[test]
+fn from_bytes() {
+    for gguf in [bpe_gguf("qwen2"), spm_gguf(&spm_vocab())] {
+        assert_same(&load_gguf_bytes(&gguf).unwrap(), &load_gguf(&gguf));
+        let gguf = with_tensor(&gguf, &[], 100);
+        assert_same(&load_gguf_bytes(&gguf).unwrap(), &load_gguf(&gguf));
+    }
+}
+
+#[test]
+fn bad_bytes() {
+    let mut empty = b"GGUF".to_vec();
+    empty.extend(3u32.to_le_bytes());
+    empty.extend([0; 16]);
+    assert!(matches!(load_gguf_bytes(&empty), Err(LoadError::Format(_))));
+    assert!(matches!(
+        load_gguf_bytes(b"not a gguf file at all"),
+        Err(LoadError::Format(_))
+    ));
+    let gguf = bpe_gguf("qwen2");
+    assert!(matches!(
+        load_gguf_bytes(&gguf[..gguf.len() - 1]),
+        Err(LoadError::Format(_))
+    ));
+}
+
+fn mmap(bytes: &[u8]) -> Mmap {
+    let path = std::env::temp_dir().join(format!("try-tokenize-mmap-{}.gguf", std::process::id()));
+    fs::write(&path, bytes).unwrap();
+    let mmap = unsafe { Mmap::map(&fs::File::open(&path).unwrap()) }.unwrap();
+    fs::remove_file(&path).unwrap();
+    mmap
+}
+
+/// 不支持的词表返回错误而不是 panic
+#[test]
+fn unsupported_vocab() {
+    let mut short_types = spm_vocab();
+    short_types.types.pop();
+    for (gguf, msg) in [
+        (unsupported_gguf("gpt-5"), "unknown tokenizer model `gpt-5`"),
+        (
+            bpe_gguf("no-such-pre"),
+            "unsupported pre-tokenizer `no-such-pre`",
+        ),
+        (spm_gguf(&short_types), "tokenizer.ggml.token_type has"),
+    ] {
+        let gguf = with_tensor(&gguf, &[], 64);
+        for result in [
+            load_gguf_bytes(&gguf),
+            load_gguf_reader(&mut Cursor::new(&gguf)),
+            try_load(mmap(&gguf)),
+        ] {
+            match result {
+                Err(LoadError::Format(e)) => assert!(e.contains(msg), "{e}"),
+                _ => panic!("expected an error containing `{msg}`"),
+            }
+        }
+        let path = std::env::temp_dir().join(format!(
+            "try-tokenize-unsupported-{}.gguf",
+            std::process::id()
+        ));
+        fs::write(&path, &gguf).unwrap();
+        let result = load_gguf_split(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(LoadError::Format(e)) if e.contains(msg)));
+    }
+}
+
+#[test]
+fn reader_skips_tensor_data() {
+    let metadata = bpe_gguf("qwen2");
+    let gguf = with_tensor(&metadata, &[], 8 << 20);
+    let mut reader = Counting {
+        inner: Cursor::new(&gguf),
+        read: 0,
+    };
+    // 从文件开头读取，与当前位置无关
+    reader.seek(SeekFrom::End(0)).unwrap();
+    assert_eq!(read_gguf_metadata(&mut reader).unwrap(), metadata);
+    assert!(reader.read < gguf.len() / 2, "{}", reader.read);
+
+    let config = load_gguf_reader(&mut Cursor::new(&gguf)).unwrap();
+    assert_same(&config, &load_gguf(&metadata));
+}
+
+#[test]
+fn reader_truncated() {
+    let gguf = bpe_gguf("qwen2");
+    for len in [0, 10, 24, gguf.len() / 2, gguf.len() - 1] {
+        let result = load_gguf_reader(&mut Cursor::new(&gguf[..len]));
+        assert!(matches!(result, Err(LoadError::Format(_))), "{len}");
+    }
+}
+
+#[test]
+fn split() {
+    let dir = std::env::temp_dir().join(format!("try-tokenize-split-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let shard = |i: usize| dir.join(format!("model-{i:05}-of-00003.gguf"));
+
+    let gguf = bpe_gguf("qwen2");
+    fs::write(
+        shard(1),
+        with_tensor(
+            &gguf,
+            &[("split.no", Kv::U16(0)), ("split.count", Kv::U16(3))],
+            64,
+        ),
+    )
+    .unwrap();
+    // 其余分片只有张量
+    let mut rest = b"GGUF".to_vec();
+    rest.extend(3u32.to_le_bytes());
+    rest.extend([0; 16]);
+    for i in [2, 3] {
+        fs::write(
+            shard(i),
+            with_tensor(&rest, &[("split.no", Kv::U16(i as u16 - 1))], 64),
+        )
+        .unwrap();
+    }
+    let expected = load_gguf(&gguf);
+    for i in 1..=3 {
+        assert_same(&load_gguf_split(shard(i)).unwrap(), &expected);
+    }
+
+    // 分片数与文件名不符
+    fs::write(
+        shard(1),
+        with_tensor(
+            &gguf,
+            &[("split.no", Kv::U16(0)), ("split.count", Kv::U16(4))],
+            64,
+        ),
+    )
+    .unwrap();
+    assert!(matches!(
+        load_gguf_split(shard(2)),
+        Err(LoadError::Format(_))
+    ));
+
+    // 缺少第一个分片
+    fs::remove_file(shard(1)).unwrap();
+    assert!(matches!(load_gguf_split(shard(3)), Err(LoadError::Io(_))));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn single_file() {
+    let path =
+        std::env::temp_dir().join(format!("try-tokenize-single-{}.gguf", std::process::id()));
+    let gguf = spm_gguf(&spm_vocab());
+    fs::write(&path, with_tensor(&gguf, &[], 64)).unwrap();
+    assert_same(&load_gguf_split(&path).unwrap(), &load_gguf(&gguf));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn vocab_only() {
+    for gguf in [bpe_gguf("qwen2"), spm_gguf(&spm_vocab())] {
+        let model = with_tensor(
+            &gguf,
+            &[
+                ("general.name", Kv::Str("test")),
+                ("llama.context_length", Kv::U16(4096)),
+                ("tokenizer.chat_template", Kv::Str("{{ messages }}")),
+                ("tokenizer.chat_template.tool_use", Kv::Str("{{ tools }}")),
+                ("tokenizer.huggingface.json", Kv::Str("{}")),
+            ],
+            1 << 16,
+        );
+        let vocab = vocab_only_gguf(&model).unwrap();
+        // 原有的键都与分词器有关，值按字节原样复制
+        let expected = with_tensor(
+            &gguf,
+            &[
+                ("tokenizer.chat_template", Kv::Str("{{ messages }}")),
+                ("tokenizer.chat_template.tool_use", Kv::Str("{{ tools }}")),
+            ],
+            0,
+        );
+        assert_eq!(vocab[16..24], expected[16..24]);
+        assert_eq!(vocab[24..], expected[24..vocab.len()]);
+        assert_eq!(vocab_only_gguf(&vocab).unwrap(), vocab);
+        assert_same(&load_gguf(&vocab), &load_gguf(&gguf));
+    }
+    // 没有词表
+    let mut empty = b"GGUF".to_vec();
+    empty.extend(3u32.to_le_bytes());
+    empty.extend([0; 16]);
+    let model = with_tensor(&empty, &[("general.name", Kv::Str("test"))], 64);
+    assert!(matches!(vocab_only_gguf(&model), Err(LoadError::Format(_))));
+}
+
+#[test]
+fn write_vocab_only_file() {
+    let dir = std::env::temp_dir().join(format!("try-tokenize-vocab-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let gguf = bpe_gguf("llama3");
+    let src = dir.join("model-00001-of-00002.gguf");
+    fs::write(
+        &src,
+        with_tensor(&gguf, &[("split.count", Kv::U16(2))], 1 << 20),
+    )
+    .unwrap();
+    let dst = dir.join("ggml-vocab-test.gguf");
+    write_vocab_only(dir.join("model-00002-of-00002.gguf"), &dst).unwrap();
+    assert!(fs::metadata(&dst).unwrap().len() < gguf.len() as u64 + 64);
+
+    let file = fs::File::open(&dst).unwrap();
+    let config = load(unsafe { memmap2::Mmap::map(&file) }.unwrap());
+    assert_same(&config, &load_gguf(&gguf));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn edit_metadata() {
+    let gguf = bpe_gguf("qwen2");
+    let model = with_tensor(&gguf, &[("general.name", Kv::Str("test"))], 1000);
+    let original = load_gguf(&gguf);
+    let im_end = original.text_to_token("<|im_end|>");
+
+    let mut edit = GGufEdit {
+        add_bos: Some(true),
+        add_eos: Some(false),
+        chat_template: Some("{% for m in messages %}{{ m.content }}{% endfor %}".into()),
+        pre: Some("llama3".into()),
+        ..Default::default()
+    };
+    edit.special_ids.insert("eos".into(), im_end);
+    edit.special_ids.insert("pad".into(), 0);
+    let edited = edit_gguf_bytes(&model, &edit).unwrap();
+
+    // 张量数据原样复制，数据段仍然对齐
+    let parsed = ggus::GGuf::new(&edited).unwrap();
+    assert_eq!(parsed.data, &model[model.len() - 1000..]);
+    assert_eq!((edited.len() - 1000) % 32, 0);
+    assert_eq!(
+        ggus::GGufMetaMapExt::get_str(&parsed, "tokenizer.chat_template").unwrap(),
+        edit.chat_template.as_deref().unwrap()
+    );
+    assert_eq!(
+        ggus::GGufMetaMapExt::get_str(&parsed, "general.name").unwrap(),
+        "test"
+    );
+
+    let config = load_gguf(&edited);
+    assert_eq!(config.eos, im_end);
+    assert_eq!(config.pad, 0);
+    assert!(config.add_bos && !config.add_eos);
+    assert!(config.ignore_merges);
+    assert_eq!(config.tokenize("Hello", true, false)[0], config.bos);
+
+    // 不修改时内容不变
+    assert_eq!(
+        edit_gguf_bytes(&model, &GGufEdit::default()).unwrap(),
+        model
+    );
+    // 再次修改替换已有的键，不会重复
+    let again = edit_gguf_bytes(&edited, &edit).unwrap();
+    assert_eq!(again, edited);
+}
+
+#[test]
+fn edit_rejects_invalid() {
+    let gguf = bpe_gguf("qwen2");
+    let n_tokens = load_gguf(&gguf).n_tokens();
+    let special = |name: &str, id| GGufEdit {
+        special_ids: [(name.to_string(), id)].into(),
+        ..Default::default()
+    };
+    for edit in [
+        special("eos", n_tokens),
+        special("end", 0),
+        GGufEdit {
+            pre: Some("unknown-pre".into()),
+            ..Default::default()
+        },
+    ] {
+        assert!(
+            matches!(edit_gguf_bytes(&gguf, &edit), Err(LoadError::Format(_))),
+            "{edit:?}"
+        );
+    }
+}
+
+#[test]
+fn edit_file_in_place() {
+    let path = std::env::temp_dir().join(format!("try-tokenize-edit-{}.gguf", std::process::id()));
+    let gguf = spm_gguf(&spm_vocab());
+    fs::write(&path, with_tensor(&gguf, &[], 4096)).unwrap();
+    let edit = GGufEdit {
+        add_bos: Some(false),
+        special_ids: [("eos".to_string(), 0)].into(),
+        ..Default::default()
+    };
+    let config = edit_gguf(&path, &path, &edit).unwrap();
+    assert!(!config.add_bos);
+    assert_eq!(config.eos, 0);
+    let bytes = fs::read(&path).unwrap();
+    assert_eq!(bytes[bytes.len() - 4096..], [0xaa; 4096]);
+    assert!(!path.with_extension("gguf.tmp").exists());
+    fs::remove_file(&path).unwrap();
+}
+
+/// 修改后不能加载时返回错误，原文件保持不变，也不留下临时文件
+#[test]
+fn edit_keeps_file_on_error() {
+    let path =
+        std::env::temp_dir().join(format!("try-tokenize-edit-bad-{}.gguf", std::process::id()));
+    let original = with_tensor(&bpe_gguf("no-such-pre"), &[], 256);
+    fs::write(&path, &original).unwrap();
+    let edit = GGufEdit {
+        add_bos: Some(false),
+        ..Default::default()
+    };
+    let result = edit_gguf(&path, &path, &edit);
+    assert!(
+        matches!(&result, Err(LoadError::Format(e)) if e.contains("no-such-pre")),
+        "{:?}",
+        result.err()
+    );
+    assert_eq!(fs::read(&path).unwrap(), original);
+    assert!(!path.with_extension("gguf.tmp").exists());
+    assert!(!path.with_extension("tmp").exists());
+
+    // 修改为可用的预分词类型后可以加载
+    let edit = GGufEdit {
+        pre: Some("qwen2".into()),
+        ..Default::default()
+    };
+    let config = edit_gguf(&path, &path, &edit).unwrap();
+    assert_eq!(config.tokenize("Hello world", false, false).len(), 2);
+    assert!(!path.with_extension("gguf.tmp").exists());
+    fs::remove_file(&path).unwrap();
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    /// 任意截断或改写字节都只返回错误，不会崩溃
+    #[test]
+    fn corrupt_metadata(len in 0usize..4096, pos in 0usize..4096, byte: u8) {
+        let mut gguf = bpe_gguf("qwen2");
+        gguf.truncate(len);
+        if let Some(b) = gguf.get_mut(pos) {
+            *b = byte;
+        }
+        let _ = read_gguf_metadata(&mut Cursor::new(&gguf));
+    }
+}
@@ -0,0 +1,187 @@
+//! GBNF 语法约束
+
+mod common;
+
+use common::{bpe, spm};
+use proptest::prelude::*;
+use try_tokenize::{Grammar, GrammarError, TokenId, TokenizerConfig};
+
+fn grammar(src: &str) -> Grammar {
+    Grammar::parse(src, "root").unwrap()
+}
+
+/// 逐个尝试词表中的标记，得到允许的标记
+fn brute_force(grammar: &Grammar, config: &TokenizerConfig) -> Vec<TokenId> {
+    config
+        .vocab()
+        .map(|(id, _)| id)
+        .filter(|&id| grammar.clone().accept_token(config, id).is_ok())
+        .collect()
+}
+
+#[test]
+fn literal() {
+    let config = bpe("gpt2");
+    let mut grammar = grammar(r#"root ::= "Hello" " world""#);
+    let expected = config
+        .vocab()
+        .map(|(id, _)| id)
+        .filter(|&id| {
+            let piece = config.token_to_piece(id, 0, false);
+            !piece.is_empty() && b"Hello world".starts_with(&piece)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(grammar.allowed_tokens(&config), expected);
+    assert!(!grammar.is_complete());
+
+    for id in config.tokenize("Hello world", false, false) {
+        assert!(grammar.allowed_tokens(&config).contains(&id));
+        grammar.accept_token(&config, id).unwrap();
+    }
+    assert!(grammar.is_complete());
+    // 匹配完整后只能结束生成
+    let eog = ["<|endoftext|>", "<|im_end|>"].map(|text| config.text_to_token(text));
+    assert_eq!(grammar.allowed_tokens(&config), eog);
+    grammar.accept_token(&config, eog[0]).unwrap();
+}
+
+#[test]
+fn eog_only_when_complete() {
+    let config = bpe("gpt2");
+    let mut grammar = grammar("root ::= [0-9]+");
+    let eot = config.text_to_token("<|endoftext|>");
+    assert!(!grammar.allowed_tokens(&config).contains(&eot));
+    assert_eq!(
+        grammar.accept_token(&config, eot),
+        Err(GrammarError::Rejected(eot))
+    );
+    grammar
+        .accept_token(&config, config.text_to_token("123"))
+        .unwrap();
+    assert!(grammar.allowed_tokens(&config).contains(&eot));
+    // 控制标记还原为空，不允许
+    let im_start = config.text_to_token("<|im_start|>");
+    assert!(!grammar.allowed_tokens(&config).contains(&im_start));
+    assert!(grammar.accept_token(&config, im_start).is_err());
+}
+
+#[test]
+fn partial_utf8() {
+    // “中” 不在 SPM 测试词表中，只能用字节回退的三个标记表示
+    let config = spm();
+    let byte = |b: u8| config.text_to_token(&format!("<0x{b:02X}>"));
+    let mut grammar = grammar("root ::= [一-龥] | \"a\"");
+    let allowed = grammar.allowed_tokens(&config);
+    assert!(allowed.contains(&byte(0xE4)));
+    assert!(allowed.contains(&byte(0xE9)));
+    assert!(!allowed.contains(&byte(0xEA)));
+    assert!(!allowed.contains(&byte(0xB8)));
+    assert!(allowed.contains(&config.text_to_token("你")));
+    assert_eq!(allowed, brute_force(&grammar, &config));
+
+    let eos = config.text_to_token("</s>");
+    for b in "中".bytes() {
+        assert!(!grammar.allowed_tokens(&config).contains(&eos));
+        grammar.accept_token(&config, byte(b)).unwrap();
+    }
+    let allowed = grammar.allowed_tokens(&config);
+    assert!(allowed.contains(&eos));
+    assert!(allowed.iter().all(|&id| config.is_eog(id)));
+}
+
+#[test]
+fn repetition() {
+    let config = spm();
+    let byte = |b: u8| config.text_to_token(&format!("<0x{b:02X}>"));
+    let mut grammar = grammar("root ::= (\"x\" | [yz]){2,3}");
+    for (b, complete) in [(b'x', false), (b'y', true), (b'z', true)] {
+        grammar.accept_token(&config, byte(b)).unwrap();
+        assert_eq!(grammar.is_complete(), complete);
+    }
+    assert!(grammar.accept_token(&config, byte(b'x')).is_err());
+}
+
+#[test]
+fn errors() {
+    let error = |src| Grammar::parse(src, "root").unwrap_err();
+    assert_eq!(
+        error("root ::= item"),
+        GrammarError::UndefinedRule("item".into())
+    );
+    assert_eq!(
+        error("expr ::= [0-9]"),
+        GrammarError::MissingRoot("root".into())
+    );
+    assert_eq!(
+        error("root ::= root \"a\" | \"b\""),
+        GrammarError::LeftRecursion("root".into())
+    );
+    assert_eq!(
+        error("root ::= empty root\nempty ::= \"a\" |"),
+        GrammarError::LeftRecursion("root".into())
+    );
+    assert!(matches!(
+        error("root ::= \"abc"),
+        GrammarError::Syntax { offset: 13, .. }
+    ));
+    assert!(matches!(
+        error("root ::= * \"a\""),
+        GrammarError::Syntax { .. }
+    ));
+    assert!(matches!(
+        error("root ::= \"a\"{3,1}"),
+        GrammarError::Syntax { .. }
+    ));
+    assert!(matches!(error("root = \"a\""), GrammarError::Syntax { .. }));
+}
+
+#[test]
+fn comments_and_escapes() {
+    let config = spm();
+    let byte = |b: u8| config.text_to_token(&format!("<0x{b:02X}>"));
+    let src = r#"
+# 以注释开头
+root ::= (
+    "\x41" # 多行的括号
+  | "B"
+) "\n" [^\]]
+"#;
+    let mut grammar = grammar(src);
+    for b in *b"B\n" {
+        grammar.accept_token(&config, byte(b)).unwrap();
+    }
+    assert!(grammar.accept_token(&config, byte(b']')).is_err());
+    grammar.accept_token(&config, byte(b'[')).unwrap();
+    assert!(grammar.is_complete());
+}
+
+thread_local! {
+    static BPE: TokenizerConfig = bpe("qwen2");
+    static SPM: TokenizerConfig = spm();
+}
+
+/// 符合语法的文本分词后，每个标记都在允许的集合中，最后可以结束生成
+fn check(config: &TokenizerConfig, text: &str) -> Result<(), TestCaseError> {
+    let mut grammar = grammar("root ::= \" \"? word (\" \" word)*\nword ::= [a-zA-Z]+ | [一-龥]+");
+    for id in config.tokenize(text, false, false) {
+        let allowed = grammar.allowed_tokens(config);
+        prop_assert_eq!(&allowed, &brute_force(&grammar, config));
+        prop_assert!(allowed.contains(&id), "{id} not allowed");
+        grammar.accept_token(config, id).unwrap();
+    }
+    prop_assert!(grammar.is_complete());
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn tokenized_text_accepted(
+        words in prop::collection::vec("[a-zA-Z]{1,6}|[\u{4e00}-\u{4e10}你好]{1,3}", 1..4)
+    ) {
+        let text = words.join(" ");
+        BPE.with(|config| check(config, &text))?;
+        SPM.with(|config| check(config, &text))?;
+    }
+}
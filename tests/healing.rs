@@ -0,0 +1,103 @@
+//! 提示词末尾的标记修复
+
+mod common;
+
+use common::{bpe, spm};
+use proptest::prelude::*;
+use try_tokenize::{TokenId, TokenizerConfig};
+
+/// 逐个检查词表得到的候选
+fn brute_force(config: &TokenizerConfig, prefix: &[u8]) -> Vec<TokenId> {
+    config
+        .vocab()
+        .map(|(id, _)| id)
+        .filter(|&id| {
+            let piece = config.token_to_piece(id, 0, false);
+            !piece.is_empty() && (piece.starts_with(prefix) || prefix.starts_with(&piece))
+        })
+        .collect()
+}
+
+#[test]
+fn back_off_last_token() {
+    let config = bpe("gpt2");
+    let healing = config.heal("Hello wor", 1, false, false);
+    let ids = config.tokenize("Hello wor", false, false);
+    assert_eq!(healing.ids, ids[..ids.len() - 1]);
+    assert_eq!(
+        healing.prefix,
+        config.token_to_piece(*ids.last().unwrap(), 0, false)
+    );
+    assert_eq!(healing.candidates, brute_force(&config, &healing.prefix));
+    // 完整的词也在候选中
+    let world = config.text_to_token("Ġworld");
+    assert!(healing.candidates.contains(&world));
+}
+
+#[test]
+fn keep_special_tokens() {
+    let config = spm();
+    let healing = config.heal("Hello", 10, true, false);
+    assert_eq!(healing.ids, [config.bos]);
+    assert_eq!(healing.prefix, b" Hello");
+
+    let config = bpe("gpt2");
+    let healing = config.heal("<|im_start|>", 1, false, true);
+    assert_eq!(healing.ids, [config.text_to_token("<|im_start|>")]);
+    assert!(healing.prefix.is_empty());
+    assert!(healing.candidates.is_empty());
+}
+
+#[test]
+fn accept() {
+    let config = bpe("gpt2");
+    let mut healing = config.heal(" tokenizer", 1, false, false);
+    assert_eq!(healing.prefix, b" tokenizer");
+    // 较短的标记只满足前缀的一部分，余下的继续约束
+    let space = config.text_to_token("Ġ");
+    assert!(healing.accept(&config, space));
+    assert_eq!(healing.prefix, b"tokenizer");
+    assert_eq!(healing.candidates, brute_force(&config, b"tokenizer"));
+    assert!(!healing.accept(&config, config.text_to_token("Ġworld")));
+    assert!(healing.accept(&config, config.text_to_token("t")));
+    assert_eq!(healing.prefix, b"okenizer");
+
+    let mut healing = config.heal(" tokenizer", 1, false, false);
+    assert!(healing.accept(&config, config.text_to_token("Ġtokenizer")));
+    assert!(healing.prefix.is_empty());
+    assert!(healing.candidates.is_empty());
+    assert!(healing.accept(&config, config.text_to_token("Ġworld")));
+}
+
+thread_local! {
+    static BPE: TokenizerConfig = bpe("qwen2");
+    static SPM: TokenizerConfig = spm();
+}
+
+fn check(config: &TokenizerConfig, text: &str, back_off: usize) -> Result<(), TestCaseError> {
+    let ids = config.tokenize(text, false, false);
+    let healing = config.heal(text, back_off, false, false);
+    let kept = ids.len().saturating_sub(back_off);
+    prop_assert_eq!(&healing.ids, &ids[..kept]);
+    let removed = ids[kept..]
+        .iter()
+        .flat_map(|&id| config.token_to_piece(id, 0, false))
+        .collect::<Vec<_>>();
+    prop_assert_eq!(&healing.prefix, &removed);
+    if removed.is_empty() {
+        prop_assert!(healing.candidates.is_empty());
+    } else {
+        prop_assert_eq!(&healing.candidates, &brute_force(config, &removed));
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn candidates_match_brute_force(text in "[ a-zA-Z你好:/]{0,12}", back_off in 0usize..4) {
+        BPE.with(|config| check(config, &text, back_off))?;
+        SPM.with(|config| check(config, &text, back_off))?;
+    }
+}
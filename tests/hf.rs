@@ -0,0 +1,472 @@
+//! 从 tokenizer.json 加载的分词器与从 GGUF 加载的结果相同
+
+mod common;
+
+use common::{
+    CONTROL, USER_DEFINED, bpe, bpe_hf, load_gguf, precompiled_charsmap, spm, spm_hf, spm_vocab,
+    ugm_gguf, ugm_hf, ugm_vocab,
+};
+use proptest::prelude::*;
+use serde_json::json;
+use try_tokenize::{
+    LoadError, NormalizationForm, Normalizer, TokenAttribute, TokenizerConfig, VocabType, load_hf,
+    load_hf_json, save_hf, to_hf_json,
+};
+
+fn load((tokenizer, tokenizer_config): (String, String)) -> TokenizerConfig {
+    load_hf_json(&tokenizer, Some(&tokenizer_config)).unwrap()
+}
+
+/// 同一个模型分别从 GGUF 和 tokenizer.json 加载
+fn pairs() -> Vec<(&'static str, TokenizerConfig, TokenizerConfig)> {
+    let mut pairs = ["gpt2", "qwen2", "llama-bpe"]
+        .into_iter()
+        .map(|pre| (pre, bpe(pre), load(bpe_hf(pre))))
+        .collect::<Vec<_>>();
+    pairs.push(("spm", spm(), load(spm_hf(&spm_vocab()))));
+    pairs
+}
+
+/// [`pairs`] 加上不能导出的 T5 词表
+fn loaded_pairs() -> Vec<(&'static str, TokenizerConfig, TokenizerConfig)> {
+    let charsmap = precompiled_charsmap(&[("Ａ", "A"), ("ﬁ", "fi"), ("\u{3000}", " ")]);
+    let mut pairs = pairs();
+    pairs.push((
+        "ugm",
+        load_gguf(&ugm_gguf(&ugm_vocab(), &charsmap)),
+        load(ugm_hf(&ugm_vocab(), &charsmap)),
+    ));
+    pairs
+}
+
+#[test]
+fn same_vocab_as_gguf() {
+    for (name, gguf, hf) in loaded_pairs() {
+        assert_eq!(gguf.vocab_type, hf.vocab_type, "{name}");
+        assert_eq!(gguf.n_tokens(), hf.n_tokens(), "{name}");
+        for (id, (a, b)) in gguf.id_to_token.iter().zip(&hf.id_to_token).enumerate() {
+            assert_eq!(a.text, b.text, "{name} {id}");
+            assert_eq!(a.attribute, b.attribute, "{name} {id} {:?}", a.text);
+        }
+        assert_eq!(
+            (gguf.bos, gguf.eos, gguf.unk),
+            (hf.bos, hf.eos, hf.unk),
+            "{name}"
+        );
+        assert_eq!(
+            (gguf.add_bos, gguf.add_eos, gguf.add_space_prefix),
+            (hf.add_bos, hf.add_eos, hf.add_space_prefix),
+            "{name}"
+        );
+        assert_eq!(gguf.ignore_merges, hf.ignore_merges, "{name}");
+        assert_eq!(gguf.clean_spaces, hf.clean_spaces, "{name}");
+        assert_eq!(gguf.special_tokens, hf.special_tokens, "{name}");
+        assert_eq!(
+            (gguf.remove_extra_whitespaces, gguf.escape_whitespaces),
+            (hf.remove_extra_whitespaces, hf.escape_whitespaces),
+            "{name}"
+        );
+        assert_eq!(gguf.precompiled_charsmap, hf.precompiled_charsmap, "{name}");
+    }
+}
+
+#[test]
+fn unknown_in_added_tokens() {
+    // <unk> 同时出现在 added_tokens 中，仍然是未知标记而不是控制标记
+    let hf = load(spm_hf(&spm_vocab()));
+    assert_eq!(hf.unk, 0);
+    assert_eq!(hf.id_to_token[0].attribute, TokenAttribute::Unknown);
+}
+
+#[test]
+fn unsupported_models() {
+    for (model, prefix) in [
+        (
+            r#"{"type": "Unigram", "vocab": [["<unk>", 0.0], ["a", -1.0]], "unk_id": 0, "byte_fallback": true}"#,
+            "unsupported Unigram",
+        ),
+        (
+            r#"{"type": "WordPiece", "vocab": {"[UNK]": 0, "a": 1}, "unk_token": "[UNK]"}"#,
+            "unsupported model",
+        ),
+    ] {
+        let tokenizer = format!(r#"{{"added_tokens": [], "model": {model}}}"#);
+        assert!(matches!(
+            load_hf_json(&tokenizer, None),
+            Err(LoadError::Format(msg)) if msg.starts_with(prefix)
+        ));
+    }
+}
+
+#[test]
+fn unigram() {
+    let config = load(ugm_hf(&ugm_vocab(), &[]));
+    assert_eq!(config.vocab_type, VocabType::Ugm);
+    assert_eq!((config.add_bos, config.add_eos), (false, true));
+    assert!(config.precompiled_charsmap.is_empty());
+
+    // charsmap 之后的规范化步骤无法按顺序执行
+    let (tokenizer, tokenizer_config) = ugm_hf(&ugm_vocab(), &precompiled_charsmap(&[("Ａ", "A")]));
+    let mut tokenizer = serde_json::from_str::<serde_json::Value>(&tokenizer).unwrap();
+    tokenizer["normalizer"]["normalizers"]
+        .as_array_mut()
+        .unwrap()
+        .push(json!({ "type": "Lowercase" }));
+    match load_hf_json(&tokenizer.to_string(), Some(&tokenizer_config)) {
+        Err(LoadError::Format(msg)) => assert!(msg.contains("after Precompiled"), "{msg}"),
+        other => panic!("unexpected {other:?}"),
+    }
+    // 无效的 charsmap
+    tokenizer["normalizer"]["normalizers"][0]["precompiled_charsmap"] = json!("AAAA");
+    assert!(load_hf_json(&tokenizer.to_string(), Some(&tokenizer_config)).is_err());
+}
+
+/// 替换 tokenizer.json 的 `key` 字段后加载
+fn with_field(
+    (tokenizer, tokenizer_config): (String, String),
+    key: &str,
+    value: serde_json::Value,
+) -> Result<TokenizerConfig, LoadError> {
+    let mut tokenizer = serde_json::from_str::<serde_json::Value>(&tokenizer).unwrap();
+    tokenizer[key] = value;
+    load_hf_json(&tokenizer.to_string(), Some(&tokenizer_config))
+}
+
+#[test]
+fn normalizers() {
+    let normalizer = json!({
+        "type": "Sequence",
+        "normalizers": [{ "type": "NFKC" }, { "type": "Lowercase" }],
+    });
+    let config = with_field(bpe_hf("qwen2"), "normalizer", normalizer).unwrap();
+    assert_eq!(
+        config.normalizers,
+        [
+            Normalizer::Form(NormalizationForm::Nfkc),
+            Normalizer::Lowercase
+        ]
+    );
+    let gguf = bpe("qwen2");
+    // 特殊标记按原文匹配，只规范化它们之间的文本
+    let text = format!("Ｈello ＷORLD{}ﬁ", CONTROL[0]);
+    let expected = format!("hello world{}fi", CONTROL[0]);
+    for parse_special in [false, true] {
+        let ids = config.tokenize(&text, true, parse_special);
+        assert_eq!(ids, gguf.tokenize(&expected, true, parse_special));
+        assert_eq!(config.count_tokens(&text, true, parse_special), ids.len());
+    }
+    assert_eq!(roundtrip(&config).normalizers, config.normalizers);
+
+    // SPM 的空格处理在规范化之后
+    let normalizer = json!({
+        "type": "Sequence",
+        "normalizers": [
+            { "type": "NFD" },
+            { "type": "StripAccents" },
+            { "type": "Prepend", "prepend": "\u{2581}" },
+            { "type": "Replace", "pattern": { "String": " " }, "content": "\u{2581}" },
+        ],
+    });
+    let config = with_field(spm_hf(&spm_vocab()), "normalizer", normalizer).unwrap();
+    assert_eq!(
+        config.tokenize("Héllo wörld", true, false),
+        spm().tokenize("Hello world", true, false)
+    );
+    assert_eq!(config.count_tokens("Héllo wörld", true, false), 3);
+    assert_eq!(roundtrip(&config).normalizers, config.normalizers);
+}
+
+#[test]
+fn unsupported_normalizers() {
+    for normalizer in [
+        json!({ "type": "BertNormalizer", "lowercase": true }),
+        json!({ "type": "Precompiled", "precompiled_charsmap": "" }),
+        json!({ "type": "Replace", "pattern": { "Regex": " {2,}" }, "content": " " }),
+        json!({ "type": "Sequence", "normalizers": [{ "type": "NFC" }, { "type": "Strip" }] }),
+        json!({}),
+    ] {
+        match with_field(bpe_hf("qwen2"), "normalizer", normalizer.clone()) {
+            Err(LoadError::Format(msg)) => {
+                assert!(msg.starts_with("unsupported normalizer"), "{msg}")
+            }
+            Err(e) => panic!("{normalizer}: unexpected error {e}"),
+            Ok(_) => panic!("{normalizer}: loaded an unsupported normalizer"),
+        }
+    }
+}
+
+#[test]
+fn pre_tokenizers() {
+    let split = |behavior: serde_json::Value| json!({ "type": "Split", "pattern": { "Regex": "\\p{N}+" }, "behavior": behavior, "invert": false });
+    let text = "abc 12345 de6";
+    let expected = with_field(bpe_hf("qwen2"), "pre_tokenizer", split(json!("Isolated")))
+        .unwrap()
+        .tokenize(text, true, false);
+    // 没有 behavior 时按默认的 Isolated 处理
+    let config = with_field(bpe_hf("qwen2"), "pre_tokenizer", split(json!(null))).unwrap();
+    assert_eq!(config.tokenize(text, true, false), expected);
+    // 不逐个拆分的 Digits 把连续的数字作为一个片段
+    let digits = json!({ "type": "Digits", "individual_digits": false });
+    let config = with_field(bpe_hf("qwen2"), "pre_tokenizer", digits).unwrap();
+    assert_eq!(config.tokenize(text, true, false), expected);
+
+    // 没有 pre_tokenizer 时不预分词，不沿用默认的正则
+    for pre_tokenizer in [
+        json!(null),
+        json!({ "type": "ByteLevel", "add_prefix_space": false, "use_regex": false }),
+    ] {
+        let config = with_field(bpe_hf("qwen2"), "pre_tokenizer", pre_tokenizer).unwrap();
+        assert!(config.session.borrow().regex_exprs().is_empty());
+    }
+
+    // 词表中没有 ▁ 出现在开头以外的标记时，按 ▁ 分割与不分割相同
+    let metaspace = json!({ "type": "Metaspace", "replacement": "\u{2581}", "prepend_scheme": "always", "split": true });
+    let model = r#"{"type": "Unigram", "vocab": [["<unk>", 0.0], ["\u2581a", -1.0], ["a", -2.0], ["b", -2.0]], "unk_id": 0}"#;
+    let tokenizer =
+        format!(r#"{{"added_tokens": [], "pre_tokenizer": {metaspace}, "model": {model}}}"#);
+    let config = load_hf_json(&tokenizer, None).unwrap();
+    assert_eq!(config.tokenize("a ab", false, false), [1, 1, 3]);
+}
+
+#[test]
+fn unsupported_pre_tokenizers() {
+    let split = |pattern: serde_json::Value, behavior: &str, invert: bool| json!({ "type": "Split", "pattern": pattern, "behavior": behavior, "invert": invert });
+    let digits = json!({ "Regex": "\\p{N}+" });
+    for (pre_tokenizer, prefix) in [
+        (split(digits.clone(), "Removed", false), "unsupported Split"),
+        (
+            split(digits.clone(), "MergedWithPrevious", false),
+            "unsupported Split",
+        ),
+        (
+            split(digits.clone(), "Contiguous", false),
+            "unsupported Split",
+        ),
+        (
+            split(digits.clone(), "Isolated", true),
+            "unsupported inverted Split",
+        ),
+        (
+            split(json!({ "Regex": "(" }), "Isolated", false),
+            "invalid Split regex",
+        ),
+        (split(json!({}), "Isolated", false), "Split without pattern"),
+        (json!({ "type": "Whitespace" }), "unsupported pre_tokenizer"),
+        (
+            json!({ "type": "BertPreTokenizer" }),
+            "unsupported pre_tokenizer",
+        ),
+        (
+            json!({ "type": "Sequence", "pretokenizers": [{ "type": "ByteLevel" }, { "type": "Punctuation" }] }),
+            "unsupported pre_tokenizer",
+        ),
+        (json!({}), "unsupported pre_tokenizer"),
+        (
+            json!({ "type": "ByteLevel", "add_prefix_space": true, "use_regex": true }),
+            "unsupported ByteLevel",
+        ),
+    ] {
+        match with_field(bpe_hf("qwen2"), "pre_tokenizer", pre_tokenizer.clone()) {
+            Err(LoadError::Format(msg)) => assert!(msg.starts_with(prefix), "{msg}"),
+            Err(e) => panic!("{pre_tokenizer}: unexpected error {e}"),
+            Ok(_) => panic!("{pre_tokenizer}: loaded an unsupported pre_tokenizer"),
+        }
+    }
+    // SPM 词表中有 ▁▁ 这样的标记，按 ▁ 分割后不能再合并
+    let metaspace = json!({ "type": "Metaspace", "replacement": "\u{2581}", "prepend_scheme": "first", "split": true });
+    match with_field(spm_hf(&spm_vocab()), "pre_tokenizer", metaspace) {
+        Err(LoadError::Format(msg)) => assert!(msg.starts_with("unsupported Metaspace"), "{msg}"),
+        other => panic!("unexpected {other:?}"),
+    }
+}
+
+#[test]
+fn post_processor_missing_specials() {
+    let endoftext = bpe("gpt2").token_to_id[CONTROL[0]];
+    let (tokenizer, _) = bpe_hf("gpt2");
+    let mut tokenizer = serde_json::from_str::<serde_json::Value>(&tokenizer).unwrap();
+    let special = |text: &str| json!({ "SpecialToken": { "id": text, "type_id": 0 } });
+    let sequence = json!({ "Sequence": { "id": "A", "type_id": 0 } });
+    for (post_processor, add_bos, add_eos) in [
+        (
+            json!({ "type": "TemplateProcessing", "single": [special(CONTROL[0]), sequence, special("<missing>")] }),
+            true,
+            false,
+        ),
+        (
+            json!({ "type": "TemplateProcessing", "single": [special("<missing>"), sequence] }),
+            false,
+            false,
+        ),
+        (
+            json!({ "type": "BertProcessing", "cls": ["<missing>", 1 << 20], "sep": [CONTROL[0], endoftext] }),
+            false,
+            true,
+        ),
+    ] {
+        tokenizer["post_processor"] = post_processor;
+        let config = load_hf_json(&tokenizer.to_string(), None).unwrap();
+        assert_eq!((config.add_bos, config.add_eos), (add_bos, add_eos));
+        // 添加的特殊标记都在词表中，可以还原
+        let ids = config.tokenize("Hello", true, false);
+        assert!(ids.iter().all(|&id| id < config.n_tokens()), "{ids:?}");
+    }
+}
+
+#[test]
+fn added_token_flags() {
+    let (tokenizer, tokenizer_config) = bpe_hf("qwen2");
+    let mut tokenizer = serde_json::from_str::<serde_json::Value>(&tokenizer).unwrap();
+    for token in tokenizer["added_tokens"].as_array_mut().unwrap() {
+        match token["content"].as_str().unwrap() {
+            text if text == USER_DEFINED[0] => {
+                token["lstrip"] = true.into();
+                token["normalized"] = true.into();
+            }
+            text if text == CONTROL[2] => {
+                token["rstrip"] = true.into();
+                token["single_word"] = true.into();
+            }
+            _ => {}
+        }
+    }
+    let config = load_hf_json(&tokenizer.to_string(), Some(&tokenizer_config)).unwrap();
+    let attribute = |text: &str| config.id_to_token[config.text_to_token(text) as usize].attribute;
+    assert_eq!(
+        attribute(USER_DEFINED[0]),
+        TokenAttribute::UserDefined | TokenAttribute::LStrIp | TokenAttribute::Normalized
+    );
+    assert_eq!(
+        attribute(CONTROL[2]),
+        TokenAttribute::Control | TokenAttribute::RStrIp | TokenAttribute::SingleWord
+    );
+    assert_eq!(attribute(CONTROL[1]), TokenAttribute::Control);
+}
+
+/// 导出后重新加载，与原分词器相同
+fn roundtrip(config: &TokenizerConfig) -> TokenizerConfig {
+    let (tokenizer, tokenizer_config) = to_hf_json(config).unwrap();
+    load_hf_json(&tokenizer, Some(&tokenizer_config)).unwrap()
+}
+
+#[test]
+fn export_same_vocab() {
+    for (name, gguf, _) in pairs() {
+        let hf = roundtrip(&gguf);
+        assert_eq!(gguf.vocab_type, hf.vocab_type, "{name}");
+        assert_eq!(gguf.n_tokens(), hf.n_tokens(), "{name}");
+        for (id, (a, b)) in gguf.id_to_token.iter().zip(&hf.id_to_token).enumerate() {
+            assert_eq!(a.text, b.text, "{name} {id}");
+            assert_eq!(a.attribute, b.attribute, "{name} {id} {:?}", a.text);
+        }
+        assert_eq!(
+            (gguf.bos, gguf.eos, gguf.unk),
+            (hf.bos, hf.eos, hf.unk),
+            "{name}"
+        );
+        assert_eq!(
+            (gguf.add_bos, gguf.add_eos, gguf.add_space_prefix),
+            (hf.add_bos, hf.add_eos, hf.add_space_prefix),
+            "{name}"
+        );
+        assert_eq!(gguf.ignore_merges, hf.ignore_merges, "{name}");
+        assert_eq!(gguf.clean_spaces, hf.clean_spaces, "{name}");
+        assert_eq!(gguf.special_tokens, hf.special_tokens, "{name}");
+    }
+}
+
+#[test]
+fn export_added_token_flags() {
+    let mut config = load(bpe_hf("qwen2"));
+    let id = config.text_to_token(USER_DEFINED[0]) as usize;
+    config.id_to_token[id].attribute |= TokenAttribute::LStrIp | TokenAttribute::SingleWord;
+    let hf = roundtrip(&config);
+    assert_eq!(
+        hf.id_to_token[id].attribute,
+        TokenAttribute::UserDefined | TokenAttribute::LStrIp | TokenAttribute::SingleWord
+    );
+}
+
+#[test]
+fn export_to_dir() {
+    let dir = std::env::temp_dir().join(format!("try-tokenize-hf-{}", std::process::id()));
+    let config = spm();
+    save_hf(&config, &dir).unwrap();
+    let hf = load_hf(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        config.tokenize(" Hello world", true, false),
+        hf.tokenize(" Hello world", true, false)
+    );
+}
+
+#[test]
+fn export_unsupported_vocab() {
+    let mut config = spm();
+    config.vocab_type = VocabType::Wpm;
+    assert!(matches!(to_hf_json(&config), Err(LoadError::Format(_))));
+}
+
+/// 夹杂特殊标记文本的任意字符串
+fn text() -> impl Strategy<Value = String> {
+    let specials = CONTROL
+        .iter()
+        .chain(USER_DEFINED)
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+    prop::collection::vec(
+        prop_oneof![
+            4 => any::<String>(),
+            2 => prop::sample::select(specials),
+            2 => "[ a-zA-Z0-9'\n]{1,12}",
+            1 => prop::sample::select(vec![
+                "Hello world", " the tokenizer", "123456", "你好 café", " привет", "  \n\n",
+            ]).prop_map(str::to_string),
+        ],
+        0..8,
+    )
+    .prop_map(|parts| parts.concat())
+}
+
+thread_local! {
+    static PAIRS: Vec<(&'static str, TokenizerConfig, TokenizerConfig)> = loaded_pairs();
+    static EXPORTED: Vec<(&'static str, TokenizerConfig, TokenizerConfig)> = pairs()
+        .into_iter()
+        .map(|(name, gguf, _)| {
+            let hf = roundtrip(&gguf);
+            (name, gguf, hf)
+        })
+        .collect();
+}
+
+proptest! {
+    #[test]
+    fn same_ids_as_gguf(text in text(), add_special: bool, parse_special: bool) {
+        PAIRS.with(|pairs| {
+            for (name, gguf, hf) in pairs {
+                prop_assert_eq!(
+                    gguf.tokenize(&text, add_special, parse_special),
+                    hf.tokenize(&text, add_special, parse_special),
+                    "{}",
+                    name
+                );
+            }
+            Ok(())
+        })?;
+    }
+
+    #[test]
+    fn exported_same_ids(text in text(), add_special: bool, parse_special: bool) {
+        EXPORTED.with(|pairs| {
+            for (name, gguf, hf) in pairs {
+                prop_assert_eq!(
+                    gguf.tokenize(&text, add_special, parse_special),
+                    hf.tokenize(&text, add_special, parse_special),
+                    "{}",
+                    name
+                );
+            }
+            Ok(())
+        })?;
+    }
+}
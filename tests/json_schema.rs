@@ -0,0 +1,186 @@
+//! JSON Schema 约束
+
+mod common;
+
+use common::bpe;
+use proptest::prelude::*;
+use serde_json::{Value, json};
+use try_tokenize::{Grammar, GrammarError, SchemaError, TokenizerConfig, json_schema_to_gbnf};
+
+fn schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "name": { "type": "string", "maxLength": 8 },
+            "age": { "type": "integer" },
+            "tags": { "type": "array", "items": { "enum": ["a", "b"] }, "maxItems": 3 },
+            "ok": { "type": ["boolean", "null"] },
+        },
+        "required": ["name"],
+    })
+}
+
+fn validate(config: &TokenizerConfig, grammar: &Grammar, text: &str) -> Result<(), GrammarError> {
+    grammar.validate(config, &config.tokenize(text, false, false))
+}
+
+#[test]
+fn object() {
+    let config = bpe("qwen2");
+    let grammar = Grammar::from_json_schema(&schema()).unwrap();
+    for text in [
+        r#"{"name":"Bob"}"#,
+        r#"{"age":-12,"name":"Bob","ok":null}"#,
+        r#"{ "name": "", "tags": ["a", "b"] }"#,
+        r#"{"age":0,"name":"\"quoted\"","ok":true,"tags":[]}"#,
+    ] {
+        assert_eq!(validate(&config, &grammar, text), Ok(()), "{text}");
+    }
+    for text in [
+        // 缺少必需的属性
+        r#"{"age":1}"#,
+        // 属性按名称排序
+        r#"{"name":"Bob","age":1}"#,
+        // 未列出的属性
+        r#"{"name":"Bob","extra":1}"#,
+        r#"{"name":"too long name"}"#,
+        r#"{"name":"Bob","tags":["c"]}"#,
+        r#"{"name":"Bob","tags":["a","a","a","a"]}"#,
+        r#"{"age":01,"name":"Bob"}"#,
+        r#"{"name":"Bob""#,
+    ] {
+        assert!(validate(&config, &grammar, text).is_err(), "{text}");
+    }
+    assert_eq!(
+        validate(&config, &grammar, r#"{"name":"Bob""#),
+        Err(GrammarError::Incomplete)
+    );
+}
+
+#[test]
+fn eog_after_complete() {
+    let config = bpe("qwen2");
+    let grammar = Grammar::from_json_schema(&json!({ "const": [1, "x"] })).unwrap();
+    let mut ids = config.tokenize(r#"[1,"x"]"#, false, false);
+    ids.push(config.text_to_token("<|endoftext|>"));
+    assert_eq!(grammar.validate(&config, &ids), Ok(()));
+    assert_eq!(
+        grammar.validate(&config, &ids[1..]),
+        Err(GrammarError::Rejected(ids[1]))
+    );
+    // 掩码与允许的标记一致
+    let mask = grammar.token_mask(&config);
+    let allowed = grammar.allowed_tokens(&config);
+    assert_eq!(mask.iter().filter(|&&b| b).count(), allowed.len());
+    assert!(allowed.iter().all(|&id| mask[id as usize]));
+}
+
+#[test]
+fn recursive_ref() {
+    let config = bpe("qwen2");
+    let schema = json!({
+        "$defs": {
+            "node": {
+                "type": "object",
+                "properties": {
+                    "children": { "type": "array", "items": { "$ref": "#/$defs/node" } },
+                    "value": { "type": "number" },
+                },
+                "required": ["value"],
+            },
+        },
+        "$ref": "#/$defs/node",
+    });
+    let grammar = Grammar::from_json_schema(&schema).unwrap();
+    let text = r#"{"children":[{"value":1.5},{"children":[{"value":-2e10}],"value":3}],"value":0}"#;
+    assert_eq!(validate(&config, &grammar, text), Ok(()));
+    assert!(validate(&config, &grammar, r#"{"children":[{}],"value":0}"#).is_err());
+}
+
+#[test]
+fn any_of_and_free_values() {
+    let config = bpe("qwen2");
+    let schema = json!({
+        "anyOf": [
+            { "type": "array", "minItems": 2, "maxItems": 2 },
+            { "type": "object" },
+        ],
+    });
+    let grammar = Grammar::from_json_schema(&schema).unwrap();
+    assert_eq!(
+        validate(&config, &grammar, r#"[null, {"k": [true]}]"#),
+        Ok(())
+    );
+    assert_eq!(validate(&config, &grammar, r#"{"any": "thing"}"#), Ok(()));
+    assert!(validate(&config, &grammar, "[1]").is_err());
+    assert!(validate(&config, &grammar, "1").is_err());
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        json_schema_to_gbnf(&json!({ "type": "string", "pattern": "a+" })),
+        Err(SchemaError::Unsupported("keyword `pattern`".into()))
+    );
+    assert_eq!(
+        json_schema_to_gbnf(&json!({ "$ref": "#/$defs/missing" })),
+        Err(SchemaError::InvalidRef("#/$defs/missing".into()))
+    );
+    assert!(matches!(
+        json_schema_to_gbnf(&json!({ "enum": [] })),
+        Err(SchemaError::Unsupported(_))
+    ));
+    assert!(matches!(
+        json_schema_to_gbnf(
+            &json!({ "type": "object", "properties": { "a": true }, "required": ["x"] })
+        ),
+        Err(SchemaError::Unsupported(_))
+    ));
+    assert!(matches!(
+        json_schema_to_gbnf(&json!({ "type": "array", "minItems": 3, "maxItems": 2 })),
+        Err(SchemaError::Unsupported(_))
+    ));
+}
+
+thread_local! {
+    static BPE: TokenizerConfig = bpe("qwen2");
+    static GRAMMAR: Grammar = Grammar::from_json_schema(&schema()).unwrap();
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    /// serde_json 序列化的符合模式的值总能通过检查，且每一步的标记都在掩码中
+    #[test]
+    fn serialized_values_accepted(
+        name in "[a-zA-Z \"\\\\\n你好]{0,8}",
+        age in prop::option::of(any::<i32>()),
+        ok in prop::option::of(prop::option::of(any::<bool>())),
+        tags in prop::option::of(prop::collection::vec(prop::sample::select(vec!["a", "b"]), 0..=3)),
+    ) {
+        let mut value = json!({ "name": name });
+        if let Some(age) = age {
+            value["age"] = json!(age);
+        }
+        if let Some(ok) = ok {
+            value["ok"] = json!(ok);
+        }
+        if let Some(tags) = tags {
+            value["tags"] = json!(tags);
+        }
+        let text = if name.len() % 2 == 0 {
+            serde_json::to_string(&value).unwrap()
+        } else {
+            serde_json::to_string_pretty(&value).unwrap()
+        };
+        BPE.with(|config| GRAMMAR.with(|grammar| {
+            let mut grammar = grammar.clone();
+            for id in config.tokenize(&text, false, false) {
+                prop_assert!(grammar.token_mask(config)[id as usize]);
+                grammar.accept_token(config, id).unwrap();
+            }
+            prop_assert!(grammar.is_complete());
+            Ok(())
+        }))?;
+    }
+}
@@ -0,0 +1,246 @@
+//! Unicode 规范化
+
+use std::collections::HashSet;
+
+use proptest::prelude::*;
+use try_tokenize::{
+    NormalizationForm::{self, Nfc, Nfd, Nfkc, Nfkd},
+    NormalizedText,
+    unicode::{
+        unicode_combining_class, unicode_cpt_flags_from_cpt, unicode_normalize,
+        unicode_strip_accents,
+    },
+};
+
+const FORMS: [NormalizationForm; 4] = [Nfc, Nfd, Nfkc, Nfkd];
+
+#[test]
+fn forms() {
+    // (原文, NFC, NFD, NFKC, NFKD)
+    for case in [
+        ("café", "café", "cafe\u{301}", "café", "cafe\u{301}"),
+        ("cafe\u{301}", "café", "cafe\u{301}", "café", "cafe\u{301}"),
+        // 韩文音节按算法分解和组合
+        (
+            "한국",
+            "한국",
+            "\u{1112}\u{1161}\u{11ab}\u{1100}\u{116e}\u{11a8}",
+            "한국",
+            "\u{1112}\u{1161}\u{11ab}\u{1100}\u{116e}\u{11a8}",
+        ),
+        // 兼容字符只在 NFKC/NFKD 中分解
+        ("ﬁ²", "ﬁ²", "ﬁ²", "fi2", "fi2"),
+        ("①ｶ", "①ｶ", "①ｶ", "1カ", "1カ"),
+        // 单字符分解：埃符号变为 Å
+        ("\u{212b}", "Å", "A\u{30a}", "Å", "A\u{30a}"),
+        // 组合排除：天城文 U+0958 不会重新组合
+        (
+            "\u{958}",
+            "\u{915}\u{93c}",
+            "\u{915}\u{93c}",
+            "\u{915}\u{93c}",
+            "\u{915}\u{93c}",
+        ),
+        // 组合标记按组合类排序，下点（220）在抑扬符（230）之前
+        (
+            "a\u{302}\u{323}",
+            "\u{1ead}",
+            "a\u{323}\u{302}",
+            "\u{1ead}",
+            "a\u{323}\u{302}",
+        ),
+        // 组合类相同的标记阻断之后的组合
+        (
+            "a\u{308}\u{301}",
+            "ä\u{301}",
+            "a\u{308}\u{301}",
+            "ä\u{301}",
+            "a\u{308}\u{301}",
+        ),
+        ("\u{301}a", "\u{301}a", "\u{301}a", "\u{301}a", "\u{301}a"),
+        ("", "", "", "", ""),
+    ] {
+        let (text, expected) = (case.0, [case.1, case.2, case.3, case.4]);
+        for (form, expected) in FORMS.into_iter().zip(expected) {
+            assert_eq!(unicode_normalize(text, form), expected, "{text:?} {form:?}");
+        }
+    }
+}
+
+/// Unicode 官方的 `NormalizationTest.txt`，由 `scripts/fetch_normalization_test.py` 下载
+const NORMALIZATION_TEST: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/data/NormalizationTest.txt"
+);
+
+fn parse_column(column: &str) -> String {
+    column
+        .split_whitespace()
+        .map(|cpt| char::from_u32(u32::from_str_radix(cpt, 16).unwrap()).unwrap())
+        .collect()
+}
+
+/// 按官方测试文件检查，文件需要先下载，逐个字符检查所有码位也较慢，默认不运行
+#[test]
+#[ignore = "run scripts/fetch_normalization_test.py first"]
+fn conformance() {
+    let test = std::fs::read_to_string(NORMALIZATION_TEST)
+        .unwrap_or_else(|e| panic!("{NORMALIZATION_TEST}: {e}"));
+    // 与 src/unicode_data.rs 的版本相同
+    assert_eq!(
+        test.lines().next(),
+        Some("# NormalizationTest-14.0.0.txt"),
+        "{NORMALIZATION_TEST} is not the official 14.0.0 file"
+    );
+    let mut part = "";
+    let mut part1 = HashSet::new();
+    for line in test.lines() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('@') {
+            part = name;
+            continue;
+        }
+        let columns = line
+            .split(';')
+            .take(5)
+            .map(parse_column)
+            .collect::<Vec<_>>();
+        let [c1, c2, c3, c4, c5] = &columns[..] else {
+            panic!("bad line {line:?}");
+        };
+        // 文件开头说明的不变式
+        for (form, expected, sources) in [
+            (Nfc, c2, [c1, c2, c3].as_slice()),
+            (Nfc, c4, &[c4, c5]),
+            (Nfd, c3, &[c1, c2, c3]),
+            (Nfd, c5, &[c4, c5]),
+            (Nfkc, c4, &[c1, c2, c3, c4, c5]),
+            (Nfkd, c5, &[c1, c2, c3, c4, c5]),
+        ] {
+            for source in sources {
+                assert_eq!(
+                    &unicode_normalize(source, form),
+                    expected,
+                    "{line} {form:?} {source:?}"
+                );
+            }
+        }
+        if part == "Part1" {
+            part1.insert(c1.chars().next().unwrap());
+        }
+    }
+    assert!(!part1.is_empty());
+    // Part 1 之外的字符在各种形式下都不变
+    for ch in (0..=char::MAX as u32).filter_map(char::from_u32) {
+        if !part1.contains(&ch) {
+            let text = ch.encode_utf8(&mut [0; 4]).to_string();
+            for form in FORMS {
+                assert_eq!(unicode_normalize(&text, form), text, "{ch:?} {form:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn strip_accents() {
+    assert_eq!(unicode_strip_accents("Crème brûlée"), "Creme brulee");
+    assert_eq!(unicode_strip_accents("Ångström ﬁ"), "Angstrom ﬁ");
+    // 只去掉非间距标记，韩文字母不受影响
+    assert_eq!(unicode_strip_accents("한"), "\u{1112}\u{1161}\u{11ab}");
+}
+
+#[test]
+fn offsets() {
+    let text = "ﬁ café Å";
+    let normalized = NormalizedText::new(text).normalize(Nfkd);
+    assert_eq!(normalized.as_str(), "fi cafe\u{301} A\u{30a}");
+    let original = |range| &text[normalized.original_range(range).unwrap()];
+    // 分解得到的每个字符都对应原来的字符
+    assert_eq!(original(0..1), "ﬁ");
+    assert_eq!(original(0..2), "ﬁ");
+    assert_eq!(original(3..6), "caf");
+    assert_eq!(original(6..7), "é");
+    assert_eq!(original(7..9), "é");
+    assert_eq!(original(10..11), "Å");
+    assert_eq!(normalized.original_range(3..3), Some(4..4));
+    assert_eq!(
+        normalized.original_range(13..13),
+        Some(text.len()..text.len())
+    );
+    assert_eq!(normalized.original_range(8..9), None);
+    assert_eq!(normalized.original_range(0..14), None);
+
+    // 组合得到的字符对应两个字符的范围
+    let text = "e\u{301}\u{323}x";
+    let normalized = NormalizedText::new(text).normalize(Nfc);
+    assert_eq!(normalized.as_str(), "\u{1eb9}\u{301}x");
+    assert_eq!(
+        normalized.alignments().collect::<Vec<_>>(),
+        [('\u{1eb9}', 0..5), ('\u{301}', 1..3), ('x', 5..6)]
+    );
+
+    // 去除重音后剩余字符仍对应原文
+    let text = "Crème";
+    let stripped = NormalizedText::new(text).normalize(Nfd).strip_accents();
+    assert_eq!(stripped.to_string(), "Creme");
+    assert_eq!(&text[stripped.original_range(2..4).unwrap()], "èm");
+}
+
+#[test]
+fn flags() {
+    assert!(unicode_cpt_flags_from_cpt('a' as u32).is_nfd);
+    assert!(unicode_cpt_flags_from_cpt(0x301).is_nfd);
+    assert!(!unicode_cpt_flags_from_cpt('é' as u32).is_nfd);
+    assert!(!unicode_cpt_flags_from_cpt('한' as u32).is_nfd);
+    assert!(!unicode_cpt_flags_from_cpt(0x212b).is_nfd);
+    // 兼容字符没有规范分解
+    assert!(unicode_cpt_flags_from_cpt('ﬁ' as u32).is_nfd);
+    assert_eq!(unicode_combining_class('a'), 0);
+    assert_eq!(unicode_combining_class('\u{301}'), 230);
+    assert_eq!(unicode_combining_class('\u{323}'), 220);
+}
+
+/// 组合标记、韩文字母和预组合字符较多的字符串
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<String>(),
+        "[a-zA-Zé Åﬁ①\u{300}-\u{36f}\u{1100}-\u{1112}\u{1161}-\u{1175}\u{11a8}-\u{11c2}한\u{958}\u{93c}]{0,16}",
+    ]
+}
+
+proptest! {
+    #[test]
+    fn idempotent(text in text()) {
+        for form in FORMS {
+            let once = unicode_normalize(&text, form);
+            prop_assert_eq!(unicode_normalize(&once, form), once.clone(), "{:?}", form);
+        }
+    }
+
+    #[test]
+    fn consistent(text in text()) {
+        let nfd = unicode_normalize(&text, Nfd);
+        let nfkd = unicode_normalize(&text, Nfkd);
+        prop_assert_eq!(unicode_normalize(&nfd, Nfc), unicode_normalize(&text, Nfc));
+        prop_assert_eq!(unicode_normalize(&unicode_normalize(&text, Nfc), Nfd), nfd.clone());
+        prop_assert_eq!(unicode_normalize(&nfd, Nfkd), nfkd.clone());
+        prop_assert_eq!(unicode_normalize(&nfkd, Nfkc), unicode_normalize(&text, Nfkc));
+        prop_assert!(nfd.chars().all(|ch| unicode_cpt_flags_from_cpt(ch as u32).is_nfd));
+    }
+
+    #[test]
+    fn alignments_cover_text(text in text()) {
+        for form in FORMS {
+            let normalized = NormalizedText::new(&text).normalize(form).strip_accents();
+            let whole = normalized.original_range(0..normalized.as_str().len()).unwrap();
+            for (_, range) in normalized.alignments() {
+                prop_assert!(text.is_char_boundary(range.start) && text.is_char_boundary(range.end));
+                prop_assert!(range.start < range.end && range.end <= text.len());
+                prop_assert!(whole.start <= range.start && range.end <= whole.end);
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! 词表前缀索引
+
+mod common;
+
+use common::{bpe, spm};
+use proptest::prelude::*;
+use try_tokenize::{TokenId, TokenizerConfig};
+
+/// 遍历词表得到的还原字节
+fn pieces(config: &TokenizerConfig) -> Vec<(Vec<u8>, TokenId)> {
+    config
+        .vocab()
+        .map(|(id, _)| (config.token_to_piece(id, 0, false), id))
+        .filter(|(piece, _)| !piece.is_empty())
+        .collect()
+}
+
+fn starting_with(config: &TokenizerConfig, prefix: &[u8]) -> Vec<TokenId> {
+    let mut ids = config
+        .prefix_index()
+        .starting_with(prefix)
+        .map(|(piece, id)| {
+            assert!(piece.starts_with(prefix));
+            id
+        })
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    ids
+}
+
+fn prefixes_of(config: &TokenizerConfig, text: &[u8]) -> Vec<TokenId> {
+    let mut ids = config
+        .prefix_index()
+        .prefixes_of(text)
+        .into_iter()
+        .map(|(piece, id)| {
+            assert!(text.starts_with(piece));
+            id
+        })
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    ids
+}
+
+#[test]
+fn index_excludes_control() {
+    let config = bpe("gpt2");
+    let index = config.prefix_index();
+    assert_eq!(index.len(), pieces(&config).len());
+    assert!(index.iter().is_sorted());
+    let eot = config.text_to_token("<|endoftext|>");
+    assert!(index.iter().all(|(_, id)| id != eot));
+    assert!(std::ptr::eq(index, config.prefix_index()));
+}
+
+#[test]
+fn spm_pieces_decoded() {
+    let config = spm();
+    let hello = config.text_to_token("▁Hello");
+    assert!(starting_with(&config, b" He").contains(&hello));
+    // 字节标记还原为单个字节
+    let a = config.text_to_token("<0x41>");
+    assert_eq!(prefixes_of(&config, b"ABC").first(), Some(&a));
+    assert!(prefixes_of(&config, b"").is_empty());
+}
+
+#[test]
+fn empty_prefix_matches_all() {
+    let config = spm();
+    assert_eq!(
+        starting_with(&config, b"").len(),
+        config.prefix_index().len()
+    );
+}
+
+thread_local! {
+    static BPE: TokenizerConfig = bpe("qwen2");
+    static SPM: TokenizerConfig = spm();
+}
+
+fn check(config: &TokenizerConfig, text: &str) -> Result<(), TestCaseError> {
+    let all = pieces(config);
+    let bytes = text.as_bytes();
+    for len in 0..=bytes.len().min(4) {
+        let prefix = &bytes[..len];
+        let mut expected = all
+            .iter()
+            .filter(|(piece, _)| piece.starts_with(prefix))
+            .map(|(_, id)| *id)
+            .collect::<Vec<_>>();
+        expected.sort_unstable();
+        prop_assert_eq!(starting_with(config, prefix), expected);
+    }
+    let mut expected = all
+        .iter()
+        .filter(|(piece, _)| bytes.starts_with(piece))
+        .map(|(_, id)| *id)
+        .collect::<Vec<_>>();
+    expected.sort_unstable();
+    prop_assert_eq!(prefixes_of(config, bytes), expected);
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn same_as_brute_force(text in "[ a-zA-Z\u{4e00}-\u{4e10}]{0,12}") {
+        BPE.with(|config| check(config, &text))?;
+        SPM.with(|config| check(config, &text))?;
+    }
+}
@@ -0,0 +1,137 @@
+//! `tokenizer.ggml.pre` 到预分词正则的映射和 `ignore_merges`
+
+mod common;
+
+use common::{UNMERGED, bpe, bpe_gguf};
+use try_tokenize::{
+    LoadError, TokenizerConfig, load_gguf_bytes,
+    unicode::{unicode_byte_to_utf8, unicode_regex_split},
+};
+
+/// llama.cpp 支持且这里已映射的预分词类型
+const SUPPORTED: &[&str] = &[
+    "default",
+    "llama3",
+    "llama-v3",
+    "llama-bpe",
+    "falcon3",
+    "dbrx",
+    "smaug-bpe",
+    "deepseek-coder",
+    "falcon",
+    "starcoder",
+    "refact",
+    "codeshell",
+    "exaone",
+    "minerva-7b",
+    "command-r",
+    "smollm",
+    "gpt2",
+    "gpt-2",
+    "phi-2",
+    "mpt",
+    "olmo",
+    "jais",
+    "roberta-bpe",
+    "qwen2",
+    "deepseek-r1-qwen",
+    "megrez",
+    "stablelm2",
+    "poro-chat",
+    "bloom",
+    "gpt3-finnish",
+    "viking",
+    "chatglm-bpe",
+    "tekken",
+    "gpt-4o",
+];
+
+const TEXT: &str = "Hello world! It's 12345 tokens,\n\n  你好 café\tпривет 🦙 <tag>";
+
+/// 字节级编码后的文本
+fn encoded(text: &str) -> String {
+    text.bytes().map(unicode_byte_to_utf8).collect()
+}
+
+/// 标记文本拼接的结果
+fn pieces(config: &TokenizerConfig, ids: &[u32]) -> String {
+    ids.iter()
+        .map(|&id| config.id_to_token[id as usize].text.as_str())
+        .collect()
+}
+
+#[test]
+fn supported_pre_tokenizers() {
+    for &pre in SUPPORTED {
+        let config = bpe(pre);
+        let regex_exprs = config.session.borrow().regex_exprs().to_vec();
+        assert!(!regex_exprs.is_empty(), "{pre}");
+        // 正则都能编译，分割结果覆盖整个文本
+        let words = unicode_regex_split(TEXT, &regex_exprs);
+        assert!(words.len() > 1, "{pre}");
+        let ids = config.tokenize(TEXT, false, false);
+        assert_eq!(pieces(&config, &ids), encoded(TEXT), "{pre}");
+    }
+}
+
+#[test]
+fn pre_tokenizer_regexes() {
+    let regexes = |pre| bpe(pre).session.borrow().regex_exprs().len();
+    assert_eq!(regexes("default"), 4);
+    assert_eq!(regexes("falcon"), 3);
+    assert_eq!(regexes("starcoder"), 2);
+    assert_eq!(regexes("gpt2"), 1);
+
+    // 不同类型对数字的切分不同
+    let split = |pre: &str, text: &str| {
+        let config = bpe(pre);
+        let regex_exprs = config.session.borrow().regex_exprs().to_vec();
+        unicode_regex_split(text, &regex_exprs)
+    };
+    assert_eq!(split("gpt2", "abc 12345"), ["abc", "Ġ12345"]);
+    assert_eq!(split("llama-bpe", "abc 12345"), ["abc", "Ġ", "123", "45"]);
+    assert_eq!(split("qwen2", "12345"), ["1", "2", "3", "4", "5"]);
+    assert_eq!(split("starcoder", "12345"), ["1", "2", "3", "4", "5"]);
+}
+
+#[test]
+fn pre_tokenizer_options() {
+    assert!(bpe("llama-bpe").ignore_merges);
+    assert!(bpe("tekken").ignore_merges);
+    assert!(!bpe("qwen2").ignore_merges);
+    assert!(!bpe("qwen2").clean_spaces);
+    assert!(bpe("gpt2").clean_spaces);
+}
+
+#[test]
+fn unsupported_pre_tokenizer() {
+    for pre in ["deepseek-llm", "chameleon", "llama4", ""] {
+        let Err(LoadError::Format(msg)) = load_gguf_bytes(&bpe_gguf(pre)) else {
+            panic!("{pre}: expected a format error");
+        };
+        assert!(msg.contains("unsupported pre-tokenizer"), "{pre}: {msg}");
+    }
+}
+
+#[test]
+fn ignore_merges() {
+    // 忽略合并时，在词表中的词直接作为一个标记输出
+    let llama3 = bpe("llama-bpe");
+    let ids = llama3.tokenize(UNMERGED, false, false);
+    assert_eq!(ids.len(), 1);
+    assert_eq!(pieces(&llama3, &ids), encoded(UNMERGED));
+
+    // 不忽略合并时，没有合并规则的词按字节输出
+    let qwen = bpe("qwen2");
+    let ids = qwen.tokenize(UNMERGED, false, false);
+    assert_eq!(ids.len(), UNMERGED.len());
+    assert_eq!(pieces(&qwen, &ids), encoded(UNMERGED));
+
+    // 不在词表中的词仍按合并规则处理
+    let ids = llama3.tokenize("Hellox", false, false);
+    let pieces = ids
+        .iter()
+        .map(|&id| llama3.id_to_token[id as usize].text.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(pieces, ["Hello", "x"]);
+}
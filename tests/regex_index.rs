@@ -0,0 +1,119 @@
+//! 正则约束的标记索引
+
+mod common;
+
+use common::{bpe, spm};
+use proptest::prelude::*;
+use try_tokenize::{Grammar, RegexIndex, RegexIndexError, TokenizerConfig};
+
+#[test]
+fn digits() {
+    let config = bpe("gpt2");
+    let index = RegexIndex::new(&config, "[0-9]{1,3}").unwrap();
+    let state = index.initial_state();
+    let expected = config
+        .vocab()
+        .map(|(id, _)| id)
+        .filter(|&id| {
+            let piece = config.token_to_piece(id, 0, false);
+            (1..=3).contains(&piece.len()) && piece.iter().all(u8::is_ascii_digit)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(index.allowed_tokens(state), expected);
+    assert!(!index.is_accepting(state));
+
+    let state = index
+        .next_state(state, config.text_to_token("123"))
+        .unwrap();
+    assert!(index.is_accepting(state));
+    // 已经有三位数字，只能结束生成
+    let eot = config.text_to_token("<|endoftext|>");
+    assert!(index.allowed_tokens(state).contains(&eot));
+    assert!(
+        index
+            .allowed_tokens(state)
+            .iter()
+            .all(|&id| config.is_eog(id))
+    );
+    assert_eq!(index.next_state(state, eot), Some(state));
+    assert_eq!(index.next_state(state, config.text_to_token("1")), None);
+}
+
+#[test]
+fn partial_utf8() {
+    // “中” 不在 SPM 测试词表中，只能用字节回退的三个标记表示
+    let config = spm();
+    let byte = |b: u8| config.text_to_token(&format!("<0x{b:02X}>"));
+    let index = RegexIndex::new(&config, "中+").unwrap();
+    let mut state = index.initial_state();
+    assert_eq!(index.allowed_tokens(state), [byte(0xE4)]);
+    for (i, b) in "中中".bytes().enumerate() {
+        assert_eq!(index.is_accepting(state), i == 3);
+        state = index.next_state(state, byte(b)).unwrap();
+    }
+    assert!(index.is_accepting(state));
+    assert!(index.allowed_tokens(state).contains(&byte(0xE4)));
+    assert!(
+        index
+            .allowed_tokens(state)
+            .contains(&config.text_to_token("</s>"))
+    );
+}
+
+#[test]
+fn unsatisfiable() {
+    let config = spm();
+    let index = RegexIndex::new(&config, "[a&&b]").unwrap();
+    assert_eq!(index.n_states(), 1);
+    assert!(index.allowed_tokens(index.initial_state()).is_empty());
+    assert!(matches!(
+        RegexIndex::new(&config, "("),
+        Err(RegexIndexError::Build(_))
+    ));
+}
+
+#[test]
+fn serialize() {
+    let config = bpe("qwen2");
+    let index = RegexIndex::new(&config, r"\d+(\.\d+)?").unwrap();
+    let json = serde_json::to_string(&index).unwrap();
+    let restored = serde_json::from_str::<RegexIndex>(&json).unwrap();
+    assert_eq!(restored, index);
+    assert_eq!(restored.pattern(), r"\d+(\.\d+)?");
+    assert_eq!(restored.n_tokens(), config.n_tokens());
+}
+
+thread_local! {
+    static BPE: TokenizerConfig = bpe("qwen2");
+    static SPM: TokenizerConfig = spm();
+}
+
+const PATTERN: &str = " ?(?:[a-zA-Z]+|[一-龥]+)(?: (?:[a-zA-Z]+|[一-龥]+))*";
+const GRAMMAR: &str = "root ::= \" \"? word (\" \" word)*\nword ::= [a-zA-Z]+ | [一-龥]+";
+
+/// 与表达同一语言的 GBNF 语法给出相同的标记，符合正则的文本分词后总能被接受
+fn check(config: &TokenizerConfig, index: &RegexIndex, text: &str) -> Result<(), TestCaseError> {
+    let mut grammar = Grammar::parse(GRAMMAR, "root").unwrap();
+    let mut state = index.initial_state();
+    for id in config.tokenize(text, false, false) {
+        let expected = grammar.allowed_tokens(config);
+        prop_assert_eq!(index.allowed_tokens(state), expected.as_slice());
+        state = index.next_state(state, id).unwrap();
+        grammar.accept_token(config, id).unwrap();
+    }
+    prop_assert!(index.is_accepting(state));
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn same_as_grammar(
+        words in prop::collection::vec("[a-zA-Z]{1,6}|[\u{4e00}-\u{4e10}你好]{1,3}", 1..4)
+    ) {
+        let text = words.join(" ");
+        BPE.with(|config| check(config, &RegexIndex::new(config, PATTERN).unwrap(), &text))?;
+        SPM.with(|config| check(config, &RegexIndex::new(config, PATTERN).unwrap(), &text))?;
+    }
+}
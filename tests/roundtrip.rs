@@ -0,0 +1,204 @@
+//! 分词往返与健壮性的性质测试
+//!
+//! 对每种已实现分词的词表类型（字节级 BPE 和带字节回退的 SPM），以及不同的加载器和预分词器，
+//! 检查任意 Unicode 文本满足 `detokenize(tokenize(s)) == s`。比较前关闭 `clean_spaces`，
+//! 它会有意去掉标点前的空格。SPM 有两处已知的规范化：
+//!
+//! - U+2581 与空格使用相同的标记，还原为空格；
+//! - 特殊标记之后的文本开头会补一个空格，还原时不会去掉。
+//!
+//! 另外检查夹杂特殊标记文本和控制字符的任意输入在各种参数下都不会 panic，Unigram 词表也在其中。
+
+mod common;
+
+use common::{
+    CONTROL, USER_DEFINED, bpe, bpe_hf, hf, load_gguf, precompiled_charsmap, spm, spm_hf,
+    spm_sentencepiece, spm_vocab, ugm, ugm_gguf, ugm_vocab, unsupported_gguf,
+};
+use proptest::prelude::*;
+use try_tokenize::{TextFragment, TokenizerConfig, VocabType};
+
+/// 已实现分词的词表
+fn configs() -> Vec<(&'static str, TokenizerConfig)> {
+    let mut configs = vec![
+        ("gpt2", bpe("gpt2")),
+        ("qwen2", bpe("qwen2")),
+        ("llama3", bpe("llama3")),
+        ("tekken", bpe("tekken")),
+        ("gpt-4o", bpe("gpt-4o")),
+        ("hf-bpe", hf(bpe_hf("llama-bpe"))),
+        ("spm", spm()),
+        ("sentencepiece", spm_sentencepiece(&spm_vocab())),
+        ("hf-spm", hf(spm_hf(&spm_vocab()))),
+    ];
+    for (_, config) in &mut configs {
+        config.clean_spaces = false;
+    }
+    configs
+}
+
+thread_local! {
+    static CONFIGS: Vec<(&'static str, TokenizerConfig)> = configs();
+    /// 尚未实现分词的词表类型，只检查不会 panic
+    /// Unigram 词表，规范化有损，只检查不会 panic 和计数一致
+    static UGM: Vec<TokenizerConfig> = vec![
+        ugm(),
+        load_gguf(&ugm_gguf(
+            &ugm_vocab(),
+            &precompiled_charsmap(&[("Ａ", "A"), ("ﬁ", "fi"), ("\u{3000}", " "), ("\u{0}", "")]),
+        )),
+    ];
+    static UNSUPPORTED: Vec<TokenizerConfig> = ["bert", "rwkv"]
+        .into_iter()
+        .map(|model| load_gguf(&unsupported_gguf(model)))
+        .collect();
+}
+
+/// 按文档中的规范化得到 `detokenize(tokenize(text))` 的期望结果
+fn normalized(config: &TokenizerConfig, text: &str, parse_special: bool) -> String {
+    if config.vocab_type != VocabType::Spm {
+        return text.to_string();
+    }
+    let mut expected = String::new();
+    let mut prev_special = false;
+    for fragment in config.partition_special(text, parse_special) {
+        match fragment {
+            TextFragment::Token(id) => {
+                expected.push_str(&config.id_to_token[id as usize].text);
+                prev_special = true;
+            }
+            TextFragment::Text(s) => {
+                if prev_special && config.add_space_prefix {
+                    expected.push(' ');
+                }
+                expected.push_str(&s.replace('\u{2581}', " "));
+                prev_special = false;
+            }
+        }
+    }
+    expected
+}
+
+/// 夹杂特殊标记文本、单独的控制字符和替换字符的任意字符串
+fn text_with_specials() -> impl Strategy<Value = String> {
+    let specials = CONTROL
+        .iter()
+        .chain(USER_DEFINED)
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+    prop::collection::vec(
+        prop_oneof![
+            4 => any::<String>(),
+            2 => prop::sample::select(specials),
+            2 => "[\u{0}-\u{1f}\u{7f}\u{85}\u{2028}\u{fffd}\u{2581} <|>]{1,4}",
+        ],
+        0..8,
+    )
+    .prop_map(|parts| parts.concat())
+}
+
+#[test]
+fn spm_normalization() {
+    let config = spm();
+    let ids = config.tokenize("a\u{2581}b<|im_end|>c", false, true);
+    assert_eq!(config.detokenize(&ids, false, true), "a b<|im_end|> c");
+    // 用户定义的标记总是会匹配，之后同样补空格
+    let ids = config.tokenize("<tool_call>x", false, false);
+    assert_eq!(config.detokenize(&ids, false, false), "<tool_call> x");
+}
+
+proptest! {
+    #[test]
+    fn round_trip(text in any::<String>()) {
+        CONFIGS.with(|configs| {
+            for (name, config) in configs {
+                let ids = config.tokenize(&text, false, false);
+                let expected = normalized(config, &text, false);
+                prop_assert_eq!(config.detokenize(&ids, false, false), expected, "{}", name);
+            }
+            Ok(())
+        })?;
+    }
+
+    #[test]
+    fn round_trip_special(text in text_with_specials(), parse_special: bool) {
+        CONFIGS.with(|configs| {
+            for (name, config) in configs {
+                let ids = config.tokenize(&text, false, parse_special);
+                let expected = normalized(config, &text, parse_special);
+                prop_assert_eq!(config.detokenize(&ids, false, true), expected, "{}", name);
+                // 解析特殊标记时文本中的每个特殊标记恰好对应一个标记
+                if parse_special {
+                    for special in CONTROL.iter().chain(USER_DEFINED) {
+                        let id = config.text_to_token(special);
+                        prop_assert_eq!(
+                            ids.iter().filter(|&&t| t == id).count(),
+                            text.matches(special).count(),
+                            "{} {}",
+                            name,
+                            special
+                        );
+                    }
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    #[test]
+    fn never_panics(text in text_with_specials(), add_special: bool, parse_special: bool) {
+        CONFIGS.with(|configs| {
+            for (name, config) in configs {
+                let ids = config.tokenize(&text, add_special, parse_special);
+                prop_assert!(ids.iter().all(|&id| id < config.n_tokens()), "{}", name);
+                prop_assert_eq!(
+                    config.count_tokens(&text, add_special, parse_special),
+                    ids.len(),
+                    "{}",
+                    name
+                );
+                config.detokenize(&ids, add_special, parse_special);
+                config.detokenize(&ids, !add_special, !parse_special);
+            }
+            Ok(())
+        })?;
+        UGM.with(|configs| {
+            for config in configs {
+                let ids = config.tokenize(&text, add_special, parse_special);
+                prop_assert!(ids.iter().all(|&id| id < config.n_tokens()));
+                prop_assert_eq!(config.count_tokens(&text, add_special, parse_special), ids.len());
+                config.detokenize(&ids, add_special, parse_special);
+            }
+            Ok(())
+        })?;
+        UNSUPPORTED.with(|configs| {
+            for config in configs {
+                prop_assert!(config.tokenize(&text, add_special, parse_special).is_empty());
+                config.partition_special(&text, parse_special);
+            }
+            Ok(())
+        })?;
+    }
+
+    #[test]
+    fn partition_covers_text(text in text_with_specials(), parse_special: bool) {
+        CONFIGS.with(|configs| {
+            for (name, config) in configs {
+                let mut joined = String::new();
+                for fragment in config.partition_special(&text, parse_special) {
+                    match fragment {
+                        TextFragment::Token(id) => {
+                            joined.push_str(&config.id_to_token[id as usize].text)
+                        }
+                        TextFragment::Text(s) => {
+                            prop_assert!(!s.is_empty(), "{}", name);
+                            joined.push_str(s);
+                        }
+                    }
+                }
+                prop_assert_eq!(&joined, &text, "{}", name);
+            }
+            Ok(())
+        })?;
+    }
+}
@@ -0,0 +1,220 @@
+//! SentencePiece `.model` 文件的加载、SPM 分词和 Unigram 分词
+
+mod common;
+
+use common::{precompiled_charsmap, sentencepiece_model};
+use try_tokenize::{LoadError, TokenizerConfig, VocabType, load_sentencepiece_bytes};
+
+const NORMAL: u64 = 1;
+const UNKNOWN: u64 = 2;
+const CONTROL: u64 = 3;
+
+fn load(pieces: &[(&str, f32, u64)], add_dummy_prefix: bool) -> TokenizerConfig {
+    load_sentencepiece_bytes(&sentencepiece_model(pieces, Some(2), add_dummy_prefix)).unwrap()
+}
+
+const SPECIALS: [(&str, f32, u64); 3] = [
+    ("<unk>", 0.0, UNKNOWN),
+    ("<s>", 0.0, CONTROL),
+    ("</s>", 0.0, CONTROL),
+];
+
+#[test]
+fn highest_score_first() {
+    // 分数与编号顺序无关，先合并分数高的 ab，而不是 ▁a
+    let mut pieces = SPECIALS.to_vec();
+    pieces.extend([
+        ("\u{2581}", -1.0, NORMAL),
+        ("a", -1.0, NORMAL),
+        ("b", -1.0, NORMAL),
+        ("ab", -2.0, NORMAL),
+        ("\u{2581}a", -9.0, NORMAL),
+    ]);
+    let config = load(&pieces, true);
+    assert_eq!(config.tokenize("ab", false, false), [3, 6]);
+    assert_eq!(config.tokenize("ab", true, false), [1, 3, 6]);
+}
+
+#[test]
+fn leftmost_on_tie() {
+    let mut pieces = SPECIALS.to_vec();
+    pieces.extend([("a", -1.0, NORMAL), ("aa", -1.0, NORMAL)]);
+    let config = load(&pieces, false);
+    assert_eq!(config.tokenize("aaa", false, false), [4, 3]);
+    assert_eq!(config.tokenize("aaaaa", false, false), [4, 4, 3]);
+}
+
+#[test]
+fn special_ids() {
+    let mut pieces = SPECIALS.to_vec();
+    pieces.push(("a", -1.0, NORMAL));
+    let config = load(&pieces, true);
+    assert_eq!((config.unk, config.bos, config.eos), (0, 1, 2));
+    assert!(config.add_space_prefix);
+    assert!(!load(&pieces, false).add_space_prefix);
+}
+
+#[test]
+fn long_input() {
+    let mut pieces = SPECIALS.to_vec();
+    pieces.extend([
+        ("a", -1.0, NORMAL),
+        ("b", -1.0, NORMAL),
+        ("ab", -2.0, NORMAL),
+    ]);
+    let config = load(&pieces, false);
+    let text = "ab".repeat(100_000);
+    let ids = config.tokenize(&text, false, false);
+    assert_eq!(ids.len(), 100_000);
+    assert!(ids.iter().all(|&id| id == 5));
+}
+
+#[test]
+fn missing_byte_pieces() {
+    // 没有 <0xXX> 字节标记时，词表外的字符是一个未知标记
+    let mut pieces = SPECIALS.to_vec();
+    pieces.extend([
+        ("\u{2581}", -1.0, NORMAL),
+        ("a", -1.0, NORMAL),
+        ("b", -1.0, NORMAL),
+        ("\u{2581}a", -2.0, NORMAL),
+    ]);
+    let config = load(&pieces, true);
+    assert_eq!(config.tokenize("a z", false, false), [6, 3, 0]);
+    assert_eq!(config.tokenize("b中a", false, false), [3, 5, 0, 4]);
+    assert_eq!(config.count_tokens("a z", false, false), 3);
+}
+
+#[test]
+fn reject_word_and_char() {
+    let pieces = SPECIALS.to_vec();
+    for model_type in [Some(3), Some(4)] {
+        match load_sentencepiece_bytes(&sentencepiece_model(&pieces, model_type, true)) {
+            Err(LoadError::Format(msg)) => assert!(msg.contains("model type"), "{msg}"),
+            Err(e) => panic!("{model_type:?}: unexpected error {e}"),
+            Ok(_) => panic!("{model_type:?}: loaded an unsupported model"),
+        }
+    }
+}
+
+/// 在 Unigram 模型后追加 `normalizer_spec { precompiled_charsmap }`，后出现的字段覆盖前面的
+fn unigram(pieces: &[(&str, f32, u64)], charsmap: &[u8]) -> TokenizerConfig {
+    let mut data = sentencepiece_model(pieces, None, true);
+    let mut field = vec![0x12];
+    push_varint(&mut field, charsmap.len());
+    field.extend(charsmap);
+    data.push(0x1a);
+    push_varint(&mut data, field.len());
+    data.extend(field);
+    load_sentencepiece_bytes(&data).unwrap()
+}
+
+fn push_varint(buf: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+#[test]
+fn unigram_best_score() {
+    let mut pieces = SPECIALS.to_vec();
+    pieces.extend([
+        ("\u{2581}", -2.0, NORMAL),
+        ("a", -1.0, NORMAL),
+        ("b", -1.0, NORMAL),
+        ("ab", -1.5, NORMAL),
+        ("\u{2581}ab", -5.0, NORMAL),
+    ]);
+    let config = unigram(&pieces, &[]);
+    assert_eq!(config.vocab_type, VocabType::Ugm);
+    // ▁ + ab 的分数 -3.5 高于 ▁ab 的 -5 和 ▁ + a + b 的 -4
+    assert_eq!(config.tokenize("ab", false, false), [3, 6]);
+    // Unigram 模型只在末尾加 EOS
+    assert_eq!(config.tokenize("ab", true, false), [3, 6, 2]);
+    assert_eq!(config.count_tokens("ab", true, false), 3);
+    // 空白合并为一个，末尾的空白去掉
+    assert_eq!(
+        config.tokenize("  ab   ab  ", false, false),
+        config.tokenize("ab ab", false, false)
+    );
+    assert_eq!(config.tokenize("ab ab", false, false), [3, 6, 3, 6]);
+}
+
+#[test]
+fn unigram_unknown() {
+    let mut pieces = SPECIALS.to_vec();
+    pieces.extend([("\u{2581}", -1.0, NORMAL), ("a", -1.0, NORMAL)]);
+    let config = unigram(&pieces, &[]);
+    // 连续的未知字符合并为一个未知标记
+    assert_eq!(config.tokenize("a你好a", false, false), [3, 4, 0, 4]);
+    assert_eq!(config.tokenize("你好", false, false), [3, 0]);
+}
+
+#[test]
+fn unigram_charsmap() {
+    let mut pieces = SPECIALS.to_vec();
+    pieces.extend([
+        ("\u{2581}", -1.0, NORMAL),
+        ("A", -1.0, NORMAL),
+        ("f", -1.0, NORMAL),
+        ("i", -1.0, NORMAL),
+        ("x", -1.0, NORMAL),
+    ]);
+    let charsmap = precompiled_charsmap(&[
+        ("Ａ", "A"),
+        ("ﬁ", "fi"),
+        ("\u{3000}", " "),
+        // 最长匹配优先
+        ("ＡＡ", "x"),
+    ]);
+    let config = unigram(&pieces, &charsmap);
+    assert_eq!(config.precompiled_charsmap, charsmap);
+    assert_eq!(config.tokenize("Ａ", false, false), [3, 4]);
+    assert_eq!(config.tokenize("ﬁ", false, false), [3, 5, 6]);
+    assert_eq!(config.tokenize("ＡＡＡ", false, false), [3, 7, 4]);
+    // 替换出的空格也会合并
+    assert_eq!(
+        config.tokenize("Ａ\u{3000}\u{3000}Ａ", false, false),
+        [3, 4, 3, 4]
+    );
+}
+
+#[test]
+fn unigram_invalid_charsmap() {
+    let pieces = SPECIALS.to_vec();
+    let mut data = sentencepiece_model(&pieces, None, true);
+    // 双数组的长度超出数据
+    data.extend([0x1a, 8, 0x12, 6, 0xff, 0, 0, 0, 1, 2]);
+    match load_sentencepiece_bytes(&data) {
+        Err(LoadError::Format(msg)) => assert!(msg.contains("precompiled_charsmap"), "{msg}"),
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("loaded an invalid precompiled_charsmap"),
+    }
+}
+
+#[test]
+fn reject_bpe_charsmap() {
+    let pieces = SPECIALS.to_vec();
+    let mut data = sentencepiece_model(&pieces, Some(2), true);
+    // BPE 模型不支持 charsmap：normalizer_spec { precompiled_charsmap: "\x01\x02" }
+    data.extend([0x1a, 4, 0x12, 2, 1, 2]);
+    match load_sentencepiece_bytes(&data) {
+        Err(LoadError::Format(msg)) => assert!(msg.contains("precompiled_charsmap"), "{msg}"),
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("loaded a model with precompiled_charsmap"),
+    }
+    // 空的 charsmap 等同于没有
+    let mut data = sentencepiece_model(&pieces, Some(2), true);
+    data.extend([0x1a, 2, 0x12, 0]);
+    assert!(load_sentencepiece_bytes(&data).is_ok());
+}
+
+#[test]
+fn truncated_model() {
+    let mut pieces = SPECIALS.to_vec();
+    pieces.push(("a", -1.0, NORMAL));
+    let data = sentencepiece_model(&pieces, Some(2), true);
+    assert!(load_sentencepiece_bytes(&data[..data.len() - 1]).is_err());
+}
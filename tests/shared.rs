@@ -0,0 +1,178 @@
+//! 借用 GGUF 映射的词表
+
+mod common;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    collections::HashMap,
+    fs::{self, File},
+    sync::Arc,
+};
+
+use common::{CONTROL, bpe_gguf, bpe_vocab, bpe_vocab_gguf, load_gguf, spm_gguf, spm_vocab};
+use memmap2::Mmap;
+use proptest::prelude::*;
+use try_tokenize::{Piece, TokenizerConfig, load_shared};
+
+/// 按线程统计仍未释放的字节数，并行运行的其他测试不会计入
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+}
+
+fn count(size: isize) {
+    let _ = ALLOCATED.try_with(|n| n.set(n.get() + size));
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size() as isize);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        count(-(layout.size() as isize));
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size as isize - layout.size() as isize);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// `f` 在当前线程上分配且返回时仍未释放的字节数
+fn allocated<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.with(Cell::get);
+    let result = f();
+    (result, (ALLOCATED.with(Cell::get) - before) as usize)
+}
+
+fn mmap(bytes: &[u8], name: &str) -> Arc<Mmap> {
+    let path = std::env::temp_dir().join(format!(
+        "try-tokenize-shared-{}-{name}.gguf",
+        std::process::id()
+    ));
+    fs::write(&path, bytes).unwrap();
+    let file = File::open(&path).unwrap();
+    let mmap = unsafe { Mmap::map(&file) }.unwrap();
+    fs::remove_file(&path).unwrap();
+    Arc::new(mmap)
+}
+
+#[test]
+fn borrows_mapping() {
+    for (name, gguf) in [("bpe", bpe_gguf("qwen2")), ("spm", spm_gguf(&spm_vocab()))] {
+        let file = mmap(&gguf, name);
+        let config = load_shared(file.clone());
+        let owned = load_gguf(&gguf);
+        let range = file.as_ptr_range();
+        for ((id, token), (_, expected)) in config.vocab().zip(owned.vocab()) {
+            assert!(token.text.is_mapped());
+            assert!(range.contains(&token.text.as_ptr()) || token.text.is_empty());
+            assert_eq!(token.text, expected.text);
+            assert_eq!(config.text_to_token(&token.text), id);
+        }
+        assert_eq!(config.token_to_id, owned.token_to_id);
+        // 每个标记持有一个引用，映射在分词器释放后才会释放
+        assert!(Arc::strong_count(&file) > config.n_tokens() as usize);
+        drop(config);
+        assert_eq!(Arc::strong_count(&file), 1);
+    }
+}
+
+#[test]
+fn resident_size() {
+    // 额外加入 n 个两字母标记和对应的合并规则
+    let mut vocab = bpe_vocab();
+    let n = vocab.tokens.len();
+    for a in 'a'..='z' {
+        for b in 'a'..='z' {
+            let token = format!("{a}{b}");
+            if !vocab.tokens.contains(&token) {
+                vocab.tokens.push(token);
+                vocab.types.push(1);
+                vocab.merges.push(format!("{a} {b}"));
+            }
+        }
+    }
+    let extra = vocab.tokens.len() - n;
+    let small = bpe_gguf("qwen2");
+    let large = bpe_vocab_gguf(&vocab, "qwen2");
+    // 预分词正则编译后进入全局缓存，先加载一次
+    load_gguf(&small);
+
+    let (config, small_bytes) = allocated(|| load_shared(mmap(&small, "small")));
+    drop(config);
+    let (config, large_bytes) = allocated(|| load_shared(mmap(&large, "large")));
+    let (owned, owned_bytes) = allocated(|| load_gguf(&large));
+    assert_eq!(config.bpe_ranks.len(), owned.bpe_ranks.len());
+    // 标记的文本借用映射，不计入常驻内存
+    let text = owned
+        .vocab()
+        .map(|(_, token)| token.text.len())
+        .sum::<usize>();
+    assert!(
+        large_bytes + text <= owned_bytes,
+        "{large_bytes} {owned_bytes}"
+    );
+    // 每个标记和合并规则只有定长的表项：id_to_token、token_to_id 和 bpe_ranks 中各一项，
+    // 合并规则按文本保存时约 180 字节
+    let per_token = (large_bytes - small_bytes) / extra;
+    assert!(per_token <= 150, "{per_token} bytes per token and merge");
+    assert!(size_of::<Piece>() <= 24);
+}
+
+#[test]
+fn outlives_caller_mapping() {
+    let gguf = bpe_gguf("qwen2");
+    let config = load_shared(mmap(&gguf, "outlives"));
+    let ids = config.tokenize("<|im_start|>Hello world<|im_end|>", false, true);
+    assert_eq!(config.token_to_piece(ids[1], 0, false), b"Hello");
+    assert_eq!(
+        config.detokenize(&ids, false, true),
+        "<|im_start|>Hello world<|im_end|>"
+    );
+    let id = config.text_to_token(CONTROL[0]);
+    assert_eq!(config.id_to_token[id as usize].text, CONTROL[0]);
+}
+
+#[test]
+fn piece_behaves_like_str() {
+    let owned = Piece::from("Hello");
+    assert!(!owned.is_mapped());
+    assert_eq!(owned, "Hello");
+    assert_eq!("Hello", owned);
+    assert_eq!(owned, String::from("Hello"));
+    assert_eq!(owned.len(), 5);
+    assert_eq!(format!("{owned} {owned:?}"), "Hello \"Hello\"");
+    assert_eq!(serde_json::to_string(&owned).unwrap(), "\"Hello\"");
+    assert!(Piece::default().is_empty());
+
+    let map = HashMap::from([(owned.clone(), 1)]);
+    assert_eq!(map.get("Hello"), Some(&1));
+}
+
+thread_local! {
+    static CONFIGS: [(TokenizerConfig, TokenizerConfig); 2] = [
+        (load_gguf(&bpe_gguf("llama3")), load_shared(mmap(&bpe_gguf("llama3"), "llama3"))),
+        (load_gguf(&spm_gguf(&spm_vocab())), load_shared(mmap(&spm_gguf(&spm_vocab()), "spm"))),
+    ];
+}
+
+proptest! {
+    #[test]
+    fn same_tokens(text in "[a-z ,.你好中é\n]{0,24}|\\PC{0,12}") {
+        CONFIGS.with(|configs| {
+            for (owned, shared) in configs {
+                prop_assert_eq!(owned.tokenize(&text, true, true), shared.tokenize(&text, true, true));
+            }
+            Ok(())
+        })?;
+    }
+}
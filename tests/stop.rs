@@ -0,0 +1,147 @@
+//! 跨标记的停止字符串
+
+mod common;
+
+use common::{bpe, spm};
+use proptest::prelude::*;
+use try_tokenize::{StopMatch, StopMatcher, StopReason, TokenizerConfig};
+
+/// 逐个输入分词结果，返回输出的文本、停止条件和全部输入的字节
+fn run(
+    config: &TokenizerConfig,
+    stops: &[&str],
+    text: &str,
+) -> (String, Option<StopMatch>, Vec<u8>) {
+    let mut matcher = StopMatcher::new(stops);
+    let mut output = String::new();
+    let mut fed = Vec::new();
+    for id in config.tokenize(text, false, false) {
+        fed.extend(config.token_to_piece(id, 0, false));
+        let step = matcher.feed(config, id);
+        output.push_str(&step.text);
+        if step.stop.is_some() {
+            return (output, step.stop, fed);
+        }
+    }
+    output.push_str(&matcher.finish());
+    (output, None, fed)
+}
+
+#[test]
+fn across_tokens() {
+    let config = bpe("gpt2");
+    let (output, stop, fed) = run(&config, &["\nUser:"], "Hello\nUser: hi");
+    assert_eq!(output, "Hello");
+    let stop = stop.unwrap();
+    assert_eq!(stop.reason, StopReason::String(0));
+    assert_eq!(fed.len() - stop.trim, "Hello".len());
+}
+
+#[test]
+fn hold_back_partial_match() {
+    let config = bpe("gpt2");
+    let mut matcher = StopMatcher::new(&["\nUser:"]);
+    let hello = matcher.feed(&config, config.text_to_token("Hello"));
+    assert_eq!(hello.text, "Hello");
+    let newline = config.text_to_token("Ċ");
+    let step = matcher.feed(&config, newline);
+    assert_eq!(step.text, "");
+    assert_eq!(matcher.pending(), b"\n");
+    // 后续不匹配时一并输出
+    let step = matcher.feed(&config, config.text_to_token("Hello"));
+    assert_eq!(step.text, "\nHello");
+    assert_eq!(step.stop, None);
+}
+
+#[test]
+fn earliest_match_wins() {
+    let config = bpe("gpt2");
+    let (output, stop, _) = run(&config, &["world", "Hello w"], "Hello world");
+    assert_eq!(output, "");
+    assert_eq!(stop.unwrap().reason, StopReason::String(1));
+}
+
+#[test]
+fn eog() {
+    let config = bpe("gpt2");
+    let mut matcher = StopMatcher::new(&["Hello!"]);
+    assert_eq!(
+        matcher.feed(&config, config.text_to_token("Hello")).text,
+        ""
+    );
+    let eot = config.text_to_token("<|endoftext|>");
+    let step = matcher.feed(&config, eot);
+    assert_eq!(step.text, "Hello");
+    assert_eq!(
+        step.stop,
+        Some(StopMatch {
+            reason: StopReason::Eog(eot),
+            trim: 0
+        })
+    );
+    // 不是结束生成的控制标记没有文本
+    let im_start = config.text_to_token("<|im_start|>");
+    assert_eq!(matcher.feed(&config, im_start).stop, None);
+}
+
+#[test]
+fn utf8_held_until_complete() {
+    // SPM 测试词表没有“中”，由三个字节标记组成
+    let config = spm();
+    let mut matcher = StopMatcher::new::<&str>(&[]);
+    let bytes = "中".bytes().collect::<Vec<_>>();
+    let mut output = String::new();
+    for (i, b) in bytes.iter().enumerate() {
+        let step = matcher.feed(&config, config.text_to_token(&format!("<0x{b:02X}>")));
+        assert_eq!(step.text.is_empty(), i < 2);
+        output.push_str(&step.text);
+    }
+    assert_eq!(output, "中");
+}
+
+thread_local! {
+    static BPE: TokenizerConfig = bpe("qwen2");
+    static SPM: TokenizerConfig = spm();
+}
+
+fn check(config: &TokenizerConfig, text: &str, stops: &[&str]) -> Result<(), TestCaseError> {
+    let (output, stop, fed) = run(config, stops, text);
+    let found = stops
+        .iter()
+        .enumerate()
+        .filter(|(_, stop)| !stop.is_empty())
+        .filter_map(|(i, stop)| {
+            fed.windows(stop.len())
+                .position(|w| w == stop.as_bytes())
+                .map(|pos| (pos, i))
+        })
+        .min();
+    match found {
+        Some((pos, i)) => {
+            // 停止在第一次完整出现的位置，之前的文本都已输出
+            let stop = stop.unwrap();
+            prop_assert_eq!(stop.reason, StopReason::String(i));
+            prop_assert_eq!(stop.trim, fed.len() - pos);
+            prop_assert_eq!(output.as_bytes(), &fed[..pos]);
+        }
+        None => {
+            prop_assert_eq!(stop, None);
+            prop_assert_eq!(output.as_bytes(), fed.as_slice());
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn same_as_search(
+        text in "[ab \n你好]{0,16}",
+        stops in prop::collection::vec("[ab \n你好]{0,3}", 0..3),
+    ) {
+        let stops = stops.iter().map(String::as_str).collect::<Vec<_>>();
+        BPE.with(|config| check(config, &text, &stops))?;
+        SPM.with(|config| check(config, &text, &stops))?;
+    }
+}
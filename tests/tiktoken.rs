@@ -0,0 +1,98 @@
+//! `.tiktoken` 排名文件的加载
+
+mod common;
+
+use std::collections::HashMap;
+
+use try_tokenize::{LoadError, NULL, TokenizerConfig, common::LLAMA3, load_tiktoken_str};
+
+const ENDOFTEXT: &str = "<|endoftext|>";
+
+/// 单字节的排名等于字节值，之后是 `merged` 中的多字节标记
+fn ranks(merged: &[&[u8]]) -> String {
+    let tokens = (0..=255u8)
+        .map(|b| vec![b])
+        .chain(merged.iter().map(|bytes| bytes.to_vec()))
+        .collect::<Vec<_>>();
+    common::tiktoken_ranks(&tokens)
+}
+
+fn load(merged: &[&[u8]], specials: &[(&str, u32)]) -> Result<TokenizerConfig, LoadError> {
+    let specials = specials
+        .iter()
+        .map(|&(text, id)| (text.to_string(), id))
+        .collect::<HashMap<_, _>>();
+    load_tiktoken_str(&ranks(merged), LLAMA3, &specials)
+}
+
+fn hello() -> TokenizerConfig {
+    load(&[b"he", b"ll", b"llo", b"hello"], &[(ENDOFTEXT, 260)]).unwrap()
+}
+
+#[test]
+fn tokenize() {
+    let config = hello();
+    assert_eq!(config.n_tokens(), 261);
+    assert_eq!(config.tokenize("hello", false, false), [259]);
+    assert_eq!(config.tokenize("hell", false, false), [256, 257]);
+    // 不在词表中的词按由排名还原的合并表合并
+    assert_eq!(config.tokenize("hellos", false, false), [259, b's' as u32]);
+
+    let text = format!("hello{ENDOFTEXT} wörld");
+    let ids = config.tokenize(&text, false, true);
+    assert_eq!(ids[..2], [259, 260]);
+    // 不解析特殊标记时按普通文本处理
+    assert!(!config.tokenize(&text, false, false).contains(&260));
+}
+
+#[test]
+fn special_token_collides_with_rank() {
+    match load(&[b"he"], &[(ENDOFTEXT, 256)]) {
+        Err(LoadError::Format(msg)) => assert!(msg.contains(ENDOFTEXT), "{msg}"),
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("special token overwrote a ranked token"),
+    }
+    assert!(load(&[b"he"], &[("<|a|>", 257), ("<|b|>", 257)]).is_err());
+}
+
+#[test]
+fn invalid_ranks() {
+    let specials = HashMap::new();
+    assert!(load_tiktoken_str("aGU=\n", LLAMA3, &specials).is_err());
+    assert!(load_tiktoken_str("!!! 0\n", LLAMA3, &specials).is_err());
+    assert!(load_tiktoken_str("aGU= x\n", LLAMA3, &specials).is_err());
+    // 多字节标记必须能由更低排名的合并得到
+    assert!(load(&[b"xyz"], &[]).is_err());
+    // 排名必须连续且不重复，巨大的排名不会按它分配内存
+    assert!(load_tiktoken_str("YQ== 4000000000\n", LLAMA3, &specials).is_err());
+    assert!(load_tiktoken_str("YQ== 0\nYg== 2\n", LLAMA3, &specials).is_err());
+    assert!(load_tiktoken_str("YQ== 0\nYg== 0\n", LLAMA3, &specials).is_err());
+    assert!(load_tiktoken_str("YQ== 0\nYQ== 1\n", LLAMA3, &specials).is_err());
+    assert!(load(&[], &[(ENDOFTEXT, 4_000_000_000)]).is_err());
+}
+
+#[test]
+fn sparse_special_ids() {
+    // 与 cl100k 一样，特殊标记与排名之间可以空出编号，占位标记不能通过文本查到
+    let config = load(&[b"he"], &[(ENDOFTEXT, 260)]).unwrap();
+    assert_eq!(config.n_tokens(), 261);
+    assert_eq!(config.text_to_token("[PAD258]"), NULL);
+    assert_eq!(config.token_to_id.len(), 258);
+
+    // 与占位标记同名的真实标记不会被遮住
+    let config = load(&[b"he"], &[("[PAD258]", 257), (ENDOFTEXT, 259)]).unwrap();
+    assert_eq!(config.text_to_token("[PAD258]"), 257);
+}
+
+#[test]
+fn long_tokens() {
+    // 长度为 2^k 的标记，每个都由两个更短的合并得到
+    let tokens = (1..=14).map(|k| vec![b'a'; 1 << k]).collect::<Vec<_>>();
+    let merged = tokens.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    let config = load(&merged, &[]).unwrap();
+    let text = "a".repeat((1 << 14) + (1 << 3) + 1);
+    assert_eq!(
+        config.tokenize(&text, false, false),
+        [256 + 13, 256 + 2, b'a' as u32]
+    );
+}
@@ -0,0 +1,81 @@
+//! 词表的遍历、按属性过滤和查找
+
+mod common;
+
+use common::{CONTROL, USER_DEFINED, bpe, spm};
+use try_tokenize::{TokenAttribute, TokenId};
+
+#[test]
+fn iterate() {
+    let config = spm();
+    let vocab = config.vocab().collect::<Vec<_>>();
+    assert_eq!(vocab.len(), config.n_tokens() as usize);
+    for (id, token) in vocab {
+        assert_eq!(config.text_to_token(&token.text), id);
+    }
+}
+
+#[test]
+fn filter_by_attribute() {
+    let config = spm();
+    let ids = |attribute| {
+        config
+            .tokens_with(attribute)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ids(TokenAttribute::Byte).len(), 256);
+    assert_eq!(ids(TokenAttribute::Unknown), [0]);
+    assert_eq!(
+        ids(TokenAttribute::UserDefined),
+        [config.text_to_token(USER_DEFINED[0])]
+    );
+    let control = ids(TokenAttribute::Control);
+    assert!(control.contains(&config.bos) && control.contains(&config.eos));
+    for text in CONTROL {
+        assert!(control.contains(&config.text_to_token(text)));
+    }
+    assert!(ids(TokenAttribute::Unused).is_empty());
+    assert_eq!(
+        ids(TokenAttribute::Undefined).len(),
+        config.n_tokens() as usize
+    );
+}
+
+#[test]
+fn decoded_pieces() {
+    let config = bpe("gpt2");
+    let hello = config.text_to_token("ĠHello");
+    assert_eq!(config.decoded_piece(hello), " Hello");
+    let endoftext = config.text_to_token(CONTROL[0]);
+    assert_eq!(config.decoded_piece(endoftext), CONTROL[0]);
+    // 单独的 UTF-8 续字节
+    let byte = config.byte_to_token(0xA0);
+    assert_eq!(config.decoded_piece(byte), "<0xA0>");
+
+    let config = spm();
+    let world = config.text_to_token("\u{2581}world");
+    assert_eq!(config.decoded_piece(world), " world");
+    assert_eq!(config.decoded_piece(config.byte_to_token(b'\n')), "\n");
+}
+
+#[test]
+fn search() {
+    for config in [bpe("gpt2"), spm()] {
+        let texts = |ids: Vec<TokenId>| {
+            ids.into_iter()
+                .map(|id| config.decoded_piece(id))
+                .collect::<Vec<_>>()
+        };
+        let prefix = texts(config.find_prefix(" wor").map(|(id, _)| id).collect());
+        assert!(prefix.contains(&" world".to_string()), "{prefix:?}");
+        assert!(prefix.iter().all(|text| text.starts_with(" wor")));
+
+        let substring = texts(config.find_substring("orl").map(|(id, _)| id).collect());
+        assert!(substring.contains(&" world".to_string()), "{substring:?}");
+        assert!(substring.iter().all(|text| text.contains("orl")));
+
+        assert_eq!(config.find_prefix("<|im_").count(), 2);
+        assert_eq!(config.find_substring("not in vocab").count(), 0);
+    }
+}